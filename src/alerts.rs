@@ -0,0 +1,418 @@
+//! Every notice announced in a report, such as a season onset or a site that stopped reporting, is
+//! recorded in `alerts` along with where its report went and whether it got there, so there's an
+//! auditable history of what was announced. `hygieia alerts list` prints it, and the site build
+//! publishes it as a page and as `alerts.json`.
+//!
+//! `hygieia alerts backtest` replays a rule over past samples to show when it would have fired, so
+//! its thresholds can be tuned before they page anyone.
+
+use std::fmt;
+
+use chrono::NaiveDate;
+use color_eyre::eyre::{self, eyre};
+use rusqlite::{named_params, params, Connection};
+use serde::Serialize;
+
+use crate::analysis::AnalysisOptions;
+use crate::context::Config;
+use crate::coverage::CoverageChange;
+use crate::report::{self, DateRange, Notices};
+use crate::retrospective::Period;
+use crate::season::{self, OnsetRule};
+use crate::tenants::Tenant;
+use crate::useful::Clock;
+
+/// Fired when a season starts in a county.
+pub const SEASON_ONSET_RULE: &str = "season-onset";
+/// Fired when the sites reporting in a county change.
+pub const COVERAGE_CHANGE_RULE: &str = "coverage-change";
+/// Fired when samples are restated upstream.
+pub const REVISION_RULE: &str = "revision";
+/// Fired by a tenant's alert rules, see [crate::rules].
+pub const THRESHOLD_RULE: &str = "threshold";
+
+/// An alert as stored in `alerts`.
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    pub id: i64,
+    pub pending_report_id: i64,
+    pub tenant: String,
+    pub created_timestamp: u64,
+    /// What fired the alert, e.g. [SEASON_ONSET_RULE].
+    pub rule: String,
+    pub county: String,
+    pub pathogen: Option<String>,
+    /// What was measured, when the rule measures something, e.g. a season onset's elevated sites.
+    pub value: Option<f64>,
+    /// What the value had to reach for the rule to fire.
+    pub threshold: Option<f64>,
+    pub message: String,
+    /// Where the report was sent, once a delivery was attempted.
+    pub destinations: Vec<String>,
+    /// pending, delivered, failed, or superseded if a newer report was delivered instead.
+    pub status: String,
+    pub error: Option<String>,
+    pub finished_timestamp: Option<u64>,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} tenant={} {} {}",
+            self.id, self.created_timestamp, self.tenant, self.rule, self.status
+        )?;
+        if !self.destinations.is_empty() {
+            write!(f, " to {}", self.destinations.join(","))?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(error) = &self.error {
+            write!(f, " (error: {error})")?;
+        }
+        Ok(())
+    }
+}
+
+/// An alert a rule would have fired, found by [backtest].
+#[derive(Debug, Serialize)]
+pub struct BacktestFiring {
+    /// Collection date of the sample the alert would have fired after.
+    pub date: NaiveDate,
+    pub rule: String,
+    /// Tenant whose rule it is, for rules tenants set.
+    pub tenant: Option<String>,
+    pub county: String,
+    pub pathogen: Option<String>,
+    pub value: Option<f64>,
+    pub threshold: Option<f64>,
+    pub message: String,
+}
+
+impl fmt::Display for BacktestFiring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tenant {
+            Some(tenant) => write!(
+                f,
+                "{} {} tenant={tenant}: {}",
+                self.date, self.rule, self.message
+            ),
+            None => write!(f, "{} {}: {}", self.date, self.rule, self.message),
+        }
+    }
+}
+
+/// Records the notices of the report stored as `pending_report_id`, returning how many there were.
+pub fn insert_alerts(
+    conn: &Connection,
+    clock: &dyn Clock,
+    pending_report_id: i64,
+    tenant: &str,
+    notices: &Notices,
+) -> eyre::Result<usize> {
+    const INSERT_ALERT_SQL: &str = "
+    INSERT INTO alerts (pending_report_id, tenant, created_timestamp, rule, county, pathogen, value,
+        threshold, message) VALUES
+    (:pending_report_id, :tenant, :created_timestamp, :rule, :county, :pathogen, :value,
+        :threshold, :message)";
+
+    let onsets = notices.season_onsets.iter().map(|onset| {
+        (
+            SEASON_ONSET_RULE,
+            onset.county.as_str(),
+            Some(onset.pathogen.as_str()),
+            Some(onset.elevated_sites as f64),
+            // At least half of the county's sites with a baseline
+            Some(onset.sites as f64 / 2.0),
+            onset.to_string(),
+        )
+    });
+    let coverage_changes = notices.coverage_changes.iter().map(|change| {
+        let pathogen = match change {
+            CoverageChange::TargetAdded { target } | CoverageChange::TargetRemoved { target } => {
+                Some(target.pcr_pathogen_target.as_str())
+            }
+            CoverageChange::NewSite { .. } | CoverageChange::MissedSamples { .. } => None,
+        };
+        let value = match change {
+            CoverageChange::MissedSamples { cadence } => Some(f64::from(cadence.missed_samples)),
+            _ => None,
+        };
+        (
+            COVERAGE_CHANGE_RULE,
+            change.county(),
+            pathogen,
+            value,
+            None,
+            change.to_string(),
+        )
+    });
+    let revisions = notices.revisions.iter().map(|revisions| {
+        (
+            REVISION_RULE,
+            revisions.county.as_str(),
+            None,
+            Some(revisions.samples as f64),
+            None,
+            revisions.to_string(),
+        )
+    });
+
+    let thresholds = notices.threshold_alerts.iter().map(|alert| {
+        (
+            THRESHOLD_RULE,
+            alert.county.as_str(),
+            Some(alert.pathogen.as_str()),
+            Some(alert.value),
+            Some(alert.threshold),
+            alert.to_string(),
+        )
+    });
+
+    let mut stmt = conn.prepare_cached(INSERT_ALERT_SQL)?;
+    let mut inserted = 0;
+    for (rule, county, pathogen, value, threshold, message) in thresholds
+        .chain(onsets)
+        .chain(coverage_changes)
+        .chain(revisions)
+    {
+        inserted += stmt.execute(named_params! {
+            ":pending_report_id": pending_report_id,
+            ":tenant": tenant,
+            ":created_timestamp": clock.unix_timestamp(),
+            ":rule": rule,
+            ":county": county,
+            ":pathogen": pathogen,
+            ":value": value,
+            ":threshold": threshold,
+            ":message": message,
+        })?;
+    }
+    Ok(inserted)
+}
+
+/// Records how delivering the report stored as `pending_report_id` to `destinations` went.
+pub fn finish_alerts(
+    conn: &Connection,
+    clock: &dyn Clock,
+    pending_report_id: i64,
+    destinations: &[String],
+    error: Option<&eyre::Report>,
+) -> eyre::Result<()> {
+    const FINISH_ALERTS_SQL: &str = "
+    UPDATE alerts SET
+        destinations = :destinations,
+        status = IIF(:error IS NULL, 'delivered', 'failed'),
+        error = :error,
+        finished_timestamp = :finished_timestamp
+    WHERE pending_report_id = :pending_report_id";
+
+    conn.prepare_cached(FINISH_ALERTS_SQL)?
+        .execute(named_params! {
+            ":destinations": destinations.join(","),
+            ":error": error.map(|e| format!("{e:#}")),
+            ":finished_timestamp": clock.unix_timestamp(),
+            ":pending_report_id": pending_report_id,
+        })?;
+    Ok(())
+}
+
+/// Whether an alert with `message` was already delivered to `tenant` by `rule`.
+pub fn was_delivered(
+    conn: &Connection,
+    tenant: &str,
+    rule: &str,
+    message: &str,
+) -> eyre::Result<bool> {
+    const SELECT_DELIVERED_SQL: &str = "
+    SELECT EXISTS (
+        SELECT 1 FROM alerts
+        WHERE tenant = :tenant AND rule = :rule AND message = :message AND status = 'delivered'
+    )";
+
+    Ok(conn.prepare_cached(SELECT_DELIVERED_SQL)?.query_row(
+        named_params! { ":tenant": tenant, ":rule": rule, ":message": message },
+        |row| row.get(0),
+    )?)
+}
+
+/// The newest `limit` alerts, or only `tenant`'s, newest first.
+pub fn select_alerts(
+    conn: &Connection,
+    tenant: Option<&str>,
+    limit: u32,
+) -> eyre::Result<Vec<Alert>> {
+    const SELECT_ALERTS_SQL: &str = "
+    SELECT a.id, a.pending_report_id, a.tenant, a.created_timestamp, a.rule, a.county, a.pathogen,
+        a.value, a.threshold, a.message, a.destinations,
+        IIF(p.status = 'superseded', 'superseded', a.status), a.error,
+        COALESCE(a.finished_timestamp, p.finished_timestamp)
+    FROM alerts a
+    JOIN pending_reports p ON p.id = a.pending_report_id
+    WHERE :tenant IS NULL OR a.tenant = :tenant
+    ORDER BY a.id DESC
+    LIMIT :limit";
+
+    let alerts = conn
+        .prepare_cached(SELECT_ALERTS_SQL)?
+        .query_map(
+            named_params! { ":tenant": tenant, ":limit": limit },
+            |row| {
+                Ok(Alert {
+                    id: row.get(0)?,
+                    pending_report_id: row.get(1)?,
+                    tenant: row.get(2)?,
+                    created_timestamp: row.get(3)?,
+                    rule: row.get(4)?,
+                    county: row.get(5)?,
+                    pathogen: row.get(6)?,
+                    value: row.get(7)?,
+                    threshold: row.get(8)?,
+                    message: row.get(9)?,
+                    destinations: row
+                        .get::<_, Option<String>>(10)?
+                        .map(|destinations| {
+                            destinations
+                                .split(',')
+                                .filter(|destination| !destination.is_empty())
+                                .map(str::to_owned)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    status: row.get(11)?,
+                    error: row.get(12)?,
+                    finished_timestamp: row.get(13)?,
+                })
+            },
+        )?
+        .collect::<Result<_, _>>()?;
+    Ok(alerts)
+}
+
+/// Replays `rule` over the samples of `counties` and `pathogens`, returning when it would have fired
+/// during `period`. Only rules evaluated from sample values can be replayed: season onsets with
+/// `onset_rule`, and every tenant's threshold rules with `config`'s analysis options. The others
+/// follow changes upstream.
+pub fn backtest(
+    conn: &Connection,
+    rule: &str,
+    counties: &[&str],
+    pathogens: &[&str],
+    period: Period,
+    onset_rule: &OnsetRule,
+    config: &Config,
+) -> eyre::Result<Vec<BacktestFiring>> {
+    match rule {
+        SEASON_ONSET_RULE => {
+            let onsets = season::backtest_season_onsets(
+                conn,
+                counties,
+                pathogens,
+                period.first_day(),
+                period.last_day(),
+                onset_rule,
+            )?;
+            Ok(onsets
+                .into_iter()
+                .map(|(date, onset)| BacktestFiring {
+                    date,
+                    rule: SEASON_ONSET_RULE.to_owned(),
+                    tenant: None,
+                    message: onset.to_string(),
+                    value: Some(onset.elevated_sites as f64),
+                    threshold: Some(onset.sites as f64 / 2.0),
+                    county: onset.county,
+                    pathogen: Some(onset.pathogen),
+                })
+                .collect())
+        }
+        THRESHOLD_RULE => backtest_threshold_rules(
+            conn,
+            &config.tenants_or_default(),
+            counties,
+            pathogens,
+            period,
+            &config.analysis,
+        ),
+        COVERAGE_CHANGE_RULE | REVISION_RULE => Err(eyre!(
+            "{rule} fires on changes upstream rather than on sample values, so it can't be backtested"
+        )),
+        _ => Err(eyre!(
+            "Unknown rule {rule}, expected {SEASON_ONSET_RULE} or {THRESHOLD_RULE}"
+        )),
+    }
+}
+
+/// Evaluates each tenant's alert rules as of every sample collected during `period`, as the report
+/// built after that sample arrived would have. Like the reports, an alert fires once per sample.
+fn backtest_threshold_rules(
+    conn: &Connection,
+    tenants: &[Tenant],
+    counties: &[&str],
+    pathogens: &[&str],
+    period: Period,
+    options: &AnalysisOptions,
+) -> eyre::Result<Vec<BacktestFiring>> {
+    let select_sample_dates_sql = format!(
+        "
+    SELECT DISTINCT sample_collection_date FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+        AND sample_collection_date >= ?3 AND sample_collection_date <= ?4
+        AND {measure} IS NOT NULL
+    ORDER BY sample_collection_date",
+        measure = options.measure.column()
+    );
+
+    let mut firings = Vec::new();
+    for tenant in tenants {
+        for &county in counties
+            .iter()
+            .filter(|&&county| tenant.counties.includes(county))
+        {
+            for &pathogen in pathogens {
+                if !tenant.pathogens.includes(pathogen)
+                    || !tenant.alert_rules.apply_to(county, pathogen)
+                {
+                    continue;
+                }
+
+                let dates: Vec<NaiveDate> = conn
+                    .prepare_cached(&select_sample_dates_sql)?
+                    .query_map(
+                        params![county, pathogen, period.first_day(), period.last_day()],
+                        |row| row.get(0),
+                    )?
+                    .collect::<Result<_, _>>()?;
+                for date in dates {
+                    let range = DateRange {
+                        since: None,
+                        until: Some(date),
+                    };
+                    let report = report::build_report(
+                        conn,
+                        &[county],
+                        &[pathogen],
+                        range,
+                        options,
+                        Notices::default(),
+                        None,
+                    );
+                    firings.extend(tenant.alert_rules.evaluate(&report.lines).into_iter().map(
+                        |alert| BacktestFiring {
+                            date,
+                            rule: THRESHOLD_RULE.to_owned(),
+                            tenant: Some(tenant.name.clone()),
+                            message: alert.to_string(),
+                            value: Some(alert.value),
+                            threshold: Some(alert.threshold),
+                            county: alert.county,
+                            pathogen: Some(alert.pathogen),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    firings.sort_by_key(|firing| firing.date);
+    Ok(firings)
+}
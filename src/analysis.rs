@@ -0,0 +1,552 @@
+//! Derived metrics computed from stored samples.
+//!
+//! # Trend estimation
+//!
+//! Trends are estimated from every sample of a county and pathogen in the [TREND_WINDOW_DAYS] days
+//! up to the latest sample. Concentrations are compared on a log scale, so a trend is a relative
+//! change and a county with one large plant doesn't drown out its smaller ones. Concentrations
+//! below [AnalysisOptions::concentration_floor] are raised to it first, so samples near zero, common
+//! for pathogens out of season, don't produce huge relative changes.
+//!
+//! Sites normalize concentrations differently, so their levels can't be compared directly. Instead
+//! of pooling samples, each site's mean (log concentration and date) is subtracted from its own
+//! samples before fitting a single least-squares slope. This is the "within" estimator of a linear
+//! model with a fixed effect per site: it answers "how fast are sites changing relative to their own
+//! recent level".
+//!
+//! The slope is converted to a weekly percentage change. A change smaller than
+//! [STEADY_WEEKLY_CHANGE] either way is called steady. Confidence is high when there are at least
+//! [HIGH_CONFIDENCE_MIN_SAMPLES] samples and the slope is at least [HIGH_CONFIDENCE_MIN_T] standard
+//! errors from zero (or, for steady trends, the slope's uncertainty fits within the steady band),
+//! and low otherwise. With fewer than [MIN_SAMPLES] samples no trend is estimated.
+//!
+//! # Sampling gaps
+//!
+//! Plants often skip samples around holidays, and a fit across the gap can show a drop that is
+//! only missing data. When a site goes more than [SAMPLING_GAP_DAYS] days between samples within
+//! the window, [find_sampling_gap] reports the longest such gap, the trend is given low confidence,
+//! and reports say sampling was limited instead of presenting the trend at face value.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, Days, NaiveDate};
+use serde::Serialize;
+
+use crate::levels::ActivityLevelConfig;
+use crate::numeric::{self, CompensatedSum};
+
+/// Number of days of samples, counting back from the latest, used to estimate a trend.
+pub const TREND_WINDOW_DAYS: i64 = 21;
+/// Weekly relative change below which a trend is considered steady.
+pub const STEADY_WEEKLY_CHANGE: f64 = 0.10;
+/// Fewest samples a trend is estimated from.
+pub const MIN_SAMPLES: usize = 3;
+/// Fewest samples a trend can be given high confidence with.
+pub const HIGH_CONFIDENCE_MIN_SAMPLES: usize = 6;
+/// Smallest slope t-statistic given high confidence.
+pub const HIGH_CONFIDENCE_MIN_T: f64 = 2.5;
+/// Default for [AnalysisOptions::concentration_floor].
+pub const DEFAULT_CONCENTRATION_FLOOR: f64 = 1.0;
+/// Longest a site can go between samples before it counts as a sampling gap.
+pub const SAMPLING_GAP_DAYS: i64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrendDirection {
+    Rising,
+    Steady,
+    Falling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    High,
+    Low,
+}
+
+/// How the change from the previous sample is expressed in reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeScale {
+    /// Absolute difference in concentration.
+    #[default]
+    Linear,
+    /// Relative change computed on a log scale, after applying the concentration floor.
+    Log,
+}
+
+impl FromStr for ChangeScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(ChangeScale::Linear),
+            "log" => Ok(ChangeScale::Log),
+            _ => Err(format!("Expected \"linear\" or \"log\", got {s}")),
+        }
+    }
+}
+
+impl fmt::Display for ChangeScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeScale::Linear => "linear",
+            ChangeScale::Log => "log",
+        })
+    }
+}
+
+/// Which of a sample's measures reports and charts show. Normalization per person hides changes
+/// in how much a plant collects, which the others show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Measure {
+    /// Gene copies per person per day, as normalized by each site.
+    #[default]
+    Normalized,
+    /// Gene copies per liter of wastewater.
+    PerLiter,
+    /// Gene copies per day: the per-liter concentration times the plant's flow.
+    FlowAdjusted,
+}
+
+/// Liters in a million US gallons.
+const LITERS_PER_MILLION_GALLONS: f64 = 3_785_411.784;
+
+impl Measure {
+    /// SQL expression for the measure over `wastewater_samples`, NULL for samples without it.
+    pub fn column(&self) -> String {
+        match self {
+            Measure::Normalized => "normalized_pathogen_concentration".to_owned(),
+            Measure::PerLiter => "concentration_per_liter".to_owned(),
+            Measure::FlowAdjusted => {
+                format!("concentration_per_liter * flow_rate_mgd * {LITERS_PER_MILLION_GALLONS}")
+            }
+        }
+    }
+
+    /// The measure's unit, e.g. "gene copies per liter".
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Measure::Normalized => "gene copies per person per day",
+            Measure::PerLiter => "gene copies per liter",
+            Measure::FlowAdjusted => "gene copies per day",
+        }
+    }
+}
+
+impl FromStr for Measure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "normalized" => Ok(Measure::Normalized),
+            "per-liter" => Ok(Measure::PerLiter),
+            "flow-adjusted" => Ok(Measure::FlowAdjusted),
+            _ => Err(format!(
+                "Expected \"normalized\", \"per-liter\", or \"flow-adjusted\", got {s}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Measure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Measure::Normalized => "normalized",
+            Measure::PerLiter => "per-liter",
+            Measure::FlowAdjusted => "flow-adjusted",
+        })
+    }
+}
+
+/// Settings for the metrics derived from samples.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnalysisOptions {
+    /// Smallest concentration used on a log scale. Must be positive.
+    pub concentration_floor: f64,
+    pub change_scale: ChangeScale,
+    /// The measure reported on.
+    pub measure: Measure,
+    /// Pathogens reported with an activity level, and their cutoffs.
+    pub activity_levels: ActivityLevelConfig,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            concentration_floor: DEFAULT_CONCENTRATION_FLOOR,
+            change_scale: ChangeScale::default(),
+            measure: Measure::default(),
+            activity_levels: ActivityLevelConfig::default(),
+        }
+    }
+}
+
+impl AnalysisOptions {
+    /// Natural log of `concentration`, raised to the floor first.
+    pub fn log_concentration(&self, concentration: f64) -> f64 {
+        concentration.max(self.concentration_floor).ln()
+    }
+
+    /// Relative change from `from` to `to` on a log scale, e.g. 0.5 for +50%.
+    pub fn relative_change(&self, from: f64, to: f64) -> f64 {
+        (self.log_concentration(to) - self.log_concentration(from)).exp() - 1.0
+    }
+}
+
+/// An estimated trend over the trend window.
+#[derive(Debug, Clone, Copy)]
+pub struct TrendEstimate {
+    pub direction: TrendDirection,
+    pub confidence: Confidence,
+    /// Estimated relative change per week, e.g. 0.25 for +25%/week.
+    pub weekly_change: f64,
+}
+
+impl TrendEstimate {
+    /// Qualitative label, e.g. "rising — high confidence" or "possibly falling — low confidence".
+    pub fn label(&self) -> String {
+        let direction = match self.direction {
+            TrendDirection::Rising => "rising",
+            TrendDirection::Steady => "steady",
+            TrendDirection::Falling => "falling",
+        };
+
+        match self.confidence {
+            Confidence::High => format!("{direction} — high confidence"),
+            Confidence::Low => format!("possibly {direction} — low confidence"),
+        }
+    }
+
+    pub fn arrow(&self) -> &'static str {
+        match self.direction {
+            TrendDirection::Rising => "↑",
+            TrendDirection::Steady => "→",
+            TrendDirection::Falling => "↓",
+        }
+    }
+
+    /// Days the concentration takes to double at the estimated rate, or to halve if negative. Only
+    /// confidently rising or falling trends have one, since the fit assumes exponential growth.
+    pub fn doubling_days(&self) -> Option<f64> {
+        if self.direction == TrendDirection::Steady || self.confidence == Confidence::Low {
+            return None;
+        }
+        let daily_rate = (1.0 + self.weekly_change).ln() / 7.0;
+        Some(std::f64::consts::LN_2 / daily_rate)
+    }
+
+    /// The doubling or halving time as written in messages, e.g. "doubling every 9 days".
+    pub fn describe_doubling(&self) -> Option<String> {
+        let days = self.doubling_days()?;
+        let verb = if days > 0.0 { "doubling" } else { "halving" };
+        Some(format!("{verb} every {:.0} days", days.abs()))
+    }
+
+    /// Indicator for chat messages, where it stands out more than [TrendEstimate::arrow].
+    pub fn emoji(&self) -> &'static str {
+        match self.direction {
+            TrendDirection::Rising => "📈",
+            TrendDirection::Steady => "➡️",
+            TrendDirection::Falling => "📉",
+        }
+    }
+}
+
+/// A sample used in trend estimation.
+#[derive(Debug, Clone)]
+pub struct TrendSample {
+    pub site_name: String,
+    pub sample_collection_date: NaiveDate,
+    pub concentration: f64,
+}
+
+/// A period in which a site had no samples, between the samples on `from` and `to`.
+#[derive(Debug, Clone)]
+pub struct SamplingGap {
+    pub site_name: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl SamplingGap {
+    pub fn days(&self) -> i64 {
+        (self.to - self.from).num_days()
+    }
+
+    /// Whether the gap spans Christmas or New Year's Day.
+    pub fn over_holidays(&self) -> bool {
+        let mut day = self.from;
+        while day < self.to {
+            day = day + Days::new(1);
+            if (day.month(), day.day()) == (12, 25) || (day.month(), day.day()) == (1, 1) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Note for reports, e.g. "limited sampling at West Point WWTP over the holidays".
+    pub fn note(&self) -> String {
+        if self.over_holidays() {
+            format!("limited sampling at {} over the holidays", self.site_name)
+        } else {
+            format!(
+                "limited sampling at {} from {} to {}",
+                self.site_name, self.from, self.to
+            )
+        }
+    }
+}
+
+/// Finds the longest gap between consecutive samples of the same site that is longer than
+/// [SAMPLING_GAP_DAYS].
+pub fn find_sampling_gap(samples: &[TrendSample]) -> Option<SamplingGap> {
+    let mut site_dates: HashMap<&str, Vec<NaiveDate>> = HashMap::new();
+    for sample in samples {
+        site_dates
+            .entry(sample.site_name.as_str())
+            .or_default()
+            .push(sample.sample_collection_date);
+    }
+
+    let mut longest: Option<SamplingGap> = None;
+    for (site, mut dates) in site_dates {
+        dates.sort_unstable();
+        dates.dedup();
+        for pair in dates.windows(2) {
+            let gap = SamplingGap {
+                site_name: site.to_owned(),
+                from: pair[0],
+                to: pair[1],
+            };
+            let longer = match &longest {
+                Some(longest) => gap.days() > longest.days(),
+                None => true,
+            };
+            if gap.days() > SAMPLING_GAP_DAYS && longer {
+                longest = Some(gap);
+            }
+        }
+    }
+    longest
+}
+
+/// Estimates the trend of `samples`, which should all fall within the trend window.
+/// See the module documentation for the method.
+pub fn estimate_trend(samples: &[TrendSample], options: &AnalysisOptions) -> Option<TrendEstimate> {
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+
+    // Days since an arbitrary epoch, and log concentration (floored so zeros stay finite)
+    let points: Vec<(&str, f64, f64)> = samples
+        .iter()
+        .map(|sample| {
+            let day = sample.sample_collection_date.num_days_from_ce() as f64;
+            let value = options.log_concentration(sample.concentration);
+            (sample.site_name.as_str(), day, value)
+        })
+        .collect();
+
+    let mut site_sums: HashMap<&str, (CompensatedSum, CompensatedSum, usize)> = HashMap::new();
+    for &(site, day, value) in &points {
+        let sums = site_sums.entry(site).or_default();
+        sums.0.add(day);
+        sums.1.add(value);
+        sums.2 += 1;
+    }
+
+    // Demean within each site
+    let demeaned: Vec<(f64, f64)> = points
+        .iter()
+        .map(|&(site, day, value)| {
+            let (day_sum, value_sum, count) = site_sums[site];
+            (
+                day - day_sum.total() / count as f64,
+                value - value_sum.total() / count as f64,
+            )
+        })
+        .collect();
+
+    let sxx = numeric::sum(demeaned.iter().map(|(x, _)| x * x));
+    if sxx == 0.0 {
+        // Every site only sampled on one day
+        return None;
+    }
+    let sxy = numeric::sum(demeaned.iter().map(|(x, y)| x * y));
+    let slope = sxy / sxx;
+
+    // One degree of freedom per site mean plus one for the slope
+    let degrees_of_freedom = points.len() as f64 - site_sums.len() as f64 - 1.0;
+    let standard_error = if degrees_of_freedom > 0.0 {
+        let residuals = numeric::sum(demeaned.iter().map(|(x, y)| (y - slope * x).powi(2)));
+        (residuals / degrees_of_freedom / sxx).sqrt()
+    } else {
+        f64::INFINITY
+    };
+
+    let weekly_change = (slope * 7.0).exp() - 1.0;
+    let steady_log_band = (1.0 + STEADY_WEEKLY_CHANGE).ln() / 7.0;
+
+    let direction = if weekly_change.abs() < STEADY_WEEKLY_CHANGE {
+        TrendDirection::Steady
+    } else if weekly_change > 0.0 {
+        TrendDirection::Rising
+    } else {
+        TrendDirection::Falling
+    };
+
+    let enough_samples = samples.len() >= HIGH_CONFIDENCE_MIN_SAMPLES;
+    let significant = match direction {
+        TrendDirection::Steady => slope.abs() + 2.0 * standard_error < steady_log_band,
+        _ => slope.abs() / standard_error >= HIGH_CONFIDENCE_MIN_T,
+    };
+    let gap = find_sampling_gap(samples);
+    let confidence = if enough_samples && significant && gap.is_none() {
+        Confidence::High
+    } else {
+        Confidence::Low
+    };
+
+    Some(TrendEstimate {
+        direction,
+        confidence,
+        weekly_change,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u64) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 11, 1).unwrap() + Days::new(day)
+    }
+
+    fn sample(site: &str, day: u64, log_concentration: f64) -> TrendSample {
+        TrendSample {
+            site_name: site.to_owned(),
+            sample_collection_date: date(day),
+            concentration: log_concentration.exp(),
+        }
+    }
+
+    /// Samples every other day from one site, with log concentrations rising `slope` a day.
+    fn series(slope: f64, days: u64) -> Vec<TrendSample> {
+        (0..days)
+            .step_by(2)
+            .map(|day| sample("A", day, 12.0 + slope * day as f64))
+            .collect()
+    }
+
+    fn estimate(samples: &[TrendSample]) -> Option<TrendEstimate> {
+        estimate_trend(samples, &AnalysisOptions::default())
+    }
+
+    #[test]
+    fn rising() {
+        let trend = estimate(&series(0.05, 20)).unwrap();
+        assert_eq!(trend.direction, TrendDirection::Rising);
+        assert_eq!(trend.confidence, Confidence::High);
+        assert!((trend.weekly_change - ((0.05f64 * 7.0).exp() - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn falling() {
+        let trend = estimate(&series(-0.05, 20)).unwrap();
+        assert_eq!(trend.direction, TrendDirection::Falling);
+        assert_eq!(trend.confidence, Confidence::High);
+        assert!((trend.weekly_change - ((-0.05f64 * 7.0).exp() - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn steady() {
+        // +5%/week is within the steady band
+        let trend = estimate(&series((1.05f64).ln() / 7.0, 20)).unwrap();
+        assert_eq!(trend.direction, TrendDirection::Steady);
+        assert_eq!(trend.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn too_few_samples() {
+        let samples = series(0.05, 2 * MIN_SAMPLES as u64 - 2);
+        assert_eq!(samples.len(), MIN_SAMPLES - 1);
+        assert!(estimate(&samples).is_none());
+    }
+
+    #[test]
+    fn high_confidence_needs_enough_samples() {
+        let samples = series(0.05, 2 * HIGH_CONFIDENCE_MIN_SAMPLES as u64);
+        assert_eq!(samples.len(), HIGH_CONFIDENCE_MIN_SAMPLES);
+        assert_eq!(estimate(&samples).unwrap().confidence, Confidence::High);
+        assert_eq!(estimate(&samples[1..]).unwrap().confidence, Confidence::Low);
+    }
+
+    #[test]
+    fn high_confidence_needs_significant_slope() {
+        // Noise orthogonal to the dates and the site mean, so the slope stays exact and only its
+        // standard error moves with the noise's amplitude
+        const NOISE: [f64; 8] = [1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0];
+        let slope = 0.05;
+        let noisy = |t: f64| {
+            // t = slope * sqrt(sxx) / (amplitude * sqrt(n / (n - 2))) for days 0, 2, ..., 14
+            let sxx: f64 = (0..8).map(|i| (2.0 * i as f64 - 7.0).powi(2)).sum();
+            let amplitude = slope * sxx.sqrt() / (t * (8.0f64 / 6.0).sqrt());
+            let samples: Vec<TrendSample> = NOISE
+                .iter()
+                .enumerate()
+                .map(|(i, noise)| {
+                    let day = 2 * i as u64;
+                    sample("A", day, 12.0 + slope * day as f64 + amplitude * noise)
+                })
+                .collect();
+            estimate(&samples).unwrap()
+        };
+
+        let above = noisy(HIGH_CONFIDENCE_MIN_T * 1.05);
+        assert_eq!(above.direction, TrendDirection::Rising);
+        assert_eq!(above.confidence, Confidence::High);
+        assert!((above.weekly_change - ((slope * 7.0).exp() - 1.0)).abs() < 1e-9);
+
+        let below = noisy(HIGH_CONFIDENCE_MIN_T * 0.95);
+        assert_eq!(below.direction, TrendDirection::Rising);
+        assert_eq!(below.confidence, Confidence::Low);
+    }
+
+    #[test]
+    fn sites_are_compared_with_themselves() {
+        // A large plant early in the window and a small one late, both flat. Pooled, they would
+        // look like a steep fall.
+        let samples: Vec<TrendSample> = (0..5)
+            .map(|i| sample("Large", 2 * i, 16.0))
+            .chain((0..5).map(|i| sample("Small", 10 + 2 * i, 8.0)))
+            .collect();
+        let trend = estimate(&samples).unwrap();
+        assert_eq!(trend.direction, TrendDirection::Steady);
+        assert!(trend.weekly_change.abs() < 1e-9);
+
+        // Both rising at the same rate from different levels
+        let samples: Vec<TrendSample> = (0..5)
+            .map(|i| sample("Large", 2 * i, 16.0 + 0.05 * (2 * i) as f64))
+            .chain((0..5).map(|i| sample("Small", 10 + 2 * i, 8.0 + 0.05 * (10 + 2 * i) as f64)))
+            .collect();
+        let trend = estimate(&samples).unwrap();
+        assert_eq!(trend.direction, TrendDirection::Rising);
+        assert!((trend.weekly_change - ((0.05f64 * 7.0).exp() - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn no_trend_when_each_site_sampled_once() {
+        let samples = [
+            sample("A", 0, 12.0),
+            sample("B", 5, 13.0),
+            sample("C", 10, 14.0),
+        ];
+        assert!(estimate(&samples).is_none());
+    }
+}
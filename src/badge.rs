@@ -0,0 +1,58 @@
+//! Shields.io-style SVG badges of a pathogen's current level, e.g. "sars-cov-2 wastewater: High ↑",
+//! for embedding in READMEs, wikis, and community sites. The site build writes one per county and
+//! pathogen to `badge/<county>/<pathogen>.svg`.
+
+use crate::levels::ActivityLevel;
+use crate::site::escape;
+
+/// Color of the message when there is no activity level to show.
+pub const NO_LEVEL_COLOR: &str = "#9f9f9f";
+
+/// Shields' colors from green to red, one per activity level.
+pub fn level_color(level: ActivityLevel) -> &'static str {
+    match level {
+        ActivityLevel::VeryLow => "#44cc11",
+        ActivityLevel::Low => "#97ca00",
+        ActivityLevel::Moderate => "#dfb317",
+        ActivityLevel::High => "#fe7d37",
+        ActivityLevel::VeryHigh => "#e05d44",
+    }
+}
+
+/// A flat badge with `label` on grey and `message` on `color`.
+pub fn badge(label: &str, message: &str, color: &str) -> String {
+    let label_width = text_width(label);
+    let message_width = text_width(message);
+    let width = label_width + message_width;
+    let (label, message) = (escape(label), escape(message));
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/><rect width="{width}" height="20" fill="url(#s)"/></g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{}" y="14">{label}</text>
+<text x="{}" y="14">{message}</text>
+</g>
+</svg>
+"##,
+        label_width / 2,
+        label_width + message_width / 2,
+    )
+}
+
+/// Approximate width of `text` in 11px Verdana, with padding on both sides.
+fn text_width(text: &str) -> usize {
+    let width: usize = text
+        .chars()
+        .map(|c| match c {
+            'i' | 'l' | 'j' | '.' | ',' | ':' | '\'' | '|' | ' ' => 4,
+            'm' | 'w' | 'M' | 'W' => 10,
+            c if c.is_uppercase() || c.is_ascii_digit() => 8,
+            _ => 7,
+        })
+        .sum();
+    width + 10
+}
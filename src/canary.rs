@@ -0,0 +1,110 @@
+//! Canary tenants receive reports built with a new version, analysis configuration, or
+//! [Config::release](crate::context::Config::release) first. Every report is stored with a snapshot
+//! of what it was built with, and until the canaries have received reports with a snapshot in
+//! [CanaryPolicy::runs] runs, other tenants' reports with it stay pending, so a formatting bug
+//! reaches a staging channel rather than the public one. A percentage of the other tenants can be
+//! let in early, chosen by a hash of the tenant and snapshot so each keeps its choice across runs.
+//!
+//! Without canary tenants every report is delivered as usual. With them, a new install's first
+//! reports go to the canaries first too.
+
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection};
+use sha2::{Digest, Sha256};
+
+/// Runs the canaries receive a new snapshot in before it's promoted, by default.
+pub const DEFAULT_CANARY_RUNS: u32 = 3;
+
+/// How reports with a new snapshot are rolled out past the canaries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CanaryPolicy {
+    /// Runs whose reports the canaries must have received before other tenants' are sent.
+    pub runs: u32,
+    /// Percentage of other tenants sent reports before promotion, from 0 to 100.
+    pub rollout_percent: u8,
+}
+
+impl Default for CanaryPolicy {
+    fn default() -> Self {
+        Self {
+            runs: DEFAULT_CANARY_RUNS,
+            rollout_percent: 0,
+        }
+    }
+}
+
+impl CanaryPolicy {
+    /// Whether a report built with `analysis_config` may be sent to `tenant`, which isn't one of
+    /// the `canaries`.
+    pub fn allows(
+        &self,
+        conn: &Connection,
+        canaries: &[String],
+        tenant: &str,
+        analysis_config: &str,
+    ) -> eyre::Result<bool> {
+        if canaries.is_empty() || in_rollout(tenant, analysis_config, self.rollout_percent) {
+            return Ok(true);
+        }
+        is_promoted(
+            conn,
+            canaries,
+            analysis_config,
+            self.runs,
+            self.rollout_percent,
+        )
+    }
+}
+
+/// Whether reports built with `analysis_config` have been delivered to the `canaries` in at least
+/// `runs` runs, or to a tenant that's neither a canary nor among the `rollout_percent` let in
+/// early. The latter only happens once a snapshot is promoted, or for one delivered before there
+/// were canaries, which existing installs keep delivering.
+pub fn is_promoted(
+    conn: &Connection,
+    canaries: &[String],
+    analysis_config: &str,
+    runs: u32,
+    rollout_percent: u8,
+) -> eyre::Result<bool> {
+    const SELECT_CANARY_RUNS_SQL: &str = "
+    SELECT COUNT(DISTINCT run_id) FROM pending_reports
+    WHERE status = 'delivered' AND analysis_config = :analysis_config
+        AND tenant IN (SELECT value FROM json_each(:canaries))";
+    const SELECT_OTHER_TENANTS_SQL: &str = "
+    SELECT DISTINCT tenant FROM pending_reports
+    WHERE status = 'delivered' AND analysis_config = :analysis_config
+        AND tenant NOT IN (SELECT value FROM json_each(:canaries))";
+
+    let params = named_params! {
+        ":canaries": serde_json::to_string(canaries)?,
+        ":analysis_config": analysis_config,
+    };
+    let canary_runs: u32 = conn
+        .prepare_cached(SELECT_CANARY_RUNS_SQL)?
+        .query_row(params, |row| row.get(0))?;
+    if canary_runs >= runs {
+        return Ok(true);
+    }
+
+    let mut stmt = conn.prepare_cached(SELECT_OTHER_TENANTS_SQL)?;
+    let mut rows = stmt.query(params)?;
+    while let Some(row) = rows.next()? {
+        let tenant: String = row.get(0)?;
+        if !in_rollout(&tenant, analysis_config, rollout_percent) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether `tenant` is among the `percent` of tenants let in early for `analysis_config`.
+fn in_rollout(tenant: &str, analysis_config: &str, percent: u8) -> bool {
+    let hash = Sha256::new()
+        .chain_update(tenant)
+        .chain_update([0])
+        .chain_update(analysis_config)
+        .finalize();
+    let bucket = u16::from_be_bytes([hash[0], hash[1]]) % 100;
+    bucket < u16::from(percent)
+}
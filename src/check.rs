@@ -0,0 +1,100 @@
+use color_eyre::eyre;
+use rusqlite::Connection;
+use tracing::{info, instrument};
+
+/// Checks the database for corruption and inconsistent data, returning a description of every
+/// problem found. An empty list means the database is healthy.
+#[instrument(skip(conn))]
+pub fn check_database(conn: &Connection) -> eyre::Result<Vec<String>> {
+    let mut problems = Vec::new();
+
+    // Reports "ok" as its only row when there is nothing wrong
+    let integrity: Vec<String> = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    problems.extend(
+        integrity
+            .into_iter()
+            .filter(|message| message != "ok")
+            .map(|message| format!("Integrity check: {message}")),
+    );
+
+    let foreign_key_violations: Vec<String> = conn
+        .prepare("PRAGMA foreign_key_check")?
+        .query_map([], |row| {
+            Ok(format!(
+                "Foreign key violation in {} row {:?} referencing {}",
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, String>(2)?
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    problems.extend(foreign_key_violations);
+
+    const DUPLICATE_SAMPLES_SQL: &str = "
+    SELECT sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target, COUNT(*)
+    FROM wastewater_samples
+    GROUP BY sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target
+    HAVING COUNT(*) > 1";
+
+    let duplicates: Vec<String> = conn
+        .prepare(DUPLICATE_SAMPLES_SQL)?
+        .query_map([], |row| {
+            Ok(format!(
+                "Duplicate sample: {} {} ({} County) {} {} stored {} times",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, i64>(5)?
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    problems.extend(duplicates);
+
+    const ORPHANED_MESSAGES_SQL: &str = "
+    SELECT message_id, thread_id FROM discord_messages m
+    WHERE thread_id IS NOT NULL AND NOT EXISTS (
+        SELECT 1 FROM discord_threads t WHERE t.webhook_id = m.webhook_id AND t.thread_id = m.thread_id
+    )";
+
+    let orphaned_messages: Vec<String> = conn
+        .prepare(ORPHANED_MESSAGES_SQL)?
+        .query_map([], |row| {
+            Ok(format!(
+                "Discord message {} is in unknown thread {}",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    problems.extend(orphaned_messages);
+
+    const ORPHANED_TARGETS_SQL: &str = "
+    SELECT site_name, county, pcr_pathogen_target, pcr_gene_target FROM site_targets t
+    WHERE NOT EXISTS (
+        SELECT 1 FROM wastewater_samples s
+        WHERE s.site_name = t.site_name AND s.county = t.county
+            AND s.pcr_pathogen_target = t.pcr_pathogen_target AND s.pcr_gene_target = t.pcr_gene_target
+    )";
+
+    let orphaned_targets: Vec<String> = conn
+        .prepare(ORPHANED_TARGETS_SQL)?
+        .query_map([], |row| {
+            Ok(format!(
+                "Site target {} ({} County) {} {} has no samples",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    problems.extend(orphaned_targets);
+
+    info!("Found {} problems", problems.len());
+    Ok(problems)
+}
@@ -0,0 +1,270 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand};
+use url::Url;
+
+use hygieia::alerts::SEASON_ONSET_RULE;
+use hygieia::daemon::Schedule;
+use hygieia::diff::DiffFormat;
+use hygieia::report::DateRange;
+use hygieia::retrospective::Period;
+use hygieia::season::{ONSET_BASELINE_MULTIPLIER, ONSET_SUSTAINED_SAMPLES};
+use hygieia::tenants::DEFAULT_TENANT;
+
+/// Polls Washington State wastewater data and reports the latest respiratory illness levels.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Only report samples collected on or after this date (YYYY-MM-DD).
+    #[arg(long, env = "REPORT_SINCE_DATE")]
+    pub since_date: Option<NaiveDate>,
+
+    /// Only report samples collected on or before this date (YYYY-MM-DD).
+    #[arg(long, env = "REPORT_UNTIL_DATE")]
+    pub until_date: Option<NaiveDate>,
+
+    /// Run as if it were this time (RFC 3339), e.g. to replay a past report.
+    /// Reports then only include samples collected by this date, unless --until-date is given.
+    #[arg(long, env = "RUN_AS_OF")]
+    pub as_of: Option<DateTime<Utc>>,
+
+    /// Seed for the run's randomness, such as retry jitter and synthetic data, so it can be
+    /// reproduced. Random when not given.
+    #[arg(long, env = "RUN_SEED")]
+    pub seed: Option<u64>,
+}
+
+impl Cli {
+    pub fn date_range(&self) -> DateRange {
+        DateRange {
+            since: self.since_date,
+            until: self
+                .until_date
+                .or(self.as_of.map(|as_of| as_of.date_naive())),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Fetch and store new data only, without building a report.
+    Fetch,
+    /// Build a report from stored data and send it, without fetching.
+    Report {
+        /// Print the report as markdown instead of storing and sending it.
+        #[arg(long)]
+        print: bool,
+    },
+    /// Fetch and store new data, and store the report for a later notify, without sending it.
+    Ingest,
+    /// Send the newest stored report, without fetching.
+    Notify,
+    /// Print sample, run, and delivery statistics for monitoring.
+    Stats {
+        /// Print JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a narrative summary of a month, quarter, or year from stored data, without fetching.
+    Retrospective {
+        /// Month, quarter, or year to summarize, e.g. 2024-12, 2024-Q4, or 2024.
+        #[arg(long)]
+        period: Period,
+    },
+    /// Print a DuckDB script that attaches the database and creates analytical views.
+    Duckdb,
+    /// Write the stored samples and sites as a Frictionless data package (datapackage.json and CSVs).
+    Export {
+        /// Directory to write the package to.
+        #[arg(long, default_value = "datapackage")]
+        dir: PathBuf,
+    },
+    /// Maintain the database.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Manage site metadata.
+    Sites {
+        #[command(subcommand)]
+        command: SitesCommand,
+    },
+    /// Generate a static site from stored data, without fetching.
+    Site {
+        #[command(subcommand)]
+        command: SiteCommand,
+    },
+    /// Print the configuration, or the schema of the tenants file, without touching the database.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print the history of alerts announced in reports.
+    Alerts {
+        #[command(subcommand)]
+        command: AlertsCommand,
+    },
+    /// Manage third-party callback URLs that new reports are posted to.
+    Subscribers {
+        #[command(subcommand)]
+        command: SubscribersCommand,
+    },
+    /// Keep running, fetching and reporting on a schedule until interrupted.
+    Daemon(Box<DaemonArgs>),
+    /// Download the current file and print how it differs from the database, without storing anything.
+    Diff {
+        /// Output format, json or csv.
+        #[arg(long, default_value = "json")]
+        format: DiffFormat,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    /// An interval like 6h, 30m, or 1d, or a cron expression in local time like "0 6 * * *".
+    #[arg(long, env = "POLL_INTERVAL", default_value = "6h")]
+    pub schedule: Schedule,
+    /// Directory the data package is regularly exported to, with the static site built in its
+    /// `site` directory. Without one nothing is exported.
+    #[arg(long, env = "PATH_EXPORT")]
+    pub export_dir: Option<PathBuf>,
+    /// When the data package is exported and the site built, in the same format as --schedule.
+    #[arg(long, env = "EXPORT_SCHEDULE", default_value = "0 3 * * *")]
+    pub export_schedule: Schedule,
+    /// Where the exported site is served from, as for `hygieia site build`.
+    #[arg(long, env = "SITE_BASE_URL")]
+    pub site_base_url: Option<Url>,
+    /// Origins allowed to embed the exported site's county widgets, as for `hygieia site build`.
+    #[arg(
+        long = "embed-origin",
+        env = "SITE_EMBED_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub embed_origins: Vec<String>,
+    /// When `hygieia db analyze` runs, in the same format as --schedule. Weekly by default.
+    #[arg(long, env = "ANALYZE_SCHEDULE", default_value = "0 4 * * 0")]
+    pub analyze_schedule: Schedule,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// Check integrity, uniqueness of samples, and orphaned records. Exits non-zero on problems.
+    Check,
+    /// Update the query planner's statistics and precompute activity level baselines, so reports
+    /// don't read a year of samples.
+    Analyze,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SitesCommand {
+    /// Fetch the site metadata listing from URL_SITE_METADATA and update the sites table.
+    Sync,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SiteCommand {
+    /// Write the index, county pages, feed, and data downloads to a directory.
+    Build {
+        /// Directory to write the site to.
+        #[arg(long, default_value = "public")]
+        out: PathBuf,
+        /// Regenerate every page, even ones whose data is unchanged.
+        #[arg(long)]
+        full: bool,
+        /// URL the site is served from, e.g. https://example.org/wastewater/. Link previews only
+        /// include the canonical URL and preview image, and sitemap.xml is only written, when it
+        /// is set.
+        #[arg(long, env = "SITE_BASE_URL")]
+        base_url: Option<Url>,
+        /// Origins allowed to embed the county widgets in an iframe, separated by commas. Any
+        /// origin may embed them when none are given.
+        #[arg(
+            long = "embed-origin",
+            env = "SITE_EMBED_ORIGINS",
+            value_delimiter = ','
+        )]
+        embed_origins: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the settings that are set and where each came from, and the tenants reports go to,
+    /// with secrets redacted.
+    Show {
+        /// Print every setting, including the defaults of those that aren't set.
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Print the JSON Schema of the tenants file, for editors to validate and autocomplete with.
+    Schema,
+    /// Check a tenants file against the schema, listing every problem with where it is. Exits
+    /// non-zero on problems.
+    Validate {
+        /// File to check.
+        #[arg(env = "PATH_TENANTS")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AlertsCommand {
+    /// List the newest alerts with where they were sent and whether they were delivered.
+    List {
+        /// Only list this tenant's alerts.
+        #[arg(long)]
+        tenant: Option<String>,
+        /// Most alerts listed.
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        /// Print JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Replay an alert rule over stored samples and list when it would have fired, for tuning its
+    /// thresholds before relying on it. Uses the configured counties and pathogens.
+    Backtest {
+        /// Rule to replay: season-onset, or threshold for every tenant's alert rules. Only rules
+        /// evaluated from sample values can be replayed.
+        #[arg(long, default_value = SEASON_ONSET_RULE)]
+        rule: String,
+        /// Month, quarter, or year to replay, e.g. 2024, 2024-Q4, or 2024-12.
+        #[arg(long)]
+        range: Period,
+        /// How many times its off-season baseline a site's samples must reach to count as elevated.
+        #[arg(long, default_value_t = ONSET_BASELINE_MULTIPLIER)]
+        multiplier: f64,
+        /// How many of a site's most recent samples must all be elevated.
+        #[arg(long, default_value_t = ONSET_SUSTAINED_SAMPLES)]
+        sustained: usize,
+        /// Print JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubscribersCommand {
+    /// Subscribe a callback URL to a tenant's reports.
+    Add {
+        callback_url: String,
+        /// Tenant whose reports are posted.
+        #[arg(long, default_value = DEFAULT_TENANT)]
+        tenant: String,
+        /// Shared secret payloads are signed with. Without one they are sent unsigned.
+        #[arg(long, env = "SUBSCRIBER_SECRET", hide_env_values = true)]
+        secret: Option<String>,
+    },
+    /// Unsubscribe a callback URL from every tenant.
+    Remove { callback_url: String },
+    /// List subscribers and how their last delivery went.
+    List {
+        /// Print JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+}
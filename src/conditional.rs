@@ -0,0 +1,82 @@
+//! Conditional GET for the wastewater CSV.
+//!
+//! The validators of the last fully stored response are kept in `http_validators` and sent back as
+//! `If-None-Match` and `If-Modified-Since`, so an unchanged file is answered with 304 Not Modified
+//! instead of being downloaded and parsed again.
+
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use ureq::{Request, Response};
+
+use crate::useful::Clock;
+
+/// A response's ETag and Last-Modified headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// The response's validators, None if it has neither.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let validators = Self {
+            etag: response.header("ETag").map(str::to_owned),
+            last_modified: response.header("Last-Modified").map(str::to_owned),
+        };
+        (validators.etag.is_some() || validators.last_modified.is_some()).then_some(validators)
+    }
+
+    /// Makes `request` conditional on the resource having changed since these validators.
+    pub fn apply(&self, mut request: Request) -> Request {
+        if let Some(etag) = &self.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+        request
+    }
+}
+
+pub fn select_validators(conn: &Connection, url: &str) -> eyre::Result<Option<Validators>> {
+    const SELECT_VALIDATORS_SQL: &str =
+        "SELECT etag, last_modified FROM http_validators WHERE url = ?1";
+
+    let validators = conn
+        .prepare_cached(SELECT_VALIDATORS_SQL)?
+        .query_row([url], |row| {
+            Ok(Validators {
+                etag: row.get(0)?,
+                last_modified: row.get(1)?,
+            })
+        })
+        .optional()?;
+    Ok(validators)
+}
+
+/// Stores the validators of `url`. Only call this once the response is fully processed, so an
+/// interrupted run requests the whole file again.
+pub fn store_validators(
+    conn: &Connection,
+    clock: &dyn Clock,
+    url: &str,
+    validators: &Validators,
+) -> eyre::Result<()> {
+    const UPSERT_VALIDATORS_SQL: &str = "
+    INSERT INTO http_validators (url, etag, last_modified, stored_timestamp) VALUES
+    (:url, :etag, :last_modified, :stored_timestamp)
+    ON CONFLICT (url) DO UPDATE SET
+        etag = excluded.etag,
+        last_modified = excluded.last_modified,
+        stored_timestamp = excluded.stored_timestamp";
+
+    conn.prepare_cached(UPSERT_VALIDATORS_SQL)?
+        .execute(named_params! {
+            ":url": url,
+            ":etag": validators.etag,
+            ":last_modified": validators.last_modified,
+            ":stored_timestamp": clock.unix_timestamp(),
+        })?;
+    Ok(())
+}
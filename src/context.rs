@@ -0,0 +1,327 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::analysis::{self, AnalysisOptions};
+use crate::canary::CanaryPolicy;
+use crate::coverage::{self, DEFAULT_MAX_MISSED_SAMPLES};
+use crate::db::DEFAULT_INSERT_CHUNK_SAMPLES;
+use crate::discord::DiscordWebhookOptions;
+use crate::email::SmtpConfig;
+use crate::history;
+use crate::http::{HttpClient, HttpConfig};
+use crate::levels;
+use crate::links::DashboardLinks;
+use crate::mastodon::MastodonAccount;
+use crate::matrix::MatrixRoom;
+use crate::ntfy::NtfyTopic;
+use crate::pipeline::{DEFAULT_COUNTIES, DEFAULT_PATHOGENS};
+use crate::precision::OutputPrecision;
+use crate::pushover::PushoverUser;
+use crate::report;
+use crate::rules::{AlertRules, NotifyMode};
+use crate::season;
+use crate::telegram::TelegramChat;
+use crate::tenants::Tenant;
+use crate::useful::{Clock, Secret, SeededRng};
+
+/// Which counties or pathogens are reported: a list, or every one with stored samples.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "SelectionValue")]
+pub enum Selection {
+    All,
+    Only(Vec<String>),
+}
+
+impl Selection {
+    pub fn only(values: &[&str]) -> Self {
+        Selection::Only(values.iter().map(|&value| value.to_owned()).collect())
+    }
+
+    /// The selected values, calling `present` to list them for [Selection::All].
+    pub fn resolve(
+        &self,
+        present: impl FnOnce() -> eyre::Result<Vec<String>>,
+    ) -> eyre::Result<Vec<String>> {
+        match self {
+            Selection::All => present(),
+            Selection::Only(values) => Ok(values.clone()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Selection::Only(values) if values.is_empty())
+    }
+
+    /// Whether `value` is selected.
+    pub fn includes(&self, value: &str) -> bool {
+        match self {
+            Selection::All => true,
+            Selection::Only(values) => values.iter().any(|selected| selected == value),
+        }
+    }
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    /// Parses "all", or a list separated by commas.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("all") {
+            return Ok(Selection::All);
+        }
+        Ok(Selection::Only(
+            s.split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        ))
+    }
+}
+
+/// A selection as written in JSON, either "all" or a list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SelectionValue {
+    Keyword(String),
+    List(Vec<String>),
+}
+
+impl TryFrom<SelectionValue> for Selection {
+    type Error = String;
+
+    fn try_from(value: SelectionValue) -> Result<Self, Self::Error> {
+        match value {
+            SelectionValue::Keyword(keyword) if keyword.eq_ignore_ascii_case("all") => {
+                Ok(Selection::All)
+            }
+            SelectionValue::Keyword(keyword) => {
+                Err(format!("Expected \"all\" or a list, got {keyword}"))
+            }
+            SelectionValue::List(values) => Ok(Selection::Only(values)),
+        }
+    }
+}
+
+/// Configuration loaded once at startup.
+/// Stages read their settings from here instead of the environment, so a run can be set up in code.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub wastewater_url: String,
+    /// Counties reported on without tenants.
+    pub counties: Selection,
+    /// Pathogen targets reported on without tenants.
+    pub pathogens: Selection,
+    /// Socrata metadata URL checked before downloading, if set.
+    pub socrata_metadata_url: Option<String>,
+    /// Where to download the CSV to. When None the response is parsed as it streams in.
+    pub download_path: Option<PathBuf>,
+    pub download_sha256: Option<String>,
+    /// Samples inserted per transaction, each commit recording how far the insert got.
+    pub insert_chunk_samples: usize,
+    /// Discord webhook to post reports to, if set.
+    pub discord_webhook_url: Option<String>,
+    pub discord_options: DiscordWebhookOptions,
+    /// Endpoint reports are posted to as JSON, if set.
+    pub json_webhook_url: Option<String>,
+    /// Secret the JSON webhook payload is signed with, if set.
+    pub json_webhook_secret: Option<Secret>,
+    /// Slack incoming webhook to post reports to, if set.
+    pub slack_webhook_url: Option<String>,
+    /// Microsoft Teams incoming webhook to post reports to, if set.
+    pub teams_webhook_url: Option<String>,
+    /// Matrix room to post reports to, if set.
+    pub matrix_room: Option<MatrixRoom>,
+    /// Telegram chat to send reports to, if set.
+    pub telegram_chat: Option<TelegramChat>,
+    /// Mastodon account to post reports from, if set.
+    pub mastodon_account: Option<MastodonAccount>,
+    /// ntfy topic to publish reports to, if set.
+    pub ntfy_topic: Option<NtfyTopic>,
+    /// Pushover user to send reports to, if set.
+    pub pushover_user: Option<PushoverUser>,
+    /// SMTP server reports are emailed through, if set.
+    pub smtp: Option<SmtpConfig>,
+    /// Addresses reports are emailed to.
+    pub email_to: Vec<String>,
+    /// Links into the dashboard for notifications, if a dashboard URL is set.
+    pub dashboard_links: Option<DashboardLinks>,
+    pub report_footer: bool,
+    pub precision: OutputPrecision,
+    /// Expected samples a site can miss before reports point it out.
+    pub max_missed_samples: u32,
+    pub analysis: AnalysisOptions,
+    pub http: HttpConfig,
+    /// Conditions reported as alerts without tenants.
+    pub alert_rules: AlertRules,
+    /// Whether reports are sent every run without tenants, or only when an alert rule fired.
+    pub notify_mode: NotifyMode,
+    /// Store and send no reports in runs that inserted or revised no samples.
+    pub new_data_only: bool,
+    /// Discord webhook told when a run sends nothing for lack of new data, if set.
+    pub admin_discord_webhook_url: Option<String>,
+    /// Tenants reported to separately. When empty, the webhooks above are a single default tenant.
+    pub tenants: Vec<Tenant>,
+    /// Seeds the run's randomness, so a run can be reproduced. Random when None.
+    pub seed: Option<u64>,
+    /// Labels the templates and analysis reports are built with, stored in
+    /// [Config::analysis_snapshot]. Changing it sends reports to canary tenants first.
+    pub release: Option<String>,
+    /// How reports built with a new version, configuration, or release reach non-canary tenants.
+    pub canary: CanaryPolicy,
+}
+
+impl Config {
+    /// Configuration that fetches from `wastewater_url`, with every optional feature off and the
+    /// defaults the binary uses otherwise.
+    pub fn new(wastewater_url: impl Into<String>) -> Self {
+        Self {
+            wastewater_url: wastewater_url.into(),
+            counties: Selection::only(&DEFAULT_COUNTIES),
+            pathogens: Selection::only(&DEFAULT_PATHOGENS),
+            socrata_metadata_url: None,
+            download_path: None,
+            download_sha256: None,
+            insert_chunk_samples: DEFAULT_INSERT_CHUNK_SAMPLES,
+            discord_webhook_url: None,
+            discord_options: DiscordWebhookOptions::default(),
+            json_webhook_url: None,
+            json_webhook_secret: None,
+            slack_webhook_url: None,
+            teams_webhook_url: None,
+            matrix_room: None,
+            telegram_chat: None,
+            mastodon_account: None,
+            ntfy_topic: None,
+            pushover_user: None,
+            smtp: None,
+            email_to: Vec::new(),
+            dashboard_links: None,
+            report_footer: true,
+            precision: OutputPrecision::default(),
+            max_missed_samples: DEFAULT_MAX_MISSED_SAMPLES,
+            analysis: AnalysisOptions::default(),
+            http: HttpConfig::default(),
+            alert_rules: AlertRules::default(),
+            notify_mode: NotifyMode::default(),
+            new_data_only: false,
+            admin_discord_webhook_url: None,
+            tenants: Vec::new(),
+            seed: None,
+            release: None,
+            canary: CanaryPolicy::default(),
+        }
+    }
+
+    /// The configured tenants, or the default tenant if there are none.
+    pub fn tenants_or_default(&self) -> Vec<Tenant> {
+        if self.tenants.is_empty() {
+            vec![Tenant::from_config(self)]
+        } else {
+            self.tenants.clone()
+        }
+    }
+
+    /// Names of the canary tenants.
+    pub fn canaries(&self) -> Vec<String> {
+        self.tenants
+            .iter()
+            .filter(|tenant| tenant.canary)
+            .map(|tenant| tenant.name.clone())
+            .collect()
+    }
+}
+
+impl Config {
+    /// The windows and thresholds reports are generated with, stored with each report so it can
+    /// still be explained after the configuration changes.
+    pub fn analysis_snapshot(&self) -> serde_json::Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "release": self.release,
+            "options": self.analysis,
+            "trend": {
+                "window_days": analysis::TREND_WINDOW_DAYS,
+                "steady_weekly_change": analysis::STEADY_WEEKLY_CHANGE,
+                "min_samples": analysis::MIN_SAMPLES,
+                "high_confidence_min_samples": analysis::HIGH_CONFIDENCE_MIN_SAMPLES,
+                "high_confidence_min_t": analysis::HIGH_CONFIDENCE_MIN_T,
+                "sampling_gap_days": analysis::SAMPLING_GAP_DAYS,
+            },
+            "coverage": {
+                "period_days": report::COVERAGE_PERIOD_DAYS,
+                "target_lapse_days": coverage::TARGET_LAPSE_DAYS,
+                "cadence_history_days": coverage::CADENCE_HISTORY_DAYS,
+                "cadence_min_samples": coverage::CADENCE_MIN_SAMPLES,
+                "max_missed_samples": self.max_missed_samples,
+            },
+            "season": {
+                "off_season_months": [season::OFF_SEASON_MONTHS.start(), season::OFF_SEASON_MONTHS.end()],
+                "min_baseline_samples": season::MIN_BASELINE_SAMPLES,
+                "onset_baseline_multiplier": season::ONSET_BASELINE_MULTIPLIER,
+                "onset_sustained_samples": season::ONSET_SUSTAINED_SAMPLES,
+            },
+            "activity_levels": {
+                "baseline_days": levels::BASELINE_DAYS,
+                "min_baseline_samples": levels::MIN_BASELINE_SAMPLES,
+            },
+            "history": {
+                "days": history::HISTORY_DAYS,
+                "min_samples": history::MIN_HISTORY_SAMPLES,
+            },
+        })
+    }
+}
+
+/// Everything a run's stages share: fetch, store, analyze, and notify all take it explicitly.
+pub struct RunContext {
+    /// Identifies the run in logs.
+    pub run_id: String,
+    pub config: Config,
+    pub clock: Arc<dyn Clock>,
+    /// When the run started, according to `clock`.
+    pub started_at: DateTime<Utc>,
+    /// Seeded with [Config::seed].
+    pub rng: SeededRng,
+    pub http: HttpClient,
+    pub db: Connection,
+}
+
+impl RunContext {
+    pub fn new(config: Config, db: Connection, clock: Arc<dyn Clock>) -> Self {
+        let started_at = clock.now();
+        let rng = SeededRng::new(config.seed);
+        let http = HttpClient::new(config.http.clone(), clock.clone(), rng.clone());
+
+        Self {
+            run_id: run_id(started_at),
+            config,
+            clock,
+            started_at,
+            rng,
+            http,
+            db,
+        }
+    }
+
+    /// Starts a new run with the same configuration and connections, as the daemon does each cycle.
+    pub fn start_run(&mut self) {
+        self.started_at = self.clock.now();
+        self.run_id = run_id(self.started_at);
+    }
+}
+
+fn run_id(started_at: DateTime<Utc>) -> String {
+    format!(
+        "{}-{:x}",
+        started_at.format("%Y%m%dT%H%M%S"),
+        std::process::id()
+    )
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use chrono::{Days, NaiveDate};
+use color_eyre::eyre;
+use rusqlite::{named_params, params, Connection};
+use tracing::{info, instrument};
+
+use crate::useful::Clock;
+
+/// A change in which sites report data, worth pointing out because it affects how trends read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoverageChange {
+    /// A site that isn't in the `sites` table started reporting.
+    NewSite { site_name: String, county: String },
+    /// A known site started reporting a target, or resumed after lapsing.
+    TargetAdded { target: SiteTarget },
+    /// A site still reports other targets, but hasn't reported this one in [TARGET_LAPSE_DAYS] days.
+    TargetRemoved { target: SiteTarget },
+    /// A site has missed more expected samples than allowed, judging by its usual cadence.
+    MissedSamples { cadence: SiteCadence },
+}
+
+impl CoverageChange {
+    pub fn county(&self) -> &str {
+        match self {
+            CoverageChange::NewSite { county, .. } => county,
+            CoverageChange::TargetAdded { target } | CoverageChange::TargetRemoved { target } => {
+                &target.county
+            }
+            CoverageChange::MissedSamples { cadence } => &cadence.county,
+        }
+    }
+}
+
+/// How many days a target can go unreported, while its site reports others, before it counts as dropped.
+pub const TARGET_LAPSE_DAYS: i64 = 28;
+/// Default number of expected samples a site can miss before it is reported.
+pub const DEFAULT_MAX_MISSED_SAMPLES: u32 = 2;
+
+/// Days of history, counting back from the newest sample, a site's cadence is learned from.
+pub const CADENCE_HISTORY_DAYS: u64 = 90;
+/// Fewest distinct sample dates a site's cadence is learned from.
+pub const CADENCE_MIN_SAMPLES: usize = 4;
+
+/// A site's typical sampling interval and how many samples it has missed since its last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteCadence {
+    pub site_name: String,
+    pub county: String,
+    /// Median days between the site's samples.
+    pub interval_days: f64,
+    pub last_sample_date: NaiveDate,
+    /// Samples the site would have taken between its last sample and the newest sample of any site.
+    pub missed_samples: u32,
+}
+
+/// A pathogen and gene target reported by a site.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SiteTarget {
+    pub site_name: String,
+    pub county: String,
+    pub pcr_pathogen_target: String,
+    pub pcr_gene_target: String,
+}
+
+impl fmt::Display for CoverageChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoverageChange::NewSite { site_name, county } => {
+                write!(f, "New reporting site: {site_name} ({county} County)")
+            }
+            CoverageChange::TargetAdded { target } => write!(
+                f,
+                "{} ({} County) started reporting {} ({})",
+                target.site_name, target.county, target.pcr_pathogen_target, target.pcr_gene_target
+            ),
+            CoverageChange::TargetRemoved { target } => write!(
+                f,
+                "{} ({} County) stopped reporting {} ({})",
+                target.site_name, target.county, target.pcr_pathogen_target, target.pcr_gene_target
+            ),
+            CoverageChange::MissedSamples { cadence } => write!(
+                f,
+                "{} ({} County) has missed {} expected samples (usually every {:.1} days, last sampled {})",
+                cadence.site_name,
+                cadence.county,
+                cadence.missed_samples,
+                cadence.interval_days,
+                cadence.last_sample_date
+            ),
+        }
+    }
+}
+
+/// Finds sites with samples that aren't in the `sites` table yet, and adds them so they are only
+/// reported once. When the table is empty every site is added without being reported, since
+/// there is nothing to compare against.
+#[instrument(skip_all)]
+pub fn detect_new_sites(conn: &Connection, clock: &dyn Clock) -> eyre::Result<Vec<CoverageChange>> {
+    const INSERT_NEW_SITES_SQL: &str = "
+    INSERT INTO sites (site_name, county, source, updated_timestamp)
+    SELECT DISTINCT site_name, county, 'ingest', ?1 FROM wastewater_samples
+    WHERE NOT EXISTS (
+        SELECT 1 FROM sites WHERE sites.site_name = wastewater_samples.site_name AND sites.county = wastewater_samples.county
+    )
+    RETURNING site_name, county";
+
+    let had_sites: bool =
+        conn.query_row("SELECT EXISTS (SELECT 1 FROM sites)", [], |row| row.get(0))?;
+
+    let new_sites = conn
+        .prepare_cached(INSERT_NEW_SITES_SQL)?
+        .query_map(params![clock.unix_timestamp()], |row| {
+            Ok(CoverageChange::NewSite {
+                site_name: row.get(0)?,
+                county: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !had_sites {
+        info!("Registered {} sites", new_sites.len());
+        return Ok(Vec::new());
+    }
+
+    for site in &new_sites {
+        info!("{site}");
+    }
+    Ok(new_sites)
+}
+
+/// Compares the targets every site currently reports with those recorded in `site_targets` at the
+/// last ingest, then records the current state.
+/// Sites without recorded targets, such as new ones, are recorded without being reported.
+#[instrument(skip(conn))]
+pub fn detect_target_changes(conn: &mut Connection) -> eyre::Result<Vec<CoverageChange>> {
+    const SELECT_CURRENT_TARGETS_SQL: &str = "
+    WITH site_latest AS (
+        SELECT site_name, county, MAX(sample_collection_date) AS latest FROM wastewater_samples
+        GROUP BY site_name, county
+    ),
+    target_latest AS (
+        SELECT site_name, county, pcr_pathogen_target, pcr_gene_target, MAX(sample_collection_date) AS latest
+        FROM wastewater_samples
+        GROUP BY site_name, county, pcr_pathogen_target, pcr_gene_target
+    )
+    SELECT t.site_name, t.county, t.pcr_pathogen_target, t.pcr_gene_target, t.latest,
+        t.latest >= date(s.latest, ?1) AS active
+    FROM target_latest t JOIN site_latest s USING (site_name, county)";
+
+    const SELECT_RECORDED_TARGETS_SQL: &str = "
+    SELECT site_name, county, pcr_pathogen_target, pcr_gene_target, active FROM site_targets";
+
+    const UPSERT_TARGET_SQL: &str = "
+    INSERT INTO site_targets (site_name, county, pcr_pathogen_target, pcr_gene_target, last_sample_date, active) VALUES
+    (:site_name, :county, :pcr_pathogen_target, :pcr_gene_target, :last_sample_date, :active)
+    ON CONFLICT (site_name, county, pcr_pathogen_target, pcr_gene_target) DO UPDATE SET
+        last_sample_date = excluded.last_sample_date,
+        active = excluded.active";
+
+    let current = conn
+        .prepare(SELECT_CURRENT_TARGETS_SQL)?
+        .query_map(params![format!("-{TARGET_LAPSE_DAYS} days")], |row| {
+            Ok((
+                SiteTarget {
+                    site_name: row.get(0)?,
+                    county: row.get(1)?,
+                    pcr_pathogen_target: row.get(2)?,
+                    pcr_gene_target: row.get(3)?,
+                },
+                row.get::<_, NaiveDate>(4)?,
+                row.get::<_, bool>(5)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let recorded: HashMap<SiteTarget, bool> = conn
+        .prepare(SELECT_RECORDED_TARGETS_SQL)?
+        .query_map([], |row| {
+            Ok((
+                SiteTarget {
+                    site_name: row.get(0)?,
+                    county: row.get(1)?,
+                    pcr_pathogen_target: row.get(2)?,
+                    pcr_gene_target: row.get(3)?,
+                },
+                row.get(4)?,
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    let recorded_sites: HashSet<(&str, &str)> = recorded
+        .keys()
+        .map(|target| (target.site_name.as_str(), target.county.as_str()))
+        .collect();
+
+    let mut changes = Vec::new();
+    for (target, _, active) in &current {
+        if !recorded_sites.contains(&(target.site_name.as_str(), target.county.as_str())) {
+            continue;
+        }
+        let was_active = recorded.get(target).copied().unwrap_or(false);
+        match (was_active, *active) {
+            (false, true) => changes.push(CoverageChange::TargetAdded {
+                target: target.clone(),
+            }),
+            (true, false) => changes.push(CoverageChange::TargetRemoved {
+                target: target.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(UPSERT_TARGET_SQL)?;
+        for (target, last_sample_date, active) in &current {
+            stmt.execute(named_params! {
+                ":site_name": target.site_name,
+                ":county": target.county,
+                ":pcr_pathogen_target": target.pcr_pathogen_target,
+                ":pcr_gene_target": target.pcr_gene_target,
+                ":last_sample_date": last_sample_date,
+                ":active": active,
+            })?;
+        }
+    }
+    tx.commit()?;
+
+    for change in &changes {
+        info!("{change}");
+    }
+    Ok(changes)
+}
+
+/// Learns each site's sampling cadence from the last [CADENCE_HISTORY_DAYS] days of samples and
+/// reports sites that have missed more than `max_missed` expected samples since their last one.
+/// Misses are counted up to the newest sample of any site rather than today, so publishing delays
+/// don't count against sites. A site is reported once when it crosses the limit, and again only
+/// after it samples and misses again. When `site_cadences` is empty every site is recorded
+/// without being reported.
+#[instrument(skip(conn, clock))]
+pub fn detect_missed_samples(
+    conn: &mut Connection,
+    clock: &dyn Clock,
+    max_missed: u32,
+) -> eyre::Result<Vec<CoverageChange>> {
+    const SELECT_SAMPLE_DATES_SQL: &str = "
+    SELECT DISTINCT site_name, county, sample_collection_date FROM wastewater_samples
+    WHERE sample_collection_date > ?1
+    ORDER BY site_name, county, sample_collection_date";
+
+    const SELECT_RECORDED_MISSES_SQL: &str = "
+    SELECT site_name, county, missed_samples FROM site_cadences";
+
+    const UPSERT_CADENCE_SQL: &str = "
+    INSERT INTO site_cadences (site_name, county, interval_days, last_sample_date, missed_samples, updated_timestamp) VALUES
+    (:site_name, :county, :interval_days, :last_sample_date, :missed_samples, :updated_timestamp)
+    ON CONFLICT (site_name, county) DO UPDATE SET
+        interval_days = excluded.interval_days,
+        last_sample_date = excluded.last_sample_date,
+        missed_samples = excluded.missed_samples,
+        updated_timestamp = excluded.updated_timestamp";
+
+    let newest: Option<NaiveDate> = conn.query_row(
+        "SELECT MAX(sample_collection_date) FROM wastewater_samples",
+        [],
+        |row| row.get(0),
+    )?;
+    let Some(newest) = newest else {
+        return Ok(Vec::new());
+    };
+    let history_start = newest - Days::new(CADENCE_HISTORY_DAYS);
+
+    let mut site_dates: HashMap<(String, String), Vec<NaiveDate>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare(SELECT_SAMPLE_DATES_SQL)?;
+        let mut rows = stmt.query(params![history_start])?;
+        while let Some(row) = rows.next()? {
+            site_dates
+                .entry((row.get(0)?, row.get(1)?))
+                .or_default()
+                .push(row.get(2)?);
+        }
+    }
+
+    let cadences: Vec<SiteCadence> = site_dates
+        .into_iter()
+        .filter_map(|((site_name, county), dates)| {
+            if dates.len() < CADENCE_MIN_SAMPLES {
+                return None;
+            }
+            let mut intervals: Vec<i64> = dates
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).num_days())
+                .collect();
+            intervals.sort_unstable();
+            let middle = intervals.len() / 2;
+            let interval_days = if intervals.len().is_multiple_of(2) {
+                (intervals[middle - 1] + intervals[middle]) as f64 / 2.0
+            } else {
+                intervals[middle] as f64
+            };
+
+            let last_sample_date = *dates.last()?;
+            let days_since = (newest - last_sample_date).num_days() as f64;
+            Some(SiteCadence {
+                site_name,
+                county,
+                interval_days,
+                last_sample_date,
+                missed_samples: (days_since / interval_days).floor() as u32,
+            })
+        })
+        .collect();
+
+    let recorded: HashMap<(String, String), u32> = conn
+        .prepare(SELECT_RECORDED_MISSES_SQL)?
+        .query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let mut changes = Vec::new();
+    if !recorded.is_empty() {
+        for cadence in &cadences {
+            let was_missed = recorded
+                .get(&(cadence.site_name.clone(), cadence.county.clone()))
+                .copied()
+                .unwrap_or(0);
+            if cadence.missed_samples > max_missed && was_missed <= max_missed {
+                changes.push(CoverageChange::MissedSamples {
+                    cadence: cadence.clone(),
+                });
+            }
+        }
+    }
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(UPSERT_CADENCE_SQL)?;
+        for cadence in &cadences {
+            stmt.execute(named_params! {
+                ":site_name": cadence.site_name,
+                ":county": cadence.county,
+                ":interval_days": cadence.interval_days,
+                ":last_sample_date": cadence.last_sample_date,
+                ":missed_samples": cadence.missed_samples,
+                ":updated_timestamp": clock.unix_timestamp(),
+            })?;
+        }
+    }
+    tx.commit()?;
+
+    for change in &changes {
+        info!("{change}");
+    }
+    Ok(changes)
+}
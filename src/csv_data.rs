@@ -19,6 +19,13 @@ pub struct WasteWaterCsvRow {
     pub pcr_gene_target: String,
     #[serde(rename = "Normalized Pathogen Concentration (gene copies/person/day)")]
     pub normalized_pathogen_concentration: f64,
+    /// Concentration before normalization, in feeds that include it.
+    #[serde(rename = "Pathogen Concentration (gene copies/L)", default)]
+    pub concentration_per_liter: Option<f64>,
+    /// The plant's flow on the collection date in millions of gallons per day, in feeds that
+    /// include it.
+    #[serde(rename = "Flow Rate (MGD)", default)]
+    pub flow_rate_mgd: Option<f64>,
     // Date the data was last updated.
     // This changes every time the data file is updated, but all rows have the same value.
     #[serde(rename = "Date/Time Updated")]
@@ -0,0 +1,390 @@
+//! Runs the pipeline on a schedule until the process is asked to stop.
+//!
+//! A schedule is either an interval (`6h`, `30m`, `1d`, `90s`) counted from when the daemon
+//! started, or a five-field cron expression (`0 6,18 * * *`) in the system's local time. A cycle
+//! that is still running when the next one is due makes the daemon skip the cycles it missed
+//! instead of running them back to back. SIGINT and SIGTERM stop the daemon after the current
+//! cycle.
+//!
+//! With an export directory configured, the daemon also rewrites the data package there on its own
+//! schedule, nightly by default, and builds the static site into its `site` directory. It runs the [maintenance] job on another, weekly by default.
+
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, TimeDelta, Timelike, Utc};
+use color_eyre::eyre;
+use tracing::{error, info, warn};
+use url::Url;
+
+use crate::context::RunContext;
+use crate::db;
+use crate::export;
+use crate::maintenance;
+use crate::pipeline::Pipeline;
+use crate::report::DateRange;
+use crate::site;
+
+/// How often sleeps wake up to check whether the daemon should stop.
+const SHUTDOWN_POLL: Duration = Duration::from_secs(1);
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// When cycles run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// The first time after `after` a cycle is due, given the daemon started at `started`.
+    /// None if a cron expression never matches.
+    pub fn next_after(
+        &self,
+        started: DateTime<Utc>,
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Every(interval) => {
+                let interval = TimeDelta::from_std(*interval).ok()?;
+                let elapsed = after - started;
+                let cycles = elapsed.num_milliseconds() / interval.num_milliseconds().max(1) + 1;
+                Some(started + interval * cycles as i32)
+            }
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    /// Parses an interval with a unit of s, m, h, or d, or a cron expression.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.contains(' ') {
+            return s.parse().map(Schedule::Cron);
+        }
+
+        let invalid = || format!("Expected an interval like 6h or a cron expression, got {s}");
+        let (unit_start, unit) = s.char_indices().last().ok_or_else(invalid)?;
+        let amount: u64 = s[..unit_start].parse().map_err(|_| invalid())?;
+        let unit_seconds: u64 = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            _ => {
+                return Err(format!(
+                    "Unknown interval unit in {s}, expected s, m, h, or d"
+                ))
+            }
+        };
+        let seconds = amount
+            .checked_mul(unit_seconds)
+            .ok_or_else(|| format!("The interval {s} is too long"))?;
+        if seconds == 0 {
+            return Err("The interval must be longer than zero".to_owned());
+        }
+
+        Ok(Schedule::Every(Duration::from_secs(seconds)))
+    }
+}
+
+/// A cron expression's minute, hour, day of month, month, and day of week fields, each as the set
+/// of values it matches. Days of week count from Sunday as 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days_of_month: Vec<u32>,
+    months: Vec<u32>,
+    days_of_week: Vec<u32>,
+    /// Whether the day of month or day of week is `*`. When both are restricted, a day matching
+    /// either matches, as in cron.
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    /// The first whole minute after `after` matching the expression in local time, looking up to
+    /// four years ahead.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after
+            .with_timezone(&Local)
+            .with_second(0)?
+            .with_nanosecond(0)?
+            + TimeDelta::minutes(1);
+        let limit = time + TimeDelta::days(4 * 366);
+
+        while time < limit {
+            if !self.matches_day(time) {
+                time = (time + TimeDelta::days(1)).with_hour(0)?.with_minute(0)?;
+                continue;
+            }
+            if !self.hours.contains(&time.hour()) {
+                time = (time + TimeDelta::hours(1)).with_minute(0)?;
+                continue;
+            }
+            if self.minutes.contains(&time.minute()) {
+                return Some(time.with_timezone(&Utc));
+            }
+            time += TimeDelta::minutes(1);
+        }
+
+        None
+    }
+
+    fn matches_day(&self, time: DateTime<Local>) -> bool {
+        if !self.months.contains(&time.month()) {
+            return false;
+        }
+        let day_of_month = self.days_of_month.contains(&time.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(&time.weekday().num_days_from_sunday());
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    /// Parses five fields of `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`), and lists of
+    /// those separated by commas.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(format!("Expected 5 cron fields, got {s}"));
+        };
+
+        Ok(Self {
+            minutes: parse_cron_field(minutes, 0..=59)?,
+            hours: parse_cron_field(hours, 0..=23)?,
+            days_of_month: parse_cron_field(days_of_month, 1..=31)?,
+            months: parse_cron_field(months, 1..=12)?,
+            // 7 is also Sunday
+            days_of_week: parse_cron_field(days_of_week, 0..=7)?
+                .into_iter()
+                .map(|day| day % 7)
+                .collect(),
+            any_day_of_month: days_of_month == "*",
+            any_day_of_week: days_of_week == "*",
+        })
+    }
+}
+
+fn parse_cron_field(field: &str, bounds: RangeInclusive<u32>) -> Result<Vec<u32>, String> {
+    let parse = |value: &str| {
+        value
+            .parse::<u32>()
+            .ok()
+            .filter(|value| bounds.contains(value))
+            .ok_or_else(|| format!("Invalid cron value {value} in {field}"))
+    };
+
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<usize>()
+                    .ok()
+                    .filter(|&step| step > 0)
+                    .ok_or_else(|| format!("Invalid cron step in {field}"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (*bounds.start(), *bounds.end()),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                // A single value with a step runs to the end, e.g. 5/15
+                None if step > 1 => (parse(range)?, *bounds.end()),
+                None => (parse(range)?, parse(range)?),
+            },
+        };
+        values.extend((start..=end).step_by(step));
+    }
+
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+/// Makes SIGINT and SIGTERM ask the daemon to stop instead of killing the process.
+#[cfg(unix)]
+fn install_shutdown_handler() {
+    extern "C" fn request_shutdown(_signal: libc::c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    let handler = request_shutdown as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_shutdown_handler() {}
+
+/// Sleeps until `until`, returning early with false if the daemon was asked to stop.
+fn sleep_until(until: DateTime<Utc>) -> bool {
+    loop {
+        if SHUTDOWN.load(Ordering::SeqCst) {
+            return false;
+        }
+        let Ok(remaining) = (until - Utc::now()).to_std() else {
+            return true;
+        };
+        thread::sleep(remaining.min(SHUTDOWN_POLL));
+    }
+}
+
+/// Writes the data package and builds the static site on a schedule of its own, so both always
+/// have fresh files.
+#[derive(Debug, Clone)]
+pub struct ExportTask {
+    pub dir: PathBuf,
+    pub schedule: Schedule,
+    /// Where the site is served from, as for `hygieia site build`.
+    pub base_url: Option<Url>,
+    pub embed_origins: Vec<String>,
+}
+
+impl ExportTask {
+    /// Writes the data package to `dir` and builds the site in `dir/site`.
+    fn run(&self, ctx: &RunContext) -> eyre::Result<()> {
+        export::export_data_package(
+            &ctx.db,
+            &self.dir,
+            &ctx.config.wastewater_url,
+            ctx.clock.now(),
+        )?;
+
+        let pathogens = ctx
+            .config
+            .pathogens
+            .resolve(|| db::select_pathogen_targets(&ctx.db))?;
+        let options = site::SiteOptions {
+            pathogens: &pathogens,
+            analysis: &ctx.config.analysis,
+            precision: ctx.config.precision.markdown,
+            source_url: &ctx.config.wastewater_url,
+            base_url: self.base_url.as_ref(),
+            embed_origins: &self.embed_origins,
+            generated_at: ctx.clock.now(),
+        };
+        site::build_site(
+            &ctx.db,
+            ctx.clock.as_ref(),
+            &self.dir.join("site"),
+            &options,
+            false,
+        )
+    }
+}
+
+/// When a schedule is first due: right away for intervals, at the first match for cron expressions.
+fn first_due(schedule: &Schedule, started: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match schedule {
+        Schedule::Every(_) => Some(started),
+        Schedule::Cron(cron) => cron.next_after(started),
+    }
+}
+
+/// Runs `pipeline` over `range` at every time `schedule` is due, `export` at every time its own
+/// schedule is due, and the maintenance job at every time `analyze_schedule` is due, until SIGINT
+/// or SIGTERM.
+/// A failed cycle, export, or maintenance job is logged and the daemon waits for the next one.
+pub fn run_daemon(
+    pipeline: &mut Pipeline,
+    range: DateRange,
+    schedule: &Schedule,
+    export: Option<&ExportTask>,
+    analyze_schedule: &Schedule,
+) -> eyre::Result<()> {
+    install_shutdown_handler();
+    let started = Utc::now();
+    info!("Starting daemon with schedule {schedule:?}");
+
+    let mut due = first_due(schedule, started);
+    let mut export_due = export.and_then(|export| {
+        info!(
+            "Exporting to {} with schedule {:?}",
+            export.dir.display(),
+            export.schedule
+        );
+        first_due(&export.schedule, started)
+    });
+    info!("Analyzing the database with schedule {analyze_schedule:?}");
+    let mut analyze_due = analyze_schedule.next_after(started, started);
+
+    while let Some(cycle_due) = due {
+        let next = [export_due, analyze_due]
+            .into_iter()
+            .flatten()
+            .fold(cycle_due, DateTime::min);
+        info!("Next cycle at {cycle_due}");
+        if !sleep_until(next) {
+            break;
+        }
+
+        if cycle_due <= next {
+            pipeline.context_mut().start_run();
+            if let Err(e) = pipeline.run(range) {
+                error!("Cycle failed: {e:?}");
+            }
+
+            let finished = Utc::now();
+            due = schedule.next_after(started, finished);
+            if let Some(next_due) = due {
+                let missed = schedule
+                    .next_after(started, cycle_due)
+                    .is_some_and(|following| following < next_due && following <= finished);
+                if missed {
+                    warn!(
+                        "The cycle due at {cycle_due} ran past the next one, skipping to {next_due}"
+                    );
+                }
+            }
+        }
+
+        if let (Some(export), Some(due)) = (export, export_due) {
+            if due <= Utc::now() {
+                if let Err(e) = export.run(pipeline.context()) {
+                    error!("Export failed: {e:?}");
+                }
+                export_due = export.schedule.next_after(started, Utc::now());
+            }
+        }
+
+        if analyze_due.is_some_and(|due| due <= Utc::now()) {
+            let ctx = pipeline.context_mut();
+            if let Err(e) =
+                maintenance::analyze_database(&mut ctx.db, ctx.clock.as_ref(), &ctx.config.analysis)
+            {
+                error!("Analyzing the database failed: {e:?}");
+            }
+            analyze_due = analyze_schedule.next_after(started, Utc::now());
+        }
+    }
+
+    if due.is_none() {
+        warn!("The schedule never matches again, stopping");
+    }
+    info!("Daemon stopped");
+    Ok(())
+}
@@ -1,11 +1,95 @@
-use std::{error::Error, time::SystemTimeError};
+use std::collections::BTreeMap;
+use std::error::Error;
 
 use chrono::{DateTime, FixedOffset, NaiveDate};
-use color_eyre::eyre;
-use rusqlite::{named_params, Connection, OptionalExtension, Row};
-use tracing::{error, info, instrument, trace};
+use color_eyre::eyre::{self, Context};
+use rusqlite::{named_params, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, instrument, trace, warn};
 
-use crate::{csv_data::WasteWaterCsvRow, useful::try_unix_timestamp};
+use crate::{csv_data::WasteWaterCsvRow, useful::Clock};
+
+/// Opens a connection to the SQLite database, creating it if it doesn't exist, and applies the
+/// schema. With a key the database is opened with SQLCipher.
+pub fn open_database(sqlite_db_path: &str, key: Option<&str>) -> eyre::Result<Connection> {
+    debug!("Opening SQLite DB at {sqlite_db_path}");
+
+    let db_conn = Connection::open(sqlite_db_path)?;
+    if let Some(key) = key {
+        apply_sqlite_key(&db_conn, key)?;
+    }
+    debug!("Successfully opened SQLite DB.");
+
+    apply_schema(&db_conn).with_context(|| match key {
+        Some(_) => "Error applying schema, is the database key correct?",
+        None => "Error applying schema",
+    })?;
+
+    Ok(db_conn)
+}
+
+/// Columns added to tables after they were first released, as (table, column, definition).
+/// `CREATE TABLE IF NOT EXISTS` leaves existing tables alone, so these are added separately.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("pending_reports", "analysis_config", "TEXT"),
+    (
+        "pending_reports",
+        "tenant",
+        "TEXT NOT NULL DEFAULT 'default'",
+    ),
+    ("poll_runs", "date_updated", "TEXT"),
+    ("discord_messages", "chunks", "INTEGER NOT NULL DEFAULT 1"),
+    ("pending_reports", "slack_blocks", "TEXT"),
+    ("pending_reports", "email_html", "TEXT"),
+    ("pending_reports", "ntfy_notification", "TEXT"),
+    ("pending_reports", "report_data", "TEXT"),
+    ("pending_reports", "teams_card", "TEXT"),
+    ("poll_runs", "rows_retracted", "INTEGER NOT NULL DEFAULT 0"),
+    ("poll_runs", "insert_progress", "TEXT"),
+    ("poll_runs", "resumed_run_id", "TEXT"),
+    ("wastewater_samples", "concentration_per_liter", "REAL"),
+    ("wastewater_samples", "flow_rate_mgd", "REAL"),
+    (
+        "site_baselines",
+        "measure",
+        "TEXT NOT NULL DEFAULT 'normalized'",
+    ),
+];
+
+/// Creates any tables, columns, and indexes that don't exist yet.
+pub fn apply_schema(conn: &Connection) -> eyre::Result<()> {
+    conn.execute_batch(include_str!("schema.sql"))?;
+
+    for (table, column, definition) in ADDED_COLUMNS {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+            [table, column],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            info!("Adding column {column} to {table}");
+            conn.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the SQLCipher key. It has to happen before anything else reads the database.
+#[cfg(feature = "sqlcipher")]
+fn apply_sqlite_key(db_conn: &Connection, key: &str) -> eyre::Result<()> {
+    db_conn.pragma_update(None, "key", key)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+fn apply_sqlite_key(_db_conn: &Connection, _key: &str) -> eyre::Result<()> {
+    Err(eyre::eyre!(
+        "A database key is set, but hygieia was built without the sqlcipher feature"
+    ))
+}
 
 #[derive(Debug)]
 /// A normalized record of a wastewater sample.
@@ -24,84 +108,163 @@ pub struct WasteWaterSample {
     /// Normalized pathogen concentration (gene copies/person/day).
     /// Note that each site uses a different normalization method, so this value is not comparable between sites.
     normalized_pathogen_concentration: f64,
+    /// Concentration before normalization (gene copies/L), if the feed has it.
+    concentration_per_liter: Option<f64>,
+    /// Plant flow (million gallons/day), if the feed has it.
+    flow_rate_mgd: Option<f64>,
     /// Date the data was last updated.
     date_updated: DateTime<FixedOffset>,
     // Unix timestamp of when this data was polled and added to the database.
     poll_timestamp: u64,
 }
 
-impl WasteWaterSample {
-    fn from_row(row: &Row) -> Result<Self, rusqlite::Error> {
-        Ok(Self {
-            sample_collection_date: row.get(0)?,
-            site_name: row.get(1)?,
-            county: row.get(2)?,
-            pcr_pathogen_target: row.get(3)?,
-            pcr_gene_target: row.get(4)?,
-            normalized_pathogen_concentration: row.get(5)?,
-            date_updated: row.get(6)?,
-            poll_timestamp: row.get(7)?,
-        })
-    }
+/// The natural key of a sample: its collection date, site, county, pathogen target, and gene target.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct SampleKey {
+    pub sample_collection_date: NaiveDate,
+    pub site_name: String,
+    pub county: String,
+    pub pcr_pathogen_target: String,
+    pub pcr_gene_target: String,
 }
 
-impl TryFrom<WasteWaterCsvRow> for WasteWaterSample {
-    type Error = SystemTimeError;
+impl From<&WasteWaterCsvRow> for SampleKey {
+    fn from(row: &WasteWaterCsvRow) -> Self {
+        Self {
+            sample_collection_date: row.sample_collection_date,
+            site_name: row.site_name.clone(),
+            county: row.county.clone(),
+            pcr_pathogen_target: row.pcr_pathogen_target.clone(),
+            pcr_gene_target: row.pcr_gene_target.clone(),
+        }
+    }
+}
 
-    fn try_from(row: WasteWaterCsvRow) -> Result<Self, Self::Error> {
-        let poll_timestamp = try_unix_timestamp()?;
+/// Converts a CSV row polled at the clock's current time.
+impl<C: Clock + ?Sized> From<(WasteWaterCsvRow, &C)> for WasteWaterSample {
+    fn from((row, clock): (WasteWaterCsvRow, &C)) -> Self {
+        let poll_timestamp = clock.unix_timestamp();
 
-        Ok(Self {
+        Self {
             sample_collection_date: row.sample_collection_date,
             site_name: row.site_name,
             county: row.county,
             pcr_pathogen_target: row.pcr_pathogen_target,
             pcr_gene_target: row.pcr_gene_target,
             normalized_pathogen_concentration: row.normalized_pathogen_concentration,
+            concentration_per_liter: row.concentration_per_liter,
+            flow_rate_mgd: row.flow_rate_mgd,
             date_updated: row.date_updated.fixed_offset(),
             poll_timestamp,
-        })
+        }
     }
 }
 
-/// Inserts a sample into the database if it doesn't exist.
-/// Returns true if the sample was inserted, false otherwise.
-pub fn insert_wastewater_sample(conn: &Connection, sample: WasteWaterSample) -> eyre::Result<bool> {
-    const SELECT_SAMPLE_SQL: &str = "
-    SELECT * FROM wastewater_samples
-    WHERE sample_collection_date = :sample_collection_date
-    AND site_name = :site_name
-    AND county = :county
-    AND pcr_pathogen_target = :pcr_pathogen_target
-    AND pcr_gene_target = :pcr_gene_target";
-    let mut select_stmt = conn.prepare_cached(SELECT_SAMPLE_SQL)?;
+/// What storing a sample did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleInsertion {
+    Inserted,
+    /// The sample existed with a different concentration, which was recorded in `sample_revisions`
+    /// and replaced.
+    Revised,
+    Unchanged,
+}
 
+/// Inserts a sample into the database if it doesn't exist, or replaces its concentration and
+/// `date_updated` if upstream restated it, keeping the earlier values in `sample_revisions`.
+pub fn insert_wastewater_sample(
+    conn: &Connection,
+    sample: WasteWaterSample,
+) -> eyre::Result<SampleInsertion> {
+    // The primary key is the natural key, so an existing sample is a conflict on it
     const INSERT_SAMPLE_SQL: &str = "
-    INSERT INTO wastewater_samples 
-    (sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target, normalized_pathogen_concentration, date_updated, poll_timestamp) VALUES 
-    (:sample_collection_date, :site_name, :county, :pcr_pathogen_target, :pcr_gene_target, :normalized_pathogen_concentration, :date_updated, :poll_timestamp)";
-    let mut insert_stmt = conn.prepare_cached(INSERT_SAMPLE_SQL)?;
-
-    let maybe_existing_sample = select_stmt
-        .query_row(
-            named_params! {
-                ":sample_collection_date": sample.sample_collection_date,
-                ":site_name": sample.site_name,
-                ":county": sample.county,
-                ":pcr_pathogen_target": sample.pcr_pathogen_target,
-                ":pcr_gene_target": sample.pcr_gene_target,
-            },
-            WasteWaterSample::from_row,
-        )
-        .optional()?;
+    INSERT INTO wastewater_samples
+    (sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target, normalized_pathogen_concentration, concentration_per_liter, flow_rate_mgd, date_updated, poll_timestamp) VALUES
+    (:sample_collection_date, :site_name, :county, :pcr_pathogen_target, :pcr_gene_target, :normalized_pathogen_concentration, :concentration_per_liter, :flow_rate_mgd, :date_updated, :poll_timestamp)
+    ON CONFLICT DO NOTHING";
 
-    match maybe_existing_sample {
-        Some(existing_sample) => {
-            trace!("Skipping sample insertion because it already exists: New: {sample:?}, Existing: {existing_sample:?}");
-            Ok(false)
-        }
-        None => {
-            insert_stmt.execute(named_params! {
+    const INSERT_REVISION_SQL: &str = "
+    INSERT INTO sample_revisions
+    (sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target,
+        previous_concentration, previous_date_updated, normalized_pathogen_concentration, date_updated, revised_timestamp)
+    SELECT sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target,
+        normalized_pathogen_concentration, date_updated, :normalized_pathogen_concentration, :date_updated, :poll_timestamp
+    FROM wastewater_samples
+    WHERE sample_collection_date = :sample_collection_date
+        AND site_name = :site_name
+        AND county = :county
+        AND pcr_pathogen_target = :pcr_pathogen_target
+        AND pcr_gene_target = :pcr_gene_target
+        AND normalized_pathogen_concentration != :normalized_pathogen_concentration";
+
+    const UPDATE_SAMPLE_SQL: &str = "
+    UPDATE wastewater_samples
+    SET normalized_pathogen_concentration = :normalized_pathogen_concentration, date_updated = :date_updated
+    WHERE sample_collection_date = :sample_collection_date
+        AND site_name = :site_name
+        AND county = :county
+        AND pcr_pathogen_target = :pcr_pathogen_target
+        AND pcr_gene_target = :pcr_gene_target";
+
+    // The other measures aren't revisions, but are filled in for samples stored before the feed
+    // included them, and kept when a later file leaves them out
+    const UPDATE_MEASURES_SQL: &str = "
+    UPDATE wastewater_samples
+    SET concentration_per_liter = COALESCE(:concentration_per_liter, concentration_per_liter),
+        flow_rate_mgd = COALESCE(:flow_rate_mgd, flow_rate_mgd)
+    WHERE sample_collection_date = :sample_collection_date
+        AND site_name = :site_name
+        AND county = :county
+        AND pcr_pathogen_target = :pcr_pathogen_target
+        AND pcr_gene_target = :pcr_gene_target
+        AND (concentration_per_liter IS NOT COALESCE(:concentration_per_liter, concentration_per_liter)
+            OR flow_rate_mgd IS NOT COALESCE(:flow_rate_mgd, flow_rate_mgd))";
+
+    let params = named_params! {
+        ":sample_collection_date": sample.sample_collection_date,
+        ":site_name": sample.site_name,
+        ":county": sample.county,
+        ":pcr_pathogen_target": sample.pcr_pathogen_target,
+        ":pcr_gene_target": sample.pcr_gene_target,
+        ":normalized_pathogen_concentration": sample.normalized_pathogen_concentration,
+        ":date_updated": sample.date_updated,
+        ":poll_timestamp": sample.poll_timestamp,
+    };
+    let measures = named_params! {
+        ":sample_collection_date": sample.sample_collection_date,
+        ":site_name": sample.site_name,
+        ":county": sample.county,
+        ":pcr_pathogen_target": sample.pcr_pathogen_target,
+        ":pcr_gene_target": sample.pcr_gene_target,
+        ":concentration_per_liter": sample.concentration_per_liter,
+        ":flow_rate_mgd": sample.flow_rate_mgd,
+    };
+
+    let inserted = conn
+        .prepare_cached(INSERT_SAMPLE_SQL)?
+        .execute(named_params! {
+            ":sample_collection_date": sample.sample_collection_date,
+            ":site_name": sample.site_name,
+            ":county": sample.county,
+            ":pcr_pathogen_target": sample.pcr_pathogen_target,
+            ":pcr_gene_target": sample.pcr_gene_target,
+            ":normalized_pathogen_concentration": sample.normalized_pathogen_concentration,
+            ":concentration_per_liter": sample.concentration_per_liter,
+            ":flow_rate_mgd": sample.flow_rate_mgd,
+            ":date_updated": sample.date_updated,
+            ":poll_timestamp": sample.poll_timestamp,
+        })?;
+    if inserted > 0 {
+        trace!("Inserted sample: {:?}", sample);
+        return Ok(SampleInsertion::Inserted);
+    }
+    conn.prepare_cached(UPDATE_MEASURES_SQL)?
+        .execute(measures)?;
+
+    if conn.prepare_cached(INSERT_REVISION_SQL)?.execute(params)? > 0 {
+        // poll_timestamp stays when the sample was first polled
+        conn.prepare_cached(UPDATE_SAMPLE_SQL)?
+            .execute(named_params! {
                 ":sample_collection_date": sample.sample_collection_date,
                 ":site_name": sample.site_name,
                 ":county": sample.county,
@@ -109,49 +272,286 @@ pub fn insert_wastewater_sample(conn: &Connection, sample: WasteWaterSample) ->
                 ":pcr_gene_target": sample.pcr_gene_target,
                 ":normalized_pathogen_concentration": sample.normalized_pathogen_concentration,
                 ":date_updated": sample.date_updated,
-                ":poll_timestamp": sample.poll_timestamp,
             })?;
-
-            trace!("Inserted sample: {:?}", sample);
-            Ok(true)
-        }
+        debug!("Revised sample: {:?}", sample);
+        return Ok(SampleInsertion::Revised);
     }
+
+    trace!("Skipping sample insertion because it already exists: {sample:?}");
+    Ok(SampleInsertion::Unchanged)
+}
+
+/// What [insert_wastewater_samples] did with the samples it was given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InsertCounts {
+    pub total: usize,
+    pub inserted: usize,
+    pub skipped: usize,
+    pub revised: usize,
+    /// Stored samples missing from the samples given, which upstream retracted, as counted by
+    /// [count_retracted_samples]. They're kept.
+    pub retracted: usize,
+    /// Samples that couldn't be converted.
+    pub errors: usize,
+}
+
+/// Samples inserted per transaction by default. Each commit records how far the insert got, so a
+/// poll that's interrupted partway through can be completed by the next.
+pub const DEFAULT_INSERT_CHUNK_SAMPLES: usize = 10_000;
+
+/// How far an insert got when it last committed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InsertProgress {
+    /// Samples committed, counted from the start of the file.
+    pub samples: usize,
+    /// SHA-256 of the committed samples in order, to tell whether a later file starts with them.
+    pub sha256: String,
+    pub counts: InsertCounts,
 }
 
-#[instrument(skip(conn, samples))]
-pub fn insert_wastewater_samples<I, S, E>(conn: &mut Connection, samples: I) -> eyre::Result<()>
+/// Inserts `samples`, committing every `chunk_samples` of them and calling `on_commit` in each
+/// chunk's transaction with the progress so far.
+///
+/// With the progress of an interrupted insert of the same file, its counts are carried over once
+/// the samples it committed are read again, since storing them now finds them unchanged. Those
+/// samples are still inserted, so a file that changed since is stored in full anyway.
+///
+/// The samples' keys are kept in a temporary table for [count_retracted_samples].
+#[instrument(skip(conn, samples, resume, on_commit))]
+pub fn insert_wastewater_samples<I, S, E>(
+    conn: &mut Connection,
+    samples: I,
+    chunk_samples: usize,
+    resume: Option<&InsertProgress>,
+    mut on_commit: impl FnMut(&Connection, &InsertProgress) -> eyre::Result<()>,
+) -> eyre::Result<InsertCounts>
 where
     E: Error,
     S: TryInto<WasteWaterSample, Error = E>,
     I: IntoIterator<Item = S>,
 {
-    let tx = conn.transaction()?;
+    const CREATE_SEEN_SAMPLES_SQL: &str = "
+    DROP TABLE IF EXISTS temp.seen_samples;
+    CREATE TEMP TABLE seen_samples (
+        sample_collection_date TEXT NOT NULL,
+        site_name TEXT NOT NULL,
+        county TEXT NOT NULL,
+        pcr_pathogen_target TEXT NOT NULL,
+        pcr_gene_target TEXT NOT NULL,
+        PRIMARY KEY (sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target)
+    );";
+    const INSERT_SEEN_SAMPLE_SQL: &str = "
+    INSERT OR IGNORE INTO temp.seen_samples (sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target)
+    VALUES (:sample_collection_date, :site_name, :county, :pcr_pathogen_target, :pcr_gene_target)";
 
-    let mut total_sample: usize = 0;
-    let mut errors: usize = 0;
-    let mut skip: usize = 0;
+    conn.execute_batch(CREATE_SEEN_SAMPLES_SQL)?;
+    let mut tx = conn.transaction()?;
+
+    let mut counts = InsertCounts::default();
+    let mut hasher = Sha256::new();
 
     for unprocessed_sample in samples {
-        total_sample += 1;
+        counts.total += 1;
 
         match unprocessed_sample.try_into() {
             Ok(sample) => {
-                let inserted = insert_wastewater_sample(&tx, sample)?;
-                if !inserted {
-                    skip += 1;
+                hash_sample(&mut hasher, &sample);
+                tx.prepare_cached(INSERT_SEEN_SAMPLE_SQL)?
+                    .execute(named_params! {
+                        ":sample_collection_date": sample.sample_collection_date,
+                        ":site_name": sample.site_name,
+                        ":county": sample.county,
+                        ":pcr_pathogen_target": sample.pcr_pathogen_target,
+                        ":pcr_gene_target": sample.pcr_gene_target,
+                    })?;
+                match insert_wastewater_sample(&tx, sample)? {
+                    SampleInsertion::Inserted => counts.inserted += 1,
+                    SampleInsertion::Revised => counts.revised += 1,
+                    SampleInsertion::Unchanged => counts.skipped += 1,
                 }
             }
             Err(e) => {
-                errors += 1;
+                hasher.update([0]);
+                counts.errors += 1;
                 error!("Skipping sample due to conversion error: {e}");
             }
         }
+
+        if let Some(resume) = resume.filter(|resume| resume.samples == counts.total) {
+            if hex(hasher.clone().finalize()) == resume.sha256 {
+                info!(
+                    "Read the {} samples the interrupted poll committed, carrying over its counts",
+                    resume.samples
+                );
+                counts = resume.counts;
+            } else {
+                warn!("The file changed since the interrupted poll, counting its samples afresh");
+            }
+        }
+
+        if counts.total.is_multiple_of(chunk_samples) {
+            on_commit(&tx, &progress(&counts, &hasher))?;
+            tx.commit()?;
+            debug!("Committed {} samples", counts.total);
+            tx = conn.transaction()?;
+        }
     }
 
+    on_commit(&tx, &progress(&counts, &hasher))?;
     tx.commit()?;
 
-    let total_insertions = total_sample - errors - skip;
-    info!("Inserted {total_insertions} records ({errors} errors, {skip} skipped, {total_sample} total)");
+    info!(
+        "Inserted {} records ({} errors, {} skipped, {} revised, {} total)",
+        counts.inserted, counts.errors, counts.skipped, counts.revised, counts.total
+    );
 
-    Ok(())
+    Ok(counts)
+}
+
+/// Counts the stored samples missing from the samples last given to [insert_wastewater_samples] on
+/// `conn`. Every poll reads the whole dataset, so those were taken down, unless some of the file's
+/// rows couldn't be read.
+pub fn count_retracted_samples(conn: &Connection) -> eyre::Result<usize> {
+    const COUNT_RETRACTED_SQL: &str = "
+    SELECT COUNT(*) FROM wastewater_samples AS s
+    WHERE NOT EXISTS (
+        SELECT 1 FROM temp.seen_samples AS seen
+        WHERE seen.sample_collection_date = s.sample_collection_date
+            AND seen.site_name = s.site_name
+            AND seen.county = s.county
+            AND seen.pcr_pathogen_target = s.pcr_pathogen_target
+            AND seen.pcr_gene_target = s.pcr_gene_target)";
+
+    let retracted: i64 = conn.query_row(COUNT_RETRACTED_SQL, [], |row| row.get(0))?;
+    Ok(retracted as usize)
+}
+
+fn progress(counts: &InsertCounts, hasher: &Sha256) -> InsertProgress {
+    InsertProgress {
+        samples: counts.total,
+        sha256: hex(hasher.clone().finalize()),
+        counts: *counts,
+    }
+}
+
+/// Adds every stored field of `sample` but its poll time to `hasher`.
+fn hash_sample(hasher: &mut Sha256, sample: &WasteWaterSample) {
+    hasher.update(
+        format!(
+            "{}\x1f{}\x1f{}\x1f{}\x1f{}\x1f{}\x1f{:?}\x1f{:?}\x1f{}\x1e",
+            sample.sample_collection_date,
+            sample.site_name,
+            sample.county,
+            sample.pcr_pathogen_target,
+            sample.pcr_gene_target,
+            sample.normalized_pathogen_concentration.to_bits(),
+            sample.concentration_per_liter.map(f64::to_bits),
+            sample.flow_rate_mgd.map(f64::to_bits),
+            sample.date_updated.to_rfc3339()
+        )
+        .as_bytes(),
+    );
+}
+
+fn hex(bytes: impl AsRef<[u8]>) -> String {
+    bytes
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Loads the concentration of every stored sample, keyed by natural key.
+pub fn select_sample_concentrations(conn: &Connection) -> eyre::Result<BTreeMap<SampleKey, f64>> {
+    const SELECT_CONCENTRATIONS_SQL: &str = "
+    SELECT sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target, normalized_pathogen_concentration
+    FROM wastewater_samples";
+
+    let mut stmt = conn.prepare(SELECT_CONCENTRATIONS_SQL)?;
+    let concentrations = stmt
+        .query_map([], |row| {
+            Ok((
+                SampleKey {
+                    sample_collection_date: row.get(0)?,
+                    site_name: row.get(1)?,
+                    county: row.get(2)?,
+                    pcr_pathogen_target: row.get(3)?,
+                    pcr_gene_target: row.get(4)?,
+                },
+                row.get(5)?,
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+
+    Ok(concentrations)
+}
+
+/// Lists every county with stored samples, alphabetically.
+pub fn select_counties(conn: &Connection) -> eyre::Result<Vec<String>> {
+    const SELECT_COUNTIES_SQL: &str =
+        "SELECT DISTINCT county FROM wastewater_samples ORDER BY county";
+
+    let mut stmt = conn.prepare_cached(SELECT_COUNTIES_SQL)?;
+    let counties = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(counties)
+}
+
+/// Lists every pathogen target with stored samples, alphabetically.
+pub fn select_pathogen_targets(conn: &Connection) -> eyre::Result<Vec<String>> {
+    const SELECT_PATHOGEN_TARGETS_SQL: &str =
+        "SELECT DISTINCT pcr_pathogen_target FROM wastewater_samples ORDER BY pcr_pathogen_target";
+
+    let mut stmt = conn.prepare_cached(SELECT_PATHOGEN_TARGETS_SQL)?;
+    let pathogens = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(pathogens)
+}
+
+/// How many samples from a county were revised upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountyRevisions {
+    pub county: String,
+    pub samples: usize,
+}
+
+impl std::fmt::Display for CountyRevisions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let noun = if self.samples == 1 {
+            "sample was"
+        } else {
+            "samples were"
+        };
+        write!(
+            f,
+            "{} {noun} revised upstream in {} County since the last report",
+            self.samples, self.county
+        )
+    }
+}
+
+/// Counts the samples of each county revised since the newest stored report was created, or ever
+/// if there is none.
+pub fn select_revisions_since_last_report(conn: &Connection) -> eyre::Result<Vec<CountyRevisions>> {
+    const SELECT_REVISIONS_SQL: &str = "
+    SELECT county, COUNT(*) FROM (
+        SELECT DISTINCT sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target
+        FROM sample_revisions
+        WHERE revised_timestamp > COALESCE((SELECT MAX(created_timestamp) FROM pending_reports), -1)
+    )
+    GROUP BY county
+    ORDER BY county";
+
+    let mut stmt = conn.prepare_cached(SELECT_REVISIONS_SQL)?;
+    let revisions = stmt
+        .query_map([], |row| {
+            Ok(CountyRevisions {
+                county: row.get(0)?,
+                samples: row.get(1)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(revisions)
 }
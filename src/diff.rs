@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde::Serialize;
+use tracing::{info, instrument, warn};
+
+use crate::csv_data;
+use crate::db::{self, SampleKey};
+
+/// Output format of `hygieia diff`.
+#[derive(Debug, Clone, Copy)]
+pub enum DiffFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("Unknown diff format: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// In the file but not the database.
+    Added,
+    /// In both, with a different concentration.
+    Changed,
+    /// In the database but no longer in the file.
+    Removed,
+}
+
+/// A differing sample. Flat rather than nesting [SampleKey] so it can be written as CSV.
+#[derive(Debug, Serialize)]
+pub struct DiffEntry {
+    pub change: Change,
+    pub sample_collection_date: NaiveDate,
+    pub site_name: String,
+    pub county: String,
+    pub pcr_pathogen_target: String,
+    pub pcr_gene_target: String,
+    /// Concentration stored in the database.
+    pub old_concentration: Option<f64>,
+    /// Concentration in the downloaded file.
+    pub new_concentration: Option<f64>,
+}
+
+impl DiffEntry {
+    fn new(
+        change: Change,
+        key: SampleKey,
+        old_concentration: Option<f64>,
+        new_concentration: Option<f64>,
+    ) -> Self {
+        Self {
+            change,
+            sample_collection_date: key.sample_collection_date,
+            site_name: key.site_name,
+            county: key.county,
+            pcr_pathogen_target: key.pcr_pathogen_target,
+            pcr_gene_target: key.pcr_gene_target,
+            old_concentration,
+            new_concentration,
+        }
+    }
+}
+
+/// Compares a downloaded CSV file against the database without modifying it.
+#[instrument(skip_all)]
+pub fn diff_against_db(conn: &Connection, reader: impl Read) -> eyre::Result<Vec<DiffEntry>> {
+    let mut stored = db::select_sample_concentrations(conn)?;
+
+    let mut downloaded = BTreeMap::new();
+    for row in csv_data::parse_data(reader) {
+        match row {
+            Ok(row) => {
+                downloaded.insert(SampleKey::from(&row), row.normalized_pathogen_concentration);
+            }
+            Err(e) => warn!("Skipping unparseable row: {e}"),
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (key, new_concentration) in downloaded {
+        match stored.remove(&key) {
+            None => entries.push(DiffEntry::new(
+                Change::Added,
+                key,
+                None,
+                Some(new_concentration),
+            )),
+            Some(old_concentration) if old_concentration != new_concentration => {
+                entries.push(DiffEntry::new(
+                    Change::Changed,
+                    key,
+                    Some(old_concentration),
+                    Some(new_concentration),
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Whatever is left was stored but is no longer published
+    entries.extend(stored.into_iter().map(|(key, old_concentration)| {
+        DiffEntry::new(Change::Removed, key, Some(old_concentration), None)
+    }));
+
+    info!("Found {} differences", entries.len());
+    Ok(entries)
+}
+
+pub fn write_diff(
+    writer: impl Write,
+    entries: &[DiffEntry],
+    format: DiffFormat,
+) -> eyre::Result<()> {
+    match format {
+        DiffFormat::Json => serde_json::to_writer_pretty(writer, entries)?,
+        DiffFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for entry in entries {
+                csv_writer.serialize(entry)?;
+            }
+            csv_writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,544 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{NaiveDate, Weekday};
+use color_eyre::eyre::{self, eyre};
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, instrument, warn};
+use url::Url;
+
+use crate::context::RunContext;
+use crate::http::{Body, HttpClient, HttpError};
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::tenants::Tenant;
+use crate::useful::Clock;
+
+/// Subset of the message object Discord returns when a webhook is executed with `wait=true`.
+#[derive(Debug, Deserialize)]
+struct DiscordMessage {
+    id: String,
+    /// Thread ID when the message was posted into a thread.
+    channel_id: String,
+}
+
+/// Most characters Discord accepts in a message's content.
+const MESSAGE_LIMIT: usize = 2000;
+
+/// A message previously posted through a webhook, as stored in `discord_messages`.
+struct PostedMessage {
+    message_id: String,
+    thread_id: Option<String>,
+    period: String,
+    /// Messages the digest was split into.
+    chunks: usize,
+}
+
+/// Whether a single "current levels" message is kept up to date in the channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatusBoardMode {
+    /// Only post digests.
+    #[default]
+    Off,
+    /// Post digests and keep the status board updated.
+    Alongside,
+    /// Only keep the status board updated, without posting digests.
+    Instead,
+}
+
+impl FromStr for StatusBoardMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "alongside" => Ok(Self::Alongside),
+            "instead" => Ok(Self::Instead),
+            _ => Err(format!("Unknown status board mode: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for StatusBoardMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Alongside => "alongside",
+            Self::Instead => "instead",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscordWebhookOptions {
+    /// Post into a thread per week, creating it on the first post of the week.
+    /// Discord only allows webhooks to create threads in forum and media channels.
+    pub thread_per_week: bool,
+    /// Edit the previous message instead of posting a new one when it covered the same period,
+    /// i.e. the data was revised rather than new samples arriving.
+    pub edit_on_revision: bool,
+    pub status_board: StatusBoardMode,
+}
+
+/// Posts reports through a Discord webhook.
+pub struct DiscordWebhook {
+    url: String,
+    options: DiscordWebhookOptions,
+}
+
+impl DiscordWebhook {
+    pub fn new(url: String, options: DiscordWebhookOptions) -> Self {
+        Self { url, options }
+    }
+
+    /// Sends `content` for the reporting `period`, which is the latest sample date the content covers.
+    #[instrument(skip(self, conn, http, clock, content))]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        clock: &dyn Clock,
+        content: &str,
+        period: &str,
+    ) -> eyre::Result<()> {
+        let webhook_id = self.webhook_id()?;
+        let digest = sha256_hex(content);
+
+        if self.options.status_board != StatusBoardMode::Instead {
+            self.post_digest(conn, http, clock, &webhook_id, content, period)?;
+        }
+        if self.options.status_board != StatusBoardMode::Off {
+            self.update_status_board(conn, http, clock, &webhook_id, content)?;
+        }
+
+        // Kept until now, so sending again after the status board failed doesn't repost the digest
+        delete_posted_chunks(conn, &webhook_id, &digest)
+    }
+
+    /// Posts `content` in as many messages as it needs, skipping those already posted.
+    fn post_digest(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        clock: &dyn Clock,
+        webhook_id: &str,
+        content: &str,
+        period: &str,
+    ) -> eyre::Result<()> {
+        let week = week_label(clock.now().date_naive());
+        let thread_id = if self.options.thread_per_week {
+            select_thread(conn, webhook_id, &week)?
+        } else {
+            None
+        };
+        let chunks = split_message(content, MESSAGE_LIMIT);
+        let digest = sha256_hex(content);
+        let posted = select_posted_chunks(conn, webhook_id, &digest)?;
+        if posted.len() == chunks.len() {
+            info!("The digest was already posted");
+            return Ok(());
+        }
+        if !posted.is_empty() {
+            info!(
+                "Resuming digest after {} of {} messages",
+                posted.len(),
+                chunks.len()
+            );
+        }
+
+        // Only single messages are edited, since a revision can change how many a digest needs
+        if self.options.edit_on_revision && chunks.len() == 1 && posted.is_empty() {
+            if let Some(previous) = select_last_message(conn, webhook_id)? {
+                let same_thread = !self.options.thread_per_week || previous.thread_id == thread_id;
+                if previous.period == period && same_thread && previous.chunks == 1 {
+                    info!(
+                        "Data for {period} was revised, editing message {}",
+                        previous.message_id
+                    );
+                    return self.edit(
+                        conn,
+                        http,
+                        &previous.message_id,
+                        previous.thread_id.as_deref(),
+                        content,
+                    );
+                }
+            }
+        }
+
+        let resumed = posted.len();
+        let mut first_message: Option<DiscordMessage> = posted.into_iter().next();
+        for (i, chunk) in chunks.iter().enumerate().skip(resumed) {
+            // Chunks after the first go into the thread the first one created
+            let thread_id = match &first_message {
+                Some(first) if self.options.thread_per_week => Some(first.channel_id.clone()),
+                _ => thread_id.clone(),
+            };
+
+            let mut url = Url::parse(&self.url)?;
+            url.query_pairs_mut().append_pair("wait", "true");
+            if let Some(thread_id) = &thread_id {
+                url.query_pairs_mut().append_pair("thread_id", thread_id);
+            }
+
+            let mut payload = json!({ "content": chunk });
+            if self.options.thread_per_week && thread_id.is_none() {
+                debug!("Creating thread for {week}");
+                payload["thread_name"] = json!(format!("Wastewater report - {week}"));
+            }
+
+            let message: DiscordMessage = http
+                .send(conn, http.post(url.as_str()), Body::Json(&payload))?
+                .into_json()?;
+            info!(
+                "Posted Discord message {} ({} of {})",
+                message.id,
+                i + 1,
+                chunks.len()
+            );
+            // Recorded right away, so a later chunk failing doesn't post this one or create the
+            // week's thread again
+            if self.options.thread_per_week && thread_id.is_none() {
+                insert_thread(conn, webhook_id, &week, &message.channel_id)?;
+            }
+            insert_posted_chunk(conn, webhook_id, &digest, i, &message)?;
+            first_message.get_or_insert(message);
+        }
+        let message = first_message.ok_or_else(|| eyre!("The digest is empty"))?;
+
+        let posted_thread_id = self.options.thread_per_week.then_some(message.channel_id);
+
+        insert_message(
+            conn,
+            clock,
+            webhook_id,
+            &PostedMessage {
+                message_id: message.id,
+                thread_id: posted_thread_id,
+                period: period.to_owned(),
+                chunks: chunks.len(),
+            },
+        )
+    }
+
+    /// Keeps the status board message current, posting a new one if it doesn't exist yet or was deleted.
+    /// Webhooks can't pin messages, so the first status board message has to be pinned by hand.
+    fn update_status_board(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        clock: &dyn Clock,
+        webhook_id: &str,
+        content: &str,
+    ) -> eyre::Result<()> {
+        let updated = clock.now().format("%Y-%m-%d %H:%M UTC");
+        let footer = format!("\n\n*Current levels, last updated {updated}*");
+        // The board is a single edited message, so whatever doesn't fit is left off
+        let limit = MESSAGE_LIMIT - footer.chars().count();
+        let chunks = split_message(content, limit);
+        if chunks.len() > 1 {
+            warn!(
+                "The status board is over {MESSAGE_LIMIT} characters, leaving off the last {} parts",
+                chunks.len() - 1
+            );
+        }
+        let content = format!("{}{footer}", chunks.first().map_or("", String::as_str));
+
+        if let Some(message_id) = select_status_board(conn, webhook_id)? {
+            match self.edit(conn, http, &message_id, None, &content) {
+                Ok(()) => {
+                    debug!("Updated status board message {message_id}");
+                    return Ok(());
+                }
+                Err(e) if is_not_found(&e) => {
+                    info!("Status board message {message_id} was deleted, posting a new one");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut url = Url::parse(&self.url)?;
+        url.query_pairs_mut().append_pair("wait", "true");
+
+        let payload = json!({ "content": content });
+        let message: DiscordMessage = http
+            .send(conn, http.post(url.as_str()), Body::Json(&payload))?
+            .into_json()?;
+        info!("Posted status board message {}", message.id);
+
+        upsert_status_board(conn, webhook_id, &message.id)
+    }
+
+    fn edit(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        message_id: &str,
+        thread_id: Option<&str>,
+        content: &str,
+    ) -> eyre::Result<()> {
+        let mut url = Url::parse(&self.url)?;
+        url.path_segments_mut()
+            .map_err(|_| eyre!("Discord webhook URL cannot be a base"))?
+            .extend(["messages", message_id]);
+        if let Some(thread_id) = thread_id {
+            url.query_pairs_mut().append_pair("thread_id", thread_id);
+        }
+
+        let payload = json!({ "content": content });
+        http.send(conn, http.patch(url.as_str()), Body::Json(&payload))?;
+
+        Ok(())
+    }
+
+    /// The webhook's ID, used to key stored state without persisting the token.
+    /// Webhook URLs look like `https://discord.com/api/webhooks/{id}/{token}`.
+    fn webhook_id(&self) -> eyre::Result<String> {
+        let url = Url::parse(&self.url)?;
+        url.path_segments()
+            .and_then(|segments| segments.skip_while(|segment| *segment != "webhooks").nth(1))
+            .map(str::to_owned)
+            .ok_or_else(|| eyre!("Discord webhook URL is missing a webhook ID"))
+    }
+}
+
+/// Posts to the tenant's Discord webhook, if it has one, with the run's Discord options.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscordNotifier;
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(url) = &tenant.discord_webhook_url else {
+            return Ok(());
+        };
+
+        DiscordWebhook::new(url.clone(), ctx.config.discord_options).send(
+            &ctx.db,
+            &ctx.http,
+            ctx.clock.as_ref(),
+            &report.markdown,
+            &report.period,
+        )
+    }
+}
+
+/// Splits `content` into messages of at most `limit` characters, breaking between lines.
+/// Lines longer than `limit` on their own are broken wherever the limit falls.
+pub fn split_message(content: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_len = 0;
+
+    for line in content.split('\n') {
+        let mut line: Vec<char> = line.chars().collect();
+        // The newline joining the line to the chunk
+        let separator = usize::from(!chunk.is_empty());
+        if chunk_len + separator + line.len() <= limit {
+            if separator == 1 {
+                chunk.push('\n');
+            }
+            chunk.extend(&line);
+            chunk_len += separator + line.len();
+            continue;
+        }
+
+        if !chunk.is_empty() {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        while line.len() > limit {
+            chunks.push(line.drain(..limit).collect());
+        }
+        chunk = line.iter().collect();
+        chunk_len = line.len();
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+fn is_not_found(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<HttpError>()
+        .is_some_and(|error| error.status == Some(404))
+}
+
+/// Label for the week containing `date`, e.g. "week of 2024-12-02". Weeks start on Monday.
+fn week_label(date: NaiveDate) -> String {
+    let monday = date.week(Weekday::Mon).first_day();
+    format!("week of {}", monday.format("%Y-%m-%d"))
+}
+
+fn select_thread(conn: &Connection, webhook_id: &str, week: &str) -> eyre::Result<Option<String>> {
+    const SELECT_THREAD_SQL: &str = "
+    SELECT thread_id FROM discord_threads
+    WHERE webhook_id = :webhook_id AND week = :week";
+
+    Ok(conn
+        .prepare_cached(SELECT_THREAD_SQL)?
+        .query_row(
+            named_params! { ":webhook_id": webhook_id, ":week": week },
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn insert_thread(
+    conn: &Connection,
+    webhook_id: &str,
+    week: &str,
+    thread_id: &str,
+) -> eyre::Result<()> {
+    const INSERT_THREAD_SQL: &str = "
+    INSERT INTO discord_threads (webhook_id, week, thread_id) VALUES (:webhook_id, :week, :thread_id)";
+
+    conn.prepare_cached(INSERT_THREAD_SQL)?
+        .execute(named_params! {
+            ":webhook_id": webhook_id,
+            ":week": week,
+            ":thread_id": thread_id,
+        })?;
+
+    Ok(())
+}
+
+fn select_last_message(conn: &Connection, webhook_id: &str) -> eyre::Result<Option<PostedMessage>> {
+    const SELECT_LAST_MESSAGE_SQL: &str = "
+    SELECT message_id, thread_id, period, chunks FROM discord_messages
+    WHERE webhook_id = :webhook_id
+    ORDER BY posted_timestamp DESC, rowid DESC
+    LIMIT 1";
+
+    Ok(conn
+        .prepare_cached(SELECT_LAST_MESSAGE_SQL)?
+        .query_row(named_params! { ":webhook_id": webhook_id }, |row| {
+            Ok(PostedMessage {
+                message_id: row.get(0)?,
+                thread_id: row.get(1)?,
+                period: row.get(2)?,
+                chunks: row.get(3)?,
+            })
+        })
+        .optional()?)
+}
+
+fn insert_message(
+    conn: &Connection,
+    clock: &dyn Clock,
+    webhook_id: &str,
+    message: &PostedMessage,
+) -> eyre::Result<()> {
+    const INSERT_MESSAGE_SQL: &str = "
+    INSERT INTO discord_messages (webhook_id, message_id, thread_id, period, posted_timestamp, chunks) VALUES
+    (:webhook_id, :message_id, :thread_id, :period, :posted_timestamp, :chunks)";
+
+    conn.prepare_cached(INSERT_MESSAGE_SQL)?
+        .execute(named_params! {
+            ":webhook_id": webhook_id,
+            ":message_id": message.message_id,
+            ":thread_id": message.thread_id,
+            ":period": message.period,
+            ":posted_timestamp": clock.unix_timestamp(),
+            ":chunks": message.chunks,
+        })?;
+
+    Ok(())
+}
+
+fn select_posted_chunks(
+    conn: &Connection,
+    webhook_id: &str,
+    digest: &str,
+) -> eyre::Result<Vec<DiscordMessage>> {
+    const SELECT_POSTED_CHUNKS_SQL: &str = "
+    SELECT message_id, channel_id FROM discord_digest_chunks
+    WHERE webhook_id = :webhook_id AND digest = :digest
+    ORDER BY chunk";
+
+    Ok(conn
+        .prepare_cached(SELECT_POSTED_CHUNKS_SQL)?
+        .query_map(
+            named_params! { ":webhook_id": webhook_id, ":digest": digest },
+            |row| {
+                Ok(DiscordMessage {
+                    id: row.get(0)?,
+                    channel_id: row.get(1)?,
+                })
+            },
+        )?
+        .collect::<Result<_, _>>()?)
+}
+
+fn insert_posted_chunk(
+    conn: &Connection,
+    webhook_id: &str,
+    digest: &str,
+    chunk: usize,
+    message: &DiscordMessage,
+) -> eyre::Result<()> {
+    const INSERT_POSTED_CHUNK_SQL: &str = "
+    INSERT INTO discord_digest_chunks (webhook_id, digest, chunk, message_id, channel_id) VALUES
+    (:webhook_id, :digest, :chunk, :message_id, :channel_id)";
+
+    conn.prepare_cached(INSERT_POSTED_CHUNK_SQL)?
+        .execute(named_params! {
+            ":webhook_id": webhook_id,
+            ":digest": digest,
+            ":chunk": chunk,
+            ":message_id": message.id,
+            ":channel_id": message.channel_id,
+        })?;
+
+    Ok(())
+}
+
+fn delete_posted_chunks(conn: &Connection, webhook_id: &str, digest: &str) -> eyre::Result<()> {
+    const DELETE_POSTED_CHUNKS_SQL: &str = "
+    DELETE FROM discord_digest_chunks WHERE webhook_id = :webhook_id AND digest = :digest";
+
+    conn.prepare_cached(DELETE_POSTED_CHUNKS_SQL)?
+        .execute(named_params! { ":webhook_id": webhook_id, ":digest": digest })?;
+
+    Ok(())
+}
+
+fn sha256_hex(input: &str) -> String {
+    Sha256::digest(input.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn select_status_board(conn: &Connection, webhook_id: &str) -> eyre::Result<Option<String>> {
+    const SELECT_STATUS_BOARD_SQL: &str = "
+    SELECT message_id FROM discord_status_boards WHERE webhook_id = :webhook_id";
+
+    Ok(conn
+        .prepare_cached(SELECT_STATUS_BOARD_SQL)?
+        .query_row(named_params! { ":webhook_id": webhook_id }, |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+fn upsert_status_board(conn: &Connection, webhook_id: &str, message_id: &str) -> eyre::Result<()> {
+    const UPSERT_STATUS_BOARD_SQL: &str = "
+    INSERT INTO discord_status_boards (webhook_id, message_id) VALUES (:webhook_id, :message_id)
+    ON CONFLICT (webhook_id) DO UPDATE SET message_id = excluded.message_id";
+
+    conn.prepare_cached(UPSERT_STATUS_BOARD_SQL)?
+        .execute(named_params! {
+            ":webhook_id": webhook_id,
+            ":message_id": message_id,
+        })?;
+
+    Ok(())
+}
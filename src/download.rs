@@ -0,0 +1,182 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{self, eyre, Context};
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, instrument, warn};
+
+use crate::http::{Body, HttpClient, HttpError};
+
+/// How many times an interrupted download is resumed before giving up.
+const MAX_RESUMES: u32 = 5;
+
+/// Downloads `url` to `path` through a `.part` file, resuming with HTTP range requests when the
+/// connection drops, and verifying the result before moving it into place.
+///
+/// The partial file's validator (ETag or Last-Modified) is kept next to it and sent as `If-Range`,
+/// so a file that changed upstream between attempts is downloaded from scratch instead of spliced.
+/// Without a validator, downloads always start from scratch.
+/// If `expected_sha256` is given, the completed file must match it.
+#[instrument(skip(conn, http))]
+pub fn download_resumable(
+    conn: &Connection,
+    http: &HttpClient,
+    url: &str,
+    path: &Path,
+    expected_sha256: Option<&str>,
+) -> eyre::Result<File> {
+    let part_path = with_suffix(path, ".part");
+    let validator_path = with_suffix(path, ".part.validator");
+
+    // A completed file is from a previous run, never resume onto it
+    if path.exists() {
+        fs::remove_file(path).with_context(|| format!("Error removing {}", path.display()))?;
+    }
+
+    let mut resumes = 0;
+    loop {
+        match download_part(conn, http, url, &part_path, &validator_path) {
+            Ok(()) => break,
+            Err(e) if resumes < MAX_RESUMES && is_interrupted(&e) => {
+                resumes += 1;
+                warn!("Download interrupted, resuming (attempt {resumes} of {MAX_RESUMES}): {e}");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let sha256 = sha256_file(&part_path)?;
+    info!("Downloaded {} (sha256 {sha256})", path.display());
+    if let Some(expected) = expected_sha256 {
+        if !sha256.eq_ignore_ascii_case(expected) {
+            fs::remove_file(&part_path)?;
+            let _ = fs::remove_file(&validator_path);
+            return Err(eyre!(
+                "Checksum mismatch for {url}: expected {expected}, got {sha256}"
+            ));
+        }
+    }
+
+    fs::rename(&part_path, path)?;
+    let _ = fs::remove_file(&validator_path);
+
+    Ok(File::open(path)?)
+}
+
+/// Downloads whatever is missing from the partial file.
+fn download_part(
+    conn: &Connection,
+    http: &HttpClient,
+    url: &str,
+    part_path: &Path,
+    validator_path: &Path,
+) -> eyre::Result<()> {
+    let part_len = fs::metadata(part_path).map(|m| m.len()).unwrap_or(0);
+    let validator = fs::read_to_string(validator_path).ok();
+
+    let mut request = http.get(url);
+    let offset = match &validator {
+        Some(validator) if part_len > 0 => {
+            debug!("Resuming download at byte {part_len}");
+            request = request
+                .set("Range", &format!("bytes={part_len}-"))
+                .set("If-Range", validator);
+            part_len
+        }
+        // Without a validator there's no telling whether the file changed upstream since, so the
+        // partial file is replaced rather than appended to
+        _ => {
+            if part_len > 0 {
+                info!("Partial download has no validator, restarting download");
+            }
+            0
+        }
+    };
+
+    let response = match http.send(conn, request, Body::Empty) {
+        Ok(response) => response,
+        // The partial file already holds everything
+        Err(e) if offset > 0 && is_range_not_satisfiable(&e) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let (mut file, expected_len) = if response.status() == 206 {
+        let total = response
+            .header("Content-Range")
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|total| total.parse::<u64>().ok());
+        (OpenOptions::new().append(true).open(part_path)?, total)
+    } else {
+        // The server ignored the range or the file changed upstream, so start over
+        if offset > 0 {
+            info!("Server sent the whole file, restarting download");
+        }
+        let new_validator = response
+            .header("ETag")
+            .or_else(|| response.header("Last-Modified"));
+        match new_validator {
+            Some(new_validator) => fs::write(validator_path, new_validator)?,
+            None => {
+                let _ = fs::remove_file(validator_path);
+            }
+        }
+        let total = response
+            .header("Content-Length")
+            .and_then(|len| len.parse::<u64>().ok());
+        (File::create(part_path)?, total)
+    };
+
+    io::copy(&mut response.into_reader(), &mut file)?;
+    file.flush()?;
+
+    let len = file.metadata()?.len();
+    match expected_len {
+        Some(expected_len) if len != expected_len => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Received {len} of {expected_len} bytes"),
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a download failed midway in a way resuming can recover from.
+fn is_interrupted(error: &eyre::Report) -> bool {
+    error.downcast_ref::<io::Error>().is_some()
+        || error
+            .downcast_ref::<HttpError>()
+            .is_some_and(|error| error.status.is_none())
+}
+
+fn is_range_not_satisfiable(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<HttpError>()
+        .is_some_and(|error| error.status == Some(416))
+}
+
+fn sha256_file(path: &Path) -> eyre::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
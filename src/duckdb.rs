@@ -0,0 +1,60 @@
+/// Builds a DuckDB script that attaches the SQLite database read-only and creates analytical views over it.
+/// Pipe it into DuckDB, e.g. `hygieia duckdb | duckdb analytics.duckdb`, to get a persistent analytics file.
+pub fn attach_sql(sqlite_db_path: &str) -> String {
+    let escaped_path = sqlite_db_path.replace('\'', "''");
+
+    format!(
+        r#"INSTALL sqlite;
+LOAD sqlite;
+ATTACH IF NOT EXISTS '{escaped_path}' AS hygieia (TYPE sqlite, READ_ONLY);
+
+-- Samples with proper DuckDB types
+CREATE OR REPLACE VIEW samples AS
+SELECT
+    CAST(sample_collection_date AS DATE) AS sample_collection_date,
+    site_name,
+    county,
+    pcr_pathogen_target,
+    pcr_gene_target,
+    normalized_pathogen_concentration,
+    CAST(date_updated AS TIMESTAMPTZ) AS date_updated,
+    to_timestamp(poll_timestamp) AS polled_at
+FROM hygieia.wastewater_samples;
+
+-- Latest sample of every site and target
+CREATE OR REPLACE VIEW latest_samples AS
+SELECT *
+FROM samples
+QUALIFY row_number() OVER (
+    PARTITION BY site_name, county, pcr_pathogen_target, pcr_gene_target
+    ORDER BY sample_collection_date DESC
+) = 1;
+
+-- Weekly mean concentration per county and pathogen, with how many sites contributed
+CREATE OR REPLACE VIEW weekly_county_means AS
+SELECT
+    county,
+    pcr_pathogen_target,
+    date_trunc('week', sample_collection_date) AS week,
+    avg(normalized_pathogen_concentration) AS mean_concentration,
+    count(*) AS samples,
+    count(DISTINCT site_name) AS sites
+FROM samples
+GROUP BY ALL
+ORDER BY county, pcr_pathogen_target, week;
+
+-- First and last sample of every site, to see coverage at a glance
+CREATE OR REPLACE VIEW site_coverage AS
+SELECT
+    site_name,
+    county,
+    pcr_pathogen_target,
+    min(sample_collection_date) AS first_sample,
+    max(sample_collection_date) AS last_sample,
+    count(*) AS samples
+FROM samples
+GROUP BY ALL
+ORDER BY county, site_name, pcr_pathogen_target;
+"#
+    )
+}
@@ -0,0 +1,388 @@
+//! Emails reports through an SMTP server, for readers who don't use chat apps. Messages carry the
+//! markdown as plain text alongside an HTML rendering with a table per county.
+//!
+//! A report is a single message to every recipient. It's addressed to the sender, with the
+//! recipients only in the envelope, so they don't see each other's addresses.
+
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{self, eyre, Context};
+use lettre::address::Envelope;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{Address, Message, SmtpTransport, Transport};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use crate::context::RunContext;
+use crate::links::DashboardLinks;
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::precision::Precision;
+use crate::report::Report;
+use crate::site::escape;
+use crate::tenants::Tenant;
+use crate::useful::Secret;
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmtpSecurity {
+    /// Connect in plain text and upgrade with STARTTLS, failing if the server doesn't offer it.
+    #[default]
+    StartTls,
+    /// Connect with TLS from the start, as on port 465.
+    Tls,
+    /// Never encrypt. Only for servers on the same machine or network.
+    None,
+}
+
+impl SmtpSecurity {
+    /// The port usually used with this security.
+    pub fn default_port(self) -> u16 {
+        match self {
+            SmtpSecurity::StartTls => 587,
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "Expected \"starttls\", \"tls\", or \"none\", got {s}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SmtpSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::StartTls => "starttls",
+            Self::Tls => "tls",
+            Self::None => "none",
+        })
+    }
+}
+
+/// The SMTP server reports are submitted to.
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    /// Account to authenticate as, if the server needs one.
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+    /// Address the reports are sent from.
+    pub from: String,
+}
+
+impl SmtpConfig {
+    /// Emails a report to `to`, with `text` as its plain text part and `html` as its HTML part.
+    /// `timeout` applies to connecting and to every read and write.
+    #[instrument(skip_all, fields(host = self.host, recipients = to.len()))]
+    pub fn send(
+        &self,
+        to: &[String],
+        subject: &str,
+        text: &str,
+        html: &str,
+        date: DateTime<Utc>,
+        timeout: Duration,
+    ) -> eyre::Result<()> {
+        let message = self.message(to, subject, text, html, date)?;
+        self.transport(timeout)?.send(&message).with_context(|| {
+            format!("Error emailing report through {}:{}", self.host, self.port)
+        })?;
+        info!("Emailed report to {} recipients", to.len());
+        Ok(())
+    }
+
+    /// A connection to the server, secured as configured.
+    fn transport(&self, timeout: Duration) -> eyre::Result<SmtpTransport> {
+        let builder = match self.security {
+            SmtpSecurity::StartTls => SmtpTransport::starttls_relay(&self.host)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&self.host)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&self.host),
+        };
+        let mut builder = builder
+            .port(self.port)
+            .timeout(Some(timeout))
+            .hello_name(ClientId::Domain(self.domain().to_owned()));
+        if let Some(username) = &self.username {
+            let password = self.password.as_ref().map(Secret::expose).unwrap_or("");
+            builder = builder.credentials(Credentials::new(username.clone(), password.to_owned()));
+        }
+        Ok(builder.build())
+    }
+
+    /// Domain of the sender's address, which the client introduces itself as.
+    fn domain(&self) -> &str {
+        self.from
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or("localhost")
+    }
+
+    /// A message with `text` and `html` as alternatives, sent to `to` but addressed to the sender.
+    fn message(
+        &self,
+        to: &[String],
+        subject: &str,
+        text: &str,
+        html: &str,
+        date: DateTime<Utc>,
+    ) -> eyre::Result<Message> {
+        let from: Mailbox = self
+            .from
+            .parse()
+            .with_context(|| format!("Invalid sender address {}", self.from))?;
+        let recipients = to
+            .iter()
+            .map(|recipient| {
+                recipient
+                    .parse()
+                    .with_context(|| format!("Invalid recipient address {recipient}"))
+            })
+            .collect::<eyre::Result<Vec<Address>>>()?;
+        let envelope = Envelope::new(Some(from.email.clone()), recipients)?;
+
+        Ok(Message::builder()
+            .from(from.clone())
+            .to(from)
+            .envelope(envelope)
+            .subject(subject)
+            .date(SystemTime::from(date))
+            .message_id(Some(format!("<{}@{}>", Uuid::now_v7(), self.domain())))
+            .multipart(MultiPart::alternative_plain_html(
+                text.to_owned(),
+                html.to_owned(),
+            ))?)
+    }
+}
+
+/// Emails the tenant's recipients, if it has any, through [Config::smtp](crate::context::Config).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailNotifier;
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        if tenant.email_to.is_empty() {
+            return Ok(());
+        }
+        let Some(smtp) = &ctx.config.smtp else {
+            return Err(eyre!(
+                "Tenant {} has email recipients but no SMTP server is configured",
+                tenant.name
+            ));
+        };
+
+        let subject = match report.period.as_str() {
+            "" => "Wastewater report".to_owned(),
+            period => format!("Wastewater report for {period}"),
+        };
+        let html = match &report.email_html {
+            Some(html) => html.clone(),
+            // Reports stored before HTML was rendered
+            None => format!("<pre>{}</pre>", escape(&report.markdown)),
+        };
+        smtp.send(
+            &tenant.email_to,
+            &subject,
+            &report.markdown,
+            &html,
+            ctx.clock.now(),
+            ctx.config.http.timeout,
+        )
+    }
+}
+
+/// Renders the report as an HTML email: the greeting, a table per county, the notices, and the
+/// footer. Styles are inline since many mail clients drop stylesheets.
+pub fn report_html(
+    report: &Report,
+    links: Option<&DashboardLinks>,
+    precision: Precision,
+) -> String {
+    const CELL: &str = "padding: 4px 8px; border-bottom: 1px solid #ddd; text-align: left";
+
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<body style=\"font-family: sans-serif; color: #222\">\n",
+    );
+    let _ = writeln!(html, "<p>{}</p>", escape(&report.greeting_line()));
+
+    for lines in report.lines.chunk_by(|a, b| a.county == b.county) {
+        let _ = writeln!(
+            html,
+            "<h2 style=\"font-size: 18px\">{} County</h2>",
+            escape(&lines[0].county)
+        );
+        html.push_str("<table style=\"border-collapse: collapse\">\n<tr>");
+        for header in [
+            "Pathogen",
+            "Latest",
+            "7d / 14d average",
+            "Change",
+            "Past year",
+            "Trend",
+            "Sites",
+            "Activity",
+            "Collected",
+        ] {
+            let _ = write!(html, "<th style=\"{CELL}\">{header}</th>");
+        }
+        html.push_str("</tr>\n");
+
+        for line in lines {
+            let pathogen = match (links, &line.summary) {
+                (Some(links), Some(_)) => format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape(&links.chart(&line.county_slug, &line.pathogen)),
+                    escape(&line.pathogen)
+                ),
+                _ => escape(&line.pathogen),
+            };
+            let cells = match &line.summary {
+                Some(summary) => {
+                    let trend = line
+                        .trend
+                        .as_ref()
+                        .map(|trend| match trend.describe_doubling() {
+                            Some(doubling) => {
+                                format!("{} {}, {doubling}", trend.arrow(), trend.label())
+                            }
+                            None => format!("{} {}", trend.arrow(), trend.label()),
+                        })
+                        .unwrap_or_default();
+                    let trend = match &line.gap {
+                        Some(gap) => format!("{trend} (⚠️ {})", gap.note()),
+                        None => trend,
+                    };
+                    let sites = match &line.coverage {
+                        Some(coverage) if coverage.dropped() => format!(
+                            "{} of {} (was {})",
+                            coverage.reporting, coverage.total, coverage.previous
+                        ),
+                        Some(coverage) => format!("{} of {}", coverage.reporting, coverage.total),
+                        None => String::new(),
+                    };
+                    let averages = line
+                        .averages
+                        .map(|averages| {
+                            format!(
+                                "{} / {} (latest {:+.0}%)",
+                                precision.format(averages.short),
+                                precision.format(averages.long),
+                                averages.latest_change * 100.0
+                            )
+                        })
+                        .unwrap_or_default();
+                    [
+                        precision.format(summary.latest_value),
+                        averages,
+                        report
+                            .format_change(summary, precision)
+                            .unwrap_or_else(|| "-".to_owned()),
+                        line.history
+                            .as_ref()
+                            .map(|history| match history.describe_extreme() {
+                                Some(extreme) => {
+                                    format!("{} percentile, {extreme}", history.rank())
+                                }
+                                None => format!("{} percentile", history.rank()),
+                            })
+                            .unwrap_or_default(),
+                        trend,
+                        sites,
+                        line.activity
+                            .as_ref()
+                            .map(|activity| activity.level.to_string())
+                            .unwrap_or_default(),
+                        summary.latest_date.to_string(),
+                    ]
+                }
+                None => [
+                    "no data".to_owned(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                ],
+            };
+
+            let _ = write!(html, "<tr><td style=\"{CELL}\">{pathogen}</td>");
+            for cell in cells {
+                let _ = write!(html, "<td style=\"{CELL}\">{}</td>", escape(&cell));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+    }
+
+    let notices = &report.notices;
+    let notices: Vec<String> = notices
+        .threshold_alerts
+        .iter()
+        .map(|alert| format!("🚨 {alert}"))
+        .chain(
+            notices
+                .coverage_changes
+                .iter()
+                .map(|change| format!("📍 {change}")),
+        )
+        .chain(
+            notices
+                .season_onsets
+                .iter()
+                .map(|onset| format!("🦠 {onset}")),
+        )
+        .chain(
+            notices
+                .revisions
+                .iter()
+                .map(|revisions| format!("✏️ {revisions}")),
+        )
+        .chain(
+            notices
+                .vintage
+                .iter()
+                .map(|vintage| format!("🔄 {vintage}")),
+        )
+        .collect();
+    if !notices.is_empty() {
+        html.push_str("<ul>\n");
+        for notice in notices {
+            let _ = writeln!(html, "<li>{}</li>", escape(&notice));
+        }
+        html.push_str("</ul>\n");
+    }
+
+    if let Some(provenance) = &report.provenance {
+        let _ = writeln!(
+            html,
+            "<p style=\"color: #666; font-size: 12px\">{}</p>",
+            escape(&provenance.footer())
+        );
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use color_eyre::eyre::{self, Context};
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, instrument};
+
+/// A sample as exported, with where and when each row came from.
+#[derive(Debug, Serialize)]
+struct ExportedSample {
+    sample_collection_date: NaiveDate,
+    site_name: String,
+    county: String,
+    pcr_pathogen_target: String,
+    pcr_gene_target: String,
+    normalized_pathogen_concentration: f64,
+    date_updated: DateTime<FixedOffset>,
+    polled_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedSite {
+    site_name: String,
+    county: String,
+    population: Option<u64>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    source: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+/// File names of the tables in an exported package.
+pub const SAMPLES_PATH: &str = "wastewater_samples.csv";
+pub const SITES_PATH: &str = "sites.csv";
+pub const SITES_GEOJSON_PATH: &str = "sites.geojson";
+
+/// Writes the stored samples and sites to `dir` as a Frictionless tabular data package:
+/// a CSV per table and a `datapackage.json` describing their schemas and where the data came from.
+/// The sites are also written as GeoJSON with each target's latest sample, for maps. It's left out of
+/// the descriptor, which may only list tabular resources.
+#[instrument(skip(conn))]
+pub fn export_data_package(
+    conn: &Connection,
+    dir: &Path,
+    source_url: &str,
+    created: DateTime<Utc>,
+) -> eyre::Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Error creating {}", dir.display()))?;
+
+    const SELECT_SAMPLES_SQL: &str = "
+    SELECT sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target,
+        normalized_pathogen_concentration, date_updated, poll_timestamp
+    FROM wastewater_samples
+    ORDER BY sample_collection_date, county, site_name, pcr_pathogen_target, pcr_gene_target";
+
+    let mut samples = csv::Writer::from_writer(File::create(dir.join(SAMPLES_PATH))?);
+    let mut sample_count = 0;
+    let mut stmt = conn.prepare(SELECT_SAMPLES_SQL)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        samples.serialize(ExportedSample {
+            sample_collection_date: row.get(0)?,
+            site_name: row.get(1)?,
+            county: row.get(2)?,
+            pcr_pathogen_target: row.get(3)?,
+            pcr_gene_target: row.get(4)?,
+            normalized_pathogen_concentration: row.get(5)?,
+            date_updated: row.get(6)?,
+            polled_at: timestamp(row.get(7)?),
+        })?;
+        sample_count += 1;
+    }
+    samples.flush()?;
+
+    const SELECT_SITES_SQL: &str = "
+    SELECT site_name, county, population, latitude, longitude, source, updated_timestamp
+    FROM sites
+    ORDER BY county, site_name";
+
+    let mut sites = csv::Writer::from_writer(File::create(dir.join(SITES_PATH))?);
+    let mut site_count = 0;
+    let mut stmt = conn.prepare(SELECT_SITES_SQL)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        sites.serialize(ExportedSite {
+            site_name: row.get(0)?,
+            county: row.get(1)?,
+            population: row.get(2)?,
+            latitude: row.get(3)?,
+            longitude: row.get(4)?,
+            source: row.get(5)?,
+            updated_at: timestamp(row.get(6)?),
+        })?;
+        site_count += 1;
+    }
+    sites.flush()?;
+
+    fs::write(
+        dir.join(SITES_GEOJSON_PATH),
+        serde_json::to_string_pretty(&sites_geojson(conn)?)?,
+    )?;
+
+    let descriptor = data_package_descriptor(source_url, created);
+    fs::write(
+        dir.join("datapackage.json"),
+        serde_json::to_string_pretty(&descriptor)?,
+    )?;
+
+    info!(
+        "Exported {sample_count} samples and {site_count} sites to {}",
+        dir.display()
+    );
+    Ok(())
+}
+
+/// A FeatureCollection with a point per site, or no geometry for sites without coordinates, and
+/// the latest sample of each pathogen and gene target the site reports.
+fn sites_geojson(conn: &Connection) -> eyre::Result<serde_json::Value> {
+    const SELECT_LATEST_SQL: &str = "
+    SELECT site_name, county, pcr_pathogen_target, pcr_gene_target,
+        MAX(sample_collection_date), normalized_pathogen_concentration
+    FROM wastewater_samples
+    GROUP BY site_name, county, pcr_pathogen_target, pcr_gene_target
+    ORDER BY county, site_name, pcr_pathogen_target, pcr_gene_target";
+
+    let mut latest: HashMap<(String, String), Vec<serde_json::Value>> = HashMap::new();
+    let mut stmt = conn.prepare(SELECT_LATEST_SQL)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        latest
+            .entry((row.get(0)?, row.get(1)?))
+            .or_default()
+            .push(json!({
+                "pcr_pathogen_target": row.get::<_, String>(2)?,
+                "pcr_gene_target": row.get::<_, String>(3)?,
+                "sample_collection_date": row.get::<_, NaiveDate>(4)?,
+                "normalized_pathogen_concentration": row.get::<_, f64>(5)?,
+            }));
+    }
+
+    const SELECT_SITES_SQL: &str = "
+    SELECT site_name, county, population, latitude, longitude
+    FROM sites
+    ORDER BY county, site_name";
+
+    let mut features = Vec::new();
+    let mut stmt = conn.prepare(SELECT_SITES_SQL)?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let site_name: String = row.get(0)?;
+        let county: String = row.get(1)?;
+        let latitude: Option<f64> = row.get(3)?;
+        let longitude: Option<f64> = row.get(4)?;
+        let geometry = match (latitude, longitude) {
+            // GeoJSON positions are longitude first
+            (Some(latitude), Some(longitude)) => {
+                json!({ "type": "Point", "coordinates": [longitude, latitude] })
+            }
+            _ => serde_json::Value::Null,
+        };
+        let samples = latest
+            .remove(&(site_name.clone(), county.clone()))
+            .unwrap_or_default();
+        features.push(json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": {
+                "site_name": site_name,
+                "county": county,
+                "population": row.get::<_, Option<u64>>(2)?,
+                "latest_samples": samples,
+            },
+        }));
+    }
+
+    Ok(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+/// The `datapackage.json` descriptor, following the Frictionless tabular data package profile.
+fn data_package_descriptor(source_url: &str, created: DateTime<Utc>) -> serde_json::Value {
+    json!({
+        "profile": "tabular-data-package",
+        "name": "hygieia-wastewater",
+        "title": "Washington State wastewater pathogen concentrations",
+        "description": "Wastewater samples polled from the Washington State Department of Health by hygieia. \
+            Concentrations are normalized differently at each site, so they aren't comparable between sites.",
+        "created": created.to_rfc3339(),
+        "sources": [{ "title": "Washington State Department of Health", "path": source_url }],
+        "contributors": [{ "title": "hygieia", "path": "https://github.com/ILikePizza555/hygieia", "role": "wrangler" }],
+        "resources": [
+            {
+                "name": "wastewater-samples",
+                "path": SAMPLES_PATH,
+                "profile": "tabular-data-resource",
+                "format": "csv",
+                "mediatype": "text/csv",
+                "encoding": "utf-8",
+                "schema": {
+                    "fields": [
+                        { "name": "sample_collection_date", "type": "date", "format": "default" },
+                        { "name": "site_name", "type": "string" },
+                        { "name": "county", "type": "string" },
+                        { "name": "pcr_pathogen_target", "type": "string" },
+                        { "name": "pcr_gene_target", "type": "string" },
+                        {
+                            "name": "normalized_pathogen_concentration",
+                            "type": "number",
+                            "description": "Gene copies per person per day"
+                        },
+                        {
+                            "name": "date_updated",
+                            "type": "datetime",
+                            "description": "When the Department of Health last updated the row"
+                        },
+                        {
+                            "name": "polled_at",
+                            "type": "datetime",
+                            "description": "When hygieia first stored the row"
+                        }
+                    ],
+                    "primaryKey": ["sample_collection_date", "site_name", "county", "pcr_pathogen_target", "pcr_gene_target"]
+                }
+            },
+            {
+                "name": "sites",
+                "path": SITES_PATH,
+                "profile": "tabular-data-resource",
+                "format": "csv",
+                "mediatype": "text/csv",
+                "encoding": "utf-8",
+                "schema": {
+                    "fields": [
+                        { "name": "site_name", "type": "string" },
+                        { "name": "county", "type": "string" },
+                        { "name": "population", "type": "integer", "description": "Population served by the sewershed" },
+                        { "name": "latitude", "type": "number" },
+                        { "name": "longitude", "type": "number" },
+                        { "name": "source", "type": "string", "description": "Where the site's metadata came from" },
+                        { "name": "updated_at", "type": "datetime" }
+                    ],
+                    "primaryKey": ["site_name", "county"]
+                }
+            }
+        ]
+    })
+}
+
+fn timestamp(unix_timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(unix_timestamp, 0).unwrap_or_default()
+}
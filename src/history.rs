@@ -0,0 +1,179 @@
+//! Where a county's latest samples sit in its sites' own history. Sites normalize differently, so
+//! a site is only ever compared with itself: its latest sample gets a percentile among its samples
+//! over the last [HISTORY_DAYS] days, and a county's percentile is the mean of its sites'.
+//!
+//! When every site's latest sample is the highest (or lowest) of its window, the report also says
+//! since when, looking back as far as the site's samples go.
+
+use chrono::{Days, NaiveDate};
+use rusqlite::{params, Connection};
+
+use crate::analysis::{Measure, TREND_WINDOW_DAYS};
+use crate::numeric;
+
+/// Days of history a site's latest sample is ranked within.
+pub const HISTORY_DAYS: u64 = 365;
+/// Fewest samples in the window for a site to be ranked.
+pub const MIN_HISTORY_SAMPLES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extreme {
+    Highest,
+    Lowest,
+}
+
+/// A county's latest samples of a pathogen, relative to its sites' history.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoricalContext {
+    /// Mean of the sites' percentiles, from 0 to 100.
+    pub percentile: f64,
+    /// How many sites were ranked.
+    pub sites: usize,
+    /// Set when every site's latest sample is its highest or lowest of the window.
+    pub extreme: Option<Extreme>,
+    /// Latest date any site was last as high (or low). None with an extreme means no site has been.
+    pub since: Option<NaiveDate>,
+}
+
+impl HistoricalContext {
+    /// The context as written in messages, e.g. "85th percentile of the past year" or "100th
+    /// percentile of the past year, highest since 2023-12".
+    pub fn describe(&self) -> String {
+        let mut text = format!("{} percentile of the past year", self.rank());
+        if let Some(extreme) = self.describe_extreme() {
+            text.push_str(&format!(", {extreme}"));
+        }
+        text
+    }
+
+    /// The percentile as an ordinal, e.g. "85th".
+    pub fn rank(&self) -> String {
+        let rank = self.percentile.round() as u64;
+        format!("{rank}{}", ordinal_suffix(rank))
+    }
+
+    /// E.g. "highest since 2023-12" or "lowest on record", if the samples are at an extreme.
+    pub fn describe_extreme(&self) -> Option<String> {
+        let extreme = match self.extreme? {
+            Extreme::Highest => "highest",
+            Extreme::Lowest => "lowest",
+        };
+        Some(match self.since {
+            Some(since) => format!("{extreme} since {}", since.format("%Y-%m")),
+            None => format!("{extreme} on record"),
+        })
+    }
+}
+
+/// "st", "nd", "rd", or "th", as in 21st, 12th, and 85th.
+fn ordinal_suffix(n: u64) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+/// Ranks the latest sample of each of the county's sites reporting `pathogen` in the trend window
+/// ending at `latest_date`. None if no site has [MIN_HISTORY_SAMPLES] samples in its window.
+pub fn county_history(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    latest_date: NaiveDate,
+    measure: Measure,
+) -> rusqlite::Result<Option<HistoricalContext>> {
+    let select_history_sql = format!(
+        "
+    SELECT site_name, sample_collection_date, {measure} FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+        AND sample_collection_date > ?3 AND sample_collection_date <= ?4
+        AND {measure} IS NOT NULL
+    ORDER BY site_name, sample_collection_date",
+        measure = measure.column()
+    );
+    // The last sample before the window at least as high as `?5`, or as low for `?6`
+    let select_since_sql = format!(
+        "
+    SELECT MAX(sample_collection_date) FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2 AND site_name = ?3
+        AND sample_collection_date <= ?4
+        AND IIF(?6, {measure} <= ?5, {measure} >= ?5)",
+        measure = measure.column()
+    );
+
+    let window_start = latest_date - Days::new(HISTORY_DAYS);
+    let trend_start = latest_date - Days::new(TREND_WINDOW_DAYS as u64);
+
+    let mut sites: Vec<(String, NaiveDate, Vec<f64>)> = Vec::new();
+    let mut stmt = conn.prepare_cached(&select_history_sql)?;
+    let mut rows = stmt.query(params![county, pathogen, window_start, latest_date])?;
+    while let Some(row) = rows.next()? {
+        let site: String = row.get(0)?;
+        let date: NaiveDate = row.get(1)?;
+        let value: f64 = row.get(2)?;
+        match sites.last_mut() {
+            Some((last, last_date, values)) if *last == site => {
+                *last_date = date;
+                values.push(value);
+            }
+            _ => sites.push((site, date, vec![value])),
+        }
+    }
+
+    let mut percentiles = Vec::new();
+    let mut extremes = Vec::new();
+    for (site, date, values) in &sites {
+        if *date <= trend_start || values.len() < MIN_HISTORY_SAMPLES {
+            continue;
+        }
+        let latest = *values.last().expect("sites have a sample");
+        let at_or_below = values.iter().filter(|&&value| value <= latest).count();
+        let below = values.iter().filter(|&&value| value < latest).count();
+        percentiles.push(at_or_below as f64 / values.len() as f64 * 100.0);
+        extremes.push(if at_or_below == values.len() {
+            Some((site, latest, Extreme::Highest))
+        } else if below == 0 {
+            Some((site, latest, Extreme::Lowest))
+        } else {
+            None
+        });
+    }
+    if percentiles.is_empty() {
+        return Ok(None);
+    }
+
+    // Every site has to be at the same extreme for the county to be
+    let extreme = extremes
+        .iter()
+        .map(|extreme| extreme.map(|(_, _, extreme)| extreme))
+        .reduce(|a, b| if a == b { a } else { None })
+        .flatten();
+    let mut since = None;
+    if let Some(extreme) = extreme {
+        let mut stmt = conn.prepare_cached(&select_since_sql)?;
+        for &(site, latest, _) in extremes.iter().flatten() {
+            let site_since: Option<NaiveDate> = stmt.query_row(
+                params![
+                    county,
+                    pathogen,
+                    site,
+                    window_start,
+                    latest,
+                    extreme == Extreme::Lowest
+                ],
+                |row| row.get(0),
+            )?;
+            since = since.max(site_since);
+        }
+    }
+
+    Ok(Some(HistoricalContext {
+        percentile: numeric::mean(&percentiles),
+        sites: percentiles.len(),
+        extreme,
+        since,
+    }))
+}
@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection};
+use tracing::{debug, instrument, warn};
+use ureq::{Agent, AgentBuilder, ErrorKind, Request, Response};
+use url::Url;
+
+use crate::useful::{Clock, SeededRng};
+
+/// Placeholder written in place of anything that looks like a secret.
+const REDACTED: &str = "REDACTED";
+
+/// Path segments at least this long (and without a file extension) are treated as secrets.
+/// Webhook tokens for Discord and Slack are well above this, while IDs and file names are not.
+const SECRET_SEGMENT_MIN_LEN: usize = 20;
+
+/// Body of an outbound request.
+#[derive(Clone, Copy)]
+pub enum Body<'a> {
+    Empty,
+    Json(&'a serde_json::Value),
+    /// Bytes sent as is. The caller sets the Content-Type.
+    Bytes(&'a [u8]),
+}
+
+/// Token bucket rate limit applied to each host separately.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Tokens added to a bucket per second. Zero or less disables rate limiting.
+    pub per_second: f64,
+    /// Maximum tokens a bucket holds, i.e. how many requests may be sent back to back.
+    pub burst: f64,
+}
+
+/// Where public data hosts can find out what hygieia is.
+const PROJECT_URL: &str = "https://github.com/ILikePizza555/hygieia";
+
+/// How requests failing with a transport error, 429, or server error are retried. See
+/// [HttpClient::send] for which failures of a POST are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first. 1 disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after and randomly shortened by up to half.
+    pub base_delay: Duration,
+    /// Longest delay before a retry, including any the server asks for with Retry-After.
+    pub max_delay: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Whether every request is recorded in the `http_audit` table.
+    pub audit: bool,
+    pub rate_limit: RateLimit,
+    pub retry: RetryPolicy,
+    /// How long connecting, or any single read or write, may take before the attempt fails.
+    pub timeout: Duration,
+    /// Operator contact (email or URL) appended to the User-Agent.
+    pub contact: Option<String>,
+}
+
+impl Default for HttpConfig {
+    /// Audited, with a burst of 5 requests per host refilled at one every 2 seconds, which stays
+    /// under Discord's webhook limits. Failed requests are tried 3 times, about 1 and 2 seconds
+    /// apart, and attempts time out after 30 seconds without progress.
+    fn default() -> Self {
+        Self {
+            audit: true,
+            rate_limit: RateLimit {
+                per_second: 0.5,
+                burst: 5.0,
+            },
+            retry: RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(60),
+            },
+            timeout: Duration::from_secs(30),
+            contact: None,
+        }
+    }
+}
+
+/// User-Agent identifying hygieia, e.g. `hygieia/0.1.0 (+https://github.com/ILikePizza555/hygieia; ops@example.com)`.
+fn user_agent(contact: Option<&str>) -> String {
+    let version = env!("CARGO_PKG_VERSION");
+    match contact {
+        Some(contact) => format!("hygieia/{version} (+{PROJECT_URL}; {contact})"),
+        None => format!("hygieia/{version} (+{PROJECT_URL})"),
+    }
+}
+
+/// A request that failed, with its URL redacted. Returned by [HttpClient::send] in place of
+/// ureq's errors, which start with the full URL.
+#[derive(Debug)]
+pub struct HttpError {
+    method: String,
+    url: String,
+    /// Status of the error response, None if there was no response.
+    pub status: Option<u16>,
+    /// What went wrong without a response, e.g. "Network Error: timed out reading response".
+    transport: Option<String>,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.status, &self.transport) {
+            (Some(status), _) => write!(f, "{} {}: status code {status}", self.method, self.url),
+            (None, Some(transport)) => write!(f, "{} {}: {transport}", self.method, self.url),
+            (None, None) => write!(f, "{} {} failed", self.method, self.url),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// The kind and message of a transport error, without the URL its Display starts with.
+fn describe_transport(transport: &ureq::Transport) -> String {
+    match transport.message() {
+        Some(message) => format!("{}: {message}", transport.kind()),
+        None => transport.kind().to_string(),
+    }
+}
+
+/// Shared HTTP client for every outbound request hygieia makes.
+pub struct HttpClient {
+    agent: Agent,
+    audit: bool,
+    rate_limiter: RateLimiter,
+    retry: RetryPolicy,
+    /// Timestamps audit records.
+    clock: Arc<dyn Clock>,
+    /// Draws retry jitter.
+    rng: SeededRng,
+}
+
+impl HttpClient {
+    pub fn new(config: HttpConfig, clock: Arc<dyn Clock>, rng: SeededRng) -> Self {
+        Self {
+            agent: AgentBuilder::new()
+                .user_agent(&user_agent(config.contact.as_deref()))
+                .timeout_connect(config.timeout)
+                .timeout_read(config.timeout)
+                .timeout_write(config.timeout)
+                .build(),
+            audit: config.audit,
+            rate_limiter: RateLimiter::new(config.rate_limit),
+            retry: config.retry,
+            clock,
+            rng,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Request {
+        self.agent.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> Request {
+        self.agent.post(url)
+    }
+
+    pub fn patch(&self, url: &str) -> Request {
+        self.agent.request("PATCH", url)
+    }
+
+    pub fn put(&self, url: &str) -> Request {
+        self.agent.put(url)
+    }
+
+    /// Sends a request, recording every attempt in the audit log.
+    /// Blocks first if the host's rate limit has been used up, and retries transport errors, 429s,
+    /// and server errors with backoff as the [RetryPolicy] allows. A POST or PATCH may have been
+    /// acted on before a timeout or server error, e.g. a webhook message posted, so those are only
+    /// retried if they failed to connect or got a 429.
+    /// Failing to write the audit record is logged but never fails the request itself.
+    #[instrument(skip_all, fields(method = request.method(), url = redact_url(request.url())))]
+    pub fn send(&self, conn: &Connection, request: Request, body: Body) -> eyre::Result<Response> {
+        let method = request.method().to_owned();
+        let url = redact_url(request.url());
+        let host = Url::parse(request.url())
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_owned));
+
+        let mut attempt = 0;
+        loop {
+            if let Some(host) = &host {
+                self.rate_limiter.acquire(host);
+            }
+
+            let started = Instant::now();
+            let request = request.clone();
+            let result = match body {
+                Body::Empty => request.call(),
+                Body::Json(json) => request.send_json(json),
+                Body::Bytes(bytes) => request.send_bytes(bytes),
+            };
+            let duration = started.elapsed();
+
+            let (status, error) = match &result {
+                Ok(response) => (Some(response.status()), None),
+                Err(ureq::Error::Status(code, _)) => (Some(*code), None),
+                // Its Display starts with the full URL, secrets and all
+                Err(ureq::Error::Transport(transport)) => {
+                    (None, Some(describe_transport(transport)))
+                }
+            };
+            debug!("{method} {url} -> {status:?} in {duration:?}");
+
+            let response = match &result {
+                Ok(response) | Err(ureq::Error::Status(_, response)) => Some(response),
+                Err(ureq::Error::Transport(_)) => None,
+            };
+            if let (Some(host), Some(response)) = (&host, response) {
+                if response.header("X-RateLimit-Remaining") == Some("0") {
+                    if let Some(reset_after) = header_seconds(response, "X-RateLimit-Reset-After") {
+                        self.rate_limiter.pause(host, Instant::now() + reset_after);
+                    }
+                }
+            }
+
+            if self.audit {
+                let entry = HttpAuditEntry {
+                    request_timestamp: self.clock.unix_timestamp(),
+                    method: &method,
+                    url: &url,
+                    status,
+                    error: error.as_deref(),
+                    duration,
+                    retry_count: attempt,
+                };
+                if let Err(e) = insert_http_audit_entry(conn, &entry) {
+                    warn!("Failed to record HTTP audit entry for {method} {url}: {e}");
+                }
+            }
+
+            attempt += 1;
+            match self.retry_delay(&method, attempt, &result) {
+                Some(delay) if attempt < self.retry.max_attempts => {
+                    let reason = error.unwrap_or_else(|| format!("status {}", status.unwrap_or(0)));
+                    warn!(
+                        "{method} {url} failed with {reason}, retrying in {delay:?} (attempt {} of {})",
+                        attempt + 1,
+                        self.retry.max_attempts
+                    );
+                    thread::sleep(delay);
+                }
+                _ => {
+                    return result.map_err(|_| {
+                        HttpError {
+                            method,
+                            url,
+                            status,
+                            transport: error,
+                        }
+                        .into()
+                    })
+                }
+            }
+        }
+    }
+
+    /// How long to wait before retrying a `method` request after `attempts` attempts ended in
+    /// `result`, None if it shouldn't be retried.
+    fn retry_delay(
+        &self,
+        method: &str,
+        attempts: u32,
+        result: &Result<Response, ureq::Error>,
+    ) -> Option<Duration> {
+        let idempotent = !matches!(method, "POST" | "PATCH");
+        let retry_after = match result {
+            Ok(_) => return None,
+            Err(ureq::Error::Status(status, response))
+                if *status == 429 || (*status >= 500 && idempotent) =>
+            {
+                header_seconds(response, "Retry-After")
+                    .or_else(|| header_seconds(response, "X-RateLimit-Reset-After"))
+            }
+            Err(ureq::Error::Status(..)) => return None,
+            Err(ureq::Error::Transport(transport)) => {
+                // The request never reached the server
+                let unsent = matches!(
+                    transport.kind(),
+                    ErrorKind::Dns | ErrorKind::ConnectionFailed | ErrorKind::ProxyConnect
+                );
+                if !idempotent && !unsent {
+                    return None;
+                }
+                None
+            }
+        };
+
+        let backoff = self
+            .retry
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempts - 1))
+            .min(self.retry.max_delay);
+        // Spreads out retries of clients that failed together
+        let backoff = backoff.mul_f64(1.0 - self.rng.next_f64() / 2.0);
+
+        Some(retry_after.map_or(backoff, |retry_after| {
+            retry_after.max(backoff).min(self.retry.max_delay)
+        }))
+    }
+}
+
+/// A header holding a number of seconds, which Discord sends with a fractional part.
+fn header_seconds(response: &Response, name: &str) -> Option<Duration> {
+    let seconds: f64 = response.header(name)?.trim().parse().ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Global outbound rate limiter keeping one token bucket per host.
+/// Every notifier shares it through the [HttpClient], so fan-out to many destinations or
+/// chunked messages can't exceed what a single host tolerates.
+/// Hosts that report their own limit as used up, e.g. Discord with `X-RateLimit-Remaining: 0`,
+/// are paused until the reset they announce, whether or not rate limiting is enabled.
+struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    paused_until: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+            paused_until: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Holds back requests to `host` until `until`.
+    fn pause(&self, host: &str, until: Instant) {
+        let mut paused_until = self
+            .paused_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let paused = paused_until.entry(host.to_owned()).or_insert(until);
+        *paused = (*paused).max(until);
+    }
+
+    /// Takes a token from the host's bucket, sleeping until one is available and the host isn't
+    /// paused.
+    fn acquire(&self, host: &str) {
+        let paused_until = self
+            .paused_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(host);
+        if let Some(wait) =
+            paused_until.and_then(|until| until.checked_duration_since(Instant::now()))
+        {
+            debug!("{host} asked to wait, waiting {wait:?}");
+            thread::sleep(wait);
+        }
+
+        if self.limit.per_second <= 0.0 {
+            return;
+        }
+
+        let wait = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let bucket = buckets.entry(host.to_owned()).or_insert(TokenBucket {
+                tokens: self.limit.burst,
+                last_refill: now,
+            });
+
+            let refill =
+                now.duration_since(bucket.last_refill).as_secs_f64() * self.limit.per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.limit.burst);
+            bucket.last_refill = now;
+
+            // Going negative reserves a future token, so concurrent callers queue up fairly.
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(-bucket.tokens / self.limit.per_second)
+            }
+        };
+
+        if !wait.is_zero() {
+            debug!("Rate limit reached for {host}, waiting {wait:?}");
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// A single outbound request as recorded in the `http_audit` table.
+struct HttpAuditEntry<'a> {
+    request_timestamp: u64,
+    method: &'a str,
+    /// URL with secrets redacted.
+    url: &'a str,
+    /// HTTP status, if a response was received at all.
+    status: Option<u16>,
+    /// Transport error, if the request never got a response.
+    error: Option<&'a str>,
+    duration: Duration,
+    /// Number of retries before this request, 0 for the first attempt.
+    retry_count: u32,
+}
+
+fn insert_http_audit_entry(conn: &Connection, entry: &HttpAuditEntry) -> eyre::Result<()> {
+    const INSERT_AUDIT_SQL: &str = "
+    INSERT INTO http_audit
+    (request_timestamp, method, url, status, error, duration_ms, retry_count) VALUES
+    (:request_timestamp, :method, :url, :status, :error, :duration_ms, :retry_count)";
+
+    conn.prepare_cached(INSERT_AUDIT_SQL)?
+        .execute(named_params! {
+            ":request_timestamp": entry.request_timestamp,
+            ":method": entry.method,
+            ":url": entry.url,
+            ":status": entry.status,
+            ":error": entry.error,
+            ":duration_ms": entry.duration.as_millis() as u64,
+            ":retry_count": entry.retry_count,
+        })?;
+
+    Ok(())
+}
+
+/// Redacts credentials, query values, and token-like path segments from a URL so it can be logged.
+pub fn redact_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return REDACTED.to_owned();
+    };
+
+    if !parsed.username().is_empty() {
+        let _ = parsed.set_username(REDACTED);
+    }
+    if parsed.password().is_some() {
+        let _ = parsed.set_password(Some(REDACTED));
+    }
+
+    let query_keys: Vec<String> = parsed.query_pairs().map(|(k, _)| k.into_owned()).collect();
+    if !query_keys.is_empty() {
+        parsed
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(query_keys.iter().map(|k| (k, REDACTED)));
+    }
+
+    let segments: Option<Vec<String>> = parsed.path_segments().map(|segments| {
+        segments
+            .map(|segment| {
+                if segment.len() >= SECRET_SEGMENT_MIN_LEN && !segment.contains('.') {
+                    REDACTED.to_owned()
+                } else {
+                    segment.to_owned()
+                }
+            })
+            .collect()
+    });
+    if let Some(segments) = segments {
+        if let Ok(mut path) = parsed.path_segments_mut() {
+            path.clear().extend(segments);
+        }
+    }
+
+    parsed.into()
+}
@@ -0,0 +1,209 @@
+//! Validates JSON against the subset of JSON Schema that hygieia's own schemas are written in:
+//! `type`, `enum`, `properties`, `required`, `additionalProperties`, `items`, `minItems`,
+//! `minLength`, and `anyOf`. Other keywords, such as `description`, are ignored.
+//!
+//! Errors point at the offending value with a JSON Pointer, e.g. `/0/countys`, and unknown
+//! properties suggest the closest known one, so typos are caught rather than silently ignored.
+
+use std::fmt;
+
+use serde_json::Value;
+
+/// A value that doesn't match the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    /// JSON Pointer to the value, empty for the whole document.
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{pointer}: {}", self.message)
+    }
+}
+
+/// Every way `instance` doesn't match `schema`, ordered by where it is.
+pub fn validate(schema: &Value, instance: &Value) -> Vec<SchemaError> {
+    let mut errors = Vec::new();
+    validate_at(schema, instance, "", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, instance: &Value, pointer: &str, errors: &mut Vec<SchemaError>) {
+    let mut error = |message: String| {
+        errors.push(SchemaError {
+            pointer: pointer.to_owned(),
+            message,
+        })
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.iter().any(|name| has_type(instance, name)) {
+            error(format!(
+                "expected {}, got {}",
+                types.join(" or "),
+                type_name(instance)
+            ));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(instance) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            error(format!(
+                "expected one of {}, got {instance}",
+                allowed.join(", ")
+            ));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(branches)) = schema.get("anyOf") {
+        let results: Vec<(&Value, Vec<SchemaError>)> = branches
+            .iter()
+            .map(|branch| (branch, validate(branch, instance)))
+            .collect();
+        if results
+            .iter()
+            .any(|(_, branch_errors)| branch_errors.is_empty())
+        {
+            return;
+        }
+        // When only one branch takes this type of value, its errors say what's wrong inside it
+        let mut typed = results.into_iter().filter(|(branch, _)| {
+            branch
+                .get("type")
+                .and_then(Value::as_str)
+                .is_none_or(|name| has_type(instance, name))
+        });
+        match (typed.next(), typed.next()) {
+            (Some((_, branch_errors)), None) => {
+                for branch_error in branch_errors {
+                    errors.push(SchemaError {
+                        pointer: format!("{pointer}{}", branch_error.pointer),
+                        message: branch_error.message,
+                    });
+                }
+            }
+            _ => error(format!("{instance} isn't any of the accepted forms")),
+        }
+        return;
+    }
+
+    match instance {
+        Value::String(string) => {
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if (string.chars().count() as u64) < min {
+                    error(format!("must be at least {min} characters"));
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    error(format!("must have at least {min} items"));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{pointer}/{i}"), errors);
+                }
+            }
+        }
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        error(format!("missing required property {name}"));
+                    }
+                }
+            }
+            for (name, value) in object {
+                let pointer = format!("{pointer}/{}", escape_pointer(name));
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property_schema) => validate_at(property_schema, value, &pointer, errors),
+                    None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                        let known = properties
+                            .into_iter()
+                            .flat_map(|properties| properties.keys());
+                        let message = match closest(name, known) {
+                            Some(suggestion) => {
+                                format!("unknown property {name}, did you mean {suggestion}?")
+                            }
+                            None => format!("unknown property {name}"),
+                        };
+                        errors.push(SchemaError { pointer, message });
+                    }
+                    None => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_type(instance: &Value, name: &str) -> bool {
+    match name {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "integer" => instance.is_i64() || instance.is_u64(),
+        "number" => instance.is_number(),
+        "string" => instance.is_string(),
+        "array" => instance.is_array(),
+        "object" => instance.is_object(),
+        _ => false,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Escapes a property name as a JSON Pointer segment.
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+/// The known name closest to `name`, if it's close enough to be a typo of it.
+fn closest<'a>(name: &str, known: impl Iterator<Item = &'a String>) -> Option<&'a str> {
+    known
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2.max(candidate.len() / 4))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
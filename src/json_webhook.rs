@@ -0,0 +1,160 @@
+//! Posts reports as JSON to an arbitrary endpoint, for consuming them from other services, e.g.
+//!
+//! ```json
+//! {
+//!     "tenant": "default",
+//!     "period": "2024-12-01",
+//!     "since": null,
+//!     "until": "2024-12-01",
+//!     "lines": [{ "county": "Pierce", "pathogen": "RSV", "latest_value": 1234.5, ... }],
+//!     "notices": ["..."],
+//!     "content": "..."
+//! }
+//! ```
+//!
+//! with the report's markdown as `content`. The data is rendered with the rest of the report and
+//! stored with it, so reports stored before it was rendered only carry `tenant`, `period`, and
+//! `content`. When a secret is set the body is signed with HMAC-SHA256 in [SIGNATURE_HEADER], with
+//! the time it was signed at in [TIMESTAMP_HEADER], which receivers can check with
+//! [crate::verify_signature].
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde_json::{json, Map, Value};
+use tracing::{info, instrument};
+
+use crate::context::RunContext;
+use crate::history::Extreme;
+use crate::http::{Body, HttpClient};
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::report::Report;
+use crate::signature::{self, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::tenants::Tenant;
+use crate::useful::{Clock, Secret};
+
+/// Posts reports as JSON to an arbitrary endpoint.
+pub struct JsonWebhook {
+    url: String,
+    /// Shared secret the payload is signed with, if any.
+    secret: Option<Secret>,
+}
+
+impl JsonWebhook {
+    pub fn new(url: String, secret: Option<Secret>) -> Self {
+        Self { url, secret }
+    }
+
+    /// Posts `payload`, signing the body at `clock`'s time when a secret is set.
+    #[instrument(skip_all)]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        clock: &dyn Clock,
+        payload: &Value,
+    ) -> eyre::Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+
+        let mut request = http.post(&self.url).set("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            let timestamp = clock.unix_timestamp();
+            request = request.set(TIMESTAMP_HEADER, &timestamp.to_string()).set(
+                SIGNATURE_HEADER,
+                &signature::sign(&payload, timestamp, secret.expose()),
+            );
+        }
+
+        http.send(conn, request, Body::Bytes(&payload))?;
+        info!("Posted report to JSON webhook");
+
+        Ok(())
+    }
+}
+
+/// Posts to the tenant's JSON webhook, if it has one, signed with the tenant's secret.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonWebhookNotifier;
+
+impl Notifier for JsonWebhookNotifier {
+    fn name(&self) -> &str {
+        "json-webhook"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(url) = &tenant.json_webhook_url else {
+            return Ok(());
+        };
+
+        let mut payload = json!({
+            "tenant": report.tenant,
+            "period": report.period,
+        });
+        if let Some(data) = &report.report_data {
+            let data: Map<String, Value> = serde_json::from_str(data)?;
+            payload
+                .as_object_mut()
+                .expect("the payload is an object")
+                .extend(data);
+        }
+        payload["content"] = json!(report.markdown);
+
+        JsonWebhook::new(url.clone(), tenant.json_webhook_secret.clone()).send(
+            &ctx.db,
+            &ctx.http,
+            ctx.clock.as_ref(),
+            &payload,
+        )
+    }
+}
+
+/// The report's date range, a line per county and pathogen with its latest values, change, trend,
+/// and activity, and its notices.
+pub fn report_data(report: &Report) -> Map<String, Value> {
+    let lines: Vec<_> = report
+        .lines
+        .iter()
+        .map(|line| {
+            let summary = line.summary.as_ref();
+            json!({
+                "county": line.county,
+                "pathogen": line.pathogen,
+                "latest_value": summary.map(|s| s.latest_value),
+                "latest_date": summary.map(|s| s.latest_date),
+                "previous_date": summary.and_then(|s| s.previous_date),
+                "difference": summary.and_then(|s| s.difference),
+                "relative_change": summary.and_then(|s| s.relative_change),
+                "average_7_day": line.averages.map(|averages| averages.short),
+                "average_14_day": line.averages.map(|averages| averages.long),
+                "average_14_day_change": line.averages.map(|averages| averages.latest_change),
+                "trend": line.trend.as_ref().map(|trend| trend.label()),
+                "trend_direction": line.trend.as_ref().map(|trend| trend.direction),
+                "trend_confidence": line.trend.as_ref().map(|trend| trend.confidence),
+                "percentile_past_year": line.history.as_ref().map(|history| history.percentile),
+                "highest_since": line.history.as_ref().filter(|history| history.extreme == Some(Extreme::Highest)).and_then(|history| history.since),
+                "lowest_since": line.history.as_ref().filter(|history| history.extreme == Some(Extreme::Lowest)).and_then(|history| history.since),
+                "weekly_change": line.trend.as_ref().map(|trend| trend.weekly_change),
+                "doubling_days": line.trend.as_ref().and_then(|trend| trend.doubling_days()),
+                "activity_level": line.activity.as_ref().map(|activity| activity.level.to_string()),
+            })
+        })
+        .collect();
+
+    let notices = &report.notices;
+    let notices: Vec<String> = notices
+        .threshold_alerts
+        .iter()
+        .map(ToString::to_string)
+        .chain(notices.coverage_changes.iter().map(ToString::to_string))
+        .chain(notices.season_onsets.iter().map(ToString::to_string))
+        .chain(notices.revisions.iter().map(ToString::to_string))
+        .chain(notices.vintage.iter().map(ToString::to_string))
+        .collect();
+
+    let mut data = Map::new();
+    data.insert("since".to_owned(), json!(report.range.since));
+    data.insert("until".to_owned(), json!(report.range.until));
+    data.insert("lines".to_owned(), json!(lines));
+    data.insert("notices".to_owned(), json!(notices));
+    data
+}
@@ -0,0 +1,442 @@
+//! Activity levels modeled on the CDC National Wastewater Surveillance System (NWSS) categories.
+//!
+//! A site's activity is how many standard deviations its latest log concentration is above its
+//! baseline, the 10th percentile of its log concentrations over the last [BASELINE_DAYS] days. A
+//! county's activity is the mean of its sites', and is put in a category by a pathogen's cutoffs.
+//!
+//! Cutoffs come from a versioned preset in [PRESETS], enabled per pathogen with a line like
+//! `ACTIVITY_LEVELS="sars-cov-2=cdc-nwss;FLUAV=cdc-nwss@1"`, or are given directly to override the
+//! preset, e.g. `RSV=2,3,5,8`. A preset without a version uses its newest one. Changing a preset's
+//! cutoffs adds a version, so configurations pinned to an old version keep their levels.
+//!
+//! Baselines can be precomputed into `site_baselines` by `hygieia db analyze`, so reports only read
+//! the trend window's samples rather than a year of them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Days, NaiveDate};
+use color_eyre::eyre;
+use rusqlite::{named_params, params, Connection};
+use serde::Serialize;
+
+use crate::analysis::{AnalysisOptions, TREND_WINDOW_DAYS};
+use crate::numeric;
+use crate::useful::Clock;
+
+/// Days of history a site's baseline is computed from.
+pub const BASELINE_DAYS: u64 = 365;
+/// Fewest samples a site's baseline is computed from.
+pub const MIN_BASELINE_SAMPLES: usize = 10;
+/// Percentile of a site's log concentrations used as its baseline.
+const BASELINE_PERCENTILE: f64 = 0.10;
+/// Days a precomputed baseline is used for after the sample it was computed through. A year of
+/// history barely moves in a week, and `hygieia db analyze` runs weekly in daemon mode.
+pub const PRECOMPUTED_BASELINE_MAX_DAYS: u64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ActivityLevel {
+    VeryLow,
+    Low,
+    Moderate,
+    High,
+    VeryHigh,
+}
+
+impl fmt::Display for ActivityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ActivityLevel::VeryLow => "Very Low",
+            ActivityLevel::Low => "Low",
+            ActivityLevel::Moderate => "Moderate",
+            ActivityLevel::High => "High",
+            ActivityLevel::VeryHigh => "Very High",
+        })
+    }
+}
+
+/// A named, versioned set of cutoffs.
+#[derive(Debug, Clone, Copy)]
+pub struct LevelPreset {
+    pub name: &'static str,
+    pub version: u32,
+    /// Lowest activity of Low, Moderate, High, and Very High.
+    pub cutoffs: [f64; 4],
+}
+
+/// Presets shipped with hygieia, oldest version first.
+pub const PRESETS: &[LevelPreset] = &[
+    // The NWSS wastewater viral activity level categories published for COVID-19 in 2024
+    LevelPreset {
+        name: "cdc-nwss",
+        version: 1,
+        cutoffs: [2.0, 3.4, 5.3, 7.8],
+    },
+];
+
+/// Cutoffs for one pathogen, and which preset they came from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelThresholds {
+    /// Preset and version, e.g. "cdc-nwss v1", or "custom" for directly given cutoffs.
+    pub source: String,
+    pub cutoffs: [f64; 4],
+}
+
+impl LevelThresholds {
+    pub fn level(&self, activity: f64) -> ActivityLevel {
+        match self
+            .cutoffs
+            .iter()
+            .filter(|&&cutoff| activity >= cutoff)
+            .count()
+        {
+            0 => ActivityLevel::VeryLow,
+            1 => ActivityLevel::Low,
+            2 => ActivityLevel::Moderate,
+            3 => ActivityLevel::High,
+            _ => ActivityLevel::VeryHigh,
+        }
+    }
+}
+
+impl FromStr for LevelThresholds {
+    type Err = String;
+
+    /// Parses a preset name with an optional version (`cdc-nwss`, `cdc-nwss@1`), or four ascending
+    /// cutoffs separated by commas.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            let cutoffs: Vec<f64> = s
+                .split(',')
+                .map(|cutoff| cutoff.trim().parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("Invalid cutoff in {s}: {e}"))?;
+            let cutoffs: [f64; 4] = cutoffs
+                .try_into()
+                .map_err(|_| format!("Expected 4 cutoffs, got {s}"))?;
+            if !cutoffs.windows(2).all(|pair| pair[0] < pair[1]) {
+                return Err(format!("Cutoffs must be ascending, got {s}"));
+            }
+            return Ok(Self {
+                source: "custom".to_owned(),
+                cutoffs,
+            });
+        }
+
+        let (name, version) = match s.split_once('@') {
+            Some((name, version)) => (
+                name,
+                Some(
+                    version
+                        .parse::<u32>()
+                        .map_err(|_| format!("Invalid preset version in {s}"))?,
+                ),
+            ),
+            None => (s, None),
+        };
+        let preset = PRESETS
+            .iter()
+            .rfind(|preset| {
+                preset.name == name && version.is_none_or(|version| preset.version == version)
+            })
+            .ok_or_else(|| format!("Unknown activity level preset {s}"))?;
+
+        Ok(Self {
+            source: format!("{} v{}", preset.name, preset.version),
+            cutoffs: preset.cutoffs,
+        })
+    }
+}
+
+/// Cutoffs for each pathogen with activity levels enabled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ActivityLevelConfig {
+    pub pathogens: HashMap<String, LevelThresholds>,
+}
+
+impl FromStr for ActivityLevelConfig {
+    type Err = String;
+
+    /// Parses `pathogen=thresholds` entries separated by semicolons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pathogens = s
+            .split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pathogen, thresholds) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected pathogen=preset, got {entry}"))?;
+                Ok((pathogen.trim().to_owned(), thresholds.parse()?))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self { pathogens })
+    }
+}
+
+/// A county's activity for a pathogen and the level it falls in.
+#[derive(Debug, Clone)]
+pub struct Activity {
+    /// Mean of the sites' standard deviations above baseline.
+    pub value: f64,
+    pub level: ActivityLevel,
+    /// Where the cutoffs came from, e.g. "cdc-nwss v1".
+    pub source: String,
+}
+
+/// A site's baseline log concentration and the standard deviation of its log concentrations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SiteBaseline {
+    pub baseline: f64,
+    pub standard_deviation: f64,
+}
+
+impl SiteBaseline {
+    /// The baseline of a site's log concentrations over [BASELINE_DAYS]. None with fewer than
+    /// [MIN_BASELINE_SAMPLES] of them or no variation, when activity can't be measured.
+    pub fn from_values(mut values: Vec<f64>) -> Option<Self> {
+        if values.len() < MIN_BASELINE_SAMPLES {
+            return None;
+        }
+
+        let mean = numeric::mean(&values);
+        let variance = numeric::sum(values.iter().map(|value| (value - mean).powi(2)))
+            / (values.len() - 1) as f64;
+        let standard_deviation = variance.sqrt();
+        if standard_deviation == 0.0 {
+            return None;
+        }
+
+        values.sort_unstable_by(f64::total_cmp);
+        let baseline = values[((values.len() - 1) as f64 * BASELINE_PERCENTILE) as usize];
+        Some(Self {
+            baseline,
+            standard_deviation,
+        })
+    }
+
+    /// Standard deviations `value` is above the baseline.
+    pub fn activity(&self, value: f64) -> f64 {
+        (value - self.baseline) / self.standard_deviation
+    }
+}
+
+/// Computes the activity of `pathogen` in `county` from each site's latest sample in the trend
+/// window ending at `latest_date`. None if no site has enough history for a baseline.
+///
+/// Baselines precomputed by [refresh_site_baselines] up to [PRECOMPUTED_BASELINE_MAX_DAYS] before
+/// `latest_date` are used when every site in the window has one, instead of a year of samples.
+pub fn county_activity(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    latest_date: NaiveDate,
+    thresholds: &LevelThresholds,
+    options: &AnalysisOptions,
+) -> rusqlite::Result<Option<Activity>> {
+    let site_values = match precomputed_site_activity(conn, county, pathogen, latest_date, options)?
+    {
+        Some(site_values) => site_values,
+        None => site_activity(conn, county, pathogen, latest_date, options)?,
+    };
+
+    if site_values.is_empty() {
+        return Ok(None);
+    }
+    let value = numeric::mean(&site_values);
+
+    Ok(Some(Activity {
+        value,
+        level: thresholds.level(value),
+        source: thresholds.source.clone(),
+    }))
+}
+
+/// Each site's activity, from its samples over the [BASELINE_DAYS] up to `latest_date`.
+fn site_activity(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    latest_date: NaiveDate,
+    options: &AnalysisOptions,
+) -> rusqlite::Result<Vec<f64>> {
+    let window_start = latest_date - Days::new(TREND_WINDOW_DAYS as u64);
+
+    Ok(
+        select_site_values(conn, county, pathogen, latest_date, options)?
+            .into_values()
+            .filter_map(|samples| {
+                let &(latest_date, latest) = samples.last()?;
+                if latest_date <= window_start {
+                    return None;
+                }
+
+                let values = samples.iter().map(|&(_, value)| value).collect();
+                Some(SiteBaseline::from_values(values)?.activity(latest))
+            })
+            .collect(),
+    )
+}
+
+/// Each site's activity from its precomputed baseline, or None if a site in the trend window has
+/// none recent enough.
+fn precomputed_site_activity(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    latest_date: NaiveDate,
+    options: &AnalysisOptions,
+) -> rusqlite::Result<Option<Vec<f64>>> {
+    let select_precomputed_sql = format!(
+        "
+    WITH samples AS (
+        SELECT county, pcr_pathogen_target, site_name, sample_collection_date, {measure} as value
+        FROM wastewater_samples
+        WHERE county = ?1 AND pcr_pathogen_target = ?2
+            AND sample_collection_date > ?3 AND sample_collection_date <= ?4
+            AND {measure} IS NOT NULL
+    )
+    SELECT s.site_name, s.value, b.baseline, b.standard_deviation, b.through_date
+    FROM samples s
+    LEFT JOIN site_baselines b
+        ON b.county = s.county AND b.pcr_pathogen_target = s.pcr_pathogen_target
+        AND b.site_name = s.site_name AND b.concentration_floor = ?5 AND b.measure = ?7
+        AND b.through_date <= ?4 AND b.through_date >= ?6
+    ORDER BY s.site_name, s.sample_collection_date",
+        measure = options.measure.column()
+    );
+
+    let window_start = latest_date - Days::new(TREND_WINDOW_DAYS as u64);
+    let oldest_baseline = latest_date - Days::new(PRECOMPUTED_BASELINE_MAX_DAYS);
+
+    // Each site's latest sample, and its baseline. A baseline row without values means the site
+    // had too little history, as opposed to no row, which means it wasn't precomputed.
+    let mut latest: HashMap<String, (f64, Option<Option<SiteBaseline>>)> = HashMap::new();
+    let mut stmt = conn.prepare_cached(&select_precomputed_sql)?;
+    let mut rows = stmt.query(params![
+        county,
+        pathogen,
+        window_start,
+        latest_date,
+        options.concentration_floor,
+        oldest_baseline,
+        options.measure.to_string()
+    ])?;
+    while let Some(row) = rows.next()? {
+        let concentration: f64 = row.get(1)?;
+        let through_date: Option<NaiveDate> = row.get(4)?;
+        let baseline: Option<f64> = row.get(2)?;
+        let standard_deviation: Option<f64> = row.get(3)?;
+        let baseline = through_date.map(|_| {
+            baseline
+                .zip(standard_deviation)
+                .map(|(baseline, standard_deviation)| SiteBaseline {
+                    baseline,
+                    standard_deviation,
+                })
+        });
+        latest.insert(
+            row.get(0)?,
+            (options.log_concentration(concentration), baseline),
+        );
+    }
+
+    Ok(latest
+        .into_values()
+        .map(|(value, baseline)| Some(baseline?.map(|baseline| baseline.activity(value))))
+        .collect::<Option<Vec<_>>>()
+        .map(|values| values.into_iter().flatten().collect()))
+}
+
+/// Each site's samples of `pathogen` in `county` over the [BASELINE_DAYS] up to `latest_date`, as
+/// dates and log concentrations in order.
+fn select_site_values(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    latest_date: NaiveDate,
+    options: &AnalysisOptions,
+) -> rusqlite::Result<HashMap<String, Vec<(NaiveDate, f64)>>> {
+    let select_baseline_samples_sql = format!(
+        "
+    SELECT site_name, sample_collection_date, {measure} FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+        AND sample_collection_date > ?3 AND sample_collection_date <= ?4
+        AND {measure} IS NOT NULL
+    ORDER BY site_name, sample_collection_date",
+        measure = options.measure.column()
+    );
+
+    let baseline_start = latest_date - Days::new(BASELINE_DAYS);
+
+    let mut site_samples: HashMap<String, Vec<(NaiveDate, f64)>> = HashMap::new();
+    let mut stmt = conn.prepare_cached(&select_baseline_samples_sql)?;
+    let mut rows = stmt.query(params![county, pathogen, baseline_start, latest_date])?;
+    while let Some(row) = rows.next()? {
+        let site: String = row.get(0)?;
+        let concentration: f64 = row.get(2)?;
+        site_samples
+            .entry(site)
+            .or_default()
+            .push((row.get(1)?, options.log_concentration(concentration)));
+    }
+    Ok(site_samples)
+}
+
+/// Replaces every site's precomputed baseline with one over the [BASELINE_DAYS] up to its county's
+/// and pathogen's latest sample, returning how many sites have one. Sites with too little history
+/// are stored without values, so reports know there's nothing to compute.
+pub fn refresh_site_baselines(
+    conn: &mut Connection,
+    clock: &dyn Clock,
+    options: &AnalysisOptions,
+) -> eyre::Result<usize> {
+    let select_series_sql = format!(
+        "
+    SELECT county, pcr_pathogen_target, MAX(sample_collection_date) FROM wastewater_samples
+    WHERE {} IS NOT NULL
+    GROUP BY county, pcr_pathogen_target",
+        options.measure.column()
+    );
+
+    const INSERT_BASELINE_SQL: &str = "
+    INSERT INTO site_baselines
+    (county, pcr_pathogen_target, site_name, through_date, concentration_floor, measure, samples, baseline, standard_deviation, computed_timestamp) VALUES
+    (:county, :pcr_pathogen_target, :site_name, :through_date, :concentration_floor, :measure, :samples, :baseline, :standard_deviation, :computed_timestamp)";
+
+    let series: Vec<(String, String, NaiveDate)> = conn
+        .prepare(&select_series_sql)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM site_baselines", [])?;
+    let mut sites = 0;
+    for (county, pathogen, through_date) in &series {
+        for (site, samples) in select_site_values(&tx, county, pathogen, *through_date, options)? {
+            let count = samples.len();
+            let baseline =
+                SiteBaseline::from_values(samples.into_iter().map(|(_, value)| value).collect());
+            tx.prepare_cached(INSERT_BASELINE_SQL)?
+                .execute(named_params! {
+                    ":county": county,
+                    ":pcr_pathogen_target": pathogen,
+                    ":site_name": site,
+                    ":through_date": through_date,
+                    ":concentration_floor": options.concentration_floor,
+                    ":measure": options.measure.to_string(),
+                    ":samples": count,
+                    ":baseline": baseline.map(|baseline| baseline.baseline),
+                    ":standard_deviation": baseline.map(|baseline| baseline.standard_deviation),
+                    ":computed_timestamp": clock.unix_timestamp(),
+                })?;
+            sites += usize::from(baseline.is_some());
+        }
+    }
+    tx.commit()?;
+
+    Ok(sites)
+}
@@ -0,0 +1,59 @@
+//! hygieia polls Washington State wastewater data into SQLite and reports on it.
+//!
+//! The binary is a thin wrapper over [Pipeline], which services can embed to run the same
+//! ingestion and reporting with their own connection and [pipeline::Notifier].
+
+pub mod alerts;
+pub mod analysis;
+pub mod badge;
+pub mod canary;
+pub mod check;
+pub mod conditional;
+pub mod context;
+pub mod coverage;
+pub mod csv_data;
+pub mod daemon;
+pub mod db;
+pub mod diff;
+pub mod discord;
+pub mod download;
+pub mod duckdb;
+pub mod email;
+pub mod export;
+pub mod history;
+pub mod http;
+pub mod json_schema;
+pub mod json_webhook;
+pub mod levels;
+pub mod links;
+pub mod maintenance;
+pub mod mastodon;
+pub mod matrix;
+pub mod ntfy;
+pub mod numeric;
+pub mod pending;
+pub mod pipeline;
+pub mod poll_runs;
+pub mod precision;
+pub mod preview;
+pub mod pushover;
+pub mod report;
+pub mod retrospective;
+pub mod rules;
+pub mod season;
+pub mod signature;
+pub mod site;
+pub mod sites;
+pub mod slack;
+pub mod slugs;
+pub mod socrata;
+pub mod stats;
+pub mod subscribers;
+pub mod synthetic;
+pub mod teams;
+pub mod telegram;
+pub mod tenants;
+pub mod useful;
+
+pub use pipeline::Pipeline;
+pub use signature::verify_signature;
@@ -0,0 +1,66 @@
+use color_eyre::eyre::{self, eyre};
+use url::Url;
+
+/// Builds links into the dashboard, so every notification links to the routes the [crate::site]
+/// writes.
+#[derive(Debug, Clone)]
+pub struct DashboardLinks {
+    base: Url,
+}
+
+impl DashboardLinks {
+    pub fn new(base_url: &str) -> eyre::Result<Self> {
+        let base = Url::parse(base_url)?;
+        if base.cannot_be_a_base() {
+            return Err(eyre!("{base_url} cannot be used as a base URL"));
+        }
+
+        Ok(Self { base })
+    }
+
+    /// Link to the chart for a county, by its [crate::slugs] slug, and pathogen: the pathogen's
+    /// section of the county's page, e.g. `https://myhost/counties/king.html#sars-cov-2`.
+    pub fn chart(&self, county_slug: &str, pathogen: &str) -> String {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("checked in DashboardLinks::new")
+            .pop_if_empty()
+            .extend(county_path(county_slug).split('/'));
+        url.set_fragment(Some(&pathogen_anchor(pathogen)));
+        url.into()
+    }
+}
+
+/// Path of a county's page, relative to the site's root.
+pub fn county_path(county_slug: &str) -> String {
+    format!("counties/{county_slug}.html")
+}
+
+/// Id of a pathogen's section on its county's page.
+pub fn pathogen_anchor(pathogen: &str) -> String {
+    slug(pathogen)
+}
+
+/// Path of a county's embeddable widget.
+pub fn embed_path(county_slug: &str) -> String {
+    format!("embed/{county_slug}.html")
+}
+
+/// Path of a county's link preview image.
+pub fn preview_path(county_slug: &str) -> String {
+    format!("previews/{county_slug}.png")
+}
+
+/// Path of a pathogen's [crate::badge] in a county.
+pub fn badge_path(county_slug: &str, pathogen: &str) -> String {
+    format!("badge/{county_slug}/{}.svg", slug(pathogen))
+}
+
+/// Lowercases and joins words with dashes, e.g. "Grays Harbor" becomes "grays-harbor".
+pub fn slug(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -1,27 +1,97 @@
-mod csv_data;
-mod db;
-mod useful;
+mod cli;
+mod resolved;
 
+use std::collections::HashSet;
 use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use color_eyre::eyre::{self, Context};
-use rusqlite::{params, Connection};
-use tracing::{debug, info, instrument, warn};
+use clap::{CommandFactory, FromArgMatches};
+use cli::{
+    AlertsCommand, Cli, Command, ConfigCommand, DaemonArgs, DbCommand, SiteCommand, SitesCommand,
+    SubscribersCommand,
+};
+use color_eyre::eyre::{self, eyre, Context};
+use hygieia::analysis::{self, AnalysisOptions, ChangeScale, Measure};
+use hygieia::canary::{CanaryPolicy, DEFAULT_CANARY_RUNS};
+use hygieia::context::{Config, RunContext, Selection};
+use hygieia::coverage::DEFAULT_MAX_MISSED_SAMPLES;
+use hygieia::daemon::ExportTask;
+use hygieia::db::DEFAULT_INSERT_CHUNK_SAMPLES;
+use hygieia::discord::DiscordWebhookOptions;
+use hygieia::email::{SmtpConfig, SmtpSecurity};
+use hygieia::http::{HttpConfig, RateLimit, RetryPolicy};
+use hygieia::levels::ActivityLevelConfig;
+use hygieia::links::DashboardLinks;
+use hygieia::mastodon::{MastodonAccount, DEFAULT_VISIBILITY};
+use hygieia::matrix::MatrixRoom;
+use hygieia::ntfy::NtfyTopic;
+use hygieia::pipeline::{ConfiguredNotifier, Pipeline, DEFAULT_COUNTIES, DEFAULT_PATHOGENS};
+use hygieia::precision::{OutputPrecision, Precision};
+use hygieia::pushover::{AlertThresholds, PushoverUser, DEFAULT_PUSHOVER_API_URL};
+use hygieia::rules::{AlertRules, NotifyMode};
+use hygieia::season::OnsetRule;
+use hygieia::sites::CsvSiteSource;
+use hygieia::synthetic::SyntheticOptions;
+use hygieia::telegram::{TelegramChat, DEFAULT_TELEGRAM_API_URL};
+use hygieia::tenants::{self, Tenant};
+use hygieia::useful::{self, Clock, FixedClock, Secret, SystemClock};
+use hygieia::{
+    alerts, check, daemon, db, diff, duckdb, export, maintenance, retrospective, site, sites,
+    stats, subscribers,
+};
+use tracing::{debug, info, info_span, instrument};
 
 static ENVVAR_WASTEWATER_URL: &str = "URL_WAGOV_WASTEWATER";
 static DEFAULT_WASTEWATER_URL: &str =
     "https://doh.wa.gov/sites/default/files/Data/Downloadable_Wastewater.csv";
 
+/// Loads the URL samples are fetched from. A `synthetic:` URL generates them instead, and is checked
+/// here so a typo in its parameters fails before anything runs.
 fn get_wastewater_url() -> eyre::Result<String> {
     let wastewater_url = useful::env_or_else(ENVVAR_WASTEWATER_URL, || {
         info!("{ENVVAR_WASTEWATER_URL} not set, using default: {DEFAULT_WASTEWATER_URL}");
         DEFAULT_WASTEWATER_URL.to_string()
     })
     .with_context(|| format!("Error getting {ENVVAR_WASTEWATER_URL}"))?;
+    SyntheticOptions::from_wastewater_url(&wastewater_url)
+        .with_context(|| format!("Error getting {ENVVAR_WASTEWATER_URL}"))?;
 
     Ok(wastewater_url)
 }
 
+static ENVVAR_REPORT_COUNTIES: &str = "REPORT_COUNTIES";
+static ENVVAR_REPORT_PATHOGENS: &str = "REPORT_PATHOGENS";
+
+/// Loads the counties and pathogen targets reported on, each separated by commas, or "all" for every
+/// one with stored samples. Defaults to Pierce and King, and FLUAV, FLUBV, RSV, and sars-cov-2.
+fn get_report_selection() -> eyre::Result<(Selection, Selection)> {
+    let counties = useful::env_or_else(ENVVAR_REPORT_COUNTIES, || {
+        Selection::only(&DEFAULT_COUNTIES)
+    })
+    .with_context(|| format!("Error getting {ENVVAR_REPORT_COUNTIES}"))?;
+    let pathogens = useful::env_or_else(ENVVAR_REPORT_PATHOGENS, || {
+        Selection::only(&DEFAULT_PATHOGENS)
+    })
+    .with_context(|| format!("Error getting {ENVVAR_REPORT_PATHOGENS}"))?;
+
+    if counties.is_empty() {
+        return Err(eyre!(
+            "{ENVVAR_REPORT_COUNTIES} must name at least one county"
+        ));
+    }
+    if pathogens.is_empty() {
+        return Err(eyre!(
+            "{ENVVAR_REPORT_PATHOGENS} must name at least one pathogen"
+        ));
+    }
+    Ok((counties, pathogens))
+}
+
 static ENVVAR_SQLITE_DB_PATH: &str = "PATH_SQLITE_DB";
 static DEFAULT_SQLITE_DB_PATH: &str = "wastewater.sqlite";
 
@@ -35,131 +105,966 @@ fn get_sqlite_db_path() -> eyre::Result<String> {
     Ok(sqlite_db_path)
 }
 
-/// Opens a connection to the SQLite database, creating it if it doesn't exist.
-/// Applies schema if it doesn't exist.
-fn init_sqlite_db() -> eyre::Result<Connection> {
-    let sqlite_db_path = get_sqlite_db_path()?;
-    debug!("Opening SQLite DB at {sqlite_db_path}");
-
-    let db_conn = Connection::open(sqlite_db_path)?;
-    debug!("Successfully opened SQLite DB.");
+static ENVVAR_SQLITE_KEY: &str = "SQLITE_KEY";
+static ENVVAR_SQLITE_KEY_FILE: &str = "SQLITE_KEY_FILE";
 
-    // Apply schema
-    db_conn.execute_batch(include_str!("schema.sql"))?;
+/// Loads the SQLCipher key from SQLITE_KEY, or from the file SQLITE_KEY_FILE names.
+/// The database is only encrypted when a key is set.
+fn get_sqlite_key() -> eyre::Result<Option<String>> {
+    if let Some(key) = useful::env_opt(ENVVAR_SQLITE_KEY)
+        .with_context(|| format!("Error getting {ENVVAR_SQLITE_KEY}"))?
+    {
+        return Ok(Some(key));
+    }
 
-    Ok(db_conn)
+    let key_file: Option<PathBuf> = useful::env_opt(ENVVAR_SQLITE_KEY_FILE)
+        .with_context(|| format!("Error getting {ENVVAR_SQLITE_KEY_FILE}"))?;
+    key_file
+        .map(|path| {
+            fs::read_to_string(&path)
+                .map(|key| key.trim_end().to_owned())
+                .with_context(|| {
+                    format!("Error reading {ENVVAR_SQLITE_KEY_FILE} {}", path.display())
+                })
+        })
+        .transpose()
 }
 
 static ENVVAR_DISCORD_WEBHOOK_URL: &str = "URL_DISCORD_WEBHOOK";
-fn get_discord_webhook() -> eyre::Result<String> {
-    env::var(ENVVAR_DISCORD_WEBHOOK_URL)
-        .with_context(|| format!("Error getting {ENVVAR_DISCORD_WEBHOOK_URL}"))
+static ENVVAR_DISCORD_THREAD_PER_WEEK: &str = "DISCORD_THREAD_PER_WEEK";
+static ENVVAR_DISCORD_EDIT_ON_REVISION: &str = "DISCORD_EDIT_ON_REVISION";
+static ENVVAR_DISCORD_STATUS_BOARD: &str = "DISCORD_STATUS_BOARD";
+
+/// Loads the Discord webhook URL, if set, and posting options.
+fn get_discord_webhook() -> eyre::Result<(Option<String>, DiscordWebhookOptions)> {
+    let url = useful::env_opt(ENVVAR_DISCORD_WEBHOOK_URL)
+        .with_context(|| format!("Error getting {ENVVAR_DISCORD_WEBHOOK_URL}"))?;
+    let defaults = DiscordWebhookOptions::default();
+    let thread_per_week = useful::env_or(ENVVAR_DISCORD_THREAD_PER_WEEK, defaults.thread_per_week)
+        .with_context(|| format!("Error getting {ENVVAR_DISCORD_THREAD_PER_WEEK}"))?;
+    let edit_on_revision =
+        useful::env_or(ENVVAR_DISCORD_EDIT_ON_REVISION, defaults.edit_on_revision)
+            .with_context(|| format!("Error getting {ENVVAR_DISCORD_EDIT_ON_REVISION}"))?;
+
+    let status_board = useful::env_or(ENVVAR_DISCORD_STATUS_BOARD, defaults.status_board)
+        .with_context(|| format!("Error getting {ENVVAR_DISCORD_STATUS_BOARD}"))?;
+
+    Ok((
+        url,
+        DiscordWebhookOptions {
+            thread_per_week,
+            edit_on_revision,
+            status_board,
+        },
+    ))
 }
 
-#[instrument]
-fn init() -> eyre::Result<(String, String, Connection)> {
-    // Load environment variables
-    // Want to do it before init_tracing to load rust_log
-    dotenvy::dotenv()?;
+static ENVVAR_JSON_WEBHOOK_URL: &str = "URL_JSON_WEBHOOK";
+static ENVVAR_JSON_WEBHOOK_SECRET: &str = "JSON_WEBHOOK_SECRET";
 
-    useful::init_tracing();
+/// Loads the JSON webhook URL and signing secret, if set.
+fn get_json_webhook() -> eyre::Result<(Option<String>, Option<Secret>)> {
+    let url = useful::env_opt(ENVVAR_JSON_WEBHOOK_URL)
+        .with_context(|| format!("Error getting {ENVVAR_JSON_WEBHOOK_URL}"))?;
+    let secret = useful::env_opt(ENVVAR_JSON_WEBHOOK_SECRET)
+        .with_context(|| format!("Error getting {ENVVAR_JSON_WEBHOOK_SECRET}"))?
+        .map(Secret::new);
+
+    Ok((url, secret))
+}
+
+static ENVVAR_SLACK_WEBHOOK_URL: &str = "URL_SLACK_WEBHOOK";
+
+/// Loads the Slack incoming webhook URL, if set.
+fn get_slack_webhook_url() -> eyre::Result<Option<String>> {
+    useful::env_opt(ENVVAR_SLACK_WEBHOOK_URL)
+        .with_context(|| format!("Error getting {ENVVAR_SLACK_WEBHOOK_URL}"))
+}
+
+static ENVVAR_TEAMS_WEBHOOK_URL: &str = "URL_TEAMS_WEBHOOK";
+
+/// Loads the Microsoft Teams incoming webhook URL, if set.
+fn get_teams_webhook_url() -> eyre::Result<Option<String>> {
+    useful::env_opt(ENVVAR_TEAMS_WEBHOOK_URL)
+        .with_context(|| format!("Error getting {ENVVAR_TEAMS_WEBHOOK_URL}"))
+}
+
+static ENVVAR_MATRIX_HOMESERVER_URL: &str = "URL_MATRIX_HOMESERVER";
+static ENVVAR_MATRIX_ACCESS_TOKEN: &str = "MATRIX_ACCESS_TOKEN";
+static ENVVAR_MATRIX_ROOM_ID: &str = "MATRIX_ROOM_ID";
+
+/// Loads the Matrix room reports are posted to. Either all of its variables are set or none are.
+fn get_matrix_room() -> eyre::Result<Option<MatrixRoom>> {
+    let homeserver_url: Option<String> = useful::env_opt(ENVVAR_MATRIX_HOMESERVER_URL)
+        .with_context(|| format!("Error getting {ENVVAR_MATRIX_HOMESERVER_URL}"))?;
+    let access_token: Option<String> = useful::env_opt(ENVVAR_MATRIX_ACCESS_TOKEN)
+        .with_context(|| format!("Error getting {ENVVAR_MATRIX_ACCESS_TOKEN}"))?;
+    let room_id: Option<String> = useful::env_opt(ENVVAR_MATRIX_ROOM_ID)
+        .with_context(|| format!("Error getting {ENVVAR_MATRIX_ROOM_ID}"))?;
+
+    match (homeserver_url, access_token, room_id) {
+        (Some(homeserver_url), Some(access_token), Some(room_id)) => Ok(Some(MatrixRoom {
+            homeserver_url,
+            access_token: Secret::new(access_token),
+            room_id,
+        })),
+        (None, None, None) => Ok(None),
+        _ => Err(eyre!(
+            "{ENVVAR_MATRIX_HOMESERVER_URL}, {ENVVAR_MATRIX_ACCESS_TOKEN}, and {ENVVAR_MATRIX_ROOM_ID} must be set together"
+        )),
+    }
+}
+
+static ENVVAR_TELEGRAM_BOT_TOKEN: &str = "TELEGRAM_BOT_TOKEN";
+static ENVVAR_TELEGRAM_CHAT_ID: &str = "TELEGRAM_CHAT_ID";
+static ENVVAR_TELEGRAM_API_URL: &str = "URL_TELEGRAM_API";
+
+/// Loads the Telegram chat reports are sent to. The bot token and chat ID are set together or not at
+/// all.
+fn get_telegram_chat() -> eyre::Result<Option<TelegramChat>> {
+    let bot_token: Option<String> = useful::env_opt(ENVVAR_TELEGRAM_BOT_TOKEN)
+        .with_context(|| format!("Error getting {ENVVAR_TELEGRAM_BOT_TOKEN}"))?;
+    let chat_id: Option<String> = useful::env_opt(ENVVAR_TELEGRAM_CHAT_ID)
+        .with_context(|| format!("Error getting {ENVVAR_TELEGRAM_CHAT_ID}"))?;
+    let api_url = useful::env_or_else(ENVVAR_TELEGRAM_API_URL, || {
+        DEFAULT_TELEGRAM_API_URL.to_owned()
+    })
+    .with_context(|| format!("Error getting {ENVVAR_TELEGRAM_API_URL}"))?;
+
+    match (bot_token, chat_id) {
+        (Some(bot_token), Some(chat_id)) => Ok(Some(TelegramChat {
+            bot_token: Secret::new(bot_token),
+            chat_id,
+            api_url,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(eyre!(
+            "{ENVVAR_TELEGRAM_BOT_TOKEN} and {ENVVAR_TELEGRAM_CHAT_ID} must be set together"
+        )),
+    }
+}
+
+static ENVVAR_MASTODON_INSTANCE_URL: &str = "URL_MASTODON_INSTANCE";
+static ENVVAR_MASTODON_ACCESS_TOKEN: &str = "MASTODON_ACCESS_TOKEN";
+static ENVVAR_MASTODON_VISIBILITY: &str = "MASTODON_VISIBILITY";
+
+/// Loads the Mastodon account reports are posted from. The instance URL and access token are set
+/// together or not at all, and statuses are public unless MASTODON_VISIBILITY says otherwise.
+fn get_mastodon_account() -> eyre::Result<Option<MastodonAccount>> {
+    let instance_url: Option<String> = useful::env_opt(ENVVAR_MASTODON_INSTANCE_URL)
+        .with_context(|| format!("Error getting {ENVVAR_MASTODON_INSTANCE_URL}"))?;
+    let access_token: Option<String> = useful::env_opt(ENVVAR_MASTODON_ACCESS_TOKEN)
+        .with_context(|| format!("Error getting {ENVVAR_MASTODON_ACCESS_TOKEN}"))?;
+    let visibility: String =
+        useful::env_or(ENVVAR_MASTODON_VISIBILITY, DEFAULT_VISIBILITY.to_owned())
+            .with_context(|| format!("Error getting {ENVVAR_MASTODON_VISIBILITY}"))?;
+    if !["public", "unlisted", "private", "direct"].contains(&visibility.as_str()) {
+        return Err(eyre!(
+            "{ENVVAR_MASTODON_VISIBILITY} must be public, unlisted, private, or direct, not {visibility}"
+        ));
+    }
+
+    match (instance_url, access_token) {
+        (Some(instance_url), Some(access_token)) => Ok(Some(MastodonAccount {
+            instance_url,
+            access_token: Secret::new(access_token),
+            visibility,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(eyre!(
+            "{ENVVAR_MASTODON_INSTANCE_URL} and {ENVVAR_MASTODON_ACCESS_TOKEN} must be set together"
+        )),
+    }
+}
+
+static ENVVAR_NTFY_TOPIC_URL: &str = "URL_NTFY_TOPIC";
+static ENVVAR_NTFY_ACCESS_TOKEN: &str = "NTFY_ACCESS_TOKEN";
+
+/// Loads the ntfy topic reports are published to, and its access token if it has one.
+fn get_ntfy_topic() -> eyre::Result<Option<NtfyTopic>> {
+    let url: Option<String> = useful::env_opt(ENVVAR_NTFY_TOPIC_URL)
+        .with_context(|| format!("Error getting {ENVVAR_NTFY_TOPIC_URL}"))?;
+    let token = useful::env_opt(ENVVAR_NTFY_ACCESS_TOKEN)
+        .with_context(|| format!("Error getting {ENVVAR_NTFY_ACCESS_TOKEN}"))?
+        .map(Secret::new);
+
+    match url {
+        Some(url) => Ok(Some(NtfyTopic { url, token })),
+        None if token.is_some() => Err(eyre!(
+            "{ENVVAR_NTFY_ACCESS_TOKEN} is set without {ENVVAR_NTFY_TOPIC_URL}"
+        )),
+        None => Ok(None),
+    }
+}
+
+static ENVVAR_PUSHOVER_APP_TOKEN: &str = "PUSHOVER_APP_TOKEN";
+static ENVVAR_PUSHOVER_USER_KEY: &str = "PUSHOVER_USER_KEY";
+static ENVVAR_PUSHOVER_ALERT_THRESHOLDS: &str = "PUSHOVER_ALERT_THRESHOLDS";
+static ENVVAR_PUSHOVER_API_URL: &str = "URL_PUSHOVER_API";
+
+/// Loads the Pushover user reports are sent to. The app token and user key are set together or not
+/// at all, and alert thresholds are `pathogen=concentration` entries separated by semicolons, e.g.
+/// `sars-cov-2=500000;RSV=100000`.
+fn get_pushover_user() -> eyre::Result<Option<PushoverUser>> {
+    let app_token: Option<String> = useful::env_opt(ENVVAR_PUSHOVER_APP_TOKEN)
+        .with_context(|| format!("Error getting {ENVVAR_PUSHOVER_APP_TOKEN}"))?;
+    let user_key: Option<String> = useful::env_opt(ENVVAR_PUSHOVER_USER_KEY)
+        .with_context(|| format!("Error getting {ENVVAR_PUSHOVER_USER_KEY}"))?;
+    let alert_thresholds =
+        useful::env_or_else(ENVVAR_PUSHOVER_ALERT_THRESHOLDS, AlertThresholds::default)
+            .with_context(|| format!("Error getting {ENVVAR_PUSHOVER_ALERT_THRESHOLDS}"))?;
+    let api_url = useful::env_or_else(ENVVAR_PUSHOVER_API_URL, || {
+        DEFAULT_PUSHOVER_API_URL.to_owned()
+    })
+    .with_context(|| format!("Error getting {ENVVAR_PUSHOVER_API_URL}"))?;
+
+    match (app_token, user_key) {
+        (Some(app_token), Some(user_key)) => Ok(Some(PushoverUser {
+            app_token: Secret::new(app_token),
+            user_key: Secret::new(user_key),
+            alert_thresholds,
+            api_url,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(eyre!(
+            "{ENVVAR_PUSHOVER_APP_TOKEN} and {ENVVAR_PUSHOVER_USER_KEY} must be set together"
+        )),
+    }
+}
+
+static ENVVAR_SMTP_HOST: &str = "SMTP_HOST";
+static ENVVAR_SMTP_PORT: &str = "SMTP_PORT";
+static ENVVAR_SMTP_SECURITY: &str = "SMTP_SECURITY";
+static ENVVAR_SMTP_USERNAME: &str = "SMTP_USERNAME";
+static ENVVAR_SMTP_PASSWORD: &str = "SMTP_PASSWORD";
+static ENVVAR_EMAIL_FROM: &str = "EMAIL_FROM";
+static ENVVAR_EMAIL_TO: &str = "EMAIL_TO";
+
+/// Loads the SMTP server reports are emailed through, if SMTP_HOST is set, and the comma-separated
+/// addresses they're emailed to. The port defaults to the usual one for the security, STARTTLS
+/// unless set otherwise.
+fn get_email() -> eyre::Result<(Option<SmtpConfig>, Vec<String>)> {
+    let email_to: Vec<String> = useful::env_opt::<_, String>(ENVVAR_EMAIL_TO)
+        .with_context(|| format!("Error getting {ENVVAR_EMAIL_TO}"))?
+        .map(|to| {
+            to.split(',')
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    let Some(host) = useful::env_opt::<_, String>(ENVVAR_SMTP_HOST)
+        .with_context(|| format!("Error getting {ENVVAR_SMTP_HOST}"))?
+    else {
+        if !email_to.is_empty() {
+            return Err(eyre!("{ENVVAR_EMAIL_TO} is set without {ENVVAR_SMTP_HOST}"));
+        }
+        return Ok((None, email_to));
+    };
+
+    let security: SmtpSecurity = useful::env_or(ENVVAR_SMTP_SECURITY, SmtpSecurity::default())
+        .with_context(|| format!("Error getting {ENVVAR_SMTP_SECURITY}"))?;
+    let port = useful::env_or(ENVVAR_SMTP_PORT, security.default_port())
+        .with_context(|| format!("Error getting {ENVVAR_SMTP_PORT}"))?;
+    let username: Option<String> = useful::env_opt(ENVVAR_SMTP_USERNAME)
+        .with_context(|| format!("Error getting {ENVVAR_SMTP_USERNAME}"))?;
+    let password = useful::env_opt(ENVVAR_SMTP_PASSWORD)
+        .with_context(|| format!("Error getting {ENVVAR_SMTP_PASSWORD}"))?
+        .map(Secret::new);
+    let from = useful::env_opt(ENVVAR_EMAIL_FROM)
+        .with_context(|| format!("Error getting {ENVVAR_EMAIL_FROM}"))?
+        .ok_or_else(|| eyre!("{ENVVAR_EMAIL_FROM} must be set with {ENVVAR_SMTP_HOST}"))?;
+
+    if password.is_some() && username.is_none() {
+        return Err(eyre!(
+            "{ENVVAR_SMTP_PASSWORD} is set without {ENVVAR_SMTP_USERNAME}"
+        ));
+    }
+    if username.is_some() && security == SmtpSecurity::None {
+        return Err(eyre!(
+            "{ENVVAR_SMTP_USERNAME} is set but {ENVVAR_SMTP_SECURITY} is none, which would send the password unencrypted"
+        ));
+    }
+
+    let smtp = SmtpConfig {
+        host,
+        port,
+        security,
+        username,
+        password,
+        from,
+    };
+    Ok((Some(smtp), email_to))
+}
+
+static ENVVAR_DASHBOARD_URL: &str = "URL_DASHBOARD";
+
+/// Loads the dashboard base URL. Notifications only include links when it is set.
+fn get_dashboard_links() -> eyre::Result<Option<DashboardLinks>> {
+    useful::env_opt::<_, String>(ENVVAR_DASHBOARD_URL)
+        .with_context(|| format!("Error getting {ENVVAR_DASHBOARD_URL}"))?
+        .map(|url| DashboardLinks::new(&url))
+        .transpose()
+        .with_context(|| format!("Error parsing {ENVVAR_DASHBOARD_URL}"))
+}
+
+static ENVVAR_SOCRATA_METADATA_URL: &str = "URL_SOCRATA_METADATA";
+
+/// Loads the Socrata metadata URL of the dataset, e.g. `https://data.wa.gov/api/views/{id}.json`.
+/// When set, the dataset revision is checked before downloading.
+fn get_socrata_metadata_url() -> eyre::Result<Option<String>> {
+    useful::env_opt(ENVVAR_SOCRATA_METADATA_URL)
+        .with_context(|| format!("Error getting {ENVVAR_SOCRATA_METADATA_URL}"))
+}
+
+static ENVVAR_REPORT_FOOTER: &str = "REPORT_FOOTER";
+static DEFAULT_REPORT_FOOTER: bool = true;
+
+/// Whether reports end with a provenance footer. Defaults to true.
+fn get_report_footer() -> eyre::Result<bool> {
+    useful::env_or(ENVVAR_REPORT_FOOTER, DEFAULT_REPORT_FOOTER)
+        .with_context(|| format!("Error getting {ENVVAR_REPORT_FOOTER}"))
+}
+
+static ENVVAR_MAX_MISSED_SAMPLES: &str = "MAX_MISSED_SAMPLES";
+
+/// How many expected samples a site can miss before reports point it out. Defaults to 2.
+fn get_max_missed_samples() -> eyre::Result<u32> {
+    useful::env_or(ENVVAR_MAX_MISSED_SAMPLES, DEFAULT_MAX_MISSED_SAMPLES)
+        .with_context(|| format!("Error getting {ENVVAR_MAX_MISSED_SAMPLES}"))
+}
+
+static ENVVAR_CONCENTRATION_FLOOR: &str = "CONCENTRATION_FLOOR";
+static ENVVAR_CHANGE_SCALE: &str = "CHANGE_SCALE";
+static ENVVAR_ACTIVITY_LEVELS: &str = "ACTIVITY_LEVELS";
+static ENVVAR_REPORT_MEASURE: &str = "REPORT_MEASURE";
+
+/// Loads the concentration floor used on log scales (default 1) and whether reports show the change
+/// from the previous sample as an absolute difference ("linear", the default) or a relative change
+/// on a log scale ("log"), and which pathogens get an activity level, e.g.
+/// `sars-cov-2=cdc-nwss;RSV=2,3,5,8`. See [levels] for the presets. Also loads the measure reported
+/// on: "normalized" (the default), "per-liter", or "flow-adjusted".
+fn get_analysis_options() -> eyre::Result<AnalysisOptions> {
+    let concentration_floor = useful::env_or(
+        ENVVAR_CONCENTRATION_FLOOR,
+        analysis::DEFAULT_CONCENTRATION_FLOOR,
+    )
+    .with_context(|| format!("Error getting {ENVVAR_CONCENTRATION_FLOOR}"))?;
+    if concentration_floor <= 0.0 || !concentration_floor.is_finite() {
+        return Err(eyre!(
+            "{ENVVAR_CONCENTRATION_FLOOR} must be a positive number, got {concentration_floor}"
+        ));
+    }
+    let change_scale = useful::env_or(ENVVAR_CHANGE_SCALE, ChangeScale::default())
+        .with_context(|| format!("Error getting {ENVVAR_CHANGE_SCALE}"))?;
+    let activity_levels = useful::env_or_else(ENVVAR_ACTIVITY_LEVELS, ActivityLevelConfig::default)
+        .with_context(|| format!("Error getting {ENVVAR_ACTIVITY_LEVELS}"))?;
+    let measure = useful::env_or(ENVVAR_REPORT_MEASURE, Measure::default())
+        .with_context(|| format!("Error getting {ENVVAR_REPORT_MEASURE}"))?;
+
+    Ok(AnalysisOptions {
+        concentration_floor,
+        change_scale,
+        measure,
+        activity_levels,
+    })
+}
+
+static ENVVAR_MARKDOWN_PRECISION: &str = "MARKDOWN_PRECISION";
+static ENVVAR_TABLE_PRECISION: &str = "TABLE_PRECISION";
+static DEFAULT_PRECISION: Precision = Precision::SignificantFigures(3);
+
+/// Loads how many significant figures (or "full") each text output rounds numbers to. Defaults to 3.
+fn get_output_precision() -> eyre::Result<OutputPrecision> {
+    let markdown = useful::env_or(ENVVAR_MARKDOWN_PRECISION, DEFAULT_PRECISION)
+        .with_context(|| format!("Error getting {ENVVAR_MARKDOWN_PRECISION}"))?;
+    let table = useful::env_or(ENVVAR_TABLE_PRECISION, DEFAULT_PRECISION)
+        .with_context(|| format!("Error getting {ENVVAR_TABLE_PRECISION}"))?;
+
+    Ok(OutputPrecision { markdown, table })
+}
+
+static ENVVAR_HTTP_AUDIT: &str = "HTTP_AUDIT";
+static DEFAULT_HTTP_AUDIT: bool = true;
 
+static ENVVAR_HTTP_RATE_LIMIT_PER_SECOND: &str = "HTTP_RATE_LIMIT_PER_SECOND";
+static DEFAULT_HTTP_RATE_LIMIT_PER_SECOND: f64 = 0.5;
+static ENVVAR_HTTP_RATE_LIMIT_BURST: &str = "HTTP_RATE_LIMIT_BURST";
+static DEFAULT_HTTP_RATE_LIMIT_BURST: f64 = 5.0;
+static ENVVAR_HTTP_CONTACT: &str = "HTTP_CONTACT";
+static ENVVAR_HTTP_MAX_ATTEMPTS: &str = "HTTP_MAX_ATTEMPTS";
+static DEFAULT_HTTP_MAX_ATTEMPTS: u32 = 3;
+static ENVVAR_HTTP_RETRY_DELAY_SECS: &str = "HTTP_RETRY_DELAY_SECS";
+static DEFAULT_HTTP_RETRY_DELAY_SECS: f64 = 1.0;
+static ENVVAR_HTTP_MAX_RETRY_DELAY_SECS: &str = "HTTP_MAX_RETRY_DELAY_SECS";
+static DEFAULT_HTTP_MAX_RETRY_DELAY_SECS: f64 = 60.0;
+static ENVVAR_HTTP_TIMEOUT_SECS: &str = "HTTP_TIMEOUT_SECS";
+static DEFAULT_HTTP_TIMEOUT_SECS: f64 = 30.0;
+
+/// Loads the shared HTTP client configuration.
+/// Auditing defaults to on, and each host gets a burst of 5 requests refilled at one every 2 seconds,
+/// which stays under Discord's webhook limits. HTTP_CONTACT adds operator contact info to the User-Agent.
+/// Failed requests are tried 3 times with backoff starting at 1 second, and time out after 30
+/// seconds without progress.
+fn get_http_config() -> eyre::Result<HttpConfig> {
+    let audit = useful::env_or(ENVVAR_HTTP_AUDIT, DEFAULT_HTTP_AUDIT)
+        .with_context(|| format!("Error getting {ENVVAR_HTTP_AUDIT}"))?;
+    let per_second = useful::env_or(
+        ENVVAR_HTTP_RATE_LIMIT_PER_SECOND,
+        DEFAULT_HTTP_RATE_LIMIT_PER_SECOND,
+    )
+    .with_context(|| format!("Error getting {ENVVAR_HTTP_RATE_LIMIT_PER_SECOND}"))?;
+    let burst = useful::env_or(ENVVAR_HTTP_RATE_LIMIT_BURST, DEFAULT_HTTP_RATE_LIMIT_BURST)
+        .with_context(|| format!("Error getting {ENVVAR_HTTP_RATE_LIMIT_BURST}"))?;
+
+    let contact = useful::env_opt(ENVVAR_HTTP_CONTACT)
+        .with_context(|| format!("Error getting {ENVVAR_HTTP_CONTACT}"))?;
+
+    let max_attempts = useful::env_or(ENVVAR_HTTP_MAX_ATTEMPTS, DEFAULT_HTTP_MAX_ATTEMPTS)
+        .with_context(|| format!("Error getting {ENVVAR_HTTP_MAX_ATTEMPTS}"))?;
+    if max_attempts == 0 {
+        return Err(eyre!("{ENVVAR_HTTP_MAX_ATTEMPTS} must be at least 1"));
+    }
+    let seconds = |name: &str, default: f64| -> eyre::Result<Duration> {
+        let seconds =
+            useful::env_or(name, default).with_context(|| format!("Error getting {name}"))?;
+        Duration::try_from_secs_f64(seconds)
+            .map_err(|_| eyre!("{name} must be a non-negative number of seconds"))
+    };
+
+    Ok(HttpConfig {
+        audit,
+        rate_limit: RateLimit { per_second, burst },
+        retry: RetryPolicy {
+            max_attempts,
+            base_delay: seconds(ENVVAR_HTTP_RETRY_DELAY_SECS, DEFAULT_HTTP_RETRY_DELAY_SECS)?,
+            max_delay: seconds(
+                ENVVAR_HTTP_MAX_RETRY_DELAY_SECS,
+                DEFAULT_HTTP_MAX_RETRY_DELAY_SECS,
+            )?,
+        },
+        timeout: seconds(ENVVAR_HTTP_TIMEOUT_SECS, DEFAULT_HTTP_TIMEOUT_SECS)?,
+        contact,
+    })
+}
+
+static ENVVAR_ALERT_RULES: &str = "ALERT_RULES";
+static ENVVAR_NOTIFY_MODE: &str = "NOTIFY_MODE";
+
+/// Loads the alert rules, separated by semicolons, and whether reports are only sent when one fires
+/// (NOTIFY_MODE=alert) or always (the default). See [hygieia::rules].
+fn get_alerting() -> eyre::Result<(AlertRules, NotifyMode)> {
+    let rules = useful::env_or_else(ENVVAR_ALERT_RULES, AlertRules::default)
+        .with_context(|| format!("Error getting {ENVVAR_ALERT_RULES}"))?;
+    let mode = useful::env_or(ENVVAR_NOTIFY_MODE, NotifyMode::default())
+        .with_context(|| format!("Error getting {ENVVAR_NOTIFY_MODE}"))?;
+    if mode == NotifyMode::Alert && rules.is_empty() {
+        return Err(eyre!(
+            "{ENVVAR_NOTIFY_MODE} is alert without {ENVVAR_ALERT_RULES}, so no report would ever be sent"
+        ));
+    }
+
+    Ok((rules, mode))
+}
+
+static ENVVAR_NOTIFY_NEW_DATA_ONLY: &str = "NOTIFY_NEW_DATA_ONLY";
+static DEFAULT_NOTIFY_NEW_DATA_ONLY: bool = false;
+static ENVVAR_DISCORD_ADMIN_WEBHOOK_URL: &str = "URL_DISCORD_ADMIN_WEBHOOK";
+
+/// Loads whether runs without new samples send nothing (default false), and the Discord webhook
+/// told when one does, if set.
+fn get_new_data_only() -> eyre::Result<(bool, Option<String>)> {
+    let new_data_only = useful::env_or(ENVVAR_NOTIFY_NEW_DATA_ONLY, DEFAULT_NOTIFY_NEW_DATA_ONLY)
+        .with_context(|| format!("Error getting {ENVVAR_NOTIFY_NEW_DATA_ONLY}"))?;
+    let admin_webhook_url = useful::env_opt(ENVVAR_DISCORD_ADMIN_WEBHOOK_URL)
+        .with_context(|| format!("Error getting {ENVVAR_DISCORD_ADMIN_WEBHOOK_URL}"))?;
+
+    Ok((new_data_only, admin_webhook_url))
+}
+
+static ENVVAR_REPORT_RELEASE: &str = "REPORT_RELEASE";
+
+/// Loads the label of the templates and analysis in use, if set.
+fn get_report_release() -> eyre::Result<Option<String>> {
+    useful::env_opt(ENVVAR_REPORT_RELEASE)
+        .with_context(|| format!("Error getting {ENVVAR_REPORT_RELEASE}"))
+}
+
+static ENVVAR_CANARY_RUNS: &str = "CANARY_RUNS";
+static ENVVAR_CANARY_ROLLOUT_PERCENT: &str = "CANARY_ROLLOUT_PERCENT";
+
+/// Loads how many runs canary tenants receive a new configuration in before other tenants do
+/// (default 3), and the percentage of other tenants that receive it early (default 0).
+fn get_canary_policy() -> eyre::Result<CanaryPolicy> {
+    let runs = useful::env_or(ENVVAR_CANARY_RUNS, DEFAULT_CANARY_RUNS)
+        .with_context(|| format!("Error getting {ENVVAR_CANARY_RUNS}"))?;
+    let rollout_percent = useful::env_or(
+        ENVVAR_CANARY_ROLLOUT_PERCENT,
+        CanaryPolicy::default().rollout_percent,
+    )
+    .with_context(|| format!("Error getting {ENVVAR_CANARY_ROLLOUT_PERCENT}"))?;
+    if rollout_percent > 100 {
+        return Err(eyre!(
+            "{ENVVAR_CANARY_ROLLOUT_PERCENT} must be between 0 and 100, got {rollout_percent}"
+        ));
+    }
+
+    Ok(CanaryPolicy {
+        runs,
+        rollout_percent,
+    })
+}
+
+static ENVVAR_TENANTS_FILE: &str = "PATH_TENANTS";
+
+/// Loads the tenants from the JSON file PATH_TENANTS names, if set. See [hygieia::tenants].
+fn get_tenants() -> eyre::Result<Vec<Tenant>> {
+    let path: Option<PathBuf> = useful::env_opt(ENVVAR_TENANTS_FILE)
+        .with_context(|| format!("Error getting {ENVVAR_TENANTS_FILE}"))?;
+    path.map(|path| tenants::load_tenants(&path))
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Loads the configuration of a run from environment variables.
+fn load_config() -> eyre::Result<Config> {
     // Load Wastewater URL from environment variable, defaulting to DEFAULT_WASTEWATER_URL if not set
     let wastewater_url = get_wastewater_url()?;
     debug!("Loaded Wastewater URL from ENV: {}", wastewater_url);
 
+    let (counties, pathogens) = get_report_selection()?;
+    let (discord_webhook_url, discord_options) = get_discord_webhook()?;
+    let (json_webhook_url, json_webhook_secret) = get_json_webhook()?;
+    let (smtp, email_to) = get_email()?;
+    let (alert_rules, notify_mode) = get_alerting()?;
+    let (new_data_only, admin_discord_webhook_url) = get_new_data_only()?;
+
+    Ok(Config {
+        wastewater_url,
+        counties,
+        pathogens,
+        socrata_metadata_url: get_socrata_metadata_url()?,
+        download_path: get_download_path()?,
+        download_sha256: get_download_sha256()?,
+        insert_chunk_samples: get_insert_chunk_samples()?,
+        discord_webhook_url,
+        discord_options,
+        json_webhook_url,
+        json_webhook_secret,
+        slack_webhook_url: get_slack_webhook_url()?,
+        teams_webhook_url: get_teams_webhook_url()?,
+        matrix_room: get_matrix_room()?,
+        telegram_chat: get_telegram_chat()?,
+        mastodon_account: get_mastodon_account()?,
+        ntfy_topic: get_ntfy_topic()?,
+        pushover_user: get_pushover_user()?,
+        smtp,
+        email_to,
+        dashboard_links: get_dashboard_links()?,
+        report_footer: get_report_footer()?,
+        precision: get_output_precision()?,
+        max_missed_samples: get_max_missed_samples()?,
+        analysis: get_analysis_options()?,
+        http: get_http_config()?,
+        alert_rules,
+        notify_mode,
+        new_data_only,
+        admin_discord_webhook_url,
+        tenants: get_tenants()?,
+        seed: None,
+        release: get_report_release()?,
+        canary: get_canary_policy()?,
+    })
+}
+
+#[instrument(skip(clock))]
+fn init(clock: Arc<dyn Clock>, seed: Option<u64>) -> eyre::Result<RunContext> {
+    useful::init_tracing();
+
+    let mut config = load_config()?;
+    config.seed = seed;
+
     // Load sqlite database, creating it if it doesn't exist
-    let db_conn = init_sqlite_db()?;
+    let sqlite_key = get_sqlite_key()?.map(Secret::new);
+    let db_conn = db::open_database(
+        &get_sqlite_db_path()?,
+        sqlite_key.as_ref().map(Secret::expose),
+    )?;
+
+    let ctx = RunContext::new(config, db_conn, clock);
+    debug!("Seeded the run's randomness with {}", ctx.rng.seed());
+    Ok(ctx)
+}
 
-    Ok((wastewater_url, get_discord_webhook()?, db_conn))
+static ENVVAR_SITE_METADATA_URL: &str = "URL_SITE_METADATA";
+
+fn get_site_metadata_url() -> eyre::Result<String> {
+    env::var(ENVVAR_SITE_METADATA_URL)
+        .with_context(|| format!("Error getting {ENVVAR_SITE_METADATA_URL}"))
+}
+
+static ENVVAR_DOWNLOAD_PATH: &str = "PATH_DOWNLOAD";
+static ENVVAR_DOWNLOAD_SHA256: &str = "DOWNLOAD_SHA256";
+
+/// Loads where to download the CSV to. When unset the response is parsed as it streams in.
+fn get_download_path() -> eyre::Result<Option<PathBuf>> {
+    useful::env_opt(ENVVAR_DOWNLOAD_PATH)
+        .with_context(|| format!("Error getting {ENVVAR_DOWNLOAD_PATH}"))
+}
+
+/// Loads the SHA-256 checksum a downloaded file must match, if any.
+fn get_download_sha256() -> eyre::Result<Option<String>> {
+    useful::env_opt(ENVVAR_DOWNLOAD_SHA256)
+        .with_context(|| format!("Error getting {ENVVAR_DOWNLOAD_SHA256}"))
+}
+
+static ENVVAR_INSERT_CHUNK_SAMPLES: &str = "INSERT_CHUNK_SAMPLES";
+
+/// Loads how many samples are inserted per transaction, 10000 by default.
+fn get_insert_chunk_samples() -> eyre::Result<usize> {
+    let chunk_samples = useful::env_or(ENVVAR_INSERT_CHUNK_SAMPLES, DEFAULT_INSERT_CHUNK_SAMPLES)
+        .with_context(|| format!("Error getting {ENVVAR_INSERT_CHUNK_SAMPLES}"))?;
+    if chunk_samples == 0 {
+        return Err(eyre!("{ENVVAR_INSERT_CHUNK_SAMPLES} must be at least 1"));
+    }
+
+    Ok(chunk_samples)
 }
 
 fn main() -> eyre::Result<()> {
-    let (wastewater_url, discord_webook, mut db_conn) = init()?;
-
-    info!("Requesting Wastewater data from {}", wastewater_url);
-
-    let response = ureq::get(&wastewater_url).call()?;
-    info!(
-        "Response: OK, Content-Type: {:?}, Content-Length: {:?}",
-        response.header("Content-Type"),
-        response.header("Content-Length")
-    );
-    let reader = response.into_reader();
-
-    let data = csv_data::parse_data(reader).filter_map(|r| r.ok());
-    db::insert_wastewater_samples(&mut db_conn, data)?;
-
-    // Query the database for latest samples and differences in Pierce and King counties
-    let counties = ["Pierce", "King"];
-    let variants = ["FLUAV", "FLUBV", "RSV", "sars-cov-2"];
-
-    let query = r#"
-        WITH ranked_samples AS (
-            SELECT *,
-                    ROW_NUMBER() OVER (PARTITION BY pcr_pathogen_target ORDER BY sample_collection_date DESC) as row_num
-            FROM wastewater_samples
-            WHERE county = ?1 AND pcr_pathogen_target = ?2
-        )
-        SELECT 
-            s1.normalized_pathogen_concentration as latest_value,
-            s1.sample_collection_date as latest_date,
-            s1.normalized_pathogen_concentration - s2.normalized_pathogen_concentration as difference,
-            s2.sample_collection_date as previous_date
-        FROM ranked_samples s1
-        LEFT JOIN ranked_samples s2 ON s2.row_num = 2 AND s1.pcr_pathogen_target = s2.pcr_pathogen_target
-        WHERE s1.row_num = 1
-    "#;
-
-    let data: Vec<(
-        String,
-        String,
-        rusqlite::Result<(f64, String, Option<f64>, Option<String>)>,
-    )> = counties
-        .iter()
-        .flat_map(|&county| variants.map(|variant| (county, variant)))
-        .map(|(county, variant)| {
-            (
-                county.to_owned(),
-                variant.to_owned(),
-                db_conn.query_row(query, params![county, variant], |row| {
-                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-                }),
-            )
-        })
+    // Load environment variables
+    // Want to do it before init_tracing to load rust_log, and before parsing arguments that fall back to env
+    let preset: HashSet<OsString> = env::vars_os().map(|(key, _)| key).collect();
+    // Running without a .env file, with everything set in the environment, is fine
+    if let Err(e) = dotenvy::dotenv() {
+        if !e.not_found() {
+            return Err(e.into());
+        }
+    }
+    let dotenv_keys: HashSet<OsString> = env::vars_os()
+        .map(|(key, _)| key)
+        .filter(|key| !preset.contains(key))
         .collect();
 
-    let mut content_vec = vec![
-        "Hello World! I've gathered the latest respratory illness wastewater data:".to_owned(),
-    ];
-
-    for result in data {
-        match result {
-            (county, variant, Ok((latest_value, latest_date, difference, previous_date))) => {
-                info!(
-                    "{} County - {}: Latest value: {} on {}, Difference: {:?} (Previous date: {:?})",
-                    county, variant, latest_value, latest_date, difference, previous_date
-                );
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let range = cli.date_range();
+    if let (Some(since), Some(until)) = (range.since, range.until) {
+        if since > until {
+            return Err(eyre!("--since-date {since} is after --until-date {until}"));
+        }
+    }
 
-                content_vec.push(format!("**{county} County - {variant}**: {latest_value} ({difference:?}) on {latest_date}"));
-            }
-            (county, variant, Err(e)) => {
-                warn!("No data found for {} County - {}: {}", county, variant, e);
+    if let Some(Command::Duckdb) = cli.command {
+        if get_sqlite_key()?.is_some() {
+            return Err(eyre!("DuckDB can't attach an encrypted database"));
+        }
+        // DuckDB resolves relative paths against its own working directory
+        let sqlite_db_path = std::path::absolute(get_sqlite_db_path()?)?;
+        print!("{}", duckdb::attach_sql(&sqlite_db_path.to_string_lossy()));
+        return Ok(());
+    }
 
-                content_vec.push(format!("**{county} County - {variant}**: There was an error getting data for this. Yell at Izzy."));
-            }
+    match &cli.command {
+        Some(Command::Config {
+            command: ConfigCommand::Show { resolved },
+        }) => {
+            resolved::print(&load_config()?, &matches, &dotenv_keys, *resolved);
+            return Ok(());
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Schema,
+        }) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&tenants::tenants_schema())?
+            );
+            return Ok(());
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Validate { path },
+        }) => {
+            let tenants = tenants::load_tenants(path)?;
+            println!(
+                "{} is valid, with {} tenants",
+                path.display(),
+                tenants.len()
+            );
+            return Ok(());
         }
+        _ => {}
     }
 
-    let message = content_vec.join("\n");
-    let discord_webhook_response =
-        ureq::post(&discord_webook).send_form(&[("content", &message)])?;
+    let clock: Arc<dyn Clock> = match cli.as_of {
+        Some(as_of) => Arc::new(FixedClock(as_of)),
+        None => Arc::new(SystemClock),
+    };
+    let mut pipeline = Pipeline::with_context(init(clock, cli.seed)?, ConfiguredNotifier);
+    let _run_span = info_span!("run", run_id = %pipeline.context().run_id).entered();
 
-    info!(
-        "Response: OK, Content-Type: {:?}, Content-Length: {:?}",
-        discord_webhook_response.header("Content-Type"),
-        discord_webhook_response.header("Content-Length")
-    );
+    match cli.command {
+        Some(Command::Retrospective { period }) => {
+            let ctx = pipeline.context();
+            let counties = ctx
+                .config
+                .counties
+                .resolve(|| db::select_counties(&ctx.db))?;
+            let pathogens = ctx
+                .config
+                .pathogens
+                .resolve(|| db::select_pathogen_targets(&ctx.db))?;
+            let retrospective = retrospective::build_retrospective(
+                &ctx.db,
+                &Vec::from_iter(counties.iter().map(String::as_str)),
+                &Vec::from_iter(pathogens.iter().map(String::as_str)),
+                period,
+                &ctx.config.analysis,
+            )?;
+            println!(
+                "{}",
+                retrospective.to_markdown(ctx.config.precision.markdown)
+            );
+            return Ok(());
+        }
+        Some(Command::Diff { format }) => {
+            let reader = pipeline.fetch_wastewater_data()?;
+            let entries = diff::diff_against_db(&pipeline.context().db, reader)?;
+            diff::write_diff(io::stdout().lock(), &entries, format)?;
+            return Ok(());
+        }
+        Some(Command::Sites {
+            command: SitesCommand::Sync,
+        }) => {
+            let source = CsvSiteSource::new(get_site_metadata_url()?);
+            let ctx = pipeline.context_mut();
+            sites::sync_sites(&mut ctx.db, &ctx.http, ctx.clock.as_ref(), &source)?;
+            return Ok(());
+        }
+        Some(Command::Notify) => return pipeline.notify(),
+        Some(Command::Export { dir }) => {
+            let ctx = pipeline.context();
+            return export::export_data_package(
+                &ctx.db,
+                &dir,
+                &ctx.config.wastewater_url,
+                ctx.started_at,
+            );
+        }
+        Some(Command::Db {
+            command: DbCommand::Check,
+        }) => {
+            let problems = check::check_database(&pipeline.context().db)?;
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                return Err(eyre!("Database check found {} problems", problems.len()));
+            }
+            println!("Database OK");
+            return Ok(());
+        }
+        Some(Command::Db {
+            command: DbCommand::Analyze,
+        }) => {
+            let ctx = pipeline.context_mut();
+            let summary = maintenance::analyze_database(
+                &mut ctx.db,
+                ctx.clock.as_ref(),
+                &ctx.config.analysis,
+            )?;
+            println!("{summary}");
+            return Ok(());
+        }
+        Some(Command::Stats { json }) => {
+            let ctx = pipeline.context();
+            let stats = stats::collect_stats(&ctx.db, ctx.clock.as_ref())?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                println!("{stats}");
+            }
+            return Ok(());
+        }
+        Some(Command::Fetch) => return pipeline.fetch(),
+        Some(Command::Report { print }) => {
+            let coverage_changes = pipeline.detect_coverage_changes()?;
+            let reports = pipeline.analyze(range, coverage_changes)?;
+            if print {
+                let ctx = pipeline.context();
+                for (tenant, report) in &reports {
+                    if !ctx.config.tenants.is_empty() {
+                        println!("# {}", tenant.name);
+                    }
+                    println!(
+                        "{}",
+                        report.to_markdown(
+                            ctx.config.dashboard_links.as_ref(),
+                            ctx.config.precision.markdown
+                        )
+                    );
+                }
+                return Ok(());
+            }
+            pipeline.store_reports(&reports)?;
+            return pipeline.notify();
+        }
+        Some(Command::Ingest) => {
+            let coverage_changes = pipeline.ingest()?;
+            let reports = pipeline.analyze(range, coverage_changes)?;
+            pipeline.store_new_reports(&reports)?;
+            return pipeline.publish(&reports);
+        }
+        Some(Command::Site {
+            command:
+                SiteCommand::Build {
+                    out,
+                    full,
+                    base_url,
+                    embed_origins,
+                },
+        }) => {
+            let ctx = pipeline.context();
+            let pathogens = ctx
+                .config
+                .pathogens
+                .resolve(|| db::select_pathogen_targets(&ctx.db))?;
+            let options = site::SiteOptions {
+                pathogens: &pathogens,
+                analysis: &ctx.config.analysis,
+                precision: ctx.config.precision.markdown,
+                source_url: &ctx.config.wastewater_url,
+                base_url: base_url.as_ref(),
+                embed_origins: &embed_origins,
+                generated_at: ctx.started_at,
+            };
+            return site::build_site(&ctx.db, ctx.clock.as_ref(), &out, &options, full);
+        }
+        Some(Command::Alerts {
+            command:
+                AlertsCommand::List {
+                    tenant,
+                    limit,
+                    json,
+                },
+        }) => {
+            let alerts = alerts::select_alerts(&pipeline.context().db, tenant.as_deref(), limit)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&alerts)?);
+            } else {
+                for alert in &alerts {
+                    println!("{alert}");
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Alerts {
+            command:
+                AlertsCommand::Backtest {
+                    rule,
+                    range,
+                    multiplier,
+                    sustained,
+                    json,
+                },
+        }) => {
+            if !multiplier.is_finite() || multiplier <= 0.0 {
+                return Err(eyre!("--multiplier must be positive"));
+            }
+            let ctx = pipeline.context();
+            let counties = ctx
+                .config
+                .counties
+                .resolve(|| db::select_counties(&ctx.db))?;
+            let pathogens = ctx
+                .config
+                .pathogens
+                .resolve(|| db::select_pathogen_targets(&ctx.db))?;
+            let onset_rule = OnsetRule {
+                baseline_multiplier: multiplier,
+                sustained_samples: sustained,
+            };
+            let firings = alerts::backtest(
+                &ctx.db,
+                &rule,
+                &Vec::from_iter(counties.iter().map(String::as_str)),
+                &Vec::from_iter(pathogens.iter().map(String::as_str)),
+                range,
+                &onset_rule,
+                &ctx.config,
+            )?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&firings)?);
+            } else {
+                for firing in &firings {
+                    println!("{firing}");
+                }
+                println!("{rule} would have fired {} times in {range}", firings.len());
+            }
+            return Ok(());
+        }
+        Some(Command::Subscribers { command }) => {
+            let ctx = pipeline.context();
+            match command {
+                SubscribersCommand::Add {
+                    callback_url,
+                    tenant,
+                    secret,
+                } => {
+                    let id = subscribers::add_subscriber(
+                        &ctx.db,
+                        ctx.clock.as_ref(),
+                        &callback_url,
+                        &tenant,
+                        secret.map(Secret::new).as_ref(),
+                    )?;
+                    println!("Subscribed {callback_url} to tenant {tenant} as subscriber {id}");
+                }
+                SubscribersCommand::Remove { callback_url } => {
+                    let removed = subscribers::remove_subscriber(&ctx.db, &callback_url)?;
+                    if removed == 0 {
+                        return Err(eyre!("{callback_url} is not subscribed"));
+                    }
+                    println!("Unsubscribed {callback_url}");
+                }
+                SubscribersCommand::List { json } => {
+                    let subscribers = subscribers::select_subscribers(&ctx.db, None)?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&subscribers)?);
+                    } else {
+                        for subscriber in &subscribers {
+                            println!("{subscriber}");
+                        }
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Daemon(args)) => {
+            let DaemonArgs {
+                schedule,
+                export_dir,
+                export_schedule,
+                site_base_url,
+                embed_origins,
+                analyze_schedule,
+            } = *args;
+            let export = export_dir.map(|dir| ExportTask {
+                dir,
+                schedule: export_schedule,
+                base_url: site_base_url,
+                embed_origins,
+            });
+            return daemon::run_daemon(
+                &mut pipeline,
+                range,
+                &schedule,
+                export.as_ref(),
+                &analyze_schedule,
+            );
+        }
+        Some(Command::Duckdb | Command::Config { .. }) | None => {}
+    }
 
-    Ok(())
+    pipeline.run(range)
 }
@@ -0,0 +1,64 @@
+//! The `hygieia db analyze` job, which keeps report generation fast as the database grows: it runs
+//! SQLite's `ANALYZE` so the query planner has current statistics and precomputes activity level
+//! baselines with [levels::refresh_site_baselines].
+//!
+//! The job only reads samples, through memory-mapped I/O, which is faster for the full scans it
+//! makes than reading pages into SQLite's cache.
+
+use std::fmt;
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use tracing::{info, instrument};
+
+use crate::analysis::AnalysisOptions;
+use crate::levels;
+use crate::useful::Clock;
+
+/// Bytes of the database file mapped into memory while the job reads it.
+const MMAP_SIZE: i64 = 256 * 1024 * 1024;
+
+/// What the job refreshed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalyzeSummary {
+    /// Sites with a precomputed baseline.
+    pub baselines: usize,
+}
+
+impl fmt::Display for AnalyzeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Analyzed database: {} site baselines precomputed",
+            self.baselines
+        )
+    }
+}
+
+/// Runs the job, computing baselines with `options`.
+#[instrument(skip_all)]
+pub fn analyze_database(
+    conn: &mut Connection,
+    clock: &dyn Clock,
+    options: &AnalysisOptions,
+) -> eyre::Result<AnalyzeSummary> {
+    let mmap_size: i64 = conn.query_row("PRAGMA mmap_size", [], |row| row.get(0))?;
+    conn.pragma_update(None, "mmap_size", MMAP_SIZE)?;
+    let result = refresh(conn, clock, options);
+    conn.pragma_update(None, "mmap_size", mmap_size)?;
+
+    let summary = result?;
+    info!("{summary}");
+    Ok(summary)
+}
+
+fn refresh(
+    conn: &mut Connection,
+    clock: &dyn Clock,
+    options: &AnalysisOptions,
+) -> eyre::Result<AnalyzeSummary> {
+    conn.execute_batch("ANALYZE")?;
+    let baselines = levels::refresh_site_baselines(conn, clock, options)?;
+
+    Ok(AnalyzeSummary { baselines })
+}
@@ -0,0 +1,172 @@
+//! Posts reports to a Mastodon account as statuses. Mastodon doesn't render markdown, so reports are
+//! posted as plain text, and those over the status limit are posted as a thread of replies, split
+//! between lines and numbered.
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, instrument};
+
+use crate::context::RunContext;
+use crate::discord::split_message;
+use crate::http::{Body, HttpClient};
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::tenants::Tenant;
+use crate::useful::Secret;
+
+/// Most characters a status can have on a default Mastodon instance.
+const STATUS_LIMIT: usize = 500;
+/// Characters kept free in each status of a thread for its number, e.g. `\n(2/3)`.
+const NUMBER_RESERVE: usize = 8;
+
+/// Visibility of statuses when none is configured.
+pub const DEFAULT_VISIBILITY: &str = "public";
+
+/// An account reports are posted from.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MastodonAccount {
+    /// Base URL of the account's instance, e.g. `https://mastodon.social`.
+    pub instance_url: String,
+    /// Token of an application with the `write:statuses` scope.
+    pub access_token: Secret,
+    /// public, unlisted, private, or direct, defaulting to public.
+    #[serde(default = "default_visibility")]
+    pub visibility: String,
+}
+
+fn default_visibility() -> String {
+    DEFAULT_VISIBILITY.to_owned()
+}
+
+#[derive(Debug, Deserialize)]
+struct Status {
+    id: String,
+}
+
+impl MastodonAccount {
+    /// Posts `text`, as a thread if it's too long for one status. `idempotency_key` identifies the
+    /// post to the instance, which ignores a request repeating a key it has recently seen, so
+    /// retries never post twice.
+    #[instrument(skip_all, fields(instance_url = self.instance_url))]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        text: &str,
+        idempotency_key: &str,
+    ) -> eyre::Result<()> {
+        let url = format!(
+            "{}/api/v1/statuses",
+            self.instance_url.trim_end_matches('/')
+        );
+
+        let mut chunks = split_message(text, STATUS_LIMIT);
+        if chunks.len() > 1 {
+            chunks = split_message(text, STATUS_LIMIT - NUMBER_RESERVE);
+            let count = chunks.len();
+            for (i, chunk) in chunks.iter_mut().enumerate() {
+                chunk.push_str(&format!("\n({}/{count})", i + 1));
+            }
+        }
+
+        let mut in_reply_to: Option<String> = None;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let payload = json!({
+                "status": chunk,
+                "visibility": self.visibility,
+                "in_reply_to_id": in_reply_to,
+            });
+            let request = http
+                .post(&url)
+                .set(
+                    "Authorization",
+                    &format!("Bearer {}", self.access_token.expose()),
+                )
+                .set("Idempotency-Key", &format!("{idempotency_key}-{i}"));
+            let status: Status = http
+                .send(conn, request, Body::Json(&payload))?
+                .into_json()?;
+            debug!(
+                "Posted Mastodon status {} ({} of {})",
+                status.id,
+                i + 1,
+                chunks.len()
+            );
+            in_reply_to = Some(status.id);
+        }
+        info!("Posted report to Mastodon in {} statuses", chunks.len());
+
+        Ok(())
+    }
+}
+
+/// Posts from the tenant's Mastodon account, if it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MastodonNotifier;
+
+impl Notifier for MastodonNotifier {
+    fn name(&self) -> &str {
+        "mastodon"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(account) = &tenant.mastodon_account else {
+            return Ok(());
+        };
+
+        account.send(
+            &ctx.db,
+            &ctx.http,
+            &plain_text(&report.markdown),
+            &format!("hygieia-report-{}", report.id),
+        )
+    }
+}
+
+/// Renders the markdown reports are written in as plain text: `**` and `-# ` markers are dropped,
+/// links are replaced with their text so the character limit goes to the report itself, and `<url>`
+/// autolinks become bare URLs.
+pub fn plain_text(markdown: &str) -> String {
+    markdown
+        .lines()
+        .map(|line| {
+            let line = line.strip_prefix("-# ").unwrap_or(line);
+            let mut text = String::new();
+            let mut rest = line;
+            while let Some(start) = rest.find('[') {
+                let link = rest[start..].split_once("](<").and_then(|(label, rest)| {
+                    let (_, after) = rest.split_once(">)")?;
+                    Some((&label[1..], after))
+                });
+                let Some((label, after)) = link else {
+                    break;
+                };
+
+                text.push_str(&rest[..start]);
+                text.push_str(label);
+                rest = after;
+            }
+            text.push_str(rest);
+            unwrap_autolinks(&text.replace("**", ""))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `text` with the brackets around `<http...>` URLs removed.
+fn unwrap_autolinks(text: &str) -> String {
+    let mut unwrapped = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<http") {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        unwrapped.push_str(&rest[..start]);
+        unwrapped.push_str(&rest[start + 1..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    unwrapped.push_str(rest);
+    unwrapped
+}
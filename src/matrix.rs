@@ -0,0 +1,149 @@
+//! Posts reports to a Matrix room through the client-server API, as an `m.text` message with the
+//! markdown as its plain body and an HTML rendering of it as the formatted body.
+
+use color_eyre::eyre::{self, eyre};
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::context::RunContext;
+use crate::http::{Body, HttpClient};
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::site::escape;
+use crate::tenants::Tenant;
+use crate::useful::Secret;
+
+/// A room reports are posted to, and the account posting them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixRoom {
+    /// Base URL of the homeserver, e.g. `https://matrix.example.org`.
+    pub homeserver_url: String,
+    pub access_token: Secret,
+    /// The room's ID, e.g. `!abcdef:example.org`. Aliases aren't resolved.
+    pub room_id: String,
+}
+
+impl MatrixRoom {
+    /// Sends `markdown` to the room. `transaction_id` identifies the message to the homeserver,
+    /// which ignores a request repeating one it has already seen, so retries never post twice.
+    #[instrument(skip_all, fields(room_id = self.room_id))]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        markdown: &str,
+        transaction_id: &str,
+    ) -> eyre::Result<()> {
+        let mut url = Url::parse(&self.homeserver_url)?;
+        url.path_segments_mut()
+            .map_err(|()| eyre!("{} cannot be a base URL", self.homeserver_url))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                transaction_id,
+            ]);
+
+        let payload = json!({
+            "msgtype": "m.text",
+            "body": markdown,
+            "format": "org.matrix.custom.html",
+            "formatted_body": markdown_to_html(markdown),
+        });
+        let request = http.put(url.as_str()).set(
+            "Authorization",
+            &format!("Bearer {}", self.access_token.expose()),
+        );
+        http.send(conn, request, Body::Json(&payload))?;
+        info!("Posted report to Matrix room");
+
+        Ok(())
+    }
+}
+
+/// Posts to the tenant's Matrix room, if it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatrixNotifier;
+
+impl Notifier for MatrixNotifier {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(room) = &tenant.matrix_room else {
+            return Ok(());
+        };
+
+        room.send(
+            &ctx.db,
+            &ctx.http,
+            &report.markdown,
+            &format!("hygieia-report-{}", report.id),
+        )
+    }
+}
+
+/// Renders the markdown reports are written in as HTML: `**bold**`, `[text](<url>)` links, and
+/// `-# ` subtext lines, with every line ending in a break.
+pub fn markdown_to_html(markdown: &str) -> String {
+    markdown
+        .lines()
+        .map(|line| match line.strip_prefix("-# ") {
+            Some(subtext) => format!("<sub>{}</sub>", inline_html(subtext)),
+            None => inline_html(line),
+        })
+        .collect::<Vec<_>>()
+        .join("<br>\n")
+}
+
+fn inline_html(line: &str) -> String {
+    let mut html = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let link = rest[start..].split_once("](<").and_then(|(text, rest)| {
+            let (url, after) = rest.split_once(">)")?;
+            Some((&text[1..], url, after))
+        });
+        let Some((text, url, after)) = link else {
+            break;
+        };
+
+        html.push_str(&bold_html(&rest[..start]));
+        html.push_str(&format!(
+            r#"<a href="{}">{}</a>"#,
+            escape(url),
+            bold_html(text)
+        ));
+        rest = after;
+    }
+    html.push_str(&bold_html(rest));
+    html
+}
+
+/// Escapes `text`, turning `**` pairs into `<strong>`.
+fn bold_html(text: &str) -> String {
+    let parts: Vec<&str> = text.split("**").collect();
+    // An odd number of ** leaves the last one unpaired, so it is kept as text
+    let paired = parts.len() - (parts.len() + 1) % 2;
+    let mut html = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i >= paired {
+            html.push_str("**");
+        }
+        if i % 2 == 1 && i < paired {
+            html.push_str(&format!("<strong>{}</strong>", escape(part)));
+        } else {
+            html.push_str(&escape(part));
+        }
+    }
+    html
+}
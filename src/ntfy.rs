@@ -0,0 +1,221 @@
+//! Publishes reports to an ntfy topic as push notifications: a line per county and pathogen, at a
+//! priority following how worrying the report is, so a phone only buzzes loudly when levels are
+//! high or climbing.
+//!
+//! The notification is rendered with the rest of the report and stored with it, like Slack's
+//! blocks.
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+use crate::analysis::{Confidence, TrendDirection};
+use crate::context::RunContext;
+use crate::http::{Body, HttpClient};
+use crate::levels::ActivityLevel;
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::precision::Precision;
+use crate::report::{Report, ReportLine};
+use crate::tenants::Tenant;
+use crate::useful::Secret;
+
+/// Most bytes ntfy shows as a message rather than turning it into an attachment.
+const MESSAGE_LIMIT: usize = 4096;
+/// ntfy's default priority, used for reports stored before notifications were rendered.
+const DEFAULT_PRIORITY: u8 = 3;
+
+/// A topic reports are published to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfyTopic {
+    /// The topic's URL, e.g. `https://ntfy.sh/my-wastewater`.
+    pub url: String,
+    /// Access token for topics that need one.
+    pub token: Option<Secret>,
+}
+
+/// A report as an ntfy notification.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub message: String,
+    /// From 1 (min) to 5 (max).
+    pub priority: u8,
+    /// Emoji shortcodes shown before the title.
+    pub tags: Vec<String>,
+}
+
+impl NtfyTopic {
+    /// Publishes `notification` with `title`. `markdown` tells ntfy to render its message as
+    /// markdown.
+    #[instrument(skip_all, fields(priority = notification.priority))]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        title: &str,
+        notification: &Notification,
+        markdown: bool,
+    ) -> eyre::Result<()> {
+        let mut request = http
+            .post(&self.url)
+            .set("Content-Type", "text/plain; charset=utf-8")
+            .set("Title", title)
+            .set("Priority", &notification.priority.to_string())
+            .set("Tags", &notification.tags.join(","));
+        if markdown {
+            request = request.set("Markdown", "yes");
+        }
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token.expose()));
+        }
+
+        http.send(conn, request, Body::Bytes(notification.message.as_bytes()))?;
+        info!("Published report to ntfy topic");
+
+        Ok(())
+    }
+}
+
+/// Publishes to the tenant's ntfy topic, if it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NtfyNotifier;
+
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(topic) = &tenant.ntfy_topic else {
+            return Ok(());
+        };
+
+        let title = match report.period.as_str() {
+            "" => "Wastewater report".to_owned(),
+            period => format!("Wastewater report for {period}"),
+        };
+        match &report.ntfy_notification {
+            Some(notification) => {
+                let notification: Notification = serde_json::from_str(notification)?;
+                topic.send(&ctx.db, &ctx.http, &title, &notification, false)
+            }
+            // Reports stored before notifications were rendered
+            None => {
+                let notification = Notification {
+                    message: truncate(&report.markdown),
+                    priority: DEFAULT_PRIORITY,
+                    tags: vec!["microbe".to_owned()],
+                };
+                topic.send(&ctx.db, &ctx.http, &title, &notification, true)
+            }
+        }
+    }
+}
+
+/// Renders the report as a notification: a line per county and pathogen followed by the notices,
+/// at the priority of its most worrying line.
+pub fn report_notification(report: &Report, precision: Precision) -> Notification {
+    let mut lines = Vec::new();
+    for line in &report.lines {
+        let Some(summary) = &line.summary else {
+            lines.push(format!("{} {}: no data", line.county, line.pathogen));
+            continue;
+        };
+
+        let mut text = format!(
+            "{} {}: {}",
+            line.county,
+            line.pathogen,
+            precision.format(summary.latest_value)
+        );
+        if let Some(trend) = &line.trend {
+            text.push_str(&format!(" {} {}", trend.arrow(), trend.label()));
+        }
+        if let Some(activity) = &line.activity {
+            text.push_str(&format!(", {} activity", activity.level));
+        }
+        lines.push(text);
+    }
+    lines.extend(
+        report
+            .notices
+            .threshold_alerts
+            .iter()
+            .map(|alert| format!("🚨 {alert}")),
+    );
+    lines.extend(
+        report
+            .notices
+            .coverage_changes
+            .iter()
+            .map(|change| format!("📍 {change}")),
+    );
+    lines.extend(
+        report
+            .notices
+            .season_onsets
+            .iter()
+            .map(|onset| format!("🦠 {onset}")),
+    );
+    lines.extend(
+        report
+            .notices
+            .revisions
+            .iter()
+            .map(|revisions| format!("✏️ {revisions}")),
+    );
+    lines.extend(
+        report
+            .notices
+            .vintage
+            .iter()
+            .map(|vintage| format!("🔄 {vintage}")),
+    );
+
+    let priority = report
+        .lines
+        .iter()
+        .map(line_priority)
+        .max()
+        .unwrap_or(DEFAULT_PRIORITY);
+    let tag = match priority {
+        4.. => "chart_with_upwards_trend",
+        ..=2 => "chart_with_downwards_trend",
+        _ => "microbe",
+    };
+
+    Notification {
+        message: truncate(&lines.join("\n")),
+        priority,
+        tags: vec![tag.to_owned()],
+    }
+}
+
+/// How urgently a line deserves attention: urgent at very high activity, high at high activity or
+/// a confident rise, and low when falling. Otherwise ntfy's default.
+fn line_priority(line: &ReportLine) -> u8 {
+    let activity = line.activity.as_ref().map(|activity| activity.level);
+    let trend = line
+        .trend
+        .as_ref()
+        .map(|trend| (trend.direction, trend.confidence));
+    match (activity, trend) {
+        (Some(ActivityLevel::VeryHigh), _) => 5,
+        (Some(ActivityLevel::High), _) | (_, Some((TrendDirection::Rising, Confidence::High))) => 4,
+        (_, Some((TrendDirection::Falling, _))) => 2,
+        _ => DEFAULT_PRIORITY,
+    }
+}
+
+/// `message` cut to fit [MESSAGE_LIMIT] bytes.
+fn truncate(message: &str) -> String {
+    if message.len() <= MESSAGE_LIMIT {
+        return message.to_owned();
+    }
+    let mut end = MESSAGE_LIMIT - '…'.len_utf8();
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &message[..end])
+}
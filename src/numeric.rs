@@ -0,0 +1,112 @@
+//! Summation that stays accurate over long series.
+//!
+//! A running f64 sum loses the low bits of every value smaller than the total so far, so its error
+//! grows with the number of values, and means and variances over a year of samples drift. Sums here
+//! use Neumaier's variant of Kahan summation, which carries the lost bits along and keeps the error
+//! at a couple of ulps of the total however many values there are. SQLite's `SUM` and `AVG` use the
+//! same method since 3.43, so aggregates computed in SQL don't need it.
+
+/// A running sum with its rounding error compensated.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    pub fn add(&mut self, value: f64) {
+        let total = self.sum + value;
+        // Recover what rounding `total` dropped from whichever operand was smaller
+        self.compensation += if self.sum.abs() >= value.abs() {
+            (self.sum - total) + value
+        } else {
+            (value - total) + self.sum
+        };
+        self.sum = total;
+    }
+
+    pub fn total(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl FromIterator<f64> for CompensatedSum {
+    fn from_iter<I: IntoIterator<Item = f64>>(values: I) -> Self {
+        let mut sum = Self::default();
+        values.into_iter().for_each(|value| sum.add(value));
+        sum
+    }
+}
+
+/// The compensated sum of `values`.
+pub fn sum(values: impl IntoIterator<Item = f64>) -> f64 {
+    values.into_iter().collect::<CompensatedSum>().total()
+}
+
+/// The mean of `values`, or NaN if there are none.
+pub fn mean(values: &[f64]) -> f64 {
+    sum(values.iter().copied()) / values.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every value summed is a whole number of 2^-UNIT_EXPONENT.
+    const UNIT_EXPONENT: i32 = 60;
+
+    /// 10^7 values from 2^-60 to 2^20, each exactly representable, with their exact sum in units
+    /// of 2^-60.
+    fn values() -> (Vec<f64>, i128) {
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            state >> 33
+        };
+        let mut exact = 0i128;
+        let values = (0..10_000_000)
+            .map(|_| {
+                let mantissa = (next() % (1 << 20)) as i128;
+                let exponent = (next() % 61) as i32;
+                exact += mantissa << exponent;
+                mantissa as f64 * 2f64.powi(exponent - UNIT_EXPONENT)
+            })
+            .collect();
+        (values, exact)
+    }
+
+    fn ulps(value: f64, reference: f64) -> f64 {
+        (value - reference).abs() / (reference.abs() * f64::EPSILON)
+    }
+
+    #[test]
+    fn compensated_sum_is_within_ulps_of_the_exact_sum() {
+        let (values, exact) = values();
+        let exact = exact as f64 * 2f64.powi(-UNIT_EXPONENT);
+
+        let compensated = sum(values.iter().copied());
+        let naive: f64 = values.iter().sum();
+        assert!(
+            ulps(compensated, exact) <= 2.0,
+            "compensated sum {compensated} is {} ulps from {exact}",
+            ulps(compensated, exact)
+        );
+        assert!(
+            ulps(naive, exact) > 100.0,
+            "naive sum {naive} is only {} ulps from {exact}",
+            ulps(naive, exact)
+        );
+    }
+
+    #[test]
+    fn mean() {
+        assert_eq!(super::mean(&[1.0, 2.0, 6.0]), 3.0);
+    }
+
+    #[test]
+    fn mean_of_nothing_is_nan() {
+        assert!(super::mean(&[]).is_nan());
+    }
+}
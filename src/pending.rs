@@ -0,0 +1,219 @@
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use tracing::{info, instrument};
+
+use crate::email;
+use crate::json_webhook;
+use crate::links::DashboardLinks;
+use crate::ntfy;
+use crate::precision::OutputPrecision;
+use crate::report::Report;
+use crate::slack;
+use crate::teams;
+use crate::useful::Clock;
+
+/// A rendered report waiting to be delivered, as stored in `pending_reports`.
+/// Ingest stores one for each tenant after every run, and notify delivers each tenant's newest, so
+/// the two can run separately and share only the database.
+#[derive(Debug)]
+pub struct PendingReport {
+    pub id: i64,
+    pub run_id: String,
+    /// Name of the tenant the report was rendered for.
+    pub tenant: String,
+    /// Latest sample date the report covers, empty if it has no data.
+    pub period: String,
+    pub markdown: String,
+    pub table: String,
+    /// Slack blocks as JSON, None for reports stored before they were rendered.
+    pub slack_blocks: Option<String>,
+    /// Teams Adaptive Card as JSON, None for reports stored before it was rendered.
+    pub teams_card: Option<String>,
+    /// HTML email body, None for reports stored before it was rendered.
+    pub email_html: Option<String>,
+    /// ntfy notification as JSON, None for reports stored before it was rendered.
+    pub ntfy_notification: Option<String>,
+    /// [json_webhook::report_data] as JSON, None for reports stored before it was rendered.
+    pub report_data: Option<String>,
+    /// [Config::analysis_snapshot](crate::context::Config::analysis_snapshot) the report was built
+    /// with, None for reports stored before it was recorded.
+    pub analysis_config: Option<String>,
+}
+
+/// A report rendered in every format it is delivered in.
+#[derive(Debug)]
+pub struct RenderedReport {
+    pub period: String,
+    pub markdown: String,
+    pub table: String,
+    /// [slack::report_blocks] as JSON.
+    pub slack_blocks: String,
+    /// [teams::report_card] as JSON.
+    pub teams_card: String,
+    /// [email::report_html].
+    pub email_html: String,
+    /// [ntfy::report_notification] as JSON.
+    pub ntfy_notification: String,
+    /// [json_webhook::report_data] as JSON.
+    pub report_data: String,
+}
+
+impl RenderedReport {
+    pub fn new(
+        report: &Report,
+        links: Option<&DashboardLinks>,
+        precision: OutputPrecision,
+    ) -> Self {
+        Self {
+            period: report.period().map(|d| d.to_string()).unwrap_or_default(),
+            markdown: report.to_markdown(links, precision.markdown),
+            table: report.to_table(precision.table),
+            slack_blocks: slack::report_blocks(report, links, precision.markdown).to_string(),
+            teams_card: teams::report_card(report, links, precision.markdown).to_string(),
+            email_html: email::report_html(report, links, precision.markdown),
+            ntfy_notification: serde_json::to_string(&ntfy::report_notification(
+                report,
+                precision.markdown,
+            ))
+            .expect("notifications serialize"),
+            report_data: serde_json::Value::from(json_webhook::report_data(report)).to_string(),
+        }
+    }
+}
+
+/// Stores a report rendered for `tenant` as pending, along with the analysis configuration it was
+/// built with.
+#[instrument(skip_all, fields(run_id, tenant))]
+pub fn insert_pending_report(
+    conn: &Connection,
+    clock: &dyn Clock,
+    run_id: &str,
+    tenant: &str,
+    report: &RenderedReport,
+    analysis_config: &serde_json::Value,
+) -> eyre::Result<i64> {
+    const INSERT_PENDING_REPORT_SQL: &str = "
+    INSERT INTO pending_reports (created_timestamp, run_id, tenant, period, markdown, report_table, slack_blocks, teams_card, email_html, ntfy_notification, report_data, status, analysis_config) VALUES
+    (:created_timestamp, :run_id, :tenant, :period, :markdown, :report_table, :slack_blocks, :teams_card, :email_html, :ntfy_notification, :report_data, 'pending', :analysis_config)";
+
+    conn.prepare_cached(INSERT_PENDING_REPORT_SQL)?
+        .execute(named_params! {
+            ":created_timestamp": clock.unix_timestamp(),
+            ":run_id": run_id,
+            ":tenant": tenant,
+            ":period": report.period,
+            ":markdown": report.markdown,
+            ":report_table": report.table,
+            ":slack_blocks": report.slack_blocks,
+            ":teams_card": report.teams_card,
+            ":email_html": report.email_html,
+            ":ntfy_notification": report.ntfy_notification,
+            ":report_data": report.report_data,
+            ":analysis_config": analysis_config.to_string(),
+        })?;
+
+    let id = conn.last_insert_rowid();
+    info!("Stored pending report {id} for tenant {tenant}");
+    Ok(id)
+}
+
+/// Takes the newest pending report of `tenant`, marking its older pending reports as superseded
+/// since only the latest is worth delivering.
+pub fn take_latest_pending_report(
+    conn: &mut Connection,
+    clock: &dyn Clock,
+    tenant: &str,
+) -> eyre::Result<Option<PendingReport>> {
+    const SELECT_LATEST_PENDING_SQL: &str = "
+    SELECT id, run_id, tenant, period, markdown, report_table, slack_blocks, email_html, ntfy_notification, report_data, teams_card, analysis_config FROM pending_reports
+    WHERE status = 'pending' AND tenant = ?1
+    ORDER BY id DESC
+    LIMIT 1";
+
+    const SUPERSEDE_OLDER_SQL: &str = "
+    UPDATE pending_reports SET status = 'superseded', finished_timestamp = :finished_timestamp
+    WHERE status = 'pending' AND tenant = :tenant AND id < :id";
+
+    let tx = conn.transaction()?;
+    let latest = tx
+        .prepare_cached(SELECT_LATEST_PENDING_SQL)?
+        .query_row([tenant], |row| {
+            Ok(PendingReport {
+                id: row.get(0)?,
+                run_id: row.get(1)?,
+                tenant: row.get(2)?,
+                period: row.get(3)?,
+                markdown: row.get(4)?,
+                table: row.get(5)?,
+                slack_blocks: row.get(6)?,
+                email_html: row.get(7)?,
+                ntfy_notification: row.get(8)?,
+                report_data: row.get(9)?,
+                teams_card: row.get(10)?,
+                analysis_config: row.get(11)?,
+            })
+        })
+        .optional()?;
+
+    if let Some(latest) = &latest {
+        let superseded = tx
+            .prepare_cached(SUPERSEDE_OLDER_SQL)?
+            .execute(named_params! {
+                ":finished_timestamp": clock.unix_timestamp(),
+                ":tenant": tenant,
+                ":id": latest.id,
+            })?;
+        if superseded > 0 {
+            info!("Skipping {superseded} older pending reports");
+        }
+    }
+    tx.commit()?;
+
+    Ok(latest)
+}
+
+/// Whether report `id` has been sent with the notifier named `notifier`.
+pub fn is_delivered_with(conn: &Connection, id: i64, notifier: &str) -> eyre::Result<bool> {
+    const SELECT_DELIVERY_SQL: &str = "
+    SELECT EXISTS (SELECT 1 FROM pending_deliveries WHERE report_id = :report_id AND notifier = :notifier)";
+
+    Ok(conn.prepare_cached(SELECT_DELIVERY_SQL)?.query_row(
+        named_params! { ":report_id": id, ":notifier": notifier },
+        |row| row.get(0),
+    )?)
+}
+
+/// Records that report `id` was sent with the notifier named `notifier`.
+pub fn record_delivery(
+    conn: &Connection,
+    clock: &dyn Clock,
+    id: i64,
+    notifier: &str,
+) -> eyre::Result<()> {
+    const INSERT_DELIVERY_SQL: &str = "
+    INSERT OR IGNORE INTO pending_deliveries (report_id, notifier, delivered_timestamp)
+    VALUES (:report_id, :notifier, :delivered_timestamp)";
+
+    conn.prepare_cached(INSERT_DELIVERY_SQL)?
+        .execute(named_params! {
+            ":report_id": id,
+            ":notifier": notifier,
+            ":delivered_timestamp": clock.unix_timestamp(),
+        })?;
+
+    Ok(())
+}
+
+pub fn mark_delivered(conn: &Connection, clock: &dyn Clock, id: i64) -> eyre::Result<()> {
+    const MARK_DELIVERED_SQL: &str = "
+    UPDATE pending_reports SET status = 'delivered', finished_timestamp = :finished_timestamp
+    WHERE id = :id";
+
+    conn.prepare_cached(MARK_DELIVERED_SQL)?
+        .execute(named_params! {
+            ":finished_timestamp": clock.unix_timestamp(),
+            ":id": id,
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,829 @@
+use std::io::{self, IsTerminal, Read};
+use std::sync::Arc;
+use std::thread;
+
+use color_eyre::eyre::{self, eyre};
+use rusqlite::Connection;
+use tracing::{debug, info, instrument, warn};
+
+use crate::alerts;
+use crate::conditional::{self, Validators};
+use crate::context::{Config, RunContext};
+use crate::coverage::{self, CoverageChange};
+use crate::csv_data;
+use crate::db;
+use crate::discord::{DiscordNotifier, DiscordWebhook, DiscordWebhookOptions};
+use crate::download;
+use crate::email::EmailNotifier;
+use crate::http::Body;
+use crate::json_webhook::JsonWebhookNotifier;
+use crate::mastodon::MastodonNotifier;
+use crate::matrix::MatrixNotifier;
+use crate::ntfy::NtfyNotifier;
+use crate::pending::{self, PendingReport, RenderedReport};
+use crate::poll_runs::{self, PollOutcome};
+use crate::pushover::PushoverNotifier;
+use crate::report::{self, DateRange, Notices, Provenance, Report};
+use crate::rules::NotifyMode;
+use crate::season;
+use crate::slack::SlackNotifier;
+use crate::slugs;
+use crate::socrata;
+use crate::subscribers;
+use crate::synthetic::{self, SyntheticOptions};
+use crate::teams::TeamsNotifier;
+use crate::telegram::TelegramNotifier;
+use crate::tenants::Tenant;
+use crate::useful::SystemClock;
+
+/// Counties and pathogen targets reported on unless configured otherwise.
+pub const DEFAULT_COUNTIES: [&str; 2] = ["Pierce", "King"];
+pub const DEFAULT_PATHOGENS: [&str; 4] = ["FLUAV", "FLUBV", "RSV", "sars-cov-2"];
+
+/// A response to the request for the wastewater CSV.
+enum Fetched {
+    NotModified,
+    Data {
+        http_status: Option<u16>,
+        validators: Option<Validators>,
+        reader: Box<dyn Read + Send>,
+    },
+}
+
+/// Delivers reports once they are rendered. A pipeline can have several notifiers, which are all
+/// sent every report.
+pub trait Notifier {
+    /// Short name for logs and errors, e.g. "discord".
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Where reports to `tenant` go, recorded with the alerts they announce. Just the
+    /// notifier's name unless it knows better.
+    fn destinations(&self, _tenant: &Tenant) -> Vec<String> {
+        vec![self.name().to_owned()]
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()>;
+}
+
+/// Sends `report` with every notifier in turn. One failing doesn't stop the others; the error
+/// names every notifier that failed. Each success is recorded, so when the report is sent again
+/// it's only sent with the notifiers that failed.
+fn send_all<'a>(
+    notifiers: impl IntoIterator<Item = &'a dyn Notifier>,
+    ctx: &RunContext,
+    tenant: &Tenant,
+    report: &PendingReport,
+) -> eyre::Result<()> {
+    let mut failed = Vec::new();
+    for notifier in notifiers {
+        if pending::is_delivered_with(&ctx.db, report.id, notifier.name())? {
+            debug!(
+                "Report {} was already sent to tenant {} with {}",
+                report.id,
+                tenant.name,
+                notifier.name()
+            );
+            continue;
+        }
+        match notifier.send(ctx, tenant, report) {
+            Ok(()) => {
+                pending::record_delivery(&ctx.db, ctx.clock.as_ref(), report.id, notifier.name())?
+            }
+            Err(e) => {
+                warn!(
+                    "Could not send the report to tenant {} with {}: {e:?}",
+                    tenant.name,
+                    notifier.name()
+                );
+                failed.push(notifier.name().to_owned());
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(eyre!("Notifiers failed: {}", failed.join(", ")));
+    }
+    Ok(())
+}
+
+/// Posts to the tenant's webhooks, room, chat, account, topic, and Pushover user and emails its
+/// recipients with [DiscordNotifier], [JsonWebhookNotifier], [SlackNotifier], [TeamsNotifier],
+/// [MatrixNotifier], [TelegramNotifier], [MastodonNotifier], [NtfyNotifier], [PushoverNotifier],
+/// and [EmailNotifier], with the run's [Config] options.
+/// Reports are also printed as a table on terminals, and printed as markdown when stdout isn't a
+/// terminal and nothing else is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfiguredNotifier;
+
+/// Whether a tenant has configured a notifier.
+type IsConfigured = fn(&Tenant) -> bool;
+
+/// The notifiers [ConfiguredNotifier] sends with, and whether a tenant has configured each.
+const CONFIGURED_NOTIFIERS: [(&dyn Notifier, IsConfigured); 10] = [
+    (&DiscordNotifier, |tenant| {
+        tenant.discord_webhook_url.is_some()
+    }),
+    (&JsonWebhookNotifier, |tenant| {
+        tenant.json_webhook_url.is_some()
+    }),
+    (&SlackNotifier, |tenant| tenant.slack_webhook_url.is_some()),
+    (&TeamsNotifier, |tenant| tenant.teams_webhook_url.is_some()),
+    (&MatrixNotifier, |tenant| tenant.matrix_room.is_some()),
+    (&TelegramNotifier, |tenant| tenant.telegram_chat.is_some()),
+    (&MastodonNotifier, |tenant| {
+        tenant.mastodon_account.is_some()
+    }),
+    (&NtfyNotifier, |tenant| tenant.ntfy_topic.is_some()),
+    (&PushoverNotifier, |tenant| tenant.pushover_user.is_some()),
+    (&EmailNotifier, |tenant| !tenant.email_to.is_empty()),
+];
+
+impl ConfiguredNotifier {
+    /// Names of the notifiers the tenant has configured.
+    fn configured(tenant: &Tenant) -> Vec<String> {
+        CONFIGURED_NOTIFIERS
+            .iter()
+            .filter(|(_, configured)| configured(tenant))
+            .map(|(notifier, _)| notifier.name().to_owned())
+            .collect()
+    }
+}
+
+impl Notifier for ConfiguredNotifier {
+    fn name(&self) -> &str {
+        "configured"
+    }
+
+    fn destinations(&self, tenant: &Tenant) -> Vec<String> {
+        let configured = Self::configured(tenant);
+        if configured.is_empty() {
+            vec!["stdout".to_owned()]
+        } else {
+            configured
+        }
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let is_terminal = io::stdout().is_terminal();
+        if is_terminal {
+            println!("{}", report.table);
+        }
+
+        if Self::configured(tenant).is_empty() {
+            warn!(
+                "No webhook, room, chat, account, topic, Pushover user, or email recipient is configured for tenant {}, printing the report instead of sending it",
+                tenant.name
+            );
+            if !is_terminal {
+                println!("{}", report.markdown);
+            }
+            return Ok(());
+        }
+
+        send_all(
+            CONFIGURED_NOTIFIERS.iter().map(|(notifier, _)| *notifier),
+            ctx,
+            tenant,
+            report,
+        )
+    }
+}
+
+/// Fetches samples, stores them, and reports on them.
+///
+/// ```no_run
+/// use hygieia::pipeline::{ConfiguredNotifier, Pipeline};
+/// use hygieia::report::DateRange;
+///
+/// # fn main() -> color_eyre::eyre::Result<()> {
+/// let conn = rusqlite::Connection::open("wastewater.sqlite")?;
+/// let mut pipeline = Pipeline::new(
+///     "https://doh.wa.gov/sites/default/files/Data/Downloadable_Wastewater.csv",
+///     conn,
+///     ConfiguredNotifier,
+/// )?;
+/// pipeline.run(DateRange::default())?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pipeline {
+    ctx: RunContext,
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl Pipeline {
+    /// A pipeline fetching from `source_url` into `conn`, with the default [Config] and the system
+    /// clock. The schema is applied to `conn` if it doesn't exist yet.
+    pub fn new(
+        source_url: impl Into<String>,
+        conn: Connection,
+        notifier: impl Notifier + 'static,
+    ) -> eyre::Result<Self> {
+        db::apply_schema(&conn)?;
+        let ctx = RunContext::new(Config::new(source_url), conn, Arc::new(SystemClock));
+        Ok(Self::with_context(ctx, notifier))
+    }
+
+    /// A pipeline running with an existing context, whose database already has the schema.
+    pub fn with_context(ctx: RunContext, notifier: impl Notifier + 'static) -> Self {
+        Self {
+            ctx,
+            notifiers: vec![Box::new(notifier)],
+        }
+    }
+
+    /// Also sends every report with `notifier`, e.g. to post to Discord and an in-house service
+    /// in the same run.
+    pub fn add_notifier(&mut self, notifier: impl Notifier + 'static) {
+        self.notifiers.push(Box::new(notifier));
+    }
+
+    pub fn context(&self) -> &RunContext {
+        &self.ctx
+    }
+
+    pub fn context_mut(&mut self) -> &mut RunContext {
+        &mut self.ctx
+    }
+
+    /// Runs every stage: ingest, analyze, store each tenant's report, publish them to subscribers,
+    /// and deliver them.
+    #[instrument(skip(self), fields(run_id = %self.ctx.run_id))]
+    pub fn run(&mut self, range: DateRange) -> eyre::Result<()> {
+        let coverage_changes = self.ingest()?;
+        let reports = self.analyze(range, coverage_changes)?;
+        self.store_new_reports(&reports)?;
+        self.publish(&reports)?;
+        self.notify()
+    }
+
+    /// Requests the wastewater CSV, returning a reader over it.
+    /// With a download path configured the file is downloaded resumably and verified first.
+    pub fn fetch_wastewater_data(&self) -> eyre::Result<Box<dyn Read + Send>> {
+        match self.request_wastewater_data(None)? {
+            Fetched::Data { reader, .. } => Ok(reader),
+            Fetched::NotModified => Err(eyre!("Unconditional request was answered with 304")),
+        }
+    }
+
+    /// Like [Pipeline::fetch_wastewater_data], but conditional on `validators` when the CSV isn't
+    /// downloaded to a file first.
+    fn request_wastewater_data(&self, validators: Option<&Validators>) -> eyre::Result<Fetched> {
+        let ctx = &self.ctx;
+        let wastewater_url = &ctx.config.wastewater_url;
+        info!("Requesting Wastewater data from {}", wastewater_url);
+
+        if let Some(options) = SyntheticOptions::from_wastewater_url(wastewater_url)? {
+            let csv = synthetic::generate_csv(&options, ctx.started_at, ctx.rng.seed())?;
+            return Ok(Fetched::Data {
+                http_status: None,
+                validators: None,
+                reader: Box::new(io::Cursor::new(csv)),
+            });
+        }
+        if let Some(download_path) = &ctx.config.download_path {
+            let file = download::download_resumable(
+                &ctx.db,
+                &ctx.http,
+                wastewater_url,
+                download_path,
+                ctx.config.download_sha256.as_deref(),
+            )?;
+            return Ok(Fetched::Data {
+                http_status: None,
+                validators: None,
+                reader: Box::new(file),
+            });
+        }
+
+        let mut request = ctx.http.get(wastewater_url);
+        if let Some(validators) = validators {
+            request = validators.apply(request);
+        }
+        let response = ctx.http.send(&ctx.db, request, Body::Empty)?;
+        if response.status() == 304 {
+            return Ok(Fetched::NotModified);
+        }
+        info!(
+            "Response: OK, Content-Type: {:?}, Content-Length: {:?}",
+            response.header("Content-Type"),
+            response.header("Content-Length")
+        );
+
+        Ok(Fetched::Data {
+            http_status: Some(response.status()),
+            validators: Validators::from_response(&response),
+            reader: Box::new(response.into_reader()),
+        })
+    }
+
+    /// Records the dataset's current revision, returning true if its rows are unchanged since the last run.
+    fn dataset_unchanged(&self) -> eyre::Result<bool> {
+        let ctx = &self.ctx;
+        let Some(metadata_url) = &ctx.config.socrata_metadata_url else {
+            return Ok(false);
+        };
+
+        let revision = socrata::fetch_revision(&ctx.db, &ctx.http, metadata_url)?;
+        let unchanged = socrata::select_last_revision(&ctx.db)?
+            .is_some_and(|last_revision| revision.same_rows_as(&last_revision));
+        socrata::insert_revision(&ctx.db, ctx.clock.as_ref(), &revision)?;
+
+        Ok(unchanged)
+    }
+
+    /// Fetches and stores new samples, returning the coverage changes since the last ingest.
+    pub fn ingest(&mut self) -> eyre::Result<Vec<CoverageChange>> {
+        self.fetch()?;
+        self.detect_coverage_changes()
+    }
+
+    /// Fetches and stores new samples only, recording what happened in `poll_runs`.
+    /// Skipped when the upstream dataset is unchanged since the last run, or when the file's
+    /// Date/Time Updated is the same as at the last poll.
+    pub fn fetch(&mut self) -> eyre::Result<()> {
+        let ctx = &self.ctx;
+        let poll_run = poll_runs::start_poll_run(
+            &ctx.db,
+            ctx.clock.as_ref(),
+            &ctx.run_id,
+            &ctx.config.wastewater_url,
+        )?;
+
+        let mut outcome = PollOutcome::default();
+        let result = self.poll(poll_run, &mut outcome);
+        if let Err(e) = &result {
+            outcome.error = Some(format!("{e:#}"));
+        }
+
+        let ctx = &self.ctx;
+        poll_runs::finish_poll_run(&ctx.db, ctx.clock.as_ref(), poll_run, &outcome)?;
+        result
+    }
+
+    fn poll(&mut self, poll_run: i64, outcome: &mut PollOutcome) -> eyre::Result<()> {
+        if self.dataset_unchanged()? {
+            info!("Dataset rows are unchanged since the last run, skipping download");
+            outcome.unchanged = true;
+            return Ok(());
+        }
+
+        let last_validators =
+            conditional::select_validators(&self.ctx.db, &self.ctx.config.wastewater_url)?;
+        let (http_status, validators, reader) =
+            match self.request_wastewater_data(last_validators.as_ref())? {
+                Fetched::NotModified => {
+                    info!("No new data, the file is unchanged since the last fetch");
+                    outcome.http_status = Some(304);
+                    outcome.unchanged = true;
+                    return Ok(());
+                }
+                Fetched::Data {
+                    http_status,
+                    validators,
+                    reader,
+                } => (http_status, validators, reader),
+            };
+        outcome.http_status = http_status;
+
+        let ctx = &mut self.ctx;
+        let clock = ctx.clock.as_ref();
+        let last_date_updated = poll_runs::select_last_date_updated(&ctx.db)?;
+        let mut data = csv_data::parse_data(reader)
+            .inspect(|row| {
+                outcome.rows_read += 1;
+                if let Err(e) = row {
+                    outcome.parse_errors += 1;
+                    warn!("Skipping row that could not be parsed: {e}");
+                }
+            })
+            .filter_map(|r| r.ok())
+            .peekable();
+
+        // Every row carries the file's Date/Time Updated, so the first one tells if anything changed
+        let date_updated = data.peek().map(|row| row.date_updated.fixed_offset());
+        if date_updated.is_some() && date_updated == last_date_updated {
+            drop(data);
+            info!(
+                "No new data, Date/Time Updated is still {}, skipping the insert",
+                date_updated.map(|d| d.to_rfc3339()).unwrap_or_default()
+            );
+            outcome.unchanged = true;
+        } else {
+            let interrupted = poll_runs::select_interrupted_insert(
+                &ctx.db,
+                poll_run,
+                &ctx.config.wastewater_url,
+            )?;
+            if let Some((run_id, progress)) = &interrupted {
+                info!(
+                    "Run {run_id} was interrupted after committing {} samples, completing its insert",
+                    progress.samples
+                );
+            }
+            outcome.counts = db::insert_wastewater_samples(
+                &mut ctx.db,
+                data.map(|row| (row, clock)),
+                ctx.config.insert_chunk_samples,
+                interrupted.as_ref().map(|(_, progress)| progress),
+                |conn, progress| poll_runs::record_insert_progress(conn, poll_run, progress),
+            )?;
+            outcome.resumed_run_id = interrupted.map(|(run_id, _)| run_id);
+            // A row that couldn't be read would count as retracted
+            let unread = outcome.parse_errors + outcome.counts.errors;
+            if unread == 0 {
+                outcome.counts.retracted = db::count_retracted_samples(&ctx.db)?;
+                if outcome.counts.retracted > 0 {
+                    info!(
+                        "{} stored samples are missing from the file",
+                        outcome.counts.retracted
+                    );
+                }
+            } else {
+                warn!("Not counting retracted samples, since {unread} rows couldn't be read");
+            }
+            slugs::assign_slugs(&ctx.db)?;
+        }
+        outcome.date_updated = date_updated;
+
+        if let Some(validators) = validators {
+            conditional::store_validators(
+                &ctx.db,
+                ctx.clock.as_ref(),
+                &ctx.config.wastewater_url,
+                &validators,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Compares the stored samples with the sites and targets recorded at the last check, returning
+    /// what changed. Changes in samples stored by [Pipeline::fetch] are found by the next check.
+    pub fn detect_coverage_changes(&mut self) -> eyre::Result<Vec<CoverageChange>> {
+        let ctx = &mut self.ctx;
+        let mut changes = coverage::detect_new_sites(&ctx.db, ctx.clock.as_ref())?;
+        changes.extend(coverage::detect_target_changes(&mut ctx.db)?);
+        changes.extend(coverage::detect_missed_samples(
+            &mut ctx.db,
+            ctx.clock.as_ref(),
+            ctx.config.max_missed_samples,
+        )?);
+        Ok(changes)
+    }
+
+    /// Builds each tenant's report from the stored samples.
+    /// The analysis runs once for every tenant's counties and pathogens together, and each tenant's
+    /// report is the part of it about their own, with the alerts their rules fired.
+    pub fn analyze(
+        &self,
+        range: DateRange,
+        coverage_changes: Vec<CoverageChange>,
+    ) -> eyre::Result<Vec<(Tenant, Report)>> {
+        let ctx = &self.ctx;
+        let tenants = ctx.config.tenants_or_default();
+        let selections = self.resolve_selections(&tenants)?;
+
+        let mut counties: Vec<&str> = Vec::new();
+        let mut pathogens: Vec<&str> = Vec::new();
+        for (tenant_counties, tenant_pathogens) in &selections {
+            for county in tenant_counties {
+                if !counties.contains(&county.as_str()) {
+                    counties.push(county);
+                }
+            }
+            for pathogen in tenant_pathogens {
+                if !pathogens.contains(&pathogen.as_str()) {
+                    pathogens.push(pathogen);
+                }
+            }
+        }
+
+        let season_onsets = season::detect_season_onsets(
+            &ctx.db,
+            ctx.clock.as_ref(),
+            &counties,
+            &pathogens,
+            range.until,
+        )?;
+
+        let provenance = if ctx.config.report_footer {
+            Some(Provenance::new(
+                &ctx.db,
+                &ctx.config.wastewater_url,
+                ctx.started_at,
+            )?)
+        } else {
+            None
+        };
+
+        let batch = report::build_report(
+            &ctx.db,
+            &counties,
+            &pathogens,
+            range,
+            &ctx.config.analysis,
+            Notices {
+                coverage_changes,
+                season_onsets,
+                revisions: db::select_revisions_since_last_report(&ctx.db)?,
+                vintage: poll_runs::select_vintage_change(&ctx.db, &ctx.run_id)?,
+                threshold_alerts: Vec::new(),
+            },
+            provenance,
+        );
+
+        tenants
+            .into_iter()
+            .zip(&selections)
+            .map(|(tenant, (counties, pathogens))| {
+                let mut report = batch.select(counties, pathogens);
+                // Without a tenants file every notice is reported, as before tenants existed
+                if !ctx.config.tenants.is_empty() {
+                    report.notices = batch.notices.about(counties);
+                }
+                report.greeting = tenant.greeting.clone();
+
+                // An alert fires once per sample, rather than every run until the next one
+                let mut threshold_alerts = Vec::new();
+                for alert in tenant.alert_rules.evaluate(&report.lines) {
+                    if !alerts::was_delivered(
+                        &ctx.db,
+                        &tenant.name,
+                        alerts::THRESHOLD_RULE,
+                        &alert.to_string(),
+                    )? {
+                        threshold_alerts.push(alert);
+                    }
+                }
+                report.notices.threshold_alerts = threshold_alerts;
+                Ok((tenant, report))
+            })
+            .collect()
+    }
+
+    /// Resolves each tenant's counties and pathogens, listing the stored ones at most once for
+    /// tenants selecting all of them.
+    fn resolve_selections(
+        &self,
+        tenants: &[Tenant],
+    ) -> eyre::Result<Vec<(Vec<String>, Vec<String>)>> {
+        let db = &self.ctx.db;
+        let mut all_counties = None;
+        let mut all_pathogens = None;
+
+        tenants
+            .iter()
+            .map(|tenant| {
+                let counties = tenant.counties.resolve(|| {
+                    if all_counties.is_none() {
+                        all_counties = Some(db::select_counties(db)?);
+                    }
+                    Ok(all_counties.clone().unwrap_or_default())
+                })?;
+                let pathogens = tenant.pathogens.resolve(|| {
+                    if all_pathogens.is_none() {
+                        all_pathogens = Some(db::select_pathogen_targets(db)?);
+                    }
+                    Ok(all_pathogens.clone().unwrap_or_default())
+                })?;
+                Ok((counties, pathogens))
+            })
+            .collect()
+    }
+
+    /// Like [Pipeline::store_reports], except that with [Config::new_data_only] a run whose poll
+    /// inserted or revised no samples stores no reports, and so sends nothing. The admin webhook,
+    /// if set, is told so instead.
+    pub fn store_new_reports(&self, reports: &[(Tenant, Report)]) -> eyre::Result<()> {
+        let ctx = &self.ctx;
+        if !ctx.config.new_data_only || poll_runs::run_stored_new_data(&ctx.db, &ctx.run_id)? {
+            return self.store_reports(reports);
+        }
+
+        info!("No new data stored in this run, not storing reports");
+        let Some(url) = &ctx.config.admin_discord_webhook_url else {
+            return Ok(());
+        };
+        let content = match poll_runs::select_last_date_updated(&ctx.db)? {
+            Some(date_updated) => format!(
+                "No new wastewater data in run {}, so no reports were sent. The dataset was last updated {}.",
+                ctx.run_id,
+                date_updated.format("%Y-%m-%d %H:%M")
+            ),
+            None => format!(
+                "No new wastewater data in run {}, so no reports were sent.",
+                ctx.run_id
+            ),
+        };
+        DiscordWebhook::new(url.clone(), DiscordWebhookOptions::default()).send(
+            &ctx.db,
+            &ctx.http,
+            ctx.clock.as_ref(),
+            &content,
+            "",
+        )
+    }
+
+    /// Renders each tenant's report concurrently and stores them for notify to deliver.
+    /// Tenants in alert mode get no report when none of their alert rules fired. A tenant whose
+    /// report can't be stored doesn't stop the others; the error names every tenant that failed.
+    pub fn store_reports(&self, reports: &[(Tenant, Report)]) -> eyre::Result<()> {
+        let ctx = &self.ctx;
+        let links = ctx.config.dashboard_links.as_ref();
+        let precision = ctx.config.precision;
+        let rendered: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = reports
+                .iter()
+                .map(|(_, report)| {
+                    scope.spawn(move || RenderedReport::new(report, links, precision))
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join()).collect()
+        });
+
+        let analysis_config = ctx.config.analysis_snapshot();
+        let mut failed = Vec::new();
+        for ((tenant, report), rendered) in reports.iter().zip(rendered) {
+            if tenant.notify_mode == NotifyMode::Alert && report.notices.threshold_alerts.is_empty()
+            {
+                info!(
+                    "No alert rule fired for tenant {}, not storing a report",
+                    tenant.name
+                );
+                continue;
+            }
+            let result = match rendered {
+                Ok(rendered) => pending::insert_pending_report(
+                    &ctx.db,
+                    ctx.clock.as_ref(),
+                    &ctx.run_id,
+                    &tenant.name,
+                    &rendered,
+                    &analysis_config,
+                )
+                .and_then(|id| {
+                    alerts::insert_alerts(
+                        &ctx.db,
+                        ctx.clock.as_ref(),
+                        id,
+                        &tenant.name,
+                        &report.notices,
+                    )
+                })
+                .map(|_| ()),
+                Err(_) => Err(eyre!("Rendering the report panicked")),
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Could not store the report for tenant {}: {e:?}",
+                    tenant.name
+                );
+                failed.push(tenant.name.as_str());
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(eyre!(
+                "Could not store reports for tenants: {}",
+                failed.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Posts each tenant's report to its subscribers, if this run stored new samples.
+    /// A subscriber that can't be reached is recorded and doesn't fail the run.
+    pub fn publish(&self, reports: &[(Tenant, Report)]) -> eyre::Result<()> {
+        let ctx = &self.ctx;
+        if !poll_runs::run_stored_new_data(&ctx.db, &ctx.run_id)? {
+            info!("No new data stored in this run, not publishing to subscribers");
+            return Ok(());
+        }
+
+        for (tenant, report) in reports {
+            let subscribers = subscribers::select_subscribers(&ctx.db, Some(&tenant.name))?;
+            if subscribers.is_empty() {
+                continue;
+            }
+
+            let rendered = RenderedReport::new(
+                report,
+                ctx.config.dashboard_links.as_ref(),
+                ctx.config.precision,
+            );
+            let payload = serde_json::to_vec(&subscribers::report_payload(
+                &ctx.run_id,
+                &tenant.name,
+                report,
+                &rendered,
+            ))?;
+            for subscriber in &subscribers {
+                if let Err(e) = subscribers::deliver(
+                    &ctx.db,
+                    &ctx.http,
+                    ctx.clock.as_ref(),
+                    subscriber,
+                    &payload,
+                ) {
+                    warn!(
+                        "Could not deliver the report to subscriber {}: {e:?}",
+                        subscriber.id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delivers each tenant's newest pending report with the notifiers.
+    /// Reports of tenants in their quiet hours stay pending for a later notify, and so do reports
+    /// built with a configuration the canary tenants haven't received enough of yet. Canaries are
+    /// notified first, so this run's deliveries to them count. A tenant whose delivery fails
+    /// doesn't stop the others, and their report stays pending; the error names every tenant that
+    /// failed.
+    pub fn notify(&mut self) -> eyre::Result<()> {
+        let mut failed = Vec::new();
+        let mut tenants = self.ctx.config.tenants_or_default();
+        tenants.sort_by_key(|tenant| !tenant.canary);
+        for tenant in tenants {
+            if let Err(e) = self.notify_tenant(&tenant) {
+                warn!(
+                    "Could not deliver the report to tenant {}: {e:?}",
+                    tenant.name
+                );
+                failed.push(tenant.name);
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(eyre!(
+                "Could not deliver reports to tenants: {}",
+                failed.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    fn notify_tenant(&mut self, tenant: &Tenant) -> eyre::Result<()> {
+        if tenant
+            .quiet_hours
+            .is_some_and(|quiet_hours| quiet_hours.contains(self.ctx.clock.now()))
+        {
+            info!(
+                "Tenant {} is in its quiet hours, keeping its report pending",
+                tenant.name
+            );
+            return Ok(());
+        }
+
+        let ctx = &mut self.ctx;
+        let Some(report) =
+            pending::take_latest_pending_report(&mut ctx.db, ctx.clock.as_ref(), &tenant.name)?
+        else {
+            info!("No pending report to send for tenant {}", tenant.name);
+            return Ok(());
+        };
+
+        if let (false, Some(analysis_config)) = (tenant.canary, &report.analysis_config) {
+            let config = &ctx.config;
+            if !config
+                .canary
+                .allows(&ctx.db, &config.canaries(), &tenant.name, analysis_config)?
+            {
+                info!(
+                    "Report {} was built with a configuration canary tenants haven't received in {} runs yet, keeping it pending for tenant {}",
+                    report.id, config.canary.runs, tenant.name
+                );
+                return Ok(());
+            }
+        }
+
+        info!(
+            "Sending pending report {} from run {} to tenant {}",
+            report.id, report.run_id, tenant.name
+        );
+
+        let destinations: Vec<String> = self
+            .notifiers
+            .iter()
+            .flat_map(|notifier| notifier.destinations(tenant))
+            .collect();
+        let result = send_all(
+            self.notifiers.iter().map(AsRef::as_ref),
+            &self.ctx,
+            tenant,
+            &report,
+        );
+        alerts::finish_alerts(
+            &self.ctx.db,
+            self.ctx.clock.as_ref(),
+            report.id,
+            &destinations,
+            result.as_ref().err(),
+        )?;
+        result?;
+        pending::mark_delivered(&self.ctx.db, self.ctx.clock.as_ref(), report.id)
+    }
+}
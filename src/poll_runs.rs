@@ -0,0 +1,294 @@
+//! Records every fetch in `poll_runs`, so it can be audited whether a scheduled job ran and what
+//! it did.
+
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::db::{InsertCounts, InsertProgress};
+use crate::useful::Clock;
+
+/// How a poll run ended.
+#[derive(Debug, Clone, Default)]
+pub struct PollOutcome {
+    /// Status of the response, if the data was requested without a download path.
+    pub http_status: Option<u16>,
+    /// Rows read from the CSV, including ones that failed to parse.
+    pub rows_read: usize,
+    /// Rows the CSV parser rejected.
+    pub parse_errors: usize,
+    pub counts: InsertCounts,
+    /// Date/Time Updated of the file, if any row was parsed.
+    pub date_updated: Option<DateTime<FixedOffset>>,
+    /// True if the download or insert was skipped because the dataset was unchanged.
+    pub unchanged: bool,
+    /// Run whose interrupted insert this poll completed, if any.
+    pub resumed_run_id: Option<String>,
+    /// The error that stopped the run, if any.
+    pub error: Option<String>,
+}
+
+/// A run as stored in `poll_runs`.
+#[derive(Debug, Serialize)]
+pub struct PollRun {
+    pub run_id: String,
+    pub started_timestamp: u64,
+    pub finished_timestamp: Option<u64>,
+    pub source_url: String,
+    /// completed, unchanged, failed, or running if it never finished.
+    pub status: String,
+    pub http_status: Option<u16>,
+    pub rows_parsed: u64,
+    pub rows_inserted: u64,
+    pub rows_skipped: u64,
+    pub rows_revised: u64,
+    pub rows_retracted: u64,
+    pub rows_failed: u64,
+    pub error: Option<String>,
+    /// Run whose interrupted insert this poll completed, if any.
+    pub resumed_run_id: Option<String>,
+}
+
+/// Records that a run started polling `source_url`, returning its row id.
+pub fn start_poll_run(
+    conn: &Connection,
+    clock: &dyn Clock,
+    run_id: &str,
+    source_url: &str,
+) -> eyre::Result<i64> {
+    const INSERT_POLL_RUN_SQL: &str = "
+    INSERT INTO poll_runs (run_id, started_timestamp, source_url, status) VALUES
+    (:run_id, :started_timestamp, :source_url, 'running')";
+
+    conn.prepare_cached(INSERT_POLL_RUN_SQL)?
+        .execute(named_params! {
+            ":run_id": run_id,
+            ":started_timestamp": clock.unix_timestamp(),
+            ":source_url": source_url,
+        })?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn finish_poll_run(
+    conn: &Connection,
+    clock: &dyn Clock,
+    id: i64,
+    outcome: &PollOutcome,
+) -> eyre::Result<()> {
+    const FINISH_POLL_RUN_SQL: &str = "
+    UPDATE poll_runs SET
+        finished_timestamp = :finished_timestamp,
+        status = :status,
+        http_status = :http_status,
+        rows_parsed = :rows_parsed,
+        rows_inserted = :rows_inserted,
+        rows_skipped = :rows_skipped,
+        rows_revised = :rows_revised,
+        rows_retracted = :rows_retracted,
+        rows_failed = :rows_failed,
+        error = :error,
+        date_updated = :date_updated,
+        resumed_run_id = :resumed_run_id
+    WHERE id = :id";
+
+    let status = if outcome.error.is_some() {
+        "failed"
+    } else if outcome.unchanged {
+        "unchanged"
+    } else {
+        "completed"
+    };
+
+    conn.prepare_cached(FINISH_POLL_RUN_SQL)?
+        .execute(named_params! {
+            ":finished_timestamp": clock.unix_timestamp(),
+            ":status": status,
+            ":http_status": outcome.http_status,
+            ":rows_parsed": outcome.rows_read - outcome.parse_errors,
+            ":rows_inserted": outcome.counts.inserted,
+            ":rows_skipped": outcome.counts.skipped,
+            ":rows_revised": outcome.counts.revised,
+            ":rows_retracted": outcome.counts.retracted,
+            ":rows_failed": outcome.parse_errors + outcome.counts.errors,
+            ":error": outcome.error,
+            ":date_updated": outcome.date_updated,
+            ":resumed_run_id": outcome.resumed_run_id,
+            ":id": id,
+        })?;
+    Ok(())
+}
+
+/// Records how far poll run `id`'s insert got, in the transaction committing it.
+pub fn record_insert_progress(
+    conn: &Connection,
+    id: i64,
+    progress: &InsertProgress,
+) -> eyre::Result<()> {
+    const RECORD_INSERT_PROGRESS_SQL: &str = "
+    UPDATE poll_runs SET insert_progress = :insert_progress WHERE id = :id";
+
+    conn.prepare_cached(RECORD_INSERT_PROGRESS_SQL)?
+        .execute(named_params! {
+            ":insert_progress": serde_json::to_string(progress)?,
+            ":id": id,
+        })?;
+    Ok(())
+}
+
+/// The run and progress of the last insert from the same source before poll run `id` that was
+/// interrupted, crashing or failing after committing some samples, unless a poll completed since.
+pub fn select_interrupted_insert(
+    conn: &Connection,
+    id: i64,
+    source_url: &str,
+) -> eyre::Result<Option<(String, InsertProgress)>> {
+    const SELECT_INTERRUPTED_INSERT_SQL: &str = "
+    SELECT run_id, insert_progress FROM poll_runs
+    WHERE id < :id
+        AND source_url = :source_url
+        AND status IN ('running', 'failed')
+        AND insert_progress IS NOT NULL
+        AND id > (
+            SELECT IFNULL(MAX(id), 0) FROM poll_runs
+            WHERE id < :id AND status IN ('completed', 'unchanged')
+        )
+    ORDER BY id DESC
+    LIMIT 1";
+
+    let interrupted: Option<(String, String)> = conn
+        .prepare_cached(SELECT_INTERRUPTED_INSERT_SQL)?
+        .query_row(
+            named_params! { ":id": id, ":source_url": source_url },
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    interrupted
+        .map(|(run_id, progress)| Ok((run_id, serde_json::from_str(&progress)?)))
+        .transpose()
+}
+
+/// The most recently started poll run.
+pub fn select_last_poll_run(conn: &Connection) -> eyre::Result<Option<PollRun>> {
+    const SELECT_LAST_POLL_RUN_SQL: &str = "
+    SELECT run_id, started_timestamp, finished_timestamp, source_url, status, http_status,
+        rows_parsed, rows_inserted, rows_skipped, rows_revised, rows_retracted, rows_failed, error,
+        resumed_run_id
+    FROM poll_runs
+    ORDER BY id DESC
+    LIMIT 1";
+
+    let run = conn
+        .prepare_cached(SELECT_LAST_POLL_RUN_SQL)?
+        .query_row([], |row| {
+            Ok(PollRun {
+                run_id: row.get(0)?,
+                started_timestamp: row.get(1)?,
+                finished_timestamp: row.get(2)?,
+                source_url: row.get(3)?,
+                status: row.get(4)?,
+                http_status: row.get(5)?,
+                rows_parsed: row.get(6)?,
+                rows_inserted: row.get(7)?,
+                rows_skipped: row.get(8)?,
+                rows_revised: row.get(9)?,
+                rows_retracted: row.get(10)?,
+                rows_failed: row.get(11)?,
+                error: row.get(12)?,
+                resumed_run_id: row.get(13)?,
+            })
+        })
+        .optional()?;
+    Ok(run)
+}
+
+/// Date/Time Updated of the file the last successful poll read, if any did.
+pub fn select_last_date_updated(conn: &Connection) -> eyre::Result<Option<DateTime<FixedOffset>>> {
+    const SELECT_LAST_DATE_UPDATED_SQL: &str = "
+    SELECT date_updated FROM poll_runs
+    WHERE status IN ('completed', 'unchanged') AND date_updated IS NOT NULL
+    ORDER BY id DESC
+    LIMIT 1";
+
+    Ok(conn
+        .prepare_cached(SELECT_LAST_DATE_UPDATED_SQL)?
+        .query_row([], |row| row.get(0))
+        .optional()?)
+}
+
+/// Whether a poll in run `run_id` inserted or revised any samples.
+pub fn run_stored_new_data(conn: &Connection, run_id: &str) -> eyre::Result<bool> {
+    const RUN_STORED_NEW_DATA_SQL: &str = "
+    SELECT EXISTS (
+        SELECT 1 FROM poll_runs
+        WHERE run_id = ?1 AND rows_inserted + rows_revised > 0
+    )";
+
+    Ok(conn
+        .prepare_cached(RUN_STORED_NEW_DATA_SQL)?
+        .query_row([run_id], |row| row.get(0))?)
+}
+
+/// A new vintage of the dataset, by how its Date/Time Updated advanced and what storing it did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VintageChange {
+    pub previous: DateTime<FixedOffset>,
+    pub current: DateTime<FixedOffset>,
+    pub inserted: u64,
+    pub revised: u64,
+    pub retracted: u64,
+}
+
+impl fmt::Display for VintageChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |n: u64, singular: &str, plural: &str| {
+            format!("{n} {}", if n == 1 { singular } else { plural })
+        };
+        write!(
+            f,
+            "data updated: {} → {}, {}, {}, {}",
+            self.previous.format("%Y-%m-%d"),
+            self.current.format("%Y-%m-%d"),
+            count(self.inserted, "new sample", "new samples"),
+            count(self.revised, "revision", "revisions"),
+            count(self.retracted, "retraction", "retractions"),
+        )
+    }
+}
+
+/// How the dataset changed in run `run_id`, if a poll in it stored a file with a later Date/Time
+/// Updated than the last successful poll before it. A first poll has nothing to compare with.
+pub fn select_vintage_change(
+    conn: &Connection,
+    run_id: &str,
+) -> eyre::Result<Option<VintageChange>> {
+    const SELECT_VINTAGE_CHANGE_SQL: &str = "
+    SELECT previous.date_updated, current.date_updated,
+        current.rows_inserted, current.rows_revised, current.rows_retracted
+    FROM poll_runs current
+    JOIN poll_runs previous ON previous.id = (
+        SELECT id FROM poll_runs
+        WHERE id < current.id AND status IN ('completed', 'unchanged') AND date_updated IS NOT NULL
+        ORDER BY id DESC
+        LIMIT 1
+    )
+    WHERE current.run_id = ?1 AND current.status = 'completed' AND current.date_updated IS NOT NULL
+    ORDER BY current.id DESC
+    LIMIT 1";
+
+    let change = conn
+        .prepare_cached(SELECT_VINTAGE_CHANGE_SQL)?
+        .query_row([run_id], |row| {
+            Ok(VintageChange {
+                previous: row.get(0)?,
+                current: row.get(1)?,
+                inserted: row.get(2)?,
+                revised: row.get(3)?,
+                retracted: row.get(4)?,
+            })
+        })
+        .optional()?;
+    Ok(change.filter(|change| change.current > change.previous))
+}
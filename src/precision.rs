@@ -0,0 +1,89 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How many digits numbers are rendered with.
+/// Text outputs round for readability, while exports like `diff` and `export` always keep full precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Full,
+    SignificantFigures(u32),
+}
+
+impl Precision {
+    /// Formats `value`, e.g. 1221458.142 as `1220000` with 3 significant figures.
+    pub fn format(&self, value: f64) -> String {
+        match *self {
+            Precision::Full => value.to_string(),
+            Precision::SignificantFigures(_) if value == 0.0 || !value.is_finite() => {
+                value.to_string()
+            }
+            Precision::SignificantFigures(figures) => {
+                let magnitude = value.abs().log10().floor() as i32;
+                let decimals = figures as i32 - 1 - magnitude;
+                if decimals >= 0 {
+                    format!("{value:.*}", decimals as usize)
+                } else {
+                    let scale = 10f64.powi(-decimals);
+                    format!("{:.0}", (value / scale).round() * scale)
+                }
+            }
+        }
+    }
+
+    /// Formats `value` with an explicit sign, e.g. `+925000`.
+    pub fn format_signed(&self, value: f64) -> String {
+        let formatted = self.format(value);
+        if formatted.starts_with('-') {
+            formatted
+        } else {
+            format!("+{formatted}")
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    /// Parses `full` or a number of significant figures.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("full") {
+            return Ok(Precision::Full);
+        }
+
+        match s.parse::<u32>() {
+            Ok(figures) if figures > 0 => Ok(Precision::SignificantFigures(figures)),
+            _ => Err(format!(
+                "Expected \"full\" or a number of significant figures, got {s}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    /// As parsed: `full` or the number of significant figures.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Precision::Full => f.write_str("full"),
+            Precision::SignificantFigures(figures) => write!(f, "{figures}"),
+        }
+    }
+}
+
+/// Precision of each text output.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputPrecision {
+    /// Markdown sent to notifiers, and retrospectives.
+    pub markdown: Precision,
+    /// Tables printed to terminals.
+    pub table: Precision,
+}
+
+impl Default for OutputPrecision {
+    /// 3 significant figures everywhere.
+    fn default() -> Self {
+        Self {
+            markdown: Precision::SignificantFigures(3),
+            table: Precision::SignificantFigures(3),
+        }
+    }
+}
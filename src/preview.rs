@@ -0,0 +1,421 @@
+//! Social preview images for the static site, drawn without any image or font dependencies: a
+//! 1200×630 PNG per county with the latest level of each pathogen next to a small chart, the size
+//! Discord, Mastodon, and most other link previews crop to.
+
+use std::io::Write as _;
+
+use chrono::NaiveDate;
+use color_eyre::eyre;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::levels::ActivityLevel;
+
+pub const WIDTH: usize = 1200;
+pub const HEIGHT: usize = 630;
+
+const MARGIN: usize = 40;
+const HEADER_HEIGHT: usize = 110;
+const FOOTER_HEIGHT: usize = 40;
+
+const WHITE: Rgb = [0xff, 0xff, 0xff];
+const TEXT: Rgb = [0x22, 0x22, 0x22];
+const MUTED: Rgb = [0x66, 0x66, 0x66];
+const DIVIDER: Rgb = [0xdd, 0xdd, 0xdd];
+const HEADER: Rgb = [0x15, 0x65, 0xc0];
+const LINE: Rgb = [0x15, 0x65, 0xc0];
+const NO_LEVEL: Rgb = [0xbd, 0xbd, 0xbd];
+
+type Rgb = [u8; 3];
+
+/// One pathogen's row in a county preview.
+pub struct PreviewRow<'a> {
+    pub pathogen: &'a str,
+    /// The latest value, already formatted, or None if there are no samples.
+    pub latest: Option<String>,
+    pub level: Option<ActivityLevel>,
+    /// Daily means to chart, oldest first.
+    pub series: &'a [(NaiveDate, f64)],
+}
+
+/// The preview image of a county as a PNG.
+pub fn county_preview(county: &str, rows: &[PreviewRow]) -> eyre::Result<Vec<u8>> {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT, WHITE);
+
+    canvas.fill_rect(0, 0, WIDTH, HEADER_HEIGHT, HEADER);
+    let title = format!("{county} County");
+    let title_scale = fitting_scale(&title, WIDTH - 2 * MARGIN, 8);
+    canvas.draw_text(
+        MARGIN,
+        (HEADER_HEIGHT - 7 * title_scale) / 2,
+        title_scale,
+        &title,
+        WHITE,
+    );
+
+    let area_top = HEADER_HEIGHT + MARGIN / 2;
+    let area_height = HEIGHT - area_top - FOOTER_HEIGHT;
+    let row_height = area_height / rows.len().max(1);
+    let text_width = WIDTH / 3;
+    for (i, row) in rows.iter().enumerate() {
+        let top = area_top + i * row_height;
+        if i > 0 {
+            canvas.fill_rect(MARGIN, top, WIDTH - 2 * MARGIN, 1, DIVIDER);
+        }
+
+        let level_color = row.level.map_or(NO_LEVEL, level_color);
+        canvas.fill_rect(
+            MARGIN,
+            top + 8,
+            12,
+            row_height.saturating_sub(16),
+            level_color,
+        );
+
+        // Two lines of text, each at most half the row
+        let scale = (row_height.saturating_sub(24) / 2 / 8).clamp(1, 4);
+        let text_left = MARGIN + 28;
+        let name_top = top + row_height.saturating_sub(16 * scale) / 2;
+        let name_scale = fitting_scale(row.pathogen, text_width - 28, scale);
+        canvas.draw_text(text_left, name_top, name_scale, row.pathogen, TEXT);
+
+        let detail = match (&row.latest, row.level) {
+            (Some(latest), Some(level)) => format!("{latest} - {level}"),
+            (Some(latest), None) => latest.clone(),
+            (None, _) => "No samples".to_owned(),
+        };
+        let detail_scale = fitting_scale(&detail, text_width - 28, scale);
+        canvas.draw_text(
+            text_left,
+            name_top + 9 * scale,
+            detail_scale,
+            &detail,
+            MUTED,
+        );
+
+        canvas.draw_series(
+            MARGIN + text_width + 20,
+            top + 12,
+            WIDTH - 2 * MARGIN - text_width - 20,
+            row_height.saturating_sub(24),
+            row.series,
+        );
+    }
+
+    canvas.draw_text(
+        MARGIN,
+        HEIGHT - FOOTER_HEIGHT + (FOOTER_HEIGHT - 14) / 2,
+        2,
+        "Washington wastewater",
+        MUTED,
+    );
+
+    canvas.encode_png()
+}
+
+/// The swatch color of an activity level, a stronger shade of the site's table cell colors.
+fn level_color(level: ActivityLevel) -> Rgb {
+    match level {
+        ActivityLevel::VeryLow => [0x81, 0xc7, 0x84],
+        ActivityLevel::Low => [0xae, 0xd5, 0x81],
+        ActivityLevel::Moderate => [0xff, 0xd5, 0x4f],
+        ActivityLevel::High => [0xff, 0xb7, 0x4d],
+        ActivityLevel::VeryHigh => [0xe5, 0x73, 0x73],
+    }
+}
+
+/// The largest scale up to `max` at which `text` fits in `width` pixels.
+fn fitting_scale(text: &str, width: usize, max: usize) -> usize {
+    let advance = 6 * text.chars().count().max(1);
+    (width / advance).clamp(1, max)
+}
+
+/// An RGB image drawn in memory.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, background: Rgb) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    /// Fills a rectangle, clipped to the canvas.
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        for row in y..(y + height).min(self.height) {
+            let start = row * self.width;
+            for column in x..(x + width).min(self.width) {
+                self.pixels[start + column] = color;
+            }
+        }
+    }
+
+    /// Draws `text` in the 5×7 font with its top left at (`x`, `y`), each font pixel `scale`
+    /// pixels square. Letters are drawn in uppercase.
+    fn draw_text(&mut self, x: usize, y: usize, scale: usize, text: &str, color: Rgb) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i * 6 * scale;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0b10000 >> column) != 0 {
+                        self.fill_rect(left + column * scale, y + row * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws a thick line between two points.
+    fn draw_line(&mut self, (x0, y0): (f64, f64), (x1, y1): (f64, f64), color: Rgb) {
+        const THICKNESS: usize = 4;
+
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let x = (x0 + (x1 - x0) * t).round() as usize;
+            let y = (y0 + (y1 - y0) * t).round() as usize;
+            self.fill_rect(
+                x.saturating_sub(THICKNESS / 2),
+                y.saturating_sub(THICKNESS / 2),
+                THICKNESS,
+                THICKNESS,
+                color,
+            );
+        }
+    }
+
+    /// Charts `series` on a log scale in the given box, like the site's SVG charts.
+    fn draw_series(
+        &mut self,
+        left: usize,
+        top: usize,
+        width: usize,
+        height: usize,
+        series: &[(NaiveDate, f64)],
+    ) {
+        // Zero can't be plotted on a log scale
+        let points: Vec<(NaiveDate, f64)> = series
+            .iter()
+            .copied()
+            .filter(|&(_, value)| value > 0.0)
+            .collect();
+        let (Some(&(first_date, _)), Some(&(last_date, _))) = (points.first(), points.last())
+        else {
+            return;
+        };
+        if points.len() < 2 {
+            return;
+        }
+
+        let (min, max) = points.iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(min, max), &(_, value)| (min.min(value), max.max(value)),
+        );
+        let (log_min, log_max) = (min.log10(), max.log10());
+        let log_span = (log_max - log_min).max(f64::EPSILON);
+        let days = (last_date - first_date).num_days().max(1) as f64;
+
+        let coordinates: Vec<(f64, f64)> = points
+            .iter()
+            .map(|&(date, value)| {
+                let x = left as f64 + (date - first_date).num_days() as f64 / days * width as f64;
+                let y = top as f64 + (1.0 - (value.log10() - log_min) / log_span) * height as f64;
+                (x, y)
+            })
+            .collect();
+        for pair in coordinates.windows(2) {
+            self.draw_line(pair[0], pair[1], LINE);
+        }
+    }
+
+    /// Encodes the canvas as an 8-bit RGB PNG.
+    fn encode_png(&self) -> eyre::Result<Vec<u8>> {
+        let mut scanlines = Vec::with_capacity(self.height * (1 + 3 * self.width));
+        for row in self.pixels.chunks(self.width) {
+            // Filter type None
+            scanlines.push(0);
+            scanlines.extend(row.iter().flatten());
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&scanlines)?;
+        let compressed = encoder.finish()?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((self.height as u32).to_be_bytes());
+        // Bit depth 8, color type RGB, default compression, filtering, and no interlacing
+        header.extend([8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &compressed);
+        write_chunk(&mut png, b"IEND", &[]);
+        Ok(png)
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc.finalize().to_be_bytes());
+}
+
+/// Rows of the 5×7 glyph for `c`, the leftmost pixel in bit 4. Characters without one are drawn as
+/// a question mark.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        '.' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+        ',' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+        ],
+        '\'' => [
+            0b01100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+        '(' => [
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ],
+        ')' => [
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ],
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        '/' => [
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ],
+        ':' => [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ],
+        '%' => [
+            0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011,
+        ],
+        _ => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100,
+        ],
+    }
+}
@@ -0,0 +1,221 @@
+//! Sends reports to a Pushover user as push notifications. Each pathogen can have an alert
+//! threshold, and a report where a county's latest value crosses it, having been below it in the
+//! sample before, is sent at high priority, which Pushover delivers through the user's quiet hours.
+//!
+//! Crossings are found in the report's structured data, stored with it since [crate::json_webhook]
+//! posts it; reports stored before it was rendered are sent at normal priority. Tenant quiet hours
+//! still hold reports back, high priority or not.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+
+use crate::context::RunContext;
+use crate::http::{Body, HttpClient};
+use crate::mastodon::plain_text;
+use crate::ntfy::Notification;
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::tenants::Tenant;
+use crate::useful::Secret;
+
+/// Where the Pushover API is served.
+pub const DEFAULT_PUSHOVER_API_URL: &str = "https://api.pushover.net";
+/// Most characters Pushover accepts in a message.
+const MESSAGE_LIMIT: usize = 1024;
+/// Pushover's normal priority.
+const NORMAL_PRIORITY: i8 = 0;
+/// Pushover's high priority, which bypasses the user's quiet hours.
+const HIGH_PRIORITY: i8 = 1;
+
+/// A Pushover user reports are sent to, and the application sending them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushoverUser {
+    /// The application's API token.
+    pub app_token: Secret,
+    /// The user's or group's key.
+    pub user_key: Secret,
+    /// Concentrations that send the report at high priority when a county crosses them, by
+    /// pathogen target.
+    #[serde(default)]
+    pub alert_thresholds: AlertThresholds,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_api_url() -> String {
+    DEFAULT_PUSHOVER_API_URL.to_owned()
+}
+
+/// Alert thresholds by pathogen target.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AlertThresholds(pub BTreeMap<String, f64>);
+
+impl FromStr for AlertThresholds {
+    type Err = String;
+
+    /// Parses `pathogen=concentration` entries separated by semicolons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let thresholds = s
+            .split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (pathogen, threshold) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected pathogen=concentration, got {entry}"))?;
+                let threshold: f64 = threshold
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("Invalid threshold {threshold}: {e}"))?;
+                Ok((pathogen.trim().to_owned(), threshold))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self(thresholds))
+    }
+}
+
+/// A county's latest value for a pathogen reaching its threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crossing {
+    pub county: String,
+    pub pathogen: String,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// A line of [crate::json_webhook::report_data], as far as crossings need it.
+#[derive(Deserialize)]
+struct DataLine {
+    county: String,
+    pathogen: String,
+    latest_value: Option<f64>,
+    difference: Option<f64>,
+}
+
+impl AlertThresholds {
+    /// The lines of `report_data` whose latest value reached its pathogen's threshold from below,
+    /// or from no previous sample.
+    pub fn crossings(&self, report_data: &str) -> eyre::Result<Vec<Crossing>> {
+        let data: Value = serde_json::from_str(report_data)?;
+        let lines: Vec<DataLine> = serde_json::from_value(data["lines"].clone())?;
+
+        Ok(lines
+            .into_iter()
+            .filter_map(|line| {
+                let threshold = *self.0.get(&line.pathogen)?;
+                let value = line.latest_value?;
+                let previous = line.difference.map(|difference| value - difference);
+                let crossed =
+                    value >= threshold && previous.is_none_or(|previous| previous < threshold);
+                crossed.then_some(Crossing {
+                    county: line.county,
+                    pathogen: line.pathogen,
+                    value,
+                    threshold,
+                })
+            })
+            .collect())
+    }
+}
+
+impl PushoverUser {
+    /// Sends `message` with `title` at `priority`.
+    #[instrument(skip_all, fields(priority = priority))]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        title: &str,
+        message: &str,
+        priority: i8,
+    ) -> eyre::Result<()> {
+        let url = format!("{}/1/messages.json", self.api_url.trim_end_matches('/'));
+        let payload = json!({
+            "token": self.app_token.expose(),
+            "user": self.user_key.expose(),
+            "title": title,
+            "message": truncate(message),
+            "priority": priority,
+        });
+
+        http.send(conn, http.post(&url), Body::Json(&payload))?;
+        info!("Sent report to Pushover");
+
+        Ok(())
+    }
+}
+
+/// Sends to the tenant's Pushover user, if it has one, at high priority when a threshold is crossed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PushoverNotifier;
+
+impl Notifier for PushoverNotifier {
+    fn name(&self) -> &str {
+        "pushover"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(user) = &tenant.pushover_user else {
+            return Ok(());
+        };
+
+        let crossings = match &report.report_data {
+            Some(data) => user.alert_thresholds.crossings(data)?,
+            None => Vec::new(),
+        };
+        let message = match &report.ntfy_notification {
+            Some(notification) => serde_json::from_str::<Notification>(notification)?.message,
+            // Reports stored before notifications were rendered
+            None => plain_text(&report.markdown),
+        };
+        let title = match report.period.as_str() {
+            "" => "Wastewater report".to_owned(),
+            period => format!("Wastewater report for {period}"),
+        };
+
+        if crossings.is_empty() {
+            return user.send(&ctx.db, &ctx.http, &title, &message, NORMAL_PRIORITY);
+        }
+        let precision = ctx.config.precision.markdown;
+        let alerts: Vec<String> = crossings
+            .iter()
+            .map(|crossing| {
+                format!(
+                    "⚠️ {} {} reached {} (threshold {})",
+                    crossing.county,
+                    crossing.pathogen,
+                    precision.format(crossing.value),
+                    precision.format(crossing.threshold)
+                )
+            })
+            .collect();
+        info!(
+            "{} thresholds crossed, sending at high priority",
+            crossings.len()
+        );
+        user.send(
+            &ctx.db,
+            &ctx.http,
+            &format!("⚠️ {title}"),
+            &format!("{}\n\n{message}", alerts.join("\n")),
+            HIGH_PRIORITY,
+        )
+    }
+}
+
+/// `message` cut to fit [MESSAGE_LIMIT] characters.
+fn truncate(message: &str) -> String {
+    if message.chars().count() <= MESSAGE_LIMIT {
+        return message.to_owned();
+    }
+    let mut truncated: String = message.chars().take(MESSAGE_LIMIT - 1).collect();
+    truncated.push('…');
+    truncated
+}
@@ -0,0 +1,1009 @@
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, Utc};
+use color_eyre::eyre;
+use comfy_table::presets::UTF8_FULL_CONDENSED;
+use comfy_table::{CellAlignment, Table};
+use rusqlite::{params, Connection};
+use tracing::{info, instrument, warn};
+
+use crate::analysis::{
+    self, AnalysisOptions, ChangeScale, Measure, SamplingGap, TrendEstimate, TrendSample,
+    TREND_WINDOW_DAYS,
+};
+use crate::coverage::CoverageChange;
+use crate::db::CountyRevisions;
+use crate::history::{self, HistoricalContext};
+use crate::levels::{self, Activity};
+use crate::links::{slug, DashboardLinks};
+use crate::poll_runs::VintageChange;
+use crate::precision::Precision;
+use crate::rules::ThresholdAlert;
+use crate::season::SeasonOnset;
+use crate::slugs;
+
+/// Latest sample for a county and pathogen, compared with the sample before it.
+#[derive(Debug, Clone)]
+pub struct SampleSummary {
+    pub latest_value: f64,
+    pub latest_date: NaiveDate,
+    /// Difference between the latest and previous values.
+    pub difference: Option<f64>,
+    /// Relative change from the previous value on a log scale, e.g. 0.5 for +50%.
+    pub relative_change: Option<f64>,
+    pub previous_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportLine {
+    pub county: String,
+    /// The county's stable slug, for links to its pages.
+    pub county_slug: String,
+    pub pathogen: String,
+    /// None if the summary couldn't be queried, usually because there is no data.
+    pub summary: Option<SampleSummary>,
+    /// Trend over the window leading up to the latest sample, if there are enough samples.
+    pub trend: Option<TrendEstimate>,
+    /// Longest sampling gap in the trend window, which makes the trend and difference unreliable.
+    pub gap: Option<SamplingGap>,
+    /// How many of the county's sites the latest values are based on.
+    pub coverage: Option<SiteCoverage>,
+    /// Activity level, if enabled for the pathogen and the sites have enough history.
+    pub activity: Option<Activity>,
+    /// Means over the days up to the latest sample, if they could be queried.
+    pub averages: Option<RollingAverages>,
+    /// Where the latest samples sit in the sites' own past year, if they have enough history.
+    pub history: Option<HistoricalContext>,
+}
+
+/// Days the short and long rolling averages cover, up to and including the latest sample's.
+pub const ROLLING_AVERAGE_DAYS: (u64, u64) = (7, 14);
+
+/// Mean concentration of a county's samples over the [ROLLING_AVERAGE_DAYS] up to its latest one.
+/// Sites sample on different days, so these are steadier than the latest value.
+#[derive(Debug, Clone, Copy)]
+pub struct RollingAverages {
+    pub short: f64,
+    pub long: f64,
+    /// Relative change of the latest value from the longer average, e.g. 0.5 for +50%. Unlike
+    /// the difference from the previous sample, it compares across pathogens.
+    pub latest_change: f64,
+}
+
+impl RollingAverages {
+    /// The averages as written in messages, e.g. "7-day average 1200, 14-day average 950 (latest
+    /// +26%)".
+    pub fn describe(&self, precision: Precision) -> String {
+        format!(
+            "{}-day average {}, {}-day average {} (latest {:+.0}%)",
+            ROLLING_AVERAGE_DAYS.0,
+            precision.format(self.short),
+            ROLLING_AVERAGE_DAYS.1,
+            precision.format(self.long),
+            self.latest_change * 100.0
+        )
+    }
+}
+
+/// Number of days counted as one reporting period when comparing site coverage.
+pub const COVERAGE_PERIOD_DAYS: u64 = 7;
+
+/// How many of a county's sites reported a pathogen in the period ending at its latest sample.
+/// A drop in coverage can look like a trend, so reports flag it.
+#[derive(Debug, Clone, Copy)]
+pub struct SiteCoverage {
+    /// Sites with a sample in the [COVERAGE_PERIOD_DAYS] days up to the latest sample.
+    pub reporting: usize,
+    /// Sites with a sample in the period before that.
+    pub previous: usize,
+    /// Sites that have ever reported the pathogen in the county, up to the latest sample.
+    pub total: usize,
+}
+
+impl SiteCoverage {
+    pub fn dropped(&self) -> bool {
+        self.reporting < self.previous
+    }
+}
+
+/// A county's activity level for one pathogen, among all counties in the database.
+#[derive(Debug, Clone)]
+pub struct CountyLevel {
+    pub county: String,
+    /// Mean of each site's latest concentration.
+    pub level: f64,
+    pub sites: usize,
+    /// Whether the county is one of the report's configured counties.
+    pub highlighted: bool,
+}
+
+/// Every county with data for a pathogen, from highest to lowest activity.
+#[derive(Debug, Clone)]
+pub struct PathogenRanking {
+    pub pathogen: String,
+    pub counties: Vec<CountyLevel>,
+}
+
+/// Where the data in a report came from and when it was processed.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub version: &'static str,
+    /// Upstream `Date/Time Updated` of the newest data in the database.
+    pub date_updated: Option<DateTime<FixedOffset>>,
+    pub source_url: String,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// Inclusive range of sample collection dates a report covers. Unbounded sides are None.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DateRange {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+}
+
+impl std::fmt::Display for DateRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.since, self.until) {
+            (Some(since), Some(until)) => write!(f, "from {since} to {until}"),
+            (Some(since), None) => write!(f, "since {since}"),
+            (None, Some(until)) => write!(f, "up to {until}"),
+            (None, None) => write!(f, "for all dates"),
+        }
+    }
+}
+
+/// Events worth pointing out alongside the numbers.
+#[derive(Debug, Clone, Default)]
+pub struct Notices {
+    /// Changes in which sites report, noticed since the last ingest.
+    pub coverage_changes: Vec<CoverageChange>,
+    /// Seasons that started since the last report.
+    pub season_onsets: Vec<SeasonOnset>,
+    /// Samples restated upstream since the last report.
+    pub revisions: Vec<CountyRevisions>,
+    /// How the dataset changed, if this run stored a new vintage of it.
+    pub vintage: Option<VintageChange>,
+    /// The tenant's alert rules that fired, each once per sample.
+    pub threshold_alerts: Vec<ThresholdAlert>,
+}
+
+impl Notices {
+    /// Keeps only the notices about `counties`.
+    pub fn about(&self, counties: &[String]) -> Notices {
+        Notices {
+            coverage_changes: self
+                .coverage_changes
+                .iter()
+                .filter(|change| counties.iter().any(|county| county == change.county()))
+                .cloned()
+                .collect(),
+            season_onsets: self
+                .season_onsets
+                .iter()
+                .filter(|onset| counties.contains(&onset.county))
+                .cloned()
+                .collect(),
+            revisions: self
+                .revisions
+                .iter()
+                .filter(|revisions| counties.contains(&revisions.county))
+                .cloned()
+                .collect(),
+            vintage: self.vintage.clone(),
+            threshold_alerts: self
+                .threshold_alerts
+                .iter()
+                .filter(|alert| counties.contains(&alert.county))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub range: DateRange,
+    pub lines: Vec<ReportLine>,
+    pub rankings: Vec<PathogenRanking>,
+    pub notices: Notices,
+    /// How changes from the previous sample are shown.
+    pub change_scale: ChangeScale,
+    /// Provenance footer, if enabled.
+    pub provenance: Option<Provenance>,
+    /// Opening line template replacing the default greeting, see [crate::tenants::Tenant::greeting].
+    pub greeting: Option<String>,
+}
+
+impl Report {
+    /// The part of a report built for many counties and pathogens about only `counties` and
+    /// `pathogens`, in their order, with rankings highlighting `counties`. Notices are kept as is.
+    pub fn select(&self, counties: &[String], pathogens: &[String]) -> Report {
+        let lines = counties
+            .iter()
+            .flat_map(|county| pathogens.iter().map(move |pathogen| (county, pathogen)))
+            .filter_map(|(county, pathogen)| {
+                self.lines
+                    .iter()
+                    .find(|line| &line.county == county && &line.pathogen == pathogen)
+                    .cloned()
+            })
+            .collect();
+
+        let rankings = pathogens
+            .iter()
+            .filter_map(|pathogen| {
+                let mut ranking = self
+                    .rankings
+                    .iter()
+                    .find(|ranking| &ranking.pathogen == pathogen)?
+                    .clone();
+                for county in &mut ranking.counties {
+                    county.highlighted = counties.contains(&county.county);
+                }
+                Some(ranking)
+            })
+            .collect();
+
+        Report {
+            range: self.range,
+            lines,
+            rankings,
+            notices: self.notices.clone(),
+            change_scale: self.change_scale,
+            provenance: self.provenance.clone(),
+            greeting: self.greeting.clone(),
+        }
+    }
+
+    /// Latest sample date covered by the report.
+    pub fn period(&self) -> Option<NaiveDate> {
+        self.lines
+            .iter()
+            .filter_map(|line| line.summary.as_ref())
+            .map(|summary| summary.latest_date)
+            .max()
+    }
+
+    /// The report's opening line, the greeting if one is set.
+    pub fn greeting_line(&self) -> String {
+        if let Some(greeting) = &self.greeting {
+            let range = if self.range.is_unbounded() {
+                "for the latest samples".to_owned()
+            } else {
+                self.range.to_string()
+            };
+            greeting.replace("{range}", &range)
+        } else if self.range.is_unbounded() {
+            "Hello World! I've gathered the latest respratory illness wastewater data:".to_owned()
+        } else {
+            format!(
+                "Hello World! I've gathered the respratory illness wastewater data {}:",
+                self.range
+            )
+        }
+    }
+
+    /// The latest value of a line with its change, coverage, trend, and activity, e.g.
+    /// "174000 (-132000) on 2024-12-27, based on 2 of 2 reporting sites — possibly falling — low
+    /// confidence". `strong` emphasizes the activity level in the output's markup.
+    /// None if the line has no samples.
+    pub fn describe_line(
+        &self,
+        line: &ReportLine,
+        precision: Precision,
+        strong: impl Fn(&str) -> String,
+    ) -> Option<String> {
+        let summary = line.summary.as_ref()?;
+        let difference = self
+            .format_change(summary, precision)
+            .unwrap_or_else(|| "no previous sample".to_owned());
+        let mut details = format!(
+            "{} ({difference}) on {}",
+            precision.format(summary.latest_value),
+            summary.latest_date
+        );
+        if let Some(coverage) = &line.coverage {
+            details.push_str(&format!(
+                ", based on {} of {} reporting sites",
+                coverage.reporting, coverage.total
+            ));
+            if coverage.dropped() {
+                details.push_str(&format!(
+                    " (⚠️ down from {} the week before)",
+                    coverage.previous
+                ));
+            }
+        }
+        if let Some(averages) = &line.averages {
+            details.push_str(&format!("; {}", averages.describe(precision)));
+        }
+        if let Some(history) = &line.history {
+            details.push_str(&format!("; {}", history.describe()));
+        }
+        if let Some(trend) = &line.trend {
+            details.push_str(&format!(" — {} {}", trend.emoji(), trend.label()));
+            if let Some(doubling) = trend.describe_doubling() {
+                details.push_str(&format!(", {doubling}"));
+            }
+        }
+        if let Some(gap) = &line.gap {
+            details.push_str(&format!(" (⚠️ {})", gap.note()));
+        }
+        if let Some(activity) = &line.activity {
+            details.push_str(&format!(
+                " — {} activity",
+                strong(&activity.level.to_string())
+            ));
+        }
+        Some(details)
+    }
+
+    /// Renders the report as Discord-flavored markdown.
+    pub fn to_markdown(&self, links: Option<&DashboardLinks>, precision: Precision) -> String {
+        let mut content_vec = vec![self.greeting_line()];
+
+        for line in &self.lines {
+            let ReportLine {
+                county,
+                county_slug,
+                pathogen,
+                ..
+            } = line;
+            match self.describe_line(line, precision, |text| format!("**{text}**")) {
+                Some(details) => {
+                    let mut line = format!("**{county} County - {pathogen}**: {details}");
+                    if let Some(links) = links {
+                        // Angle brackets stop Discord from embedding a preview for every link
+                        line.push_str(&format!(
+                            " ([details](<{}>))",
+                            links.chart(county_slug, pathogen)
+                        ));
+                    }
+                    content_vec.push(line);
+                }
+                None => {
+                    content_vec.push(format!("**{county} County - {pathogen}**: There was an error getting data for this. Yell at Izzy."));
+                }
+            }
+        }
+
+        for alert in &self.notices.threshold_alerts {
+            content_vec.push(format!("🚨 {alert}"));
+        }
+        for change in &self.notices.coverage_changes {
+            content_vec.push(format!("📍 {change}"));
+        }
+        for onset in &self.notices.season_onsets {
+            content_vec.push(format!("🦠 {onset}"));
+        }
+        for revisions in &self.notices.revisions {
+            content_vec.push(format!("✏️ {revisions}"));
+        }
+        if let Some(vintage) = &self.notices.vintage {
+            content_vec.push(format!("🔄 {vintage}"));
+        }
+
+        if self
+            .rankings
+            .iter()
+            .any(|ranking| !ranking.counties.is_empty())
+        {
+            content_vec.push(String::new());
+            content_vec
+                .push("**Statewide ranking** (mean of each site's latest sample):".to_owned());
+            for ranking in &self.rankings {
+                let counties: Vec<String> = ranking
+                    .counties
+                    .iter()
+                    .enumerate()
+                    .map(|(i, county)| {
+                        let entry = format!(
+                            "{}. {} ({})",
+                            i + 1,
+                            county.county,
+                            precision.format(county.level)
+                        );
+                        if county.highlighted {
+                            format!("**{entry}**")
+                        } else {
+                            entry
+                        }
+                    })
+                    .collect();
+                if !counties.is_empty() {
+                    content_vec.push(format!("{}: {}", ranking.pathogen, counties.join(" · ")));
+                }
+            }
+        }
+
+        if let Some(provenance) = &self.provenance {
+            // -# renders as small subtext in Discord
+            content_vec.push(format!("-# {}", provenance.footer()));
+        }
+
+        content_vec.join("\n")
+    }
+
+    /// Renders the report as an aligned table for terminals, with a trend arrow per row.
+    /// The arrow follows the estimated trend when there is one, and the last change otherwise.
+    pub fn to_table(&self, precision: Precision) -> String {
+        let mut table = Table::new();
+        table.load_preset(UTF8_FULL_CONDENSED).set_header([
+            "County",
+            "Pathogen",
+            "Latest",
+            "7d / 14d avg",
+            "Change",
+            "Past year",
+            "Trend",
+            "Sites",
+            "Activity",
+            "Date",
+        ]);
+
+        for line in &self.lines {
+            let row = match &line.summary {
+                Some(summary) => {
+                    let change = self
+                        .format_change(summary, precision)
+                        .unwrap_or_else(|| "-".to_owned());
+                    let arrow = match summary.difference {
+                        Some(difference) if difference > 0.0 => "↑",
+                        Some(difference) if difference < 0.0 => "↓",
+                        Some(_) => "→",
+                        None => "",
+                    };
+                    let trend = match &line.trend {
+                        Some(trend) => match trend.doubling_days() {
+                            Some(days) => format!(
+                                "{} {} ({:+.0}%/wk, {} in {:.0}d)",
+                                trend.arrow(),
+                                trend.label(),
+                                trend.weekly_change * 100.0,
+                                if days > 0.0 { "×2" } else { "×½" },
+                                days.abs()
+                            ),
+                            None => format!(
+                                "{} {} ({:+.0}%/wk)",
+                                trend.arrow(),
+                                trend.label(),
+                                trend.weekly_change * 100.0
+                            ),
+                        },
+                        None => arrow.to_owned(),
+                    };
+                    let trend = match &line.gap {
+                        Some(_) => format!("{trend} ⚠"),
+                        None => trend,
+                    };
+                    let sites = match &line.coverage {
+                        Some(coverage) if coverage.dropped() => format!(
+                            "{}/{} (was {})",
+                            coverage.reporting, coverage.total, coverage.previous
+                        ),
+                        Some(coverage) => format!("{}/{}", coverage.reporting, coverage.total),
+                        None => String::new(),
+                    };
+                    let averages = line
+                        .averages
+                        .map(|averages| {
+                            format!(
+                                "{} / {}",
+                                precision.format(averages.short),
+                                precision.format(averages.long)
+                            )
+                        })
+                        .unwrap_or_default();
+                    vec![
+                        line.county.clone(),
+                        line.pathogen.clone(),
+                        precision.format(summary.latest_value),
+                        averages,
+                        change,
+                        line.history
+                            .as_ref()
+                            .map(|history| match history.describe_extreme() {
+                                Some(extreme) => format!("{} ({extreme})", history.rank()),
+                                None => history.rank(),
+                            })
+                            .unwrap_or_default(),
+                        trend,
+                        sites,
+                        line.activity
+                            .as_ref()
+                            .map(|activity| {
+                                format!(
+                                    "{} {:.1} ({})",
+                                    activity.level, activity.value, activity.source
+                                )
+                            })
+                            .unwrap_or_default(),
+                        summary.latest_date.to_string(),
+                    ]
+                }
+                None => vec![
+                    line.county.clone(),
+                    line.pathogen.clone(),
+                    "no data".to_owned(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                ],
+            };
+            table.add_row(row);
+        }
+
+        for column in [2, 3, 4] {
+            if let Some(column) = table.column_mut(column) {
+                column.set_cell_alignment(CellAlignment::Right);
+            }
+        }
+
+        let mut rendered = table.to_string();
+        for line in &self.lines {
+            if let Some(gap) = &line.gap {
+                rendered.push_str(&format!(
+                    "\n⚠ {} {}: {}",
+                    line.county,
+                    line.pathogen,
+                    gap.note()
+                ));
+            }
+        }
+        for alert in &self.notices.threshold_alerts {
+            rendered.push('\n');
+            rendered.push_str(&alert.to_string());
+        }
+        for change in &self.notices.coverage_changes {
+            rendered.push('\n');
+            rendered.push_str(&change.to_string());
+        }
+        for onset in &self.notices.season_onsets {
+            rendered.push('\n');
+            rendered.push_str(&onset.to_string());
+        }
+        for revisions in &self.notices.revisions {
+            rendered.push('\n');
+            rendered.push_str(&revisions.to_string());
+        }
+        if let Some(vintage) = &self.notices.vintage {
+            rendered.push('\n');
+            rendered.push_str(&vintage.to_string());
+        }
+        if self
+            .rankings
+            .iter()
+            .any(|ranking| !ranking.counties.is_empty())
+        {
+            rendered.push('\n');
+            rendered.push_str(&self.to_ranking_table(precision));
+        }
+        if let Some(provenance) = &self.provenance {
+            rendered.push('\n');
+            rendered.push_str(&provenance.footer());
+        }
+        rendered
+    }
+
+    /// Formats the change from the previous sample in the report's change scale, or None if there
+    /// is no previous sample.
+    pub fn format_change(&self, summary: &SampleSummary, precision: Precision) -> Option<String> {
+        match self.change_scale {
+            ChangeScale::Linear => summary.difference.map(|difference| {
+                let difference = precision.format_signed(difference);
+                match summary.relative_change {
+                    Some(change) => format!("{difference}, {:+.0}%", change * 100.0),
+                    None => difference,
+                }
+            }),
+            ChangeScale::Log => summary
+                .relative_change
+                .map(|change| format!("{:+.0}%", change * 100.0)),
+        }
+    }
+
+    /// Renders the statewide rankings as a table, marking configured counties with `*`.
+    fn to_ranking_table(&self, precision: Precision) -> String {
+        let mut table = Table::new();
+        table
+            .load_preset(UTF8_FULL_CONDENSED)
+            .set_header(["Pathogen", "Rank", "County", "Level", "Sites"]);
+
+        for ranking in &self.rankings {
+            for (i, county) in ranking.counties.iter().enumerate() {
+                let name = if county.highlighted {
+                    format!("{} *", county.county)
+                } else {
+                    county.county.clone()
+                };
+                table.add_row(vec![
+                    ranking.pathogen.clone(),
+                    (i + 1).to_string(),
+                    name,
+                    precision.format(county.level),
+                    county.sites.to_string(),
+                ]);
+            }
+        }
+
+        for column in [1, 3, 4] {
+            if let Some(column) = table.column_mut(column) {
+                column.set_cell_alignment(CellAlignment::Right);
+            }
+        }
+
+        table.to_string()
+    }
+}
+
+impl Provenance {
+    pub fn new(
+        conn: &Connection,
+        source_url: &str,
+        generated_at: DateTime<Utc>,
+    ) -> eyre::Result<Self> {
+        let date_updated = conn.query_row(
+            "SELECT MAX(date_updated) FROM wastewater_samples",
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION"),
+            date_updated,
+            source_url: source_url.to_owned(),
+            generated_at,
+        })
+    }
+
+    /// One-line footer, e.g. "hygieia v0.1.0 · data updated 2024-12-03 10:00 -08:00 · source: <url> · generated 2024-12-03 19:00 UTC".
+    pub fn footer(&self) -> String {
+        let date_updated = self
+            .date_updated
+            .map(|d| d.format("%Y-%m-%d %H:%M %:z").to_string())
+            .unwrap_or_else(|| "unknown".to_owned());
+
+        format!(
+            "hygieia v{} · data updated {} · source: <{}> · generated {}",
+            self.version,
+            date_updated,
+            self.source_url,
+            self.generated_at.format("%Y-%m-%d %H:%M UTC")
+        )
+    }
+}
+
+/// Queries the latest sample and its difference from the previous one for every county and pathogen.
+/// Only samples collected within `range` are considered, so "latest" means latest within the range.
+#[instrument(skip(conn, notices, provenance))]
+pub fn build_report(
+    conn: &Connection,
+    counties: &[&str],
+    pathogens: &[&str],
+    range: DateRange,
+    options: &AnalysisOptions,
+    notices: Notices,
+    provenance: Option<Provenance>,
+) -> Report {
+    let query = format!(
+        r#"
+        WITH ranked_samples AS (
+            SELECT {measure} as value, sample_collection_date, pcr_pathogen_target,
+                    ROW_NUMBER() OVER (PARTITION BY pcr_pathogen_target ORDER BY sample_collection_date DESC) as row_num
+            FROM wastewater_samples
+            WHERE county = ?1 AND pcr_pathogen_target = ?2
+                AND (?3 IS NULL OR sample_collection_date >= ?3)
+                AND (?4 IS NULL OR sample_collection_date <= ?4)
+                AND {measure} IS NOT NULL
+        )
+        SELECT
+            s1.value as latest_value,
+            s1.sample_collection_date as latest_date,
+            s1.value - s2.value as difference,
+            s2.sample_collection_date as previous_date
+        FROM ranked_samples s1
+        LEFT JOIN ranked_samples s2 ON s2.row_num = 2 AND s1.pcr_pathogen_target = s2.pcr_pathogen_target
+        WHERE s1.row_num = 1
+    "#,
+        measure = options.measure.column()
+    );
+
+    let lines = counties
+        .iter()
+        .flat_map(|&county| pathogens.iter().map(move |&pathogen| (county, pathogen)))
+        .map(|(county, pathogen)| {
+            let result = conn.query_row(&query, params![county, pathogen, range.since, range.until], |row| {
+                let latest_value: f64 = row.get(0)?;
+                let difference: Option<f64> = row.get(2)?;
+                Ok(SampleSummary {
+                    latest_value,
+                    latest_date: row.get(1)?,
+                    difference,
+                    relative_change: difference.map(|difference| {
+                        options.relative_change(latest_value - difference, latest_value)
+                    }),
+                    previous_date: row.get(3)?,
+                })
+            });
+
+            let summary = match result {
+                Ok(summary) => {
+                    info!(
+                        "{} County - {}: Latest value: {} on {}, Difference: {:?} (Previous date: {:?})",
+                        county, pathogen, summary.latest_value, summary.latest_date, summary.difference, summary.previous_date
+                    );
+                    Some(summary)
+                }
+                Err(e) => {
+                    warn!("No data found for {} County - {}: {}", county, pathogen, e);
+                    None
+                }
+            };
+
+            let trend_samples = summary.as_ref().and_then(|summary| {
+                match select_trend_samples(conn, county, pathogen, summary.latest_date, options.measure) {
+                    Ok(samples) => Some(samples),
+                    Err(e) => {
+                        warn!("Could not query trend samples for {} County - {}: {}", county, pathogen, e);
+                        None
+                    }
+                }
+            });
+            let trend = trend_samples
+                .as_deref()
+                .and_then(|samples| analysis::estimate_trend(samples, options));
+            let gap = trend_samples.as_deref().and_then(analysis::find_sampling_gap);
+
+            let coverage = summary.as_ref().and_then(|summary| {
+                match select_site_coverage(conn, county, pathogen, range, summary.latest_date) {
+                    Ok(coverage) => Some(coverage),
+                    Err(e) => {
+                        warn!("Could not query site coverage for {} County - {}: {}", county, pathogen, e);
+                        None
+                    }
+                }
+            });
+
+            let thresholds = options.activity_levels.pathogens.get(pathogen);
+            let activity = summary.as_ref().zip(thresholds).and_then(|(summary, thresholds)| {
+                match levels::county_activity(conn, county, pathogen, summary.latest_date, thresholds, options) {
+                    Ok(activity) => activity,
+                    Err(e) => {
+                        warn!("Could not compute activity level for {} County - {}: {}", county, pathogen, e);
+                        None
+                    }
+                }
+            });
+
+            let averages = summary.as_ref().and_then(|summary| {
+                match select_rolling_averages(conn, county, pathogen, range, summary, options) {
+                    Ok(averages) => averages,
+                    Err(e) => {
+                        warn!("Could not query rolling averages for {} County - {}: {}", county, pathogen, e);
+                        None
+                    }
+                }
+            });
+
+            let history = summary.as_ref().and_then(|summary| {
+                match history::county_history(conn, county, pathogen, summary.latest_date, options.measure) {
+                    Ok(history) => history,
+                    Err(e) => {
+                        warn!("Could not rank against history for {} County - {}: {}", county, pathogen, e);
+                        None
+                    }
+                }
+            });
+
+            let county_slug = slugs::county_slug(conn, county).unwrap_or_else(|e| {
+                warn!("Could not look up the slug of {} County: {}", county, e);
+                slug(county)
+            });
+
+            ReportLine {
+                county: county.to_owned(),
+                county_slug,
+                pathogen: pathogen.to_owned(),
+                summary,
+                trend,
+                gap,
+                coverage,
+                activity,
+                averages,
+                history,
+            }
+        })
+        .collect();
+
+    let rankings = pathogens
+        .iter()
+        .map(|&pathogen| {
+            let counties = select_county_levels(conn, pathogen, range, counties, options.measure)
+                .unwrap_or_else(|e| {
+                    warn!("Could not rank counties for {}: {}", pathogen, e);
+                    Vec::new()
+                });
+            PathogenRanking {
+                pathogen: pathogen.to_owned(),
+                counties,
+            }
+        })
+        .collect();
+
+    Report {
+        range,
+        lines,
+        rankings,
+        notices,
+        change_scale: options.change_scale,
+        provenance,
+        greeting: None,
+    }
+}
+
+/// Ranks every county with samples of `pathogen` within `range` by the mean of each site's latest
+/// sample. Counties in `highlighted` are marked so readers can find their own.
+fn select_county_levels(
+    conn: &Connection,
+    pathogen: &str,
+    range: DateRange,
+    highlighted: &[&str],
+    measure: Measure,
+) -> rusqlite::Result<Vec<CountyLevel>> {
+    let select_county_levels_sql = format!(
+        "
+    WITH latest_site_samples AS (
+        SELECT county, {measure} as value,
+                ROW_NUMBER() OVER (PARTITION BY county, site_name ORDER BY sample_collection_date DESC) as row_num
+        FROM wastewater_samples
+        WHERE pcr_pathogen_target = ?1
+            AND (?2 IS NULL OR sample_collection_date >= ?2)
+            AND (?3 IS NULL OR sample_collection_date <= ?3)
+            AND {measure} IS NOT NULL
+    )
+    SELECT county, AVG(value), COUNT(*) FROM latest_site_samples
+    WHERE row_num = 1
+    GROUP BY county
+    ORDER BY 2 DESC",
+        measure = measure.column()
+    );
+
+    conn.prepare_cached(&select_county_levels_sql)?
+        .query_map(params![pathogen, range.since, range.until], |row| {
+            let county: String = row.get(0)?;
+            Ok(CountyLevel {
+                highlighted: highlighted.contains(&county.as_str()),
+                county,
+                level: row.get(1)?,
+                sites: row.get(2)?,
+            })
+        })?
+        .collect()
+}
+
+/// Counts the sites reporting `pathogen` in `county` in the period ending at `latest_date` and the
+/// period before it, out of every site that has reported it within `range`.
+fn select_site_coverage(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    range: DateRange,
+    latest_date: NaiveDate,
+) -> rusqlite::Result<SiteCoverage> {
+    const SELECT_SITE_COVERAGE_SQL: &str = "
+    SELECT
+        COUNT(DISTINCT site_name) FILTER (WHERE sample_collection_date > ?4),
+        COUNT(DISTINCT site_name) FILTER (WHERE sample_collection_date > ?5 AND sample_collection_date <= ?4),
+        COUNT(DISTINCT site_name)
+    FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+        AND (?3 IS NULL OR sample_collection_date >= ?3)
+        AND sample_collection_date <= ?6";
+
+    let period_start = latest_date - Days::new(COVERAGE_PERIOD_DAYS);
+    let previous_start = period_start - Days::new(COVERAGE_PERIOD_DAYS);
+
+    conn.prepare_cached(SELECT_SITE_COVERAGE_SQL)?.query_row(
+        params![
+            county,
+            pathogen,
+            range.since,
+            period_start,
+            previous_start,
+            latest_date
+        ],
+        |row| {
+            Ok(SiteCoverage {
+                reporting: row.get(0)?,
+                previous: row.get(1)?,
+                total: row.get(2)?,
+            })
+        },
+    )
+}
+
+/// Means of the county's samples of `pathogen` in the [ROLLING_AVERAGE_DAYS] up to the latest
+/// sample in `summary`, within `range`, and how the latest value compares with them. None if there
+/// are none, which can't happen since the latest sample is one.
+fn select_rolling_averages(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    range: DateRange,
+    summary: &SampleSummary,
+    options: &AnalysisOptions,
+) -> rusqlite::Result<Option<RollingAverages>> {
+    let select_rolling_averages_sql = format!(
+        "
+    SELECT
+        AVG(IIF(sample_collection_date > ?3, {measure}, NULL)),
+        AVG({measure})
+    FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+        AND sample_collection_date > ?4 AND sample_collection_date <= ?5
+        AND (?6 IS NULL OR sample_collection_date >= ?6)",
+        measure = options.measure.column()
+    );
+    let latest_date = summary.latest_date;
+
+    let (short_days, long_days) = ROLLING_AVERAGE_DAYS;
+    let (short, long): (Option<f64>, Option<f64>) = conn
+        .prepare_cached(&select_rolling_averages_sql)?
+        .query_row(
+            params![
+                county,
+                pathogen,
+                latest_date - Days::new(short_days),
+                latest_date - Days::new(long_days),
+                latest_date,
+                range.since
+            ],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+    Ok(short.zip(long).map(|(short, long)| RollingAverages {
+        short,
+        long,
+        latest_change: options.relative_change(long, summary.latest_value),
+    }))
+}
+
+/// Queries the samples in the trend window ending at `latest_date`.
+fn select_trend_samples(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    latest_date: NaiveDate,
+    measure: Measure,
+) -> rusqlite::Result<Vec<TrendSample>> {
+    let select_trend_samples_sql = format!(
+        "
+    SELECT site_name, sample_collection_date, {measure} FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+    AND sample_collection_date > ?3 AND sample_collection_date <= ?4
+    AND {measure} IS NOT NULL",
+        measure = measure.column()
+    );
+
+    let window_start = latest_date - Days::new(TREND_WINDOW_DAYS as u64);
+
+    conn.prepare_cached(&select_trend_samples_sql)?
+        .query_map(
+            params![county, pathogen, window_start, latest_date],
+            |row| {
+                Ok(TrendSample {
+                    site_name: row.get(0)?,
+                    sample_collection_date: row.get(1)?,
+                    concentration: row.get(2)?,
+                })
+            },
+        )?
+        .collect()
+}
@@ -0,0 +1,383 @@
+//! `hygieia config show`: every setting a run resolves, where its value came from, and the tenants
+//! reports go to, for finding out why a run isn't doing what was configured. Secrets are redacted,
+//! and so are the tokens in webhook URLs.
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory};
+use hygieia::analysis::{ChangeScale, Measure, DEFAULT_CONCENTRATION_FLOOR};
+use hygieia::canary::{CanaryPolicy, DEFAULT_CANARY_RUNS};
+use hygieia::context::{Config, Selection};
+use hygieia::coverage::DEFAULT_MAX_MISSED_SAMPLES;
+use hygieia::db::DEFAULT_INSERT_CHUNK_SAMPLES;
+use hygieia::discord::DiscordWebhookOptions;
+use hygieia::email::SmtpSecurity;
+use hygieia::http::redact_url;
+use hygieia::mastodon::DEFAULT_VISIBILITY;
+use hygieia::pipeline::{ConfiguredNotifier, Notifier, DEFAULT_COUNTIES, DEFAULT_PATHOGENS};
+use hygieia::pushover::DEFAULT_PUSHOVER_API_URL;
+use hygieia::rules::NotifyMode;
+use hygieia::telegram::DEFAULT_TELEGRAM_API_URL;
+use hygieia::tenants::Tenant;
+
+use crate::cli::Cli;
+use crate::{
+    DEFAULT_HTTP_AUDIT, DEFAULT_HTTP_MAX_ATTEMPTS, DEFAULT_HTTP_MAX_RETRY_DELAY_SECS,
+    DEFAULT_HTTP_RATE_LIMIT_BURST, DEFAULT_HTTP_RATE_LIMIT_PER_SECOND,
+    DEFAULT_HTTP_RETRY_DELAY_SECS, DEFAULT_HTTP_TIMEOUT_SECS, DEFAULT_NOTIFY_NEW_DATA_ONLY,
+    DEFAULT_PRECISION, DEFAULT_REPORT_FOOTER, DEFAULT_SQLITE_DB_PATH, DEFAULT_WASTEWATER_URL,
+    ENVVAR_ACTIVITY_LEVELS, ENVVAR_ALERT_RULES, ENVVAR_CANARY_ROLLOUT_PERCENT, ENVVAR_CANARY_RUNS,
+    ENVVAR_CHANGE_SCALE, ENVVAR_CONCENTRATION_FLOOR, ENVVAR_DASHBOARD_URL,
+    ENVVAR_DISCORD_ADMIN_WEBHOOK_URL, ENVVAR_DISCORD_EDIT_ON_REVISION, ENVVAR_DISCORD_STATUS_BOARD,
+    ENVVAR_DISCORD_THREAD_PER_WEEK, ENVVAR_DISCORD_WEBHOOK_URL, ENVVAR_DOWNLOAD_PATH,
+    ENVVAR_DOWNLOAD_SHA256, ENVVAR_EMAIL_FROM, ENVVAR_EMAIL_TO, ENVVAR_HTTP_AUDIT,
+    ENVVAR_HTTP_CONTACT, ENVVAR_HTTP_MAX_ATTEMPTS, ENVVAR_HTTP_MAX_RETRY_DELAY_SECS,
+    ENVVAR_HTTP_RATE_LIMIT_BURST, ENVVAR_HTTP_RATE_LIMIT_PER_SECOND, ENVVAR_HTTP_RETRY_DELAY_SECS,
+    ENVVAR_HTTP_TIMEOUT_SECS, ENVVAR_INSERT_CHUNK_SAMPLES, ENVVAR_JSON_WEBHOOK_SECRET,
+    ENVVAR_JSON_WEBHOOK_URL, ENVVAR_MARKDOWN_PRECISION, ENVVAR_MASTODON_ACCESS_TOKEN,
+    ENVVAR_MASTODON_INSTANCE_URL, ENVVAR_MASTODON_VISIBILITY, ENVVAR_MATRIX_ACCESS_TOKEN,
+    ENVVAR_MATRIX_HOMESERVER_URL, ENVVAR_MATRIX_ROOM_ID, ENVVAR_MAX_MISSED_SAMPLES,
+    ENVVAR_NOTIFY_MODE, ENVVAR_NOTIFY_NEW_DATA_ONLY, ENVVAR_NTFY_ACCESS_TOKEN,
+    ENVVAR_NTFY_TOPIC_URL, ENVVAR_PUSHOVER_ALERT_THRESHOLDS, ENVVAR_PUSHOVER_API_URL,
+    ENVVAR_PUSHOVER_APP_TOKEN, ENVVAR_PUSHOVER_USER_KEY, ENVVAR_REPORT_COUNTIES,
+    ENVVAR_REPORT_FOOTER, ENVVAR_REPORT_MEASURE, ENVVAR_REPORT_PATHOGENS, ENVVAR_REPORT_RELEASE,
+    ENVVAR_SITE_METADATA_URL, ENVVAR_SLACK_WEBHOOK_URL, ENVVAR_SMTP_HOST, ENVVAR_SMTP_PASSWORD,
+    ENVVAR_SMTP_PORT, ENVVAR_SMTP_SECURITY, ENVVAR_SMTP_USERNAME, ENVVAR_SOCRATA_METADATA_URL,
+    ENVVAR_SQLITE_DB_PATH, ENVVAR_SQLITE_KEY, ENVVAR_SQLITE_KEY_FILE, ENVVAR_TABLE_PRECISION,
+    ENVVAR_TEAMS_WEBHOOK_URL, ENVVAR_TELEGRAM_API_URL, ENVVAR_TELEGRAM_BOT_TOKEN,
+    ENVVAR_TELEGRAM_CHAT_ID, ENVVAR_TENANTS_FILE, ENVVAR_WASTEWATER_URL,
+};
+
+/// Shown instead of secret values.
+const REDACTED: &str = "REDACTED";
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    Environment,
+    /// The `.env` file, loaded into the environment at startup.
+    DotEnv,
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::Default => "default",
+            Source::Environment => "environment",
+            Source::DotEnv => ".env",
+            Source::CommandLine => "command line",
+        })
+    }
+}
+
+/// How a setting's value is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Plain,
+    Secret,
+    /// A URL that may carry a token, shown with it redacted.
+    Url,
+}
+
+/// A setting read from the environment, with the value it takes when unset.
+struct EnvSetting {
+    name: &'static str,
+    kind: Kind,
+    default: Option<String>,
+}
+
+fn setting(name: &'static str, default: Option<String>) -> EnvSetting {
+    EnvSetting {
+        name,
+        kind: Kind::Plain,
+        default,
+    }
+}
+
+fn secret(name: &'static str) -> EnvSetting {
+    EnvSetting {
+        name,
+        kind: Kind::Secret,
+        default: None,
+    }
+}
+
+fn url(name: &'static str) -> EnvSetting {
+    EnvSetting {
+        name,
+        kind: Kind::Url,
+        default: None,
+    }
+}
+
+/// Every setting `load_config` and the stores read from the environment, in the order they're
+/// read. Defaults that depend on other settings are taken from the resolved `config`.
+fn env_settings(config: &Config) -> Vec<EnvSetting> {
+    let some = |value: &dyn ToString| Some(value.to_string());
+    let discord_defaults = DiscordWebhookOptions::default();
+    vec![
+        setting(ENVVAR_WASTEWATER_URL, some(&DEFAULT_WASTEWATER_URL)),
+        setting(ENVVAR_REPORT_COUNTIES, some(&DEFAULT_COUNTIES.join(","))),
+        setting(ENVVAR_REPORT_PATHOGENS, some(&DEFAULT_PATHOGENS.join(","))),
+        setting(ENVVAR_SQLITE_DB_PATH, some(&DEFAULT_SQLITE_DB_PATH)),
+        secret(ENVVAR_SQLITE_KEY),
+        setting(ENVVAR_SQLITE_KEY_FILE, None),
+        url(ENVVAR_DISCORD_WEBHOOK_URL),
+        setting(
+            ENVVAR_DISCORD_THREAD_PER_WEEK,
+            some(&discord_defaults.thread_per_week),
+        ),
+        setting(
+            ENVVAR_DISCORD_EDIT_ON_REVISION,
+            some(&discord_defaults.edit_on_revision),
+        ),
+        setting(
+            ENVVAR_DISCORD_STATUS_BOARD,
+            some(&discord_defaults.status_board),
+        ),
+        url(ENVVAR_JSON_WEBHOOK_URL),
+        secret(ENVVAR_JSON_WEBHOOK_SECRET),
+        url(ENVVAR_SLACK_WEBHOOK_URL),
+        url(ENVVAR_TEAMS_WEBHOOK_URL),
+        setting(ENVVAR_MATRIX_HOMESERVER_URL, None),
+        secret(ENVVAR_MATRIX_ACCESS_TOKEN),
+        setting(ENVVAR_MATRIX_ROOM_ID, None),
+        secret(ENVVAR_TELEGRAM_BOT_TOKEN),
+        setting(ENVVAR_TELEGRAM_CHAT_ID, None),
+        setting(ENVVAR_TELEGRAM_API_URL, some(&DEFAULT_TELEGRAM_API_URL)),
+        setting(ENVVAR_MASTODON_INSTANCE_URL, None),
+        secret(ENVVAR_MASTODON_ACCESS_TOKEN),
+        setting(ENVVAR_MASTODON_VISIBILITY, some(&DEFAULT_VISIBILITY)),
+        url(ENVVAR_NTFY_TOPIC_URL),
+        secret(ENVVAR_NTFY_ACCESS_TOKEN),
+        secret(ENVVAR_PUSHOVER_APP_TOKEN),
+        secret(ENVVAR_PUSHOVER_USER_KEY),
+        setting(ENVVAR_PUSHOVER_ALERT_THRESHOLDS, None),
+        setting(ENVVAR_PUSHOVER_API_URL, some(&DEFAULT_PUSHOVER_API_URL)),
+        setting(ENVVAR_SMTP_HOST, None),
+        setting(
+            ENVVAR_SMTP_PORT,
+            config.smtp.as_ref().map(|smtp| smtp.port.to_string()),
+        ),
+        setting(ENVVAR_SMTP_SECURITY, some(&SmtpSecurity::default())),
+        setting(ENVVAR_SMTP_USERNAME, None),
+        secret(ENVVAR_SMTP_PASSWORD),
+        setting(ENVVAR_EMAIL_FROM, None),
+        setting(ENVVAR_EMAIL_TO, None),
+        setting(ENVVAR_DASHBOARD_URL, None),
+        setting(ENVVAR_SOCRATA_METADATA_URL, None),
+        setting(ENVVAR_REPORT_FOOTER, some(&DEFAULT_REPORT_FOOTER)),
+        setting(ENVVAR_MAX_MISSED_SAMPLES, some(&DEFAULT_MAX_MISSED_SAMPLES)),
+        setting(
+            ENVVAR_CONCENTRATION_FLOOR,
+            some(&DEFAULT_CONCENTRATION_FLOOR),
+        ),
+        setting(ENVVAR_CHANGE_SCALE, some(&ChangeScale::default())),
+        setting(ENVVAR_REPORT_MEASURE, some(&Measure::default())),
+        setting(ENVVAR_ACTIVITY_LEVELS, None),
+        setting(ENVVAR_MARKDOWN_PRECISION, some(&DEFAULT_PRECISION)),
+        setting(ENVVAR_TABLE_PRECISION, some(&DEFAULT_PRECISION)),
+        setting(ENVVAR_HTTP_AUDIT, some(&DEFAULT_HTTP_AUDIT)),
+        setting(
+            ENVVAR_HTTP_RATE_LIMIT_PER_SECOND,
+            some(&DEFAULT_HTTP_RATE_LIMIT_PER_SECOND),
+        ),
+        setting(
+            ENVVAR_HTTP_RATE_LIMIT_BURST,
+            some(&DEFAULT_HTTP_RATE_LIMIT_BURST),
+        ),
+        setting(ENVVAR_HTTP_CONTACT, None),
+        setting(ENVVAR_HTTP_MAX_ATTEMPTS, some(&DEFAULT_HTTP_MAX_ATTEMPTS)),
+        setting(
+            ENVVAR_HTTP_RETRY_DELAY_SECS,
+            some(&DEFAULT_HTTP_RETRY_DELAY_SECS),
+        ),
+        setting(
+            ENVVAR_HTTP_MAX_RETRY_DELAY_SECS,
+            some(&DEFAULT_HTTP_MAX_RETRY_DELAY_SECS),
+        ),
+        setting(ENVVAR_HTTP_TIMEOUT_SECS, some(&DEFAULT_HTTP_TIMEOUT_SECS)),
+        setting(ENVVAR_ALERT_RULES, None),
+        setting(ENVVAR_NOTIFY_MODE, some(&NotifyMode::default())),
+        setting(
+            ENVVAR_NOTIFY_NEW_DATA_ONLY,
+            some(&DEFAULT_NOTIFY_NEW_DATA_ONLY),
+        ),
+        url(ENVVAR_DISCORD_ADMIN_WEBHOOK_URL),
+        setting(ENVVAR_REPORT_RELEASE, None),
+        setting(ENVVAR_CANARY_RUNS, some(&DEFAULT_CANARY_RUNS)),
+        setting(
+            ENVVAR_CANARY_ROLLOUT_PERCENT,
+            some(&CanaryPolicy::default().rollout_percent),
+        ),
+        setting(ENVVAR_TENANTS_FILE, None),
+        setting(ENVVAR_SITE_METADATA_URL, None),
+        setting(ENVVAR_DOWNLOAD_PATH, None),
+        setting(ENVVAR_DOWNLOAD_SHA256, None),
+        setting(
+            ENVVAR_INSERT_CHUNK_SAMPLES,
+            some(&DEFAULT_INSERT_CHUNK_SAMPLES),
+        ),
+    ]
+}
+
+/// A setting as resolved.
+struct Resolved {
+    name: String,
+    /// None when unset without a default.
+    value: Option<String>,
+    source: Source,
+}
+
+/// Prints the settings that are set, or with `all` every setting including defaults, then the
+/// tenants. `matches` are the command line's, and `dotenv_keys` the variables the `.env` file set.
+pub fn print(config: &Config, matches: &ArgMatches, dotenv_keys: &HashSet<OsString>, all: bool) {
+    let env_source = |name: &str| match env::var_os(name) {
+        None => Source::Default,
+        Some(_) if dotenv_keys.contains(&OsString::from(name)) => Source::DotEnv,
+        Some(_) => Source::Environment,
+    };
+    let show = |kind: Kind, value: String| match kind {
+        Kind::Plain => value,
+        Kind::Secret => REDACTED.to_owned(),
+        Kind::Url => redact_url(&value),
+    };
+
+    let mut settings: Vec<Resolved> = env_settings(config)
+        .into_iter()
+        .map(|setting| {
+            let source = env_source(setting.name);
+            let value = match env::var(setting.name) {
+                Ok(value) => Some(show(setting.kind, value)),
+                Err(_) => setting.default,
+            };
+            Resolved {
+                name: setting.name.to_owned(),
+                value,
+                source,
+            }
+        })
+        .collect();
+
+    // Options of the command line that fall back to the environment. The subcommands' are shown as
+    // they'd resolve from the environment, since they only take flags when running
+    let command = Cli::command();
+    let mut commands = vec![(&command, Some(matches))];
+    let mut i = 0;
+    while i < commands.len() {
+        let (command, matches) = commands[i];
+        commands.extend(
+            command
+                .get_subcommands()
+                .map(|subcommand| (subcommand, None)),
+        );
+        i += 1;
+
+        for arg in command.get_arguments() {
+            let Some(name) = arg.get_env().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if settings.iter().any(|setting| setting.name == name) {
+                continue;
+            }
+            let kind = if arg.is_hide_env_values_set() {
+                Kind::Secret
+            } else {
+                Kind::Plain
+            };
+
+            let explicit = matches.and_then(|matches| {
+                let source = matches.value_source(arg.get_id().as_str())?;
+                let values: Vec<String> = matches
+                    .get_raw(arg.get_id().as_str())?
+                    .map(|value| value.to_string_lossy().into_owned())
+                    .collect();
+                Some((source, values.join(",")))
+            });
+            let (value, source) = match explicit {
+                Some((ValueSource::CommandLine, value)) => {
+                    (Some(show(kind, value)), Source::CommandLine)
+                }
+                Some((ValueSource::EnvVariable, value)) => {
+                    (Some(show(kind, value)), env_source(name))
+                }
+                _ => match env::var(name) {
+                    Ok(value) => (Some(show(kind, value)), env_source(name)),
+                    Err(_) => {
+                        let defaults: Vec<String> = arg
+                            .get_default_values()
+                            .iter()
+                            .map(|value| value.to_string_lossy().into_owned())
+                            .collect();
+                        (
+                            (!defaults.is_empty()).then(|| defaults.join(",")),
+                            Source::Default,
+                        )
+                    }
+                },
+            };
+            settings.push(Resolved {
+                name: name.to_owned(),
+                value,
+                source,
+            });
+        }
+    }
+
+    for setting in &settings {
+        if !all && setting.source == Source::Default {
+            continue;
+        }
+        match &setting.value {
+            Some(value) => println!("{} = {value} ({})", setting.name, setting.source),
+            None => println!("{} unset", setting.name),
+        }
+    }
+
+    println!();
+    if config.tenants.is_empty() {
+        print_tenant(&Tenant::from_config(config), "from the settings above");
+    } else {
+        for tenant in &config.tenants {
+            print_tenant(tenant, "from the tenants file");
+        }
+    }
+}
+
+fn print_tenant(tenant: &Tenant, source: &str) {
+    let selection = |selection: &Selection| match selection {
+        Selection::All => "all".to_owned(),
+        Selection::Only(values) => values.join(","),
+    };
+
+    println!("tenant {} ({source})", tenant.name);
+    println!("  counties = {}", selection(&tenant.counties));
+    println!("  pathogens = {}", selection(&tenant.pathogens));
+    println!(
+        "  destinations = {}",
+        ConfiguredNotifier.destinations(tenant).join(",")
+    );
+    if let Some(quiet_hours) = &tenant.quiet_hours {
+        println!(
+            "  quiet_hours = {}-{} {}",
+            quiet_hours.start.format("%H:%M"),
+            quiet_hours.end.format("%H:%M"),
+            quiet_hours.timezone
+        );
+    }
+    for rule in &tenant.alert_rules.0 {
+        println!("  alert_rule = {rule}");
+    }
+    if tenant.notify_mode != NotifyMode::Always {
+        println!("  notify_mode = {}", tenant.notify_mode);
+    }
+    if tenant.canary {
+        println!("  canary = true");
+    }
+}
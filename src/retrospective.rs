@@ -0,0 +1,339 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Days, Months, NaiveDate};
+use color_eyre::eyre;
+use rusqlite::{params, Connection};
+use tracing::instrument;
+
+use crate::analysis::AnalysisOptions;
+use crate::levels::{self, ActivityLevel};
+use crate::numeric;
+use crate::precision::Precision;
+use crate::site;
+
+/// A calendar month, quarter, or year, written as `2024-12`, `2024-Q4`, or `2024`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Month { year: i32, month: u32 },
+    Quarter { year: i32, quarter: u32 },
+    Year { year: i32 },
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Expected a period like 2024-12, 2024-Q4, or 2024, got {s}");
+
+        let Some((year, rest)) = s.split_once('-') else {
+            let year = s.parse().map_err(|_| invalid())?;
+            return Ok(Self::Year { year });
+        };
+        let year: i32 = year.parse().map_err(|_| invalid())?;
+
+        if let Some(quarter) = rest.strip_prefix('Q').or_else(|| rest.strip_prefix('q')) {
+            match quarter.parse() {
+                Ok(quarter @ 1..=4) => Ok(Self::Quarter { year, quarter }),
+                _ => Err(invalid()),
+            }
+        } else {
+            match rest.parse() {
+                Ok(month @ 1..=12) => Ok(Self::Month { year, month }),
+                _ => Err(invalid()),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Period::Month { .. } => write!(f, "{}", self.first_day().format("%B %Y")),
+            Period::Quarter { year, quarter } => write!(f, "{year}-Q{quarter}"),
+            Period::Year { year } => write!(f, "{year}"),
+        }
+    }
+}
+
+impl Period {
+    pub fn first_day(&self) -> NaiveDate {
+        let (year, month) = match *self {
+            Period::Month { year, month } => (year, month),
+            Period::Quarter { year, quarter } => (year, (quarter - 1) * 3 + 1),
+            Period::Year { year } => (year, 1),
+        };
+        NaiveDate::from_ymd_opt(year, month, 1).expect("periods are validated when parsed")
+    }
+
+    fn months(&self) -> Months {
+        match self {
+            Period::Month { .. } => Months::new(1),
+            Period::Quarter { .. } => Months::new(3),
+            Period::Year { .. } => Months::new(12),
+        }
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        (self.first_day() + self.months())
+            .pred_opt()
+            .expect("dates are in range")
+    }
+
+    /// The period of the same length immediately before this one.
+    pub fn previous(&self) -> Period {
+        let first_day = self.first_day() - self.months();
+        match self {
+            Period::Month { .. } => Period::Month {
+                year: first_day.year(),
+                month: first_day.month(),
+            },
+            Period::Quarter { .. } => Period::Quarter {
+                year: first_day.year(),
+                quarter: first_day.month0() / 3 + 1,
+            },
+            Period::Year { .. } => Period::Year {
+                year: first_day.year(),
+            },
+        }
+    }
+}
+
+/// Summary statistics of one county and pathogen over a period.
+#[derive(Debug)]
+pub struct PeriodSummary {
+    pub samples: usize,
+    pub mean: f64,
+    pub peak: (NaiveDate, f64),
+    pub trough: (NaiveDate, f64),
+    /// Days in the period the county's activity was High or Very High, if activity levels are
+    /// enabled for the pathogen and could be computed.
+    pub high_days: Option<u64>,
+    /// Mean across the county's sites on each sample date, for the chart.
+    pub daily_means: Vec<(NaiveDate, f64)>,
+}
+
+#[derive(Debug)]
+pub struct RetrospectiveLine {
+    pub county: String,
+    pub pathogen: String,
+    /// None if there were no samples in the period.
+    pub current: Option<PeriodSummary>,
+    pub previous: Option<PeriodSummary>,
+}
+
+#[derive(Debug)]
+pub struct Retrospective {
+    pub period: Period,
+    pub lines: Vec<RetrospectiveLine>,
+}
+
+impl Retrospective {
+    /// Renders the retrospective as a markdown narrative, one paragraph per county and pathogen,
+    /// each followed by a chart of the period embedded as an SVG image.
+    pub fn to_markdown(&self, precision: Precision) -> String {
+        let previous_period = self.period.previous();
+        let mut paragraphs = vec![format!("# {} in review", self.period)];
+
+        for line in &self.lines {
+            let heading = format!("**{} County - {}**", line.county, line.pathogen);
+
+            let Some(current) = &line.current else {
+                paragraphs.push(format!("{heading}: No samples were reported."));
+                continue;
+            };
+
+            let (peak_date, peak_value) = current.peak;
+            let (trough_date, trough_value) = current.trough;
+            let mut paragraph = format!(
+                "{heading}: Peaked at {} on {peak_date} and was lowest at {} on {trough_date}, averaging {} across {} samples.",
+                precision.format(peak_value),
+                precision.format(trough_value),
+                precision.format(current.mean),
+                current.samples
+            );
+
+            match current.high_days {
+                Some(0) => paragraph.push_str(" Activity never reached High."),
+                Some(1) => paragraph.push_str(" Activity was High or above on 1 day."),
+                Some(days) => {
+                    paragraph.push_str(&format!(" Activity was High or above on {days} days."))
+                }
+                None => {}
+            }
+
+            match &line.previous {
+                Some(previous) if previous.mean > 0.0 => {
+                    let change = (current.mean - previous.mean) / previous.mean * 100.0;
+                    let direction = if change >= 0.0 { "up" } else { "down" };
+                    paragraph.push_str(&format!(
+                        " That is {direction} {:.0}% from the {previous_period} average of {}.",
+                        change.abs(),
+                        precision.format(previous.mean)
+                    ));
+                }
+                _ => paragraph.push_str(&format!(
+                    " There is no {previous_period} data to compare against."
+                )),
+            }
+
+            if let Some(chart) = site::standalone_chart(&current.daily_means, precision) {
+                paragraph.push_str(&format!(
+                    "\n\n![{} County {} in {}](data:image/svg+xml,{})",
+                    line.county,
+                    line.pathogen,
+                    self.period,
+                    percent_encode(&chart)
+                ));
+            }
+
+            paragraphs.push(paragraph);
+        }
+
+        paragraphs.join("\n\n")
+    }
+}
+
+fn summarize_period(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    period: Period,
+    options: &AnalysisOptions,
+) -> eyre::Result<Option<PeriodSummary>> {
+    let select_period_sql = format!(
+        "
+    SELECT sample_collection_date, {measure} FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2
+    AND sample_collection_date >= ?3 AND sample_collection_date <= ?4
+    AND {measure} IS NOT NULL
+    ORDER BY sample_collection_date",
+        measure = options.measure.column()
+    );
+
+    let mut stmt = conn.prepare_cached(&select_period_sql)?;
+    let samples = stmt
+        .query_map(
+            params![county, pathogen, period.first_day(), period.last_day()],
+            |row| Ok((row.get::<_, NaiveDate>(0)?, row.get::<_, f64>(1)?)),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let Some(&first) = samples.first() else {
+        return Ok(None);
+    };
+
+    let (peak, trough) = samples
+        .iter()
+        .fold((first, first), |(peak, trough), &sample| {
+            (
+                if sample.1 > peak.1 { sample } else { peak },
+                if sample.1 < trough.1 { sample } else { trough },
+            )
+        });
+    let values: Vec<f64> = samples.iter().map(|&(_, value)| value).collect();
+
+    let mut daily_means: Vec<(NaiveDate, f64)> = Vec::new();
+    for date in samples.chunk_by(|a, b| a.0 == b.0) {
+        let values: Vec<f64> = date.iter().map(|&(_, value)| value).collect();
+        daily_means.push((date[0].0, numeric::mean(&values)));
+    }
+
+    Ok(Some(PeriodSummary {
+        samples: samples.len(),
+        mean: numeric::mean(&values),
+        peak,
+        trough,
+        high_days: high_days(conn, county, pathogen, period, options)?,
+        daily_means,
+    }))
+}
+
+/// Days in `period` the county's activity was High or above, counting each sample's level until
+/// the next sample. The level at the start comes from the last sample before it. None if activity
+/// levels aren't enabled for the pathogen or couldn't be computed for any sample.
+fn high_days(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    period: Period,
+    options: &AnalysisOptions,
+) -> eyre::Result<Option<u64>> {
+    let Some(thresholds) = options.activity_levels.pathogens.get(pathogen) else {
+        return Ok(None);
+    };
+    let select_dates_sql = format!(
+        "
+    SELECT DISTINCT sample_collection_date FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2 AND {measure} IS NOT NULL
+    AND sample_collection_date >= (
+        SELECT COALESCE(MAX(sample_collection_date), ?3) FROM wastewater_samples
+        WHERE county = ?1 AND pcr_pathogen_target = ?2 AND {measure} IS NOT NULL
+        AND sample_collection_date < ?3)
+    AND sample_collection_date <= ?4
+    ORDER BY sample_collection_date",
+        measure = options.measure.column()
+    );
+
+    let dates = conn
+        .prepare_cached(&select_dates_sql)?
+        .query_map(
+            params![county, pathogen, period.first_day(), period.last_day()],
+            |row| row.get::<_, NaiveDate>(0),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let end = period.last_day() + Days::new(1);
+    let mut high_days = None;
+    for (i, &date) in dates.iter().enumerate() {
+        let Some(activity) =
+            levels::county_activity(conn, county, pathogen, date, thresholds, options)?
+        else {
+            continue;
+        };
+        let from = date.max(period.first_day());
+        let until = dates.get(i + 1).copied().unwrap_or(end);
+        let days = high_days.get_or_insert(0);
+        if activity.level >= ActivityLevel::High {
+            *days += (until - from).num_days() as u64;
+        }
+    }
+    Ok(high_days)
+}
+
+/// `s` with everything but unreserved characters percent-encoded, for a data URL.
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Summarizes every county and pathogen over `period` in `options`' measure, compared with the
+/// period before it.
+#[instrument(skip(conn))]
+pub fn build_retrospective(
+    conn: &Connection,
+    counties: &[&str],
+    pathogens: &[&str],
+    period: Period,
+    options: &AnalysisOptions,
+) -> eyre::Result<Retrospective> {
+    let mut lines = Vec::new();
+
+    for &county in counties {
+        for &pathogen in pathogens {
+            lines.push(RetrospectiveLine {
+                county: county.to_owned(),
+                pathogen: pathogen.to_owned(),
+                current: summarize_period(conn, county, pathogen, period, options)?,
+                previous: summarize_period(conn, county, pathogen, period.previous(), options)?,
+            });
+        }
+    }
+
+    Ok(Retrospective { period, lines })
+}
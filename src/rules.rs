@@ -0,0 +1,329 @@
+//! Threshold rules over the report's numbers, e.g. `King sars-cov-2 weekly_change > 25%` or
+//! `* RSV latest >= 500000`: a county (or `*` for any), a pathogen target (or `*`), a metric, a
+//! comparison, and a value, where `%` divides it by 100. The metrics are
+//!
+//! - `latest`, the latest sample's concentration,
+//! - `change`, the relative change from the previous sample, and
+//! - `weekly_change`, the trend's estimated change per week.
+//!
+//! Rules that fire are reported as notices and recorded in `alerts`. A tenant in
+//! [NotifyMode::Alert] is only sent reports where one did, and is quiet otherwise.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::report::ReportLine;
+
+/// Whether a tenant is sent every report, or only reports where an alert rule fired.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyMode {
+    #[default]
+    Always,
+    Alert,
+}
+
+impl FromStr for NotifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "always" => Ok(NotifyMode::Always),
+            "alert" => Ok(NotifyMode::Alert),
+            _ => Err(format!("Expected always or alert, got {s}")),
+        }
+    }
+}
+
+impl fmt::Display for NotifyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NotifyMode::Always => "always",
+            NotifyMode::Alert => "alert",
+        })
+    }
+}
+
+/// What a rule compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Latest,
+    Change,
+    WeeklyChange,
+}
+
+impl Metric {
+    /// The metric's value for `line`, if it has one.
+    fn value(&self, line: &ReportLine) -> Option<f64> {
+        let summary = line.summary.as_ref()?;
+        match self {
+            Metric::Latest => Some(summary.latest_value),
+            Metric::Change => summary.relative_change,
+            Metric::WeeklyChange => line.trend.as_ref().map(|trend| trend.weekly_change),
+        }
+    }
+
+    fn is_relative(&self) -> bool {
+        matches!(self, Metric::Change | Metric::WeeklyChange)
+    }
+
+    /// `value` as written in messages.
+    fn format(&self, value: f64) -> String {
+        if self.is_relative() {
+            format!("{:+.0}%", value * 100.0)
+        } else {
+            format!("{value:.0}")
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Metric::Latest => "latest",
+            Metric::Change => "change",
+            Metric::WeeklyChange => "weekly_change",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+impl Comparison {
+    fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Above => ">",
+            Comparison::AtLeast => ">=",
+            Comparison::Below => "<",
+            Comparison::AtMost => "<=",
+        })
+    }
+}
+
+/// A condition on one county's and pathogen's numbers. None matches any county or pathogen.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct AlertRule {
+    pub county: Option<String>,
+    pub pathogen: Option<String>,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl TryFrom<String> for AlertRule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    /// Parses `county pathogen metric comparison value`. Counties can have spaces, so the rule is
+    /// read from the end.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Expected e.g. \"King sars-cov-2 weekly_change > 25%\", got {s}");
+        let mut words = s.split_whitespace().rev();
+        let (Some(value), Some(comparison), Some(metric), Some(pathogen)) =
+            (words.next(), words.next(), words.next(), words.next())
+        else {
+            return Err(invalid());
+        };
+        let county: Vec<&str> = words.rev().collect();
+        if county.is_empty() {
+            return Err(invalid());
+        }
+
+        let metric = match metric {
+            "latest" => Metric::Latest,
+            "change" => Metric::Change,
+            "weekly_change" => Metric::WeeklyChange,
+            _ => {
+                return Err(format!(
+                    "Unknown metric {metric}, expected latest, change, or weekly_change"
+                ))
+            }
+        };
+        let comparison = match comparison {
+            ">" => Comparison::Above,
+            ">=" => Comparison::AtLeast,
+            "<" => Comparison::Below,
+            "<=" => Comparison::AtMost,
+            _ => {
+                return Err(format!(
+                    "Unknown comparison {comparison}, expected >, >=, <, or <="
+                ))
+            }
+        };
+        let (number, scale) = match value.strip_suffix('%') {
+            Some(number) => (number, 0.01),
+            None => (value, 1.0),
+        };
+        let threshold = number
+            .parse::<f64>()
+            .ok()
+            .filter(|threshold| threshold.is_finite())
+            .ok_or_else(|| format!("Invalid threshold {value}"))?;
+
+        let any = |name: String| (name != "*").then_some(name);
+        Ok(Self {
+            county: any(county.join(" ")),
+            pathogen: any(pathogen.to_owned()),
+            metric,
+            comparison,
+            threshold: threshold * scale,
+        })
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let threshold = if self.metric.is_relative() {
+            format!("{}%", self.threshold * 100.0)
+        } else {
+            self.threshold.to_string()
+        };
+        write!(
+            f,
+            "{} {} {} {} {threshold}",
+            self.county.as_deref().unwrap_or("*"),
+            self.pathogen.as_deref().unwrap_or("*"),
+            self.metric,
+            self.comparison
+        )
+    }
+}
+
+impl AlertRule {
+    /// Whether the rule is about `county` and `pathogen`.
+    pub fn applies_to(&self, county: &str, pathogen: &str) -> bool {
+        let applies = |filter: &Option<String>, name: &str| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| filter.eq_ignore_ascii_case(name))
+        };
+        applies(&self.county, county) && applies(&self.pathogen, pathogen)
+    }
+
+    /// The alert the rule fires for `line`, if it applies to it and the condition holds.
+    pub fn evaluate(&self, line: &ReportLine) -> Option<ThresholdAlert> {
+        if !self.applies_to(&line.county, &line.pathogen) {
+            return None;
+        }
+
+        let value = self.metric.value(line)?;
+        self.comparison
+            .holds(value, self.threshold)
+            .then(|| ThresholdAlert {
+                county: line.county.clone(),
+                pathogen: line.pathogen.clone(),
+                metric: self.metric,
+                comparison: self.comparison,
+                value,
+                threshold: self.threshold,
+                latest_date: line
+                    .summary
+                    .as_ref()
+                    .map(|summary| summary.latest_date)
+                    .expect("lines with a metric value have a summary"),
+            })
+    }
+}
+
+/// A rule that fired.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdAlert {
+    pub county: String,
+    pub pathogen: String,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub value: f64,
+    pub threshold: f64,
+    /// Collection date of the sample the alert is about.
+    pub latest_date: NaiveDate,
+}
+
+impl fmt::Display for ThresholdAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metric = match self.metric {
+            Metric::Latest => "latest concentration",
+            Metric::Change => "change from the previous sample",
+            Metric::WeeklyChange => "weekly change",
+        };
+        let comparison = match self.comparison {
+            Comparison::Above => "above",
+            Comparison::AtLeast => "at least",
+            Comparison::Below => "below",
+            Comparison::AtMost => "at most",
+        };
+        write!(
+            f,
+            "{} County {} {metric} is {}, {comparison} {} (sample of {})",
+            self.county,
+            self.pathogen,
+            self.metric.format(self.value),
+            self.metric.format(self.threshold),
+            self.latest_date
+        )
+    }
+}
+
+/// A tenant's alert rules.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AlertRules(pub Vec<AlertRule>);
+
+impl FromStr for AlertRules {
+    type Err = String;
+
+    /// Parses rules separated by semicolons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl AlertRules {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any of the rules is about `county` and `pathogen`.
+    pub fn apply_to(&self, county: &str, pathogen: &str) -> bool {
+        self.0.iter().any(|rule| rule.applies_to(county, pathogen))
+    }
+
+    /// Every alert the rules fire for `lines`, in the order of the lines.
+    pub fn evaluate(&self, lines: &[ReportLine]) -> Vec<ThresholdAlert> {
+        lines
+            .iter()
+            .flat_map(|line| self.0.iter().filter_map(|rule| rule.evaluate(line)))
+            .collect()
+    }
+}
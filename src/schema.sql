@@ -9,6 +9,9 @@ CREATE TABLE IF NOT EXISTS wastewater_samples (
     normalized_pathogen_concentration REAL NOT NULL,
     date_updated TEXT NOT NULL,
     poll_timestamp INTEGER NOT NULL,
+    -- Measures only some feeds include: the concentration before normalization, and the flow.
+    concentration_per_liter REAL,
+    flow_rate_mgd REAL,
     PRIMARY KEY (sample_collection_date, site_name, county, pcr_pathogen_target, pcr_gene_target)
 );
 
@@ -18,4 +21,282 @@ CREATE INDEX IF NOT EXISTS idx_wastewater_samples_poll_timestamp ON wastewater_s
 -- Create an index on the date_updated for efficient querying of recently updated data
 CREATE INDEX IF NOT EXISTS idx_wastewater_samples_date_updated ON wastewater_samples(date_updated);
 
+-- Earlier values of samples whose concentration was restated upstream, one row per restatement.
+CREATE TABLE IF NOT EXISTS sample_revisions (
+    sample_collection_date TEXT NOT NULL,
+    site_name TEXT NOT NULL,
+    county TEXT NOT NULL,
+    pcr_pathogen_target TEXT NOT NULL,
+    pcr_gene_target TEXT NOT NULL,
+    previous_concentration REAL NOT NULL,
+    previous_date_updated TEXT NOT NULL,
+    normalized_pathogen_concentration REAL NOT NULL,
+    date_updated TEXT NOT NULL,
+    -- Unix timestamp of the poll that found the new value.
+    revised_timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sample_revisions_revised_timestamp ON sample_revisions(revised_timestamp);
+
+-- Audit log of every outbound HTTP request. URLs are stored with secrets redacted.
+CREATE TABLE IF NOT EXISTS http_audit (
+    request_timestamp INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    url TEXT NOT NULL,
+    status INTEGER,
+    error TEXT,
+    duration_ms INTEGER NOT NULL,
+    retry_count INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_http_audit_request_timestamp ON http_audit(request_timestamp);
+
+-- Threads created by Discord webhooks, one per webhook per week.
+CREATE TABLE IF NOT EXISTS discord_threads (
+    webhook_id TEXT NOT NULL,
+    week TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    PRIMARY KEY (webhook_id, week)
+);
+
+-- Messages posted by Discord webhooks, so a revised report can edit the previous message.
+-- period is the latest sample date the message covered.
+CREATE TABLE IF NOT EXISTS discord_messages (
+    webhook_id TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    thread_id TEXT,
+    period TEXT NOT NULL,
+    posted_timestamp INTEGER NOT NULL,
+    -- Messages the digest was split into, message_id being the first.
+    chunks INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE INDEX IF NOT EXISTS idx_discord_messages_webhook_id ON discord_messages(webhook_id, posted_timestamp);
+
+-- Chunks of a digest posted so far, so a digest that failed partway resumes after the last one
+-- instead of posting them again. digest is the SHA-256 of the digest's content. Rows are deleted
+-- once every chunk is posted.
+CREATE TABLE IF NOT EXISTS discord_digest_chunks (
+    webhook_id TEXT NOT NULL,
+    digest TEXT NOT NULL,
+    chunk INTEGER NOT NULL,
+    message_id TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    PRIMARY KEY (webhook_id, digest, chunk)
+);
+
+-- The single "current levels" message each webhook keeps edited.
+CREATE TABLE IF NOT EXISTS discord_status_boards (
+    webhook_id TEXT PRIMARY KEY NOT NULL,
+    message_id TEXT NOT NULL
+);
+
+-- Upstream dataset revisions from the Socrata metadata API, recorded before each download.
+CREATE TABLE IF NOT EXISTS dataset_revisions (
+    fetched_timestamp INTEGER NOT NULL,
+    rows_updated_at INTEGER,
+    view_last_modified INTEGER,
+    row_count INTEGER
+);
+
+-- Metadata about sampling sites, synced from a remote listing with `hygieia sites sync`.
+CREATE TABLE IF NOT EXISTS sites (
+    site_name TEXT NOT NULL,
+    county TEXT NOT NULL,
+    population INTEGER,
+    latitude REAL,
+    longitude REAL,
+    source TEXT,
+    updated_timestamp INTEGER NOT NULL,
+    PRIMARY KEY (site_name, county)
+);
+
+-- Pathogen and gene targets each site reports, to notice when a site starts or stops reporting one.
+CREATE TABLE IF NOT EXISTS site_targets (
+    site_name TEXT NOT NULL,
+    county TEXT NOT NULL,
+    pcr_pathogen_target TEXT NOT NULL,
+    pcr_gene_target TEXT NOT NULL,
+    last_sample_date TEXT NOT NULL,
+    -- Whether the target was still being reported at the last ingest.
+    active INTEGER NOT NULL,
+    PRIMARY KEY (site_name, county, pcr_pathogen_target, pcr_gene_target)
+);
+
+-- Each site's typical sampling interval, learned from its history, and how many expected samples
+-- it had missed at the last ingest.
+CREATE TABLE IF NOT EXISTS site_cadences (
+    site_name TEXT NOT NULL,
+    county TEXT NOT NULL,
+    interval_days REAL NOT NULL,
+    last_sample_date TEXT NOT NULL,
+    missed_samples INTEGER NOT NULL,
+    updated_timestamp INTEGER NOT NULL,
+    PRIMARY KEY (site_name, county)
+);
+
+-- Notable events in the data, such as the onset of a season (kind 'season_onset').
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    county TEXT NOT NULL,
+    pcr_pathogen_target TEXT NOT NULL,
+    annotation_date TEXT NOT NULL,
+    note TEXT NOT NULL,
+    created_timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_annotations_kind ON annotations(kind, county, pcr_pathogen_target);
+
+-- ETag and Last-Modified of the last fully stored response per URL, sent back on the next request.
+CREATE TABLE IF NOT EXISTS http_validators (
+    url TEXT PRIMARY KEY NOT NULL,
+    etag TEXT,
+    last_modified TEXT,
+    stored_timestamp INTEGER NOT NULL
+);
+
+-- Every fetch, whether it found new data or not. status is 'running' until it finishes, then
+-- 'completed', 'unchanged' when the dataset revision or a 304 response showed nothing new, or 'failed'.
+CREATE TABLE IF NOT EXISTS poll_runs (
+    id INTEGER PRIMARY KEY,
+    run_id TEXT NOT NULL,
+    started_timestamp INTEGER NOT NULL,
+    finished_timestamp INTEGER,
+    source_url TEXT NOT NULL,
+    status TEXT NOT NULL,
+    -- Response status, NULL when the CSV was downloaded to a file or the request failed.
+    http_status INTEGER,
+    rows_parsed INTEGER NOT NULL DEFAULT 0,
+    rows_inserted INTEGER NOT NULL DEFAULT 0,
+    rows_skipped INTEGER NOT NULL DEFAULT 0,
+    rows_revised INTEGER NOT NULL DEFAULT 0,
+    -- Stored samples the polled file no longer included.
+    rows_retracted INTEGER NOT NULL DEFAULT 0,
+    -- Rows that failed to parse or convert.
+    rows_failed INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    -- Date/Time Updated of the polled file, which every row carries.
+    date_updated TEXT,
+    -- JSON of the samples committed so far, their hash, and their counts, updated with each chunk.
+    insert_progress TEXT,
+    -- Run whose interrupted insert this poll completed.
+    resumed_run_id TEXT
+);
+
+-- Rendered reports stored by ingest until notify delivers them.
+-- status is 'pending', 'delivered', or 'superseded' when a newer report replaced it before delivery.
+CREATE TABLE IF NOT EXISTS pending_reports (
+    id INTEGER PRIMARY KEY,
+    created_timestamp INTEGER NOT NULL,
+    run_id TEXT NOT NULL,
+    period TEXT NOT NULL,
+    markdown TEXT NOT NULL,
+    report_table TEXT NOT NULL,
+    status TEXT NOT NULL,
+    finished_timestamp INTEGER,
+    -- JSON snapshot of the windows and thresholds the report was generated with.
+    analysis_config TEXT,
+    -- Tenant the report was rendered for, 'default' without a tenants file.
+    tenant TEXT NOT NULL DEFAULT 'default',
+    -- The report as Slack Block Kit blocks, a JSON array.
+    slack_blocks TEXT,
+    email_html TEXT,
+    ntfy_notification TEXT,
+    -- The report's lines and notices as JSON, posted to the JSON webhook.
+    report_data TEXT,
+    -- The report as a Teams Adaptive Card, a JSON object.
+    teams_card TEXT
+);
+
+CREATE INDEX IF NOT EXISTS pending_reports_status ON pending_reports (status, id);
+
+-- Notifiers a pending report has been sent with, so a report that failed with some is only sent
+-- again with those. notifier is the notifier's name, e.g. 'discord'.
+CREATE TABLE IF NOT EXISTS pending_deliveries (
+    report_id INTEGER NOT NULL,
+    notifier TEXT NOT NULL,
+    delivered_timestamp INTEGER NOT NULL,
+    PRIMARY KEY (report_id, notifier)
+);
+
+-- Third parties' callback URLs, posted each tenant's report as JSON when a run stores new data.
+CREATE TABLE IF NOT EXISTS subscribers (
+    id INTEGER PRIMARY KEY,
+    callback_url TEXT NOT NULL,
+    tenant TEXT NOT NULL DEFAULT 'default',
+    -- Shared secret payloads are signed with, NULL to send them unsigned.
+    secret TEXT,
+    created_timestamp INTEGER NOT NULL,
+    last_attempt_timestamp INTEGER,
+    last_delivered_timestamp INTEGER,
+    -- Error of the last delivery, NULL if it succeeded.
+    last_error TEXT,
+    -- Deliveries failed in a row, after retries.
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (callback_url, tenant)
+);
+
+-- Pages written by `site build`, with a hash of what each was built from so unchanged pages can
+-- be skipped, and the page's row in the index.
+CREATE TABLE IF NOT EXISTS site_pages (
+    path TEXT PRIMARY KEY,
+    input_hash TEXT NOT NULL,
+    index_row TEXT NOT NULL,
+    built_timestamp INTEGER NOT NULL
+);
+
+-- Stable URL slugs of counties, unique per kind. kind is always 'county'.
+CREATE TABLE IF NOT EXISTS slugs (
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    PRIMARY KEY (kind, name),
+    UNIQUE (kind, slug)
+);
+
+-- Site slugs were keyed by name alone, so same-named sites in different counties shared one.
+DELETE FROM slugs WHERE kind = 'site';
+
+-- Notices announced in reports, and how delivering their report went.
+CREATE TABLE IF NOT EXISTS alerts (
+    id INTEGER PRIMARY KEY,
+    pending_report_id INTEGER NOT NULL REFERENCES pending_reports (id),
+    tenant TEXT NOT NULL,
+    created_timestamp INTEGER NOT NULL,
+    rule TEXT NOT NULL,
+    county TEXT NOT NULL,
+    pathogen TEXT,
+    value REAL,
+    threshold REAL,
+    message TEXT NOT NULL,
+    -- Comma-separated notifier destinations, set once a delivery was attempted.
+    destinations TEXT,
+    -- pending, delivered, or failed.
+    status TEXT NOT NULL DEFAULT 'pending',
+    error TEXT,
+    finished_timestamp INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_alerts_pending_report_id ON alerts (pending_report_id);
+
+-- Nothing read the per-series summary `hygieia db analyze` used to rebuild here.
+DROP TABLE IF EXISTS series_aggregates;
+
+-- Activity level baselines in log concentration, precomputed by `hygieia db analyze` over the year
+-- up to through_date. baseline and standard_deviation are NULL when the site had too little history.
+CREATE TABLE IF NOT EXISTS site_baselines (
+    county TEXT NOT NULL,
+    pcr_pathogen_target TEXT NOT NULL,
+    site_name TEXT NOT NULL,
+    through_date TEXT NOT NULL,
+    concentration_floor REAL NOT NULL,
+    measure TEXT NOT NULL DEFAULT 'normalized',
+    samples INTEGER NOT NULL,
+    baseline REAL,
+    standard_deviation REAL,
+    computed_timestamp INTEGER NOT NULL,
+    PRIMARY KEY (county, pcr_pathogen_target, site_name)
+);
+
 COMMIT;
\ No newline at end of file
@@ -0,0 +1,306 @@
+//! Detects the start of flu and RSV seasons.
+//!
+//! Each site's off-season baseline is the median of its samples collected in [OFF_SEASON_MONTHS].
+//! A site is elevated once its last [ONSET_SUSTAINED_SAMPLES] samples are all at least
+//! [ONSET_BASELINE_MULTIPLIER] times its baseline, so a single spike doesn't count. The season has
+//! started in a county when at least half of its sites with a baseline are elevated. Onsets are
+//! stored in `annotations`, at most once per county, pathogen, and season, where seasons start on
+//! July 1st.
+//!
+//! [backtest_season_onsets] replays the rule over past samples, with other thresholds if given, to
+//! show when it would have fired.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use chrono::{Datelike, NaiveDate};
+use color_eyre::eyre;
+use rusqlite::{named_params, params, Connection};
+use tracing::{info, instrument};
+
+use crate::useful::Clock;
+
+/// Pathogen targets with a season, and how announcements name them.
+pub const SEASONAL_PATHOGENS: [(&str, &str); 3] =
+    [("FLUAV", "Flu A"), ("FLUBV", "Flu B"), ("RSV", "RSV")];
+/// Months whose samples make up a site's off-season baseline.
+pub const OFF_SEASON_MONTHS: RangeInclusive<u32> = 6..=9;
+/// Fewest off-season samples a site's baseline is computed from.
+pub const MIN_BASELINE_SAMPLES: usize = 3;
+/// How many times its baseline a site's samples must reach to count as elevated.
+pub const ONSET_BASELINE_MULTIPLIER: f64 = 3.0;
+/// How many of a site's most recent samples must all be elevated.
+pub const ONSET_SUSTAINED_SAMPLES: usize = 3;
+/// Month a season starts in.
+const SEASON_START_MONTH: u32 = 7;
+
+const SEASON_ONSET_KIND: &str = "season_onset";
+
+/// Thresholds of the onset rule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OnsetRule {
+    /// How many times its baseline a site's samples must reach to count as elevated.
+    pub baseline_multiplier: f64,
+    /// How many of a site's most recent samples must all be elevated. At least 1.
+    pub sustained_samples: usize,
+}
+
+impl Default for OnsetRule {
+    /// [ONSET_BASELINE_MULTIPLIER] and [ONSET_SUSTAINED_SAMPLES].
+    fn default() -> Self {
+        Self {
+            baseline_multiplier: ONSET_BASELINE_MULTIPLIER,
+            sustained_samples: ONSET_SUSTAINED_SAMPLES,
+        }
+    }
+}
+
+/// A season start detected in a county.
+#[derive(Debug, Clone)]
+pub struct SeasonOnset {
+    pub county: String,
+    pub pathogen: String,
+    /// First sample of the sustained rise at the site that put the county over the threshold.
+    pub onset_date: NaiveDate,
+    pub elevated_sites: usize,
+    pub sites: usize,
+}
+
+impl SeasonOnset {
+    /// Name of the pathogen's season, e.g. "Flu A".
+    pub fn season_name(&self) -> &str {
+        SEASONAL_PATHOGENS
+            .iter()
+            .find(|(target, _)| *target == self.pathogen)
+            .map(|(_, name)| *name)
+            .unwrap_or(&self.pathogen)
+    }
+}
+
+impl fmt::Display for SeasonOnset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} season onset detected in {} County wastewater: {} of {} sites sustained above their off-season baseline since {}",
+            self.season_name(),
+            self.county,
+            self.elevated_sites,
+            self.sites,
+            self.onset_date
+        )
+    }
+}
+
+/// Checks every county in `counties` for the onset of each seasonal pathogen in `pathogens`, using
+/// samples collected up to `until`. New onsets are stored as annotations and returned; onsets
+/// already annotated this season aren't returned again.
+#[instrument(skip(conn, clock))]
+pub fn detect_season_onsets(
+    conn: &Connection,
+    clock: &dyn Clock,
+    counties: &[&str],
+    pathogens: &[&str],
+    until: Option<NaiveDate>,
+) -> eyre::Result<Vec<SeasonOnset>> {
+    const ONSET_ANNOTATED_SQL: &str = "
+    SELECT EXISTS (
+        SELECT 1 FROM annotations
+        WHERE kind = :kind AND county = :county AND pcr_pathogen_target = :pcr_pathogen_target
+            AND annotation_date >= :season_start
+    )";
+
+    const INSERT_ANNOTATION_SQL: &str = "
+    INSERT INTO annotations (kind, county, pcr_pathogen_target, annotation_date, note, created_timestamp) VALUES
+    (:kind, :county, :pcr_pathogen_target, :annotation_date, :note, :created_timestamp)";
+
+    let mut onsets = Vec::new();
+    for &county in counties {
+        for &pathogen in pathogens {
+            if !SEASONAL_PATHOGENS
+                .iter()
+                .any(|(target, _)| *target == pathogen)
+            {
+                continue;
+            }
+
+            let site_samples = select_site_samples(conn, county, pathogen, until)?;
+            let Some(onset) = find_onset(
+                county,
+                pathogen,
+                site_samples.values().map(Vec::as_slice),
+                &OnsetRule::default(),
+            ) else {
+                continue;
+            };
+
+            let already_annotated: bool = conn.prepare_cached(ONSET_ANNOTATED_SQL)?.query_row(
+                named_params! {
+                    ":kind": SEASON_ONSET_KIND,
+                    ":county": county,
+                    ":pcr_pathogen_target": pathogen,
+                    ":season_start": season_start(onset.onset_date),
+                },
+                |row| row.get(0),
+            )?;
+            if already_annotated {
+                continue;
+            }
+
+            conn.prepare_cached(INSERT_ANNOTATION_SQL)?
+                .execute(named_params! {
+                    ":kind": SEASON_ONSET_KIND,
+                    ":county": county,
+                    ":pcr_pathogen_target": pathogen,
+                    ":annotation_date": onset.onset_date,
+                    ":note": onset.to_string(),
+                    ":created_timestamp": clock.unix_timestamp(),
+                })?;
+            info!("{onset}");
+            onsets.push(onset);
+        }
+    }
+
+    Ok(onsets)
+}
+
+/// Replays the onset rule as of every sample collected up to `until`, returning each onset it would
+/// have announced on or after `since` with the sample date it would have been announced on. Like
+/// [detect_season_onsets], onsets are announced once per county, pathogen, and season, but nothing
+/// is stored.
+#[instrument(skip(conn))]
+pub fn backtest_season_onsets(
+    conn: &Connection,
+    counties: &[&str],
+    pathogens: &[&str],
+    since: NaiveDate,
+    until: NaiveDate,
+    rule: &OnsetRule,
+) -> eyre::Result<Vec<(NaiveDate, SeasonOnset)>> {
+    let mut firings = Vec::new();
+    for &county in counties {
+        for &pathogen in pathogens {
+            if !SEASONAL_PATHOGENS
+                .iter()
+                .any(|(target, _)| *target == pathogen)
+            {
+                continue;
+            }
+
+            let site_samples = select_site_samples(conn, county, pathogen, Some(until))?;
+            let mut dates: Vec<NaiveDate> = site_samples
+                .values()
+                .flatten()
+                .map(|&(date, _)| date)
+                .collect();
+            dates.sort_unstable();
+            dates.dedup();
+
+            let mut announced_seasons = Vec::new();
+            for date in dates {
+                let samples_by_then = site_samples
+                    .values()
+                    .map(|samples| &samples[..samples.partition_point(|&(d, _)| d <= date)]);
+                let Some(onset) = find_onset(county, pathogen, samples_by_then, rule) else {
+                    continue;
+                };
+                let season = season_start(onset.onset_date);
+                if announced_seasons.contains(&season) {
+                    continue;
+                }
+                announced_seasons.push(season);
+                if date >= since {
+                    firings.push((date, onset));
+                }
+            }
+        }
+    }
+
+    firings.sort_by_key(|(date, _)| *date);
+    Ok(firings)
+}
+
+/// Every sample of `pathogen` in `county` collected up to `until`, by site and sorted by date.
+fn select_site_samples(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    until: Option<NaiveDate>,
+) -> eyre::Result<HashMap<String, Vec<(NaiveDate, f64)>>> {
+    const SELECT_SAMPLES_SQL: &str = "
+    SELECT site_name, sample_collection_date, normalized_pathogen_concentration FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2 AND (?3 IS NULL OR sample_collection_date <= ?3)
+    ORDER BY site_name, sample_collection_date";
+
+    let mut site_samples: HashMap<String, Vec<(NaiveDate, f64)>> = HashMap::new();
+    let mut stmt = conn.prepare_cached(SELECT_SAMPLES_SQL)?;
+    let mut rows = stmt.query(params![county, pathogen, until])?;
+    while let Some(row) = rows.next()? {
+        site_samples
+            .entry(row.get(0)?)
+            .or_default()
+            .push((row.get(1)?, row.get(2)?));
+    }
+    Ok(site_samples)
+}
+
+/// Applies the onset rule to each site's samples, sorted by date.
+fn find_onset<'a>(
+    county: &str,
+    pathogen: &str,
+    site_samples: impl IntoIterator<Item = &'a [(NaiveDate, f64)]>,
+    rule: &OnsetRule,
+) -> Option<SeasonOnset> {
+    let mut sites: usize = 0;
+    let mut elevated_since = Vec::new();
+
+    for samples in site_samples {
+        let mut baseline_values: Vec<f64> = samples
+            .iter()
+            .filter(|(date, _)| OFF_SEASON_MONTHS.contains(&date.month()))
+            .map(|&(_, value)| value)
+            .collect();
+        if baseline_values.len() < MIN_BASELINE_SAMPLES {
+            continue;
+        }
+        sites += 1;
+
+        baseline_values.sort_unstable_by(f64::total_cmp);
+        let baseline = baseline_values[baseline_values.len() / 2];
+        let elevated = |value: f64| value >= baseline * rule.baseline_multiplier;
+
+        let run = samples
+            .iter()
+            .rev()
+            .take_while(|&&(_, value)| elevated(value))
+            .count();
+        if run >= rule.sustained_samples.max(1) {
+            elevated_since.push(samples[samples.len() - run].0);
+        }
+    }
+
+    // The county crossed the threshold when the last of the sites needed started rising
+    let needed = sites.div_ceil(2);
+    if sites == 0 || elevated_since.len() < needed {
+        return None;
+    }
+    elevated_since.sort_unstable();
+
+    Some(SeasonOnset {
+        county: county.to_owned(),
+        pathogen: pathogen.to_owned(),
+        onset_date: elevated_since[needed - 1],
+        elevated_sites: elevated_since.len(),
+        sites,
+    })
+}
+
+/// First day of the season `date` falls in.
+fn season_start(date: NaiveDate) -> NaiveDate {
+    let year = if date.month() >= SEASON_START_MONTH {
+        date.year()
+    } else {
+        date.year() - 1
+    };
+    NaiveDate::from_ymd_opt(year, SEASON_START_MONTH, 1).unwrap_or(date)
+}
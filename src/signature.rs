@@ -0,0 +1,88 @@
+//! Signatures of webhook payloads. The HMAC covers the Unix timestamp the payload was signed at,
+//! sent in [TIMESTAMP_HEADER], followed by a `.` and the body, so a captured request can't be
+//! replayed later with its signature. Receivers should reject timestamps more than
+//! [SIGNATURE_TOLERANCE_SECS] away from their own clock, as [verify_signature] does; a retried
+//! delivery keeps its first timestamp, so the window has to cover the retries' backoff too.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Header carrying the signature of a webhook payload.
+pub const SIGNATURE_HEADER: &str = "X-Hygieia-Signature";
+/// Header carrying the Unix timestamp, in seconds, the payload was signed at.
+pub const TIMESTAMP_HEADER: &str = "X-Hygieia-Timestamp";
+/// How far a signed timestamp may be from the receiver's clock, in either direction.
+pub const SIGNATURE_TOLERANCE_SECS: u64 = 5 * 60;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(payload: &[u8], timestamp: u64, secret: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(payload);
+    mac
+}
+
+/// Signs `payload` with `secret` at `timestamp`, returning the signature header value: `sha256=`
+/// followed by the hex-encoded HMAC-SHA256 of `{timestamp}.` and the exact payload bytes.
+pub fn sign(payload: &[u8], timestamp: u64, secret: &str) -> String {
+    let mac = mac(payload, timestamp, secret);
+
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
+
+/// Verifies the signature and timestamp headers produced by [sign], for services receiving
+/// hygieia's webhooks, at `now` in Unix seconds. `payload` must be the raw request body, before any
+/// JSON parsing. Timestamps further than [SIGNATURE_TOLERANCE_SECS] from `now` are rejected, and
+/// the comparison is constant time.
+///
+/// ```
+/// let signature = hygieia::signature::sign(b"{}", 1_700_000_000, "secret");
+/// assert!(hygieia::verify_signature(b"{}", "1700000000", &signature, "secret", 1_700_000_060));
+/// assert!(!hygieia::verify_signature(b"{}", "1700000000", &signature, "other secret", 1_700_000_060));
+/// assert!(!hygieia::verify_signature(b"{}", "1700000001", &signature, "secret", 1_700_000_060));
+/// assert!(!hygieia::verify_signature(b"{}", "1700000000", &signature, "secret", 1_700_003_600));
+/// ```
+pub fn verify_signature(
+    payload: &[u8],
+    timestamp_header: &str,
+    signature_header: &str,
+    secret: &str,
+    now: u64,
+) -> bool {
+    let Ok(timestamp) = timestamp_header.trim().parse::<u64>() else {
+        return false;
+    };
+    if timestamp.abs_diff(now) > SIGNATURE_TOLERANCE_SECS {
+        return false;
+    }
+    let Some(expected) = signature_header
+        .trim()
+        .strip_prefix("sha256=")
+        .and_then(decode_hex)
+    else {
+        return false;
+    };
+
+    mac(payload, timestamp, secret)
+        .verify_slice(&expected)
+        .is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,973 @@
+//! Builds a static mini-site from the database, deployable to any static host without a server:
+//! an index with the latest level of every pathogen in every county, a page per county with
+//! charts, an Atom feed of delivered reports, the [crate::alerts] history as a page and as
+//! `alerts.json`, and the data package to download, plus a [crate::badge] of every pathogen's level
+//! in every county.
+//!
+//! Pages carry Open Graph and Twitter card tags, and every county has a [crate::preview] image so
+//! shared links unfurl with its latest levels. Since the tags need absolute URLs, the image and
+//! canonical URL are only set when the site's base URL is given, as is sitemap.xml.
+//!
+//! Each county also gets a self-contained widget at `embed/<county>.html` for community sites to
+//! show in an iframe. Static hosts can't be asked to send headers from the HTML itself, so when
+//! embedding is limited to some origins the `frame-ancestors` policy is written to `_headers`,
+//! which Netlify and Cloudflare Pages read.
+//!
+//! Rebuilds are incremental: only the pages of counties whose samples changed are regenerated.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use color_eyre::eyre::{self, eyre, Context};
+use rusqlite::{named_params, params, Connection};
+use sha2::{Digest, Sha256};
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::alerts;
+use crate::analysis::{AnalysisOptions, Measure};
+use crate::badge;
+use crate::export::{self, SAMPLES_PATH, SITES_PATH};
+use crate::links::{badge_path, county_path, embed_path, pathogen_anchor, preview_path, slug};
+use crate::precision::Precision;
+use crate::preview::{self, PreviewRow};
+use crate::report::{self, DateRange, Notices, Provenance, Report, ReportLine};
+use crate::slugs;
+use crate::useful::Clock;
+
+/// Newest delivered reports listed in the feed.
+const FEED_ENTRIES: u32 = 20;
+/// Newest alerts listed on the alerts page.
+const ALERT_ENTRIES: u32 = 200;
+
+const STYLESHEET: &str = "\
+body { font-family: system-ui, sans-serif; margin: 0 auto; max-width: 60rem; padding: 1rem; color: #222; }
+header a { font-weight: bold; text-decoration: none; color: inherit; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.4rem; text-align: left; vertical-align: top; }
+td small { display: block; color: #666; }
+td.level-low, td.level-very-low { background: #e8f5e9; }
+td.level-moderate { background: #fff8e1; }
+td.level-high { background: #ffe0b2; }
+td.level-very-high { background: #ffcdd2; }
+svg { width: 100%; height: auto; }
+svg polyline { fill: none; stroke: #1565c0; stroke-width: 2; }
+svg text { font-size: 12px; fill: #666; }
+footer { margin-top: 2rem; font-size: 0.8rem; color: #666; }
+";
+
+/// Inlined into widgets, so they look the same on any page embedding them.
+const EMBED_STYLESHEET: &str = "\
+html, body { margin: 0; padding: 0; background: #fff; }
+body { font: 14px/1.4 system-ui, sans-serif; color: #222; padding: 0.5rem; }
+h1 { font-size: 1rem; margin: 0 0 0.4rem; }
+ul { list-style: none; margin: 0; padding: 0; }
+li { margin-bottom: 0.4rem; }
+li small { color: #666; }
+svg { width: 100%; height: auto; max-height: 70px; }
+svg polyline { fill: none; stroke: #1565c0; stroke-width: 2; }
+svg text { display: none; }
+a { color: #1565c0; }
+";
+
+/// Inlined into [standalone_chart]s.
+const CHART_STYLESHEET: &str =
+    "polyline { fill: none; stroke: #1565c0; stroke-width: 2; } text { font: 12px sans-serif; fill: #666; }";
+
+/// What the site is built from besides the stored samples.
+pub struct SiteOptions<'a> {
+    /// Pathogen targets shown for every county.
+    pub pathogens: &'a [String],
+    pub analysis: &'a AnalysisOptions,
+    pub precision: Precision,
+    pub source_url: &'a str,
+    /// Where the site is served from, which absolute URLs in meta tags are built from.
+    pub base_url: Option<&'a Url>,
+    /// Origins allowed to embed the widgets, e.g. `https://example.org`. Any origin may when empty.
+    pub embed_origins: &'a [String],
+    pub generated_at: DateTime<Utc>,
+}
+
+/// Writes the site to `out`. County pages whose samples and options are unchanged since they were
+/// last built, according to the hashes in `site_pages`, are kept as they are unless `full` is set.
+#[instrument(skip(conn, clock, options))]
+pub fn build_site(
+    conn: &Connection,
+    clock: &dyn Clock,
+    out: &Path,
+    options: &SiteOptions,
+    full: bool,
+) -> eyre::Result<()> {
+    if let Some(origin) = options
+        .embed_origins
+        .iter()
+        .find(|origin| origin.is_empty() || origin.contains(char::is_whitespace))
+    {
+        return Err(eyre!("Invalid embed origin {origin:?}"));
+    }
+
+    for dir in ["counties", "previews", "badge", "embed"] {
+        let dir = out.join(dir);
+        fs::create_dir_all(&dir).with_context(|| format!("Error creating {}", dir.display()))?;
+    }
+
+    let options_key = format!(
+        "{}|{:?}|{:?}|{}|{:?}",
+        env!("CARGO_PKG_VERSION"),
+        options.pathogens,
+        options.precision,
+        serde_json::to_string(options.analysis)?,
+        options.base_url.map(Url::as_str)
+    );
+    slugs::assign_slugs(conn)?;
+    let county_slugs = slugs::select_county_slugs(conn)?;
+    let built = select_site_pages(conn)?;
+    let mut pages = Vec::new();
+    let mut stale = Vec::new();
+    for (county, fingerprint) in select_county_fingerprints(conn)? {
+        let county_slug = county_slugs
+            .get(&county)
+            .cloned()
+            .unwrap_or_else(|| slug(&county));
+        let path = county_path(&county_slug);
+        let hash = sha256_hex(&format!("{options_key}|{fingerprint}"));
+        let up_to_date = !full
+            && out.join(&path).exists()
+            && out.join(preview_path(&county_slug)).exists()
+            && out.join(embed_path(&county_slug)).exists()
+            && options
+                .pathogens
+                .iter()
+                .all(|pathogen| out.join(badge_path(&county_slug, pathogen)).exists())
+            && built
+                .get(&path)
+                .is_some_and(|(built_hash, _)| *built_hash == hash);
+        if !up_to_date {
+            stale.push(county.clone());
+        }
+        pages.push((county, county_slug, path, hash));
+    }
+
+    let provenance = Provenance::new(conn, options.source_url, options.generated_at)?;
+    let footer = escape(&provenance.footer());
+    let report = report::build_report(
+        conn,
+        &Vec::from_iter(stale.iter().map(String::as_str)),
+        &Vec::from_iter(options.pathogens.iter().map(String::as_str)),
+        DateRange::default(),
+        options.analysis,
+        Notices::default(),
+        Some(provenance),
+    );
+
+    let mut index_rows = Vec::new();
+    for (county, county_slug, path, hash) in &pages {
+        if !stale.contains(county) {
+            index_rows.push(built[path].1.clone());
+            continue;
+        }
+
+        write_file(
+            out,
+            path,
+            county_page(conn, &report, county, county_slug, options, &footer)?,
+        )?;
+        write_file(
+            out,
+            &preview_path(county_slug),
+            county_preview(conn, &report, county, options)?,
+        )?;
+        write_file(
+            out,
+            &embed_path(county_slug),
+            embed_page(conn, &report, county, county_slug, options)?,
+        )?;
+        fs::create_dir_all(out.join("badge").join(county_slug))?;
+        for pathogen in options.pathogens {
+            write_file(
+                out,
+                &badge_path(county_slug, pathogen),
+                level_badge(&report, county, pathogen, options.precision),
+            )?;
+        }
+        let index_row = index_row(&report, county, path, options);
+        upsert_site_page(conn, clock, path, hash, &index_row)?;
+        index_rows.push(index_row);
+    }
+
+    write_file(out, "style.css", STYLESHEET)?;
+    write_file(out, "index.html", index_page(&index_rows, options, &footer))?;
+    write_file(out, "feed.xml", feed(conn, options.generated_at)?)?;
+    // Delivery errors can name private hosts and endpoints, so the public pages only say it failed
+    let alerts: Vec<alerts::Alert> = alerts::select_alerts(conn, None, ALERT_ENTRIES)?
+        .into_iter()
+        .map(|alert| alerts::Alert {
+            error: None,
+            ..alert
+        })
+        .collect();
+    write_file(out, "alerts.json", serde_json::to_string_pretty(&alerts)?)?;
+    write_file(out, "alerts.html", alerts_page(&alerts, options, &footer))?;
+    let sitemap_url = absolute_url(options, "sitemap.xml");
+    if sitemap_url.is_some() {
+        let lastmods = select_county_lastmods(conn)?;
+        let paths = pages
+            .iter()
+            .map(|(county, _, path, _)| (path.as_str(), lastmods.get(county).copied()));
+        write_file(out, "sitemap.xml", sitemap(paths, options))?;
+    }
+    write_file(out, "robots.txt", robots(sitemap_url.as_ref()))?;
+    write_file(out, "_headers", headers(options.embed_origins))?;
+    if !stale.is_empty() || !out.join("data").join(SAMPLES_PATH).exists() {
+        export::export_data_package(
+            conn,
+            &out.join("data"),
+            options.source_url,
+            options.generated_at,
+        )?;
+    }
+
+    info!(
+        "Built site in {}, regenerating {} of {} county pages",
+        out.display(),
+        stale.len(),
+        pages.len()
+    );
+    Ok(())
+}
+
+/// A fingerprint of each county's samples, which changes when samples are added or restated.
+fn select_county_fingerprints(conn: &Connection) -> eyre::Result<Vec<(String, String)>> {
+    const SELECT_COUNTY_FINGERPRINTS_SQL: &str = "
+    SELECT county, COUNT(*), MAX(poll_timestamp), MAX(date_updated),
+        TOTAL(normalized_pathogen_concentration), TOTAL(concentration_per_liter), TOTAL(flow_rate_mgd)
+    FROM wastewater_samples
+    GROUP BY county
+    ORDER BY county";
+
+    let fingerprints = conn
+        .prepare_cached(SELECT_COUNTY_FINGERPRINTS_SQL)?
+        .query_map([], |row| {
+            let count: i64 = row.get(1)?;
+            let polled: i64 = row.get(2)?;
+            let date_updated: String = row.get(3)?;
+            let normalized: f64 = row.get(4)?;
+            let per_liter: f64 = row.get(5)?;
+            let flow_rate: f64 = row.get(6)?;
+            Ok((
+                row.get(0)?,
+                format!("{count}|{polled}|{date_updated}|{normalized}|{per_liter}|{flow_rate}"),
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(fingerprints)
+}
+
+/// Each built page's input hash and its row in the index.
+fn select_site_pages(conn: &Connection) -> eyre::Result<HashMap<String, (String, String)>> {
+    const SELECT_SITE_PAGES_SQL: &str = "SELECT path, input_hash, index_row FROM site_pages";
+
+    let pages = conn
+        .prepare_cached(SELECT_SITE_PAGES_SQL)?
+        .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<Result<_, _>>()?;
+    Ok(pages)
+}
+
+fn upsert_site_page(
+    conn: &Connection,
+    clock: &dyn Clock,
+    path: &str,
+    input_hash: &str,
+    index_row: &str,
+) -> eyre::Result<()> {
+    const UPSERT_SITE_PAGE_SQL: &str = "
+    INSERT INTO site_pages (path, input_hash, index_row, built_timestamp) VALUES
+    (:path, :input_hash, :index_row, :built_timestamp)
+    ON CONFLICT (path) DO UPDATE SET
+        input_hash = excluded.input_hash,
+        index_row = excluded.index_row,
+        built_timestamp = excluded.built_timestamp";
+
+    conn.prepare_cached(UPSERT_SITE_PAGE_SQL)?
+        .execute(named_params! {
+            ":path": path,
+            ":input_hash": input_hash,
+            ":index_row": index_row,
+            ":built_timestamp": clock.unix_timestamp(),
+        })?;
+    Ok(())
+}
+
+/// When each county's samples were last stored or revised, as a Unix timestamp.
+fn select_county_lastmods(conn: &Connection) -> eyre::Result<HashMap<String, i64>> {
+    const SELECT_COUNTY_LASTMODS_SQL: &str = "
+    SELECT county, MAX(poll_timestamp) FROM wastewater_samples GROUP BY county";
+
+    let lastmods = conn
+        .prepare_cached(SELECT_COUNTY_LASTMODS_SQL)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(lastmods)
+}
+
+fn sha256_hex(input: &str) -> String {
+    Sha256::digest(input.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn write_file(out: &Path, path: &str, content: impl AsRef<[u8]>) -> eyre::Result<()> {
+    let path = out.join(path);
+    fs::write(&path, content).with_context(|| format!("Error writing {}", path.display()))
+}
+
+/// Escapes text for HTML and XML.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The absolute URL of `path` on the site, if its base URL is known.
+fn absolute_url(options: &SiteOptions, path: &str) -> Option<Url> {
+    let mut base = options.base_url?.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join(path).ok()
+}
+
+/// The pathogen's activity level in the county with its trend arrow, e.g. "High ↑", or its latest
+/// value if it has no level.
+fn level_badge(report: &Report, county: &str, pathogen: &str, precision: Precision) -> String {
+    let label = format!("{pathogen} wastewater");
+    let line = find_line(report, county, pathogen);
+    let arrow = line
+        .and_then(|line| line.trend.as_ref())
+        .map(|trend| format!(" {}", trend.arrow()))
+        .unwrap_or_default();
+
+    match line {
+        Some(ReportLine {
+            activity: Some(activity),
+            ..
+        }) => badge::badge(
+            &label,
+            &format!("{}{arrow}", activity.level),
+            badge::level_color(activity.level),
+        ),
+        Some(ReportLine {
+            summary: Some(summary),
+            ..
+        }) => badge::badge(
+            &label,
+            &format!("{}{arrow}", precision.format(summary.latest_value)),
+            badge::NO_LEVEL_COLOR,
+        ),
+        _ => badge::badge(&label, "no data", badge::NO_LEVEL_COLOR),
+    }
+}
+
+/// Link preview tags for the page at `path`, with `image` the path of its preview image.
+fn meta_tags(
+    title: &str,
+    description: &str,
+    path: &str,
+    image: Option<&str>,
+    options: &SiteOptions,
+) -> String {
+    let absolute = |path: &str| absolute_url(options, path);
+
+    let mut tags = format!(
+        r#"<meta name="description" content="{description}">
+<meta property="og:type" content="website">
+<meta property="og:site_name" content="Washington wastewater">
+<meta property="og:title" content="{title}">
+<meta property="og:description" content="{description}">
+"#,
+        title = escape(title),
+        description = escape(description),
+    );
+    if let Some(url) = absolute(path) {
+        let _ = writeln!(
+            tags,
+            r#"<meta property="og:url" content="{}">"#,
+            escape(url.as_str())
+        );
+    }
+    match image.and_then(absolute) {
+        Some(image) => {
+            let _ = write!(
+                tags,
+                r#"<meta property="og:image" content="{}">
+<meta property="og:image:type" content="image/png">
+<meta property="og:image:width" content="{}">
+<meta property="og:image:height" content="{}">
+<meta name="twitter:card" content="summary_large_image">
+"#,
+                escape(image.as_str()),
+                preview::WIDTH,
+                preview::HEIGHT
+            );
+        }
+        None => tags.push_str("<meta name=\"twitter:card\" content=\"summary\">\n"),
+    }
+    tags
+}
+
+/// Wraps `body` in the page layout. `root` is the relative path to the site's root, and `meta` the
+/// page's [meta_tags].
+fn page(title: &str, root: &str, meta: &str, body: &str, footer: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+{meta}
+<link rel="stylesheet" href="{root}style.css">
+<link rel="alternate" type="application/atom+xml" title="Wastewater reports" href="{root}feed.xml">
+</head>
+<body>
+<header><a href="{root}index.html">Washington wastewater</a></header>
+<main>
+{body}</main>
+<footer>{footer}</footer>
+</body>
+</html>
+"#,
+        title = escape(title),
+    )
+}
+
+fn find_line<'a>(report: &'a Report, county: &str, pathogen: &str) -> Option<&'a ReportLine> {
+    report
+        .lines
+        .iter()
+        .find(|line| line.county == county && line.pathogen == pathogen)
+}
+
+/// The county's row in the index table.
+fn index_row(report: &Report, county: &str, path: &str, options: &SiteOptions) -> String {
+    let mut row = format!(r#"<tr><th><a href="{path}">{}</a></th>"#, escape(county));
+    for pathogen in options.pathogens {
+        let Some((line, summary)) = find_line(report, county, pathogen)
+            .and_then(|line| line.summary.as_ref().map(|summary| (line, summary)))
+        else {
+            row.push_str("<td>–</td>");
+            continue;
+        };
+
+        let class = line
+            .activity
+            .as_ref()
+            .map(|activity| format!(r#" class="level-{}""#, slug(&activity.level.to_string())))
+            .unwrap_or_default();
+        let mut details = summary.latest_date.to_string();
+        if let Some(trend) = &line.trend {
+            let _ = write!(details, " · {}", trend.label());
+        }
+        if let Some(activity) = &line.activity {
+            let _ = write!(details, " · {} activity", activity.level);
+        }
+        let _ = write!(
+            row,
+            "<td{class}>{}<small>{}</small></td>",
+            options.precision.format(summary.latest_value),
+            escape(&details)
+        );
+    }
+    row.push_str("</tr>\n");
+    row
+}
+
+fn index_page(rows: &[String], options: &SiteOptions, footer: &str) -> String {
+    let mut body = format!(
+        "<h1>Respiratory illness in Washington wastewater</h1>\n\
+        <p>The latest sample of each pathogen in every county, in {}.</p>\n\
+        <table>\n<thead><tr><th>County</th>",
+        options.analysis.measure.unit()
+    );
+    for pathogen in options.pathogens {
+        let _ = write!(body, "<th>{}</th>", escape(pathogen));
+    }
+    body.push_str("</tr></thead>\n<tbody>\n");
+    for row in rows {
+        body.push_str(row);
+    }
+
+    let _ = write!(
+        body,
+        r#"</tbody>
+</table>
+<h2>Downloads</h2>
+<ul>
+<li><a href="data/{SAMPLES_PATH}">Samples (CSV)</a></li>
+<li><a href="data/{SITES_PATH}">Sites (CSV)</a></li>
+<li><a href="data/datapackage.json">Data package descriptor</a></li>
+<li><a href="feed.xml">Report feed (Atom)</a></li>
+<li><a href="alerts.html">Alert history</a> (<a href="alerts.json">JSON</a>)</li>
+</ul>
+"#
+    );
+
+    let meta = meta_tags(
+        "Washington wastewater",
+        "The latest respiratory illness levels in Washington wastewater, by county.",
+        "index.html",
+        None,
+        options,
+    );
+    page("Washington wastewater", "", &meta, &body, footer)
+}
+
+fn county_page(
+    conn: &Connection,
+    report: &Report,
+    county: &str,
+    county_slug: &str,
+    options: &SiteOptions,
+    footer: &str,
+) -> eyre::Result<String> {
+    let mut body = format!("<h1>{} County</h1>\n", escape(county));
+
+    for pathogen in options.pathogens {
+        let _ = writeln!(
+            body,
+            "<section id=\"{}\">\n<h2>{}</h2>",
+            pathogen_anchor(pathogen),
+            escape(pathogen)
+        );
+        match find_line(report, county, pathogen)
+            .and_then(|line| Some((line, line.summary.as_ref()?)))
+        {
+            Some((line, summary)) => {
+                let change = report
+                    .format_change(summary, options.precision)
+                    .unwrap_or_else(|| "no previous sample".to_owned());
+                let mut text = format!(
+                    "{} ({change}) on {}",
+                    options.precision.format(summary.latest_value),
+                    summary.latest_date
+                );
+                if let Some(coverage) = &line.coverage {
+                    let _ = write!(
+                        text,
+                        ", based on {} of {} reporting sites",
+                        coverage.reporting, coverage.total
+                    );
+                }
+                if let Some(averages) = &line.averages {
+                    let _ = write!(text, "; {}", averages.describe(options.precision));
+                }
+                if let Some(history) = &line.history {
+                    let _ = write!(text, "; {}", history.describe());
+                }
+                if let Some(trend) = &line.trend {
+                    let _ = write!(text, " — {}", trend.label());
+                    if let Some(doubling) = trend.describe_doubling() {
+                        let _ = write!(text, ", {doubling}");
+                    }
+                }
+                if let Some(gap) = &line.gap {
+                    let _ = write!(text, " ({})", gap.note());
+                }
+                if let Some(activity) = &line.activity {
+                    let _ = write!(text, " — {} activity", activity.level);
+                }
+                let _ = writeln!(body, "<p>{}</p>", escape(&text));
+                let badge = badge_path(county_slug, pathogen);
+                let _ = writeln!(
+                    body,
+                    r#"<p><a href="../{badge}"><img src="../{badge}" alt="{} badge"></a></p>"#,
+                    escape(pathogen)
+                );
+
+                let series = select_daily_means(conn, county, pathogen, options.analysis.measure)?;
+                body.push_str(&chart(&series, options.precision));
+            }
+            None => body.push_str("<p>No samples.</p>\n"),
+        }
+        body.push_str("</section>\n");
+    }
+
+    let title = format!("{county} County");
+    let meta = meta_tags(
+        &title,
+        &county_description(report, county, options),
+        &county_path(county_slug),
+        Some(&preview_path(county_slug)),
+        options,
+    );
+    Ok(page(&title, "../", &meta, &body, footer))
+}
+
+/// The latest level of each pathogen in the county, e.g. "FLUAV 174000 (Low activity) on
+/// 2024-12-27 · RSV 1100000 on 2024-12-27".
+fn county_description(report: &Report, county: &str, options: &SiteOptions) -> String {
+    let levels: Vec<String> = options
+        .pathogens
+        .iter()
+        .filter_map(|pathogen| {
+            let line = find_line(report, county, pathogen)?;
+            let summary = line.summary.as_ref()?;
+            let mut level = format!(
+                "{pathogen} {}",
+                options.precision.format(summary.latest_value)
+            );
+            if let Some(activity) = &line.activity {
+                let _ = write!(level, " ({} activity)", activity.level);
+            }
+            let _ = write!(level, " on {}", summary.latest_date);
+            Some(level)
+        })
+        .collect();
+
+    if levels.is_empty() {
+        format!("Wastewater samples from {county} County.")
+    } else {
+        levels.join(" · ")
+    }
+}
+
+/// The county's [preview] image.
+fn county_preview(
+    conn: &Connection,
+    report: &Report,
+    county: &str,
+    options: &SiteOptions,
+) -> eyre::Result<Vec<u8>> {
+    let series = options
+        .pathogens
+        .iter()
+        .map(|pathogen| select_daily_means(conn, county, pathogen, options.analysis.measure))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let rows: Vec<PreviewRow> = options
+        .pathogens
+        .iter()
+        .zip(&series)
+        .map(|(pathogen, series)| {
+            let line = find_line(report, county, pathogen);
+            PreviewRow {
+                pathogen,
+                latest: line
+                    .and_then(|line| line.summary.as_ref())
+                    .map(|summary| options.precision.format(summary.latest_value)),
+                level: line
+                    .and_then(|line| line.activity.as_ref())
+                    .map(|activity| activity.level),
+                series,
+            }
+        })
+        .collect();
+
+    preview::county_preview(county, &rows)
+}
+
+/// Mean of `measure` for `pathogen` across the county's sites on each sample date.
+fn select_daily_means(
+    conn: &Connection,
+    county: &str,
+    pathogen: &str,
+    measure: Measure,
+) -> eyre::Result<Vec<(NaiveDate, f64)>> {
+    let select_daily_means_sql = format!(
+        "
+    SELECT sample_collection_date, AVG({measure})
+    FROM wastewater_samples
+    WHERE county = ?1 AND pcr_pathogen_target = ?2 AND {measure} IS NOT NULL
+    GROUP BY sample_collection_date
+    ORDER BY sample_collection_date",
+        measure = measure.column()
+    );
+
+    let series = conn
+        .prepare_cached(&select_daily_means_sql)?
+        .query_map(params![county, pathogen], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(series)
+}
+
+/// An SVG line chart of `series` on a log scale, which keeps both waves and lulls readable.
+fn chart(series: &[(NaiveDate, f64)], precision: Precision) -> String {
+    chart_svg(series, precision)
+        .unwrap_or_else(|| "<p>Not enough samples to chart.</p>\n".to_owned())
+}
+
+/// [chart] as a standalone SVG document with its styles inlined, e.g. to embed as an image. None
+/// if there aren't enough samples to chart.
+pub fn standalone_chart(series: &[(NaiveDate, f64)], precision: Precision) -> Option<String> {
+    let svg = chart_svg(series, precision)?;
+    let (open, rest) = svg.split_once('\n')?;
+    Some(format!(
+        "{}\n<style>{CHART_STYLESHEET}</style>\n{rest}",
+        open.replacen("<svg ", "<svg xmlns=\"http://www.w3.org/2000/svg\" ", 1)
+    ))
+}
+
+/// The chart, or None with fewer than two samples above zero.
+fn chart_svg(series: &[(NaiveDate, f64)], precision: Precision) -> Option<String> {
+    const WIDTH: f64 = 640.0;
+    const HEIGHT: f64 = 200.0;
+    const LEFT: f64 = 70.0;
+    const RIGHT: f64 = 10.0;
+    const TOP: f64 = 10.0;
+    const BOTTOM: f64 = 25.0;
+
+    // Zero can't be plotted on a log scale
+    let points: Vec<(NaiveDate, f64)> = series
+        .iter()
+        .copied()
+        .filter(|&(_, value)| value > 0.0)
+        .collect();
+    let (Some(&(first_date, _)), Some(&(last_date, _))) = (points.first(), points.last()) else {
+        return None;
+    };
+    if points.len() < 2 {
+        return None;
+    }
+
+    let (min, max) = points.iter().fold(
+        (f64::INFINITY, f64::NEG_INFINITY),
+        |(min, max), &(_, value)| (min.min(value), max.max(value)),
+    );
+    let (log_min, log_max) = (min.log10(), max.log10());
+    let log_span = (log_max - log_min).max(f64::EPSILON);
+    let days = (last_date - first_date).num_days().max(1) as f64;
+
+    let coordinates: Vec<String> = points
+        .iter()
+        .map(|&(date, value)| {
+            let x = LEFT + (date - first_date).num_days() as f64 / days * (WIDTH - LEFT - RIGHT);
+            let y = TOP + (1.0 - (value.log10() - log_min) / log_span) * (HEIGHT - TOP - BOTTOM);
+            format!("{x:.1},{y:.1}")
+        })
+        .collect();
+
+    Some(format!(
+        r#"<svg viewBox="0 0 {WIDTH} {HEIGHT}" role="img" aria-label="Daily mean concentration from {first_date} to {last_date}">
+<polyline points="{}"/>
+<text x="0" y="{}">{}</text>
+<text x="0" y="{}">{}</text>
+<text x="{LEFT}" y="{}">{first_date}</text>
+<text x="{}" y="{}" text-anchor="end">{last_date}</text>
+</svg>
+"#,
+        coordinates.join(" "),
+        TOP + 10.0,
+        precision.format(max),
+        HEIGHT - BOTTOM,
+        precision.format(min),
+        HEIGHT - 5.0,
+        WIDTH - RIGHT,
+        HEIGHT - 5.0,
+    ))
+}
+
+/// The alert history, newest first.
+fn alerts_page(alerts: &[alerts::Alert], options: &SiteOptions, footer: &str) -> String {
+    let mut body = String::from(
+        "<h1>Alert history</h1>\n\
+        <p>Every notice announced in a report, where it was sent, and whether it was delivered.</p>\n\
+        <table>\n<thead><tr><th>Announced</th><th>Tenant</th><th>Rule</th><th>Alert</th>\
+        <th>Sent to</th><th>Status</th></tr></thead>\n<tbody>\n",
+    );
+    for alert in alerts {
+        let announced = DateTime::from_timestamp(alert.created_timestamp as i64, 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M UTC");
+        let _ = writeln!(
+            body,
+            "<tr><td>{announced}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&alert.tenant),
+            escape(&alert.rule),
+            escape(&alert.message),
+            escape(&alert.destinations.join(", ")),
+            escape(&alert.status),
+        );
+    }
+    if alerts.is_empty() {
+        body.push_str("<tr><td colspan=\"6\">No alerts yet.</td></tr>\n");
+    }
+    body.push_str("</tbody>\n</table>\n");
+
+    let meta = meta_tags(
+        "Alert history",
+        "Notices announced in Washington wastewater reports.",
+        "alerts.html",
+        None,
+        options,
+    );
+    page("Alert history", "", &meta, &body, footer)
+}
+
+/// A widget with the county's latest levels and a small chart of each, with its styles inlined and
+/// links opening outside the iframe.
+fn embed_page(
+    conn: &Connection,
+    report: &Report,
+    county: &str,
+    county_slug: &str,
+    options: &SiteOptions,
+) -> eyre::Result<String> {
+    let mut levels = String::new();
+    for pathogen in options.pathogens {
+        let Some((line, summary)) = find_line(report, county, pathogen)
+            .and_then(|line| Some((line, line.summary.as_ref()?)))
+        else {
+            continue;
+        };
+
+        let mut details = format!(
+            "{} on {}",
+            options.precision.format(summary.latest_value),
+            summary.latest_date
+        );
+        if let Some(trend) = &line.trend {
+            let _ = write!(details, " {}", trend.arrow());
+        }
+        if let Some(activity) = &line.activity {
+            let _ = write!(details, " · {} activity", activity.level);
+        }
+        let series = select_daily_means(conn, county, pathogen, options.analysis.measure)?;
+        let _ = write!(
+            levels,
+            "<li><strong>{}</strong> <small>{}</small>\n{}</li>\n",
+            escape(pathogen),
+            escape(&details),
+            chart(&series, options.precision)
+        );
+    }
+    if levels.is_empty() {
+        levels.push_str("<li>No samples.</li>\n");
+    }
+
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{title}</title>
+<style>
+{EMBED_STYLESHEET}</style>
+</head>
+<body>
+<h1>{title} wastewater</h1>
+<ul>
+{levels}</ul>
+<a href="../{county_page}" target="_blank" rel="noopener">Details and history</a>
+</body>
+</html>
+"#,
+        title = escape(&format!("{county} County")),
+        county_page = county_path(county_slug),
+    ))
+}
+
+/// Headers for static hosts limiting which origins may embed the widgets.
+fn headers(embed_origins: &[String]) -> String {
+    let ancestors = if embed_origins.is_empty() {
+        "*".to_owned()
+    } else {
+        embed_origins.join(" ")
+    };
+    format!("/embed/*\n  Content-Security-Policy: frame-ancestors {ancestors}\n")
+}
+
+/// A sitemap of the index and the county pages, each `(path, lastmod)`. The index was last modified
+/// whenever any county was.
+fn sitemap<'a>(
+    pages: impl Iterator<Item = (&'a str, Option<i64>)> + Clone,
+    options: &SiteOptions,
+) -> String {
+    let mut sitemap = String::from(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+"#,
+    );
+
+    let index_lastmod = pages.clone().filter_map(|(_, lastmod)| lastmod).max();
+    for (path, lastmod) in std::iter::once(("index.html", index_lastmod)).chain(pages) {
+        let Some(url) = absolute_url(options, path) else {
+            continue;
+        };
+        let _ = write!(sitemap, "<url><loc>{}</loc>", escape(url.as_str()));
+        if let Some(lastmod) = lastmod.and_then(|lastmod| DateTime::from_timestamp(lastmod, 0)) {
+            let _ = write!(sitemap, "<lastmod>{}</lastmod>", lastmod.to_rfc3339());
+        }
+        sitemap.push_str("</url>\n");
+    }
+
+    sitemap.push_str("</urlset>\n");
+    sitemap
+}
+
+/// Allows crawling the whole site, pointing crawlers at the sitemap if there is one.
+fn robots(sitemap_url: Option<&Url>) -> String {
+    let mut robots = String::from("User-agent: *\nAllow: /\n");
+    if let Some(sitemap_url) = sitemap_url {
+        let _ = writeln!(robots, "\nSitemap: {sitemap_url}");
+    }
+    robots
+}
+
+/// An Atom feed of the newest delivered reports.
+fn feed(conn: &Connection, generated_at: DateTime<Utc>) -> eyre::Result<String> {
+    const SELECT_DELIVERED_REPORTS_SQL: &str = "
+    SELECT id, tenant, period, markdown, finished_timestamp
+    FROM pending_reports
+    WHERE status = 'delivered'
+    ORDER BY id DESC
+    LIMIT ?1";
+
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>Washington wastewater reports</title>
+<id>urn:hygieia:reports</id>
+<updated>{}</updated>
+<author><name>hygieia</name></author>
+<link href="index.html"/>
+"#,
+        generated_at.to_rfc3339()
+    );
+
+    let mut stmt = conn.prepare_cached(SELECT_DELIVERED_REPORTS_SQL)?;
+    let mut rows = stmt.query([FEED_ENTRIES])?;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let tenant: String = row.get(1)?;
+        let period: String = row.get(2)?;
+        let markdown: String = row.get(3)?;
+        let delivered = DateTime::from_timestamp(row.get(4)?, 0).unwrap_or_default();
+        let _ = write!(
+            feed,
+            r#"<entry>
+<title>Report for {} ({})</title>
+<id>urn:hygieia:report:{id}</id>
+<updated>{}</updated>
+<content type="text">{}</content>
+</entry>
+"#,
+            escape(&period),
+            escape(&tenant),
+            delivered.to_rfc3339(),
+            escape(&markdown)
+        );
+    }
+
+    feed.push_str("</feed>\n");
+    Ok(feed)
+}
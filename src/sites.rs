@@ -0,0 +1,116 @@
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection};
+use serde::Deserialize;
+use tracing::{info, instrument, warn};
+
+use crate::http::{redact_url, Body, HttpClient};
+use crate::slugs;
+use crate::useful::Clock;
+
+/// Metadata about a sampling site, as stored in the `sites` table.
+#[derive(Debug, Deserialize)]
+pub struct SiteMetadata {
+    #[serde(alias = "Site Name", alias = "wwtp_name", alias = "sewershed_name")]
+    pub site_name: String,
+    #[serde(alias = "County", alias = "county_names")]
+    pub county: String,
+    /// Population served by the sewershed.
+    #[serde(default, alias = "Population Served", alias = "population_served")]
+    pub population: Option<u64>,
+    #[serde(default, alias = "Latitude")]
+    pub latitude: Option<f64>,
+    #[serde(default, alias = "Longitude")]
+    pub longitude: Option<f64>,
+}
+
+/// A remote source of site metadata.
+pub trait SiteSource {
+    /// Short name recorded with every site this source updates.
+    fn name(&self) -> &str;
+
+    fn fetch(&self, conn: &Connection, http: &HttpClient) -> eyre::Result<Vec<SiteMetadata>>;
+}
+
+/// Site metadata published as a CSV file.
+/// Columns are matched by name, accepting both this table's column names and the common
+/// spellings used by the DOH and CDC NWSS sewershed listings.
+pub struct CsvSiteSource {
+    url: String,
+    /// The URL with secrets redacted.
+    name: String,
+}
+
+impl CsvSiteSource {
+    pub fn new(url: String) -> Self {
+        let name = redact_url(&url);
+        Self { url, name }
+    }
+}
+
+impl SiteSource for CsvSiteSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&self, conn: &Connection, http: &HttpClient) -> eyre::Result<Vec<SiteMetadata>> {
+        let response = http.send(conn, http.get(&self.url), Body::Empty)?;
+        let reader = csv::Reader::from_reader(response.into_reader());
+
+        let sites = reader
+            .into_deserialize()
+            .filter_map(|row| match row {
+                Ok(site) => Some(site),
+                Err(e) => {
+                    warn!("Skipping unparseable site row: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        Ok(sites)
+    }
+}
+
+/// Fetches site metadata from `source` and upserts it into the `sites` table.
+/// Values the source leaves empty don't overwrite what is already stored.
+#[instrument(skip_all, fields(source = source.name()))]
+pub fn sync_sites(
+    conn: &mut Connection,
+    http: &HttpClient,
+    clock: &dyn Clock,
+    source: &dyn SiteSource,
+) -> eyre::Result<usize> {
+    const UPSERT_SITE_SQL: &str = "
+    INSERT INTO sites (site_name, county, population, latitude, longitude, source, updated_timestamp) VALUES
+    (:site_name, :county, :population, :latitude, :longitude, :source, :updated_timestamp)
+    ON CONFLICT (site_name, county) DO UPDATE SET
+        population = COALESCE(excluded.population, sites.population),
+        latitude = COALESCE(excluded.latitude, sites.latitude),
+        longitude = COALESCE(excluded.longitude, sites.longitude),
+        source = excluded.source,
+        updated_timestamp = excluded.updated_timestamp";
+
+    let sites = source.fetch(conn, http)?;
+    let updated_timestamp = clock.unix_timestamp();
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(UPSERT_SITE_SQL)?;
+        for site in &sites {
+            stmt.execute(named_params! {
+                ":site_name": site.site_name,
+                ":county": site.county,
+                ":population": site.population,
+                ":latitude": site.latitude,
+                ":longitude": site.longitude,
+                ":source": source.name(),
+                ":updated_timestamp": updated_timestamp,
+            })?;
+        }
+    }
+    tx.commit()?;
+    slugs::assign_slugs(conn)?;
+
+    info!("Synced {} sites", sites.len());
+    Ok(sites.len())
+}
@@ -0,0 +1,233 @@
+//! Posts reports to a Slack incoming webhook, formatted with Block Kit: the greeting, a section per
+//! county, the notices and rankings, and a context block with the sample dates and provenance.
+//!
+//! The blocks are rendered with the rest of the report and stored with it, so a report delivered
+//! later by `notify` looks the same as one delivered right away.
+
+use std::fmt::Write as _;
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+
+use crate::context::RunContext;
+use crate::http::{Body, HttpClient};
+use crate::links::DashboardLinks;
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::precision::Precision;
+use crate::report::Report;
+use crate::tenants::Tenant;
+
+/// Most blocks Slack accepts in a message.
+const MAX_BLOCKS: usize = 50;
+/// Most characters Slack accepts in a section's text.
+const MAX_SECTION_TEXT: usize = 3000;
+
+/// Posts reports to a Slack incoming webhook.
+pub struct SlackWebhook {
+    url: String,
+}
+
+impl SlackWebhook {
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+
+    /// Posts the report's `blocks`, with `text` shown in notifications and by clients that can't
+    /// display blocks. Without blocks only the text is posted.
+    #[instrument(skip_all)]
+    pub fn send(
+        &self,
+        conn: &Connection,
+        http: &HttpClient,
+        text: &str,
+        blocks: Option<&str>,
+    ) -> eyre::Result<()> {
+        let mut payload = json!({ "text": text });
+        if let Some(blocks) = blocks {
+            payload["blocks"] = serde_json::from_str(blocks)?;
+        }
+
+        http.send(conn, http.post(&self.url), Body::Json(&payload))?;
+        info!("Posted report to Slack webhook");
+
+        Ok(())
+    }
+}
+
+/// Posts to the tenant's Slack webhook, if it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlackNotifier;
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(url) = &tenant.slack_webhook_url else {
+            return Ok(());
+        };
+
+        let webhook = SlackWebhook::new(url.clone());
+        match &report.slack_blocks {
+            Some(blocks) => {
+                let text = match report.period.as_str() {
+                    "" => "Wastewater report".to_owned(),
+                    period => format!("Wastewater report for {period}"),
+                };
+                webhook.send(&ctx.db, &ctx.http, &text, Some(blocks))
+            }
+            // Reports stored before blocks were rendered
+            None => webhook.send(&ctx.db, &ctx.http, &report.markdown, None),
+        }
+    }
+}
+
+/// Renders the report as Block Kit blocks.
+pub fn report_blocks(
+    report: &Report,
+    links: Option<&DashboardLinks>,
+    precision: Precision,
+) -> Value {
+    let mut blocks = vec![section(&escape(&report.greeting_line()))];
+
+    let counties: Vec<_> = report.lines.chunk_by(|a, b| a.county == b.county).collect();
+    // Room for the notices, rankings, context, and the note about counties left out
+    let room = MAX_BLOCKS - blocks.len() - 4;
+    for lines in counties.iter().take(room) {
+        let mut text = format!("*{} County*", escape(&lines[0].county));
+        for line in *lines {
+            let details = report
+                .describe_line(line, precision, |level| format!("*{level}*"))
+                .map(|details| escape(&details))
+                .unwrap_or_else(|| "There was an error getting data for this.".to_owned());
+            let _ = write!(text, "\n• *{}*: {details}", escape(&line.pathogen));
+            if let (Some(links), Some(_)) = (links, &line.summary) {
+                let _ = write!(
+                    text,
+                    " (<{}|details>)",
+                    links.chart(&line.county_slug, &line.pathogen)
+                );
+            }
+        }
+        blocks.push(section(&text));
+    }
+    if counties.len() > room {
+        blocks.push(section(&format!(
+            "…and {} more counties",
+            counties.len() - room
+        )));
+    }
+
+    let notices = &report.notices;
+    let notices: Vec<String> = notices
+        .threshold_alerts
+        .iter()
+        .map(|alert| format!("🚨 {}", escape(&alert.to_string())))
+        .chain(
+            notices
+                .coverage_changes
+                .iter()
+                .map(|change| format!("📍 {}", escape(&change.to_string()))),
+        )
+        .chain(
+            notices
+                .season_onsets
+                .iter()
+                .map(|onset| format!("🦠 {}", escape(&onset.to_string()))),
+        )
+        .chain(
+            notices
+                .revisions
+                .iter()
+                .map(|revisions| format!("✏️ {}", escape(&revisions.to_string()))),
+        )
+        .chain(
+            notices
+                .vintage
+                .iter()
+                .map(|vintage| format!("🔄 {}", escape(&vintage.to_string()))),
+        )
+        .collect();
+    if !notices.is_empty() {
+        blocks.push(section(&notices.join("\n")));
+    }
+
+    let rankings: Vec<String> = report
+        .rankings
+        .iter()
+        .filter(|ranking| !ranking.counties.is_empty())
+        .map(|ranking| {
+            let counties: Vec<String> = ranking
+                .counties
+                .iter()
+                .enumerate()
+                .map(|(i, county)| {
+                    let entry = format!(
+                        "{}. {} ({})",
+                        i + 1,
+                        escape(&county.county),
+                        precision.format(county.level)
+                    );
+                    if county.highlighted {
+                        format!("*{entry}*")
+                    } else {
+                        entry
+                    }
+                })
+                .collect();
+            format!("{}: {}", escape(&ranking.pathogen), counties.join(" · "))
+        })
+        .collect();
+    if !rankings.is_empty() {
+        blocks.push(section(&format!(
+            "*Statewide ranking* (mean of each site's latest sample):\n{}",
+            rankings.join("\n")
+        )));
+    }
+
+    let mut context = Vec::new();
+    if let Some(period) = report.period() {
+        let dates = if report.range.is_unbounded() {
+            format!("Latest samples collected {period}")
+        } else {
+            format!("Samples {}, latest collected {period}", report.range)
+        };
+        context.push(json!({ "type": "mrkdwn", "text": escape(&dates) }));
+    }
+    if let Some(provenance) = &report.provenance {
+        // Not escaped, since the footer's <url> is a link in Slack too
+        context.push(json!({ "type": "mrkdwn", "text": provenance.footer() }));
+    }
+    if !context.is_empty() {
+        blocks.push(json!({ "type": "context", "elements": context }));
+    }
+
+    Value::Array(blocks)
+}
+
+/// A section block of mrkdwn text, cut short if it's over Slack's limit.
+fn section(text: &str) -> Value {
+    let text = if text.chars().count() > MAX_SECTION_TEXT {
+        let mut cut: String = text.chars().take(MAX_SECTION_TEXT - 1).collect();
+        cut.push('…');
+        cut
+    } else {
+        text.to_owned()
+    };
+
+    json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": text },
+    })
+}
+
+/// Escapes the characters Slack's mrkdwn uses for links and mentions.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
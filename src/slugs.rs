@@ -0,0 +1,79 @@
+//! Stable URL slugs for counties, stored in `slugs` so a page keeps its URL once it has one. Names
+//! that normalize to the same slug, like "Grays Harbor" and "Grays-Harbor", get a numbered suffix
+//! in the order they were first seen.
+
+use std::collections::{HashMap, HashSet};
+
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use tracing::info;
+
+use crate::links::slug;
+
+const SELECT_COUNTY_NAMES_SQL: &str = "
+SELECT county FROM wastewater_samples
+UNION SELECT county FROM sites
+ORDER BY 1";
+
+/// Gives every county without a slug one, returning how many were added.
+pub fn assign_slugs(conn: &Connection) -> eyre::Result<usize> {
+    const INSERT_SLUG_SQL: &str = "
+    INSERT INTO slugs (kind, name, slug) VALUES ('county', :name, :slug)";
+
+    let mut added = 0;
+    let assigned = select_county_slugs(conn)?;
+    let mut taken: HashSet<String> = assigned.values().cloned().collect();
+    let names: Vec<String> = conn
+        .prepare_cached(SELECT_COUNTY_NAMES_SQL)?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    for name in names.iter().filter(|name| !assigned.contains_key(*name)) {
+        let base = match slug(name) {
+            base if base.is_empty() => "unnamed".to_owned(),
+            base => base,
+        };
+        let unique = (1..)
+            .map(|n| match n {
+                1 => base.clone(),
+                n => format!("{base}-{n}"),
+            })
+            .find(|candidate| !taken.contains(candidate))
+            .expect("some suffix is free");
+
+        conn.prepare_cached(INSERT_SLUG_SQL)?
+            .execute(named_params! {
+                ":name": name,
+                ":slug": unique,
+            })?;
+        taken.insert(unique);
+        added += 1;
+    }
+
+    if added > 0 {
+        info!("Assigned {added} slugs");
+    }
+    Ok(added)
+}
+
+/// Every assigned county slug, by county.
+pub fn select_county_slugs(conn: &Connection) -> eyre::Result<HashMap<String, String>> {
+    const SELECT_SLUGS_SQL: &str = "SELECT name, slug FROM slugs WHERE kind = 'county'";
+
+    let slugs = conn
+        .prepare_cached(SELECT_SLUGS_SQL)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(slugs)
+}
+
+/// The county's assigned slug, or the one it would most likely get if it has none yet.
+pub fn county_slug(conn: &Connection, county: &str) -> eyre::Result<String> {
+    const SELECT_SLUG_SQL: &str = "SELECT slug FROM slugs WHERE kind = 'county' AND name = ?1";
+
+    let assigned = conn
+        .prepare_cached(SELECT_SLUG_SQL)?
+        .query_row([county], |row| row.get(0))
+        .optional()?;
+    Ok(assigned.unwrap_or_else(|| slug(county)))
+}
@@ -0,0 +1,112 @@
+use color_eyre::eyre;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+use crate::http::{Body, HttpClient};
+use crate::useful::Clock;
+
+/// Subset of a Socrata view's metadata, as returned by `https://data.wa.gov/api/views/{id}.json`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ViewMetadata {
+    /// Unix timestamp of the last change to the dataset's rows.
+    rows_updated_at: Option<i64>,
+    /// Unix timestamp of the last change to the dataset's metadata.
+    view_last_modified: Option<i64>,
+    #[serde(default)]
+    columns: Vec<ViewColumn>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ViewColumn {
+    cached_contents: Option<CachedContents>,
+}
+
+/// Column statistics Socrata caches. They count values, so together they give the row count.
+#[derive(Debug, Deserialize)]
+struct CachedContents {
+    non_null: Option<u64>,
+    null: Option<u64>,
+}
+
+/// A revision of the upstream dataset, as recorded in `dataset_revisions`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DatasetRevision {
+    pub rows_updated_at: Option<i64>,
+    pub view_last_modified: Option<i64>,
+    pub row_count: Option<u64>,
+}
+
+impl DatasetRevision {
+    /// Whether this revision has the same rows as `other`. Unknown timestamps never match.
+    pub fn same_rows_as(&self, other: &DatasetRevision) -> bool {
+        self.rows_updated_at.is_some() && self.rows_updated_at == other.rows_updated_at
+    }
+}
+
+/// Fetches the current revision of a dataset from its Socrata metadata URL.
+#[instrument(skip(conn, http))]
+pub fn fetch_revision(
+    conn: &Connection,
+    http: &HttpClient,
+    metadata_url: &str,
+) -> eyre::Result<DatasetRevision> {
+    let metadata: ViewMetadata = http
+        .send(conn, http.get(metadata_url), Body::Empty)?
+        .into_json()?;
+
+    let row_count = metadata
+        .columns
+        .first()
+        .and_then(|column| column.cached_contents.as_ref())
+        .map(|contents| contents.non_null.unwrap_or(0) + contents.null.unwrap_or(0));
+
+    let revision = DatasetRevision {
+        rows_updated_at: metadata.rows_updated_at,
+        view_last_modified: metadata.view_last_modified,
+        row_count,
+    };
+    debug!("Fetched dataset revision: {revision:?}");
+
+    Ok(revision)
+}
+
+pub fn select_last_revision(conn: &Connection) -> eyre::Result<Option<DatasetRevision>> {
+    const SELECT_LAST_REVISION_SQL: &str = "
+    SELECT rows_updated_at, view_last_modified, row_count FROM dataset_revisions
+    ORDER BY fetched_timestamp DESC, rowid DESC
+    LIMIT 1";
+
+    Ok(conn
+        .prepare_cached(SELECT_LAST_REVISION_SQL)?
+        .query_row([], |row| {
+            Ok(DatasetRevision {
+                rows_updated_at: row.get(0)?,
+                view_last_modified: row.get(1)?,
+                row_count: row.get(2)?,
+            })
+        })
+        .optional()?)
+}
+
+pub fn insert_revision(
+    conn: &Connection,
+    clock: &dyn Clock,
+    revision: &DatasetRevision,
+) -> eyre::Result<()> {
+    const INSERT_REVISION_SQL: &str = "
+    INSERT INTO dataset_revisions (fetched_timestamp, rows_updated_at, view_last_modified, row_count) VALUES
+    (:fetched_timestamp, :rows_updated_at, :view_last_modified, :row_count)";
+
+    conn.prepare_cached(INSERT_REVISION_SQL)?
+        .execute(named_params! {
+            ":fetched_timestamp": clock.unix_timestamp(),
+            ":rows_updated_at": revision.rows_updated_at,
+            ":view_last_modified": revision.view_last_modified,
+            ":row_count": revision.row_count,
+        })?;
+
+    Ok(())
+}
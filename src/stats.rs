@@ -0,0 +1,204 @@
+use std::fmt;
+
+use chrono::DateTime;
+use color_eyre::eyre;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::poll_runs::{self, PollRun};
+use crate::useful::Clock;
+
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+
+/// Health statistics for external monitoring, printed by `hygieia stats`.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub samples: SampleStats,
+    pub last_run: Option<LastRun>,
+    pub last_poll: Option<PollRun>,
+    pub delivery: DeliveryStats,
+    pub http: HttpStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SampleStats {
+    pub total: u64,
+    /// Samples first polled in the last 24 hours.
+    pub last_24h: u64,
+    /// Samples first polled in the last 7 days.
+    pub last_7d: u64,
+    pub latest_sample_date: Option<String>,
+}
+
+/// The run that stored the newest report.
+#[derive(Debug, Serialize)]
+pub struct LastRun {
+    pub run_id: String,
+    pub created_timestamp: u64,
+    /// Status of its report: pending, delivered, or superseded.
+    pub status: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryStats {
+    pub pending: u64,
+    pub delivered: u64,
+    pub superseded: u64,
+    pub discord_messages: u64,
+    pub last_discord_post_timestamp: Option<u64>,
+}
+
+/// Requests recorded in the audit log over the last 24 hours.
+#[derive(Debug, Serialize)]
+pub struct HttpStats {
+    pub requests_24h: u64,
+    /// Requests that got no response or an error status.
+    pub failures_24h: u64,
+}
+
+pub fn collect_stats(conn: &Connection, clock: &dyn Clock) -> eyre::Result<Stats> {
+    let now = clock.unix_timestamp();
+    let day_ago = now.saturating_sub(DAY_SECONDS);
+    let week_ago = now.saturating_sub(7 * DAY_SECONDS);
+
+    let samples = conn.query_row(
+        "SELECT COUNT(*),
+            COUNT(*) FILTER (WHERE poll_timestamp >= ?1),
+            COUNT(*) FILTER (WHERE poll_timestamp >= ?2),
+            MAX(sample_collection_date)
+        FROM wastewater_samples",
+        params![day_ago, week_ago],
+        |row| {
+            Ok(SampleStats {
+                total: row.get(0)?,
+                last_24h: row.get(1)?,
+                last_7d: row.get(2)?,
+                latest_sample_date: row.get(3)?,
+            })
+        },
+    )?;
+
+    let last_run = conn
+        .query_row(
+            "SELECT run_id, created_timestamp, status FROM pending_reports ORDER BY id DESC LIMIT 1",
+            [],
+            |row| {
+                Ok(LastRun {
+                    run_id: row.get(0)?,
+                    created_timestamp: row.get(1)?,
+                    status: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+
+    let delivery = conn.query_row(
+        "SELECT
+            (SELECT COUNT(*) FROM pending_reports WHERE status = 'pending'),
+            (SELECT COUNT(*) FROM pending_reports WHERE status = 'delivered'),
+            (SELECT COUNT(*) FROM pending_reports WHERE status = 'superseded'),
+            (SELECT COUNT(*) FROM discord_messages),
+            (SELECT MAX(posted_timestamp) FROM discord_messages)",
+        [],
+        |row| {
+            Ok(DeliveryStats {
+                pending: row.get(0)?,
+                delivered: row.get(1)?,
+                superseded: row.get(2)?,
+                discord_messages: row.get(3)?,
+                last_discord_post_timestamp: row.get(4)?,
+            })
+        },
+    )?;
+
+    let http = conn.query_row(
+        "SELECT COUNT(*),
+            COUNT(*) FILTER (WHERE error IS NOT NULL OR status >= 400)
+        FROM http_audit
+        WHERE request_timestamp >= ?1",
+        params![day_ago],
+        |row| {
+            Ok(HttpStats {
+                requests_24h: row.get(0)?,
+                failures_24h: row.get(1)?,
+            })
+        },
+    )?;
+
+    Ok(Stats {
+        samples,
+        last_run,
+        last_poll: poll_runs::select_last_poll_run(conn)?,
+        delivery,
+        http,
+    })
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let samples = &self.samples;
+        writeln!(
+            f,
+            "Samples: {} total, {} in the last 24h, {} in the last 7d, latest collected {}",
+            samples.total,
+            samples.last_24h,
+            samples.last_7d,
+            samples.latest_sample_date.as_deref().unwrap_or("never")
+        )?;
+
+        match &self.last_run {
+            Some(run) => writeln!(
+                f,
+                "Last run: {} at {}, report {}",
+                run.run_id,
+                format_timestamp(run.created_timestamp),
+                run.status
+            )?,
+            None => writeln!(f, "Last run: none")?,
+        }
+
+        match &self.last_poll {
+            Some(poll) => writeln!(
+                f,
+                "Last poll: {} at {}, {}: {} parsed, {} inserted, {} skipped, {} revised, {} retracted, {} failed{}{}",
+                poll.run_id,
+                format_timestamp(poll.started_timestamp),
+                poll.status,
+                poll.rows_parsed,
+                poll.rows_inserted,
+                poll.rows_skipped,
+                poll.rows_revised,
+                poll.rows_retracted,
+                poll.rows_failed,
+                poll.resumed_run_id
+                    .as_ref()
+                    .map(|run_id| format!(", completing {run_id}"))
+                    .unwrap_or_default(),
+                poll.error
+                    .as_ref()
+                    .map(|error| format!(" ({error})"))
+                    .unwrap_or_default()
+            )?,
+            None => writeln!(f, "Last poll: none")?,
+        }
+
+        let delivery = &self.delivery;
+        writeln!(
+            f,
+            "Reports: {} pending, {} delivered, {} superseded; {} Discord messages posted",
+            delivery.pending, delivery.delivered, delivery.superseded, delivery.discord_messages
+        )?;
+
+        write!(
+            f,
+            "HTTP: {} requests in the last 24h, {} failed",
+            self.http.requests_24h, self.http.failures_24h
+        )
+    }
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
@@ -0,0 +1,219 @@
+//! Third parties subscribe a callback URL to a tenant's reports with `hygieia subscribers add`, and
+//! every run that stores new data posts them the report as JSON, e.g.
+//!
+//! ```json
+//! {
+//!     "run_id": "...",
+//!     "tenant": "default",
+//!     "period": "2024-12-01",
+//!     "lines": [{ "county": "Pierce", "pathogen": "RSV", "latest_value": 1234.5, ... }],
+//!     "notices": ["..."],
+//!     "markdown": "..."
+//! }
+//! ```
+//!
+//! Payloads to subscribers with a secret carry the same signature and timestamp headers as the JSON
+//! webhook, which can be checked with [crate::verify_signature]. Deliveries still failing after the HTTP client's
+//! retries are recorded on the subscriber and tried again with the next new data.
+
+use std::fmt;
+
+use color_eyre::eyre::{self, eyre};
+use rusqlite::{named_params, Connection};
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::http::{Body, HttpClient};
+use crate::json_webhook;
+use crate::pending::RenderedReport;
+use crate::report::Report;
+use crate::signature::{self, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::useful::{Clock, Secret};
+
+/// A callback URL as stored in `subscribers`.
+#[derive(Debug, Serialize)]
+pub struct Subscriber {
+    pub id: i64,
+    pub callback_url: String,
+    /// Name of the tenant whose reports are posted.
+    pub tenant: String,
+    #[serde(skip)]
+    pub secret: Option<Secret>,
+    pub created_timestamp: u64,
+    pub last_attempt_timestamp: Option<u64>,
+    pub last_delivered_timestamp: Option<u64>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u64,
+}
+
+impl fmt::Display for Subscriber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} tenant={} signed={} ",
+            self.id,
+            self.callback_url,
+            self.tenant,
+            self.secret.is_some()
+        )?;
+        match (&self.last_error, self.last_delivered_timestamp) {
+            (Some(error), _) => write!(
+                f,
+                "failing ({} in a row): {error}",
+                self.consecutive_failures
+            ),
+            (None, Some(delivered)) => write!(f, "last delivered at {delivered}"),
+            (None, None) => f.write_str("never delivered"),
+        }
+    }
+}
+
+/// Subscribes `callback_url` to `tenant`'s reports, returning its id.
+/// The URL must be http or https, and may be subscribed to each tenant once.
+pub fn add_subscriber(
+    conn: &Connection,
+    clock: &dyn Clock,
+    callback_url: &str,
+    tenant: &str,
+    secret: Option<&Secret>,
+) -> eyre::Result<i64> {
+    const INSERT_SUBSCRIBER_SQL: &str = "
+    INSERT INTO subscribers (callback_url, tenant, secret, created_timestamp) VALUES
+    (:callback_url, :tenant, :secret, :created_timestamp)
+    ON CONFLICT (callback_url, tenant) DO NOTHING";
+
+    let url =
+        Url::parse(callback_url).map_err(|e| eyre!("Invalid callback URL {callback_url}: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(eyre!("Callback URL {callback_url} must be http or https"));
+    }
+
+    let inserted = conn
+        .prepare_cached(INSERT_SUBSCRIBER_SQL)?
+        .execute(named_params! {
+            ":callback_url": callback_url,
+            ":tenant": tenant,
+            ":secret": secret.map(Secret::expose),
+            ":created_timestamp": clock.unix_timestamp(),
+        })?;
+    if inserted == 0 {
+        return Err(eyre!(
+            "{callback_url} is already subscribed to tenant {tenant}"
+        ));
+    }
+
+    Ok(conn.last_insert_rowid())
+}
+
+/// Unsubscribes `callback_url` from every tenant, returning how many subscriptions were removed.
+pub fn remove_subscriber(conn: &Connection, callback_url: &str) -> eyre::Result<usize> {
+    const DELETE_SUBSCRIBER_SQL: &str = "DELETE FROM subscribers WHERE callback_url = ?1";
+
+    Ok(conn
+        .prepare_cached(DELETE_SUBSCRIBER_SQL)?
+        .execute([callback_url])?)
+}
+
+/// Every subscriber, or only `tenant`'s, oldest first.
+pub fn select_subscribers(
+    conn: &Connection,
+    tenant: Option<&str>,
+) -> eyre::Result<Vec<Subscriber>> {
+    const SELECT_SUBSCRIBERS_SQL: &str = "
+    SELECT id, callback_url, tenant, secret, created_timestamp, last_attempt_timestamp,
+        last_delivered_timestamp, last_error, consecutive_failures
+    FROM subscribers
+    WHERE :tenant IS NULL OR tenant = :tenant
+    ORDER BY id";
+
+    let subscribers = conn
+        .prepare_cached(SELECT_SUBSCRIBERS_SQL)?
+        .query_map(named_params! { ":tenant": tenant }, |row| {
+            Ok(Subscriber {
+                id: row.get(0)?,
+                callback_url: row.get(1)?,
+                tenant: row.get(2)?,
+                secret: row.get::<_, Option<String>>(3)?.map(Secret::new),
+                created_timestamp: row.get(4)?,
+                last_attempt_timestamp: row.get(5)?,
+                last_delivered_timestamp: row.get(6)?,
+                last_error: row.get(7)?,
+                consecutive_failures: row.get(8)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(subscribers)
+}
+
+/// The JSON posted to subscribers for `tenant`'s report.
+pub fn report_payload(
+    run_id: &str,
+    tenant: &str,
+    report: &Report,
+    rendered: &RenderedReport,
+) -> serde_json::Value {
+    let mut payload = json!({
+        "run_id": run_id,
+        "tenant": tenant,
+        "period": rendered.period,
+    });
+    payload
+        .as_object_mut()
+        .expect("the payload is an object")
+        .extend(json_webhook::report_data(report));
+    payload["markdown"] = json!(rendered.markdown);
+    payload
+}
+
+/// Posts `payload` to the subscriber and records how the delivery went.
+#[instrument(skip_all, fields(subscriber = subscriber.id))]
+pub fn deliver(
+    conn: &Connection,
+    http: &HttpClient,
+    clock: &dyn Clock,
+    subscriber: &Subscriber,
+    payload: &[u8],
+) -> eyre::Result<()> {
+    let mut request = http
+        .post(&subscriber.callback_url)
+        .set("Content-Type", "application/json");
+    if let Some(secret) = &subscriber.secret {
+        let timestamp = clock.unix_timestamp();
+        request = request.set(TIMESTAMP_HEADER, &timestamp.to_string()).set(
+            SIGNATURE_HEADER,
+            &signature::sign(payload, timestamp, secret.expose()),
+        );
+    }
+
+    let result = http.send(conn, request, Body::Bytes(payload)).map(|_| ());
+    record_delivery(conn, clock, subscriber.id, result.as_ref().err())?;
+    if result.is_ok() {
+        info!("Posted report to subscriber {}", subscriber.id);
+    }
+    result
+}
+
+fn record_delivery(
+    conn: &Connection,
+    clock: &dyn Clock,
+    id: i64,
+    error: Option<&eyre::Report>,
+) -> eyre::Result<()> {
+    const RECORD_DELIVERY_SQL: &str = "
+    UPDATE subscribers SET
+        last_attempt_timestamp = :timestamp,
+        last_delivered_timestamp = IIF(:error IS NULL, :timestamp, last_delivered_timestamp),
+        last_error = :error,
+        consecutive_failures = IIF(:error IS NULL, 0, consecutive_failures + 1)
+    WHERE id = :id";
+
+    conn.prepare_cached(RECORD_DELIVERY_SQL)?
+        .execute(named_params! {
+            ":timestamp": clock.unix_timestamp(),
+            ":error": error.map(|e| format!("{e:#}")),
+            ":id": id,
+        })?;
+    Ok(())
+}
@@ -0,0 +1,282 @@
+//! Synthetic wastewater data, for demos and for exercising the pipeline, alerts, and site without
+//! the upstream dataset. Runs read it instead of downloading when the wastewater URL uses the
+//! `synthetic:` scheme, with the series shaped by query parameters, e.g.
+//! `synthetic:?seed=7&weeks=52&noise=0.3&outbreak=2024-11-15:6:3`.
+//!
+//! Every series is its off-season baseline times a seasonal wave peaking at the pathogen's usual time
+//! of year, a weekly trend, any injected outbreaks, and log-normal noise. Noise is drawn from the
+//! seed, the site, and the collection date alone, so with the seed and `since` set later runs
+//! extend the same history rather than revising it.
+
+use std::f64::consts::PI;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use chrono_tz::US;
+use color_eyre::eyre::{self, eyre, Context};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use url::Url;
+
+use crate::pipeline::{DEFAULT_COUNTIES, DEFAULT_PATHOGENS};
+
+/// Scheme of wastewater URLs served by [generate_csv].
+pub const SYNTHETIC_SCHEME: &str = "synthetic";
+
+/// A burst of activity added to every series on top of the seasonal wave.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outbreak {
+    /// When the outbreak peaks.
+    pub peak: NaiveDate,
+    /// How many times the usual level the peak reaches.
+    pub multiplier: f64,
+    /// Roughly how many weeks the outbreak lasts.
+    pub weeks: f64,
+}
+
+/// The shape of the generated series, set by the query parameters of a `synthetic:` URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticOptions {
+    /// `seed`: the same seed always generates the same samples. Defaults to the run's seed.
+    pub seed: Option<u64>,
+    /// `county`, repeatable, defaulting to [DEFAULT_COUNTIES].
+    pub counties: Vec<String>,
+    /// `pathogen`, repeatable, defaulting to [DEFAULT_PATHOGENS].
+    pub pathogens: Vec<String>,
+    /// `sites`: sites per county.
+    pub sites: usize,
+    /// `since`: date of the first samples, which the trend is measured from.
+    pub since: Option<NaiveDate>,
+    /// `weeks`: weeks of history up to the run's date, when `since` isn't set.
+    pub weeks: u32,
+    /// `baseline`: off-season concentration, in gene copies per person per day.
+    pub baseline: f64,
+    /// `amplitude`: how many times the baseline the seasonal peak reaches.
+    pub amplitude: f64,
+    /// `noise`: standard deviation of the log-normal noise on every sample.
+    pub noise: f64,
+    /// `trend`: relative change per week on top of the seasons, e.g. 0.02 for 2% a week.
+    pub trend: f64,
+    /// `outbreak`, repeatable, written `<peak date>:<multiplier>:<weeks>`.
+    pub outbreaks: Vec<Outbreak>,
+}
+
+impl Default for SyntheticOptions {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            counties: DEFAULT_COUNTIES.map(str::to_owned).to_vec(),
+            pathogens: DEFAULT_PATHOGENS.map(str::to_owned).to_vec(),
+            sites: 2,
+            since: None,
+            weeks: 52,
+            baseline: 100_000.0,
+            amplitude: 8.0,
+            noise: 0.25,
+            trend: 0.0,
+            outbreaks: Vec::new(),
+        }
+    }
+}
+
+impl SyntheticOptions {
+    /// The options of `url`, or None if it isn't a `synthetic:` URL.
+    pub fn from_wastewater_url(url: &str) -> eyre::Result<Option<Self>> {
+        let Ok(url) = Url::parse(url) else {
+            return Ok(None);
+        };
+        if url.scheme() != SYNTHETIC_SCHEME {
+            return Ok(None);
+        }
+
+        let mut options = Self::default();
+        let mut counties = Vec::new();
+        let mut pathogens = Vec::new();
+        for (key, value) in url.query_pairs() {
+            let invalid = || format!("Invalid synthetic data parameter {key}={value}");
+            match key.as_ref() {
+                "seed" => options.seed = Some(value.parse().with_context(invalid)?),
+                "county" => counties.push(value.into_owned()),
+                "pathogen" => pathogens.push(value.into_owned()),
+                "sites" => options.sites = value.parse().with_context(invalid)?,
+                "since" => options.since = Some(value.parse().with_context(invalid)?),
+                "weeks" => options.weeks = value.parse().with_context(invalid)?,
+                "baseline" => options.baseline = value.parse().with_context(invalid)?,
+                "amplitude" => options.amplitude = value.parse().with_context(invalid)?,
+                "noise" => options.noise = value.parse().with_context(invalid)?,
+                "trend" => options.trend = value.parse().with_context(invalid)?,
+                "outbreak" => options
+                    .outbreaks
+                    .push(parse_outbreak(&value).ok_or_else(|| eyre!(invalid()))?),
+                _ => return Err(eyre!("Unknown synthetic data parameter {key}")),
+            }
+        }
+        if !counties.is_empty() {
+            options.counties = counties;
+        }
+        if !pathogens.is_empty() {
+            options.pathogens = pathogens;
+        }
+
+        if options.sites == 0 || options.weeks == 0 {
+            return Err(eyre!("Synthetic data needs at least one site and one week"));
+        }
+        if !(options.baseline > 0.0 && options.amplitude >= 1.0 && options.noise >= 0.0) {
+            return Err(eyre!(
+                "Synthetic data needs a positive baseline, an amplitude of at least 1, and noise of at least 0"
+            ));
+        }
+        if options.trend <= -1.0 {
+            return Err(eyre!("A synthetic trend must be above -1"));
+        }
+        Ok(Some(options))
+    }
+}
+
+/// Parses `2024-11-15:6:3`.
+fn parse_outbreak(value: &str) -> Option<Outbreak> {
+    let mut parts = value.split(':');
+    let outbreak = Outbreak {
+        peak: parts.next()?.parse().ok()?,
+        multiplier: parts.next()?.parse().ok()?,
+        weeks: parts.next()?.parse().ok()?,
+    };
+    let valid = parts.next().is_none() && outbreak.multiplier >= 1.0 && outbreak.weeks > 0.0;
+    valid.then_some(outbreak)
+}
+
+/// Day of the year each pathogen's season peaks on, with its share of the seasonal amplitude.
+/// Pathogens not listed peak midwinter.
+fn season_peaks(pathogen: &str) -> &'static [(f64, f64)] {
+    match pathogen {
+        "FLUAV" => &[(15.0, 1.0)],
+        "FLUBV" => &[(60.0, 0.6)],
+        "RSV" => &[(350.0, 0.8)],
+        // A winter wave and a smaller summer one
+        "sars-cov-2" => &[(1.0, 0.7), (225.0, 0.4)],
+        _ => &[(15.0, 1.0)],
+    }
+}
+
+/// Gene targets each pathogen is measured with, as upstream.
+fn gene_targets(pathogen: &str) -> &'static [&'static str] {
+    match pathogen {
+        "FLUAV" | "FLUBV" => &["M"],
+        "RSV" => &["N"],
+        "sars-cov-2" => &["N1", "N2"],
+        _ => &["-"],
+    }
+}
+
+/// The noiseless level of a series on `date`, `weeks` after the first sample.
+fn level(options: &SyntheticOptions, pathogen: &str, date: NaiveDate, weeks: f64) -> f64 {
+    // How far `date` is from a day of the year, wrapping around new year
+    let distance = |peak: f64| {
+        let days = (f64::from(date.ordinal0()) - peak).abs() % 365.0;
+        days.min(365.0 - days)
+    };
+    let season: f64 = season_peaks(pathogen)
+        .iter()
+        .map(|&(peak, share)| {
+            let days = distance(peak);
+            share * (options.amplitude - 1.0) * (-(days / 30.0).powi(2) / 2.0).exp()
+        })
+        .sum();
+    let outbreaks: f64 = options
+        .outbreaks
+        .iter()
+        .map(|outbreak| {
+            let days = (date - outbreak.peak).num_days() as f64;
+            let spread = outbreak.weeks * 7.0 / 4.0;
+            (outbreak.multiplier - 1.0) * (-(days / spread).powi(2) / 2.0).exp()
+        })
+        .sum();
+
+    options.baseline * (1.0 + season) * (1.0 + options.trend).powf(weeks) * (1.0 + outbreaks)
+}
+
+/// A generator for one draw, fixed by the seed and what is being drawn.
+fn rng(seed: u64, parts: &[u64]) -> SmallRng {
+    // FNV-1a over the parts, so nearby inputs still land far apart
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for part in parts {
+        for byte in part.to_le_bytes() {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    SmallRng::seed_from_u64(hash)
+}
+
+/// A standard normal draw, by the Box-Muller transform.
+fn standard_normal(rng: &mut SmallRng) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+/// Generates the samples collected up to `generated_at` as a CSV in the upstream format, sampled
+/// twice a week and last updated at `generated_at`. `run_seed` is used when the options have no
+/// seed.
+pub fn generate_csv(
+    options: &SyntheticOptions,
+    generated_at: DateTime<Utc>,
+    run_seed: u64,
+) -> eyre::Result<Vec<u8>> {
+    let seed = options.seed.unwrap_or(run_seed);
+    let until = generated_at.with_timezone(&US::Pacific).date_naive();
+    let since = options
+        .since
+        .unwrap_or(until - Duration::weeks(i64::from(options.weeks)));
+    let updated = generated_at
+        .with_timezone(&US::Pacific)
+        .format("%Y-%m-%d %H:%M:%S%.3f")
+        .to_string();
+    let dates: Vec<NaiveDate> = since
+        .iter_days()
+        .take_while(|date| *date <= until)
+        .filter(|date| matches!(date.weekday(), Weekday::Mon | Weekday::Thu))
+        .collect();
+
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    csv.write_record([
+        "Sample Collection Date",
+        "Site Name",
+        "County",
+        "PCR Pathogen Target",
+        "PCR Gene Target",
+        "Normalized Pathogen Concentration (gene copies/person/day)",
+        "Date/Time Updated",
+    ])?;
+    for (county_index, county) in options.counties.iter().enumerate() {
+        for site in 0..options.sites {
+            let site_name = format!("{county} Synthetic WWTP {}", site + 1);
+            let site_parts = [county_index as u64, site as u64];
+            // Sites serve different populations, so they sit at different levels
+            let site_scale = (0.3 * standard_normal(&mut rng(seed, &site_parts))).exp();
+
+            for (pathogen_index, pathogen) in options.pathogens.iter().enumerate() {
+                for &date in &dates {
+                    let weeks = (date - since).num_days() as f64 / 7.0;
+                    let value = level(options, pathogen, date, weeks) * site_scale;
+                    let day = date.num_days_from_ce() as u64;
+                    let mut rng = rng(
+                        seed,
+                        &[site_parts[0], site_parts[1], pathogen_index as u64, day],
+                    );
+                    for gene_target in gene_targets(pathogen) {
+                        let sample = value * (options.noise * standard_normal(&mut rng)).exp();
+                        csv.write_record([
+                            date.to_string().as_str(),
+                            &site_name,
+                            county,
+                            pathogen,
+                            gene_target,
+                            &format!("{sample:.3}"),
+                            &updated,
+                        ])?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(csv.into_inner()?)
+}
@@ -0,0 +1,247 @@
+//! Posts reports to a Microsoft Teams incoming webhook as an Adaptive Card: the greeting, a fact set
+//! per county, the notices and rankings, and the sample dates and provenance in small print.
+//!
+//! The card is rendered with the rest of the report and stored with it, like Slack's blocks.
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+
+use crate::context::RunContext;
+use crate::http::{Body, HttpClient};
+use crate::links::DashboardLinks;
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::precision::Precision;
+use crate::report::Report;
+use crate::tenants::Tenant;
+
+/// Bytes of card body kept under Teams' 28 KB message limit, leaving room for the envelope.
+const MAX_CARD_BYTES: usize = 24_000;
+
+/// Posts reports to a Teams incoming webhook, or a Workflows webhook accepting Adaptive Cards.
+pub struct TeamsWebhook {
+    url: String,
+}
+
+impl TeamsWebhook {
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+
+    /// Posts `card` as a message.
+    #[instrument(skip_all)]
+    pub fn send(&self, conn: &Connection, http: &HttpClient, card: &Value) -> eyre::Result<()> {
+        let payload = json!({
+            "type": "message",
+            "attachments": [{
+                "contentType": "application/vnd.microsoft.card.adaptive",
+                "contentUrl": null,
+                "content": card,
+            }],
+        });
+
+        http.send(conn, http.post(&self.url), Body::Json(&payload))?;
+        info!("Posted report to Teams webhook");
+
+        Ok(())
+    }
+}
+
+/// Posts to the tenant's Teams webhook, if it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TeamsNotifier;
+
+impl Notifier for TeamsNotifier {
+    fn name(&self) -> &str {
+        "teams"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(url) = &tenant.teams_webhook_url else {
+            return Ok(());
+        };
+
+        let card = match &report.teams_card {
+            Some(card) => serde_json::from_str(card)?,
+            // Reports stored before cards were rendered
+            None => card(vec![text_block(&report.markdown)]),
+        };
+        TeamsWebhook::new(url.clone()).send(&ctx.db, &ctx.http, &card)
+    }
+}
+
+/// Renders the report as an Adaptive Card.
+pub fn report_card(report: &Report, links: Option<&DashboardLinks>, precision: Precision) -> Value {
+    let mut body = vec![json!({
+        "type": "TextBlock",
+        "text": report.greeting_line(),
+        "size": "Medium",
+        "weight": "Bolder",
+        "wrap": true,
+    })];
+    let mut size = body[0].to_string().len();
+
+    let counties: Vec<_> = report.lines.chunk_by(|a, b| a.county == b.county).collect();
+    for (i, lines) in counties.iter().enumerate() {
+        let facts: Vec<Value> = lines
+            .iter()
+            .map(|line| {
+                let mut details = report
+                    .describe_line(line, precision, |level| format!("**{level}**"))
+                    .unwrap_or_else(|| "There was an error getting data for this.".to_owned());
+                if let (Some(links), Some(_)) = (links, &line.summary) {
+                    details.push_str(&format!(
+                        " ([details]({}))",
+                        links.chart(&line.county_slug, &line.pathogen)
+                    ));
+                }
+                json!({ "title": line.pathogen, "value": details })
+            })
+            .collect();
+        let county = json!({
+            "type": "Container",
+            "separator": true,
+            "items": [
+                {
+                    "type": "TextBlock",
+                    "text": format!("{} County", lines[0].county),
+                    "weight": "Bolder",
+                    "wrap": true,
+                },
+                { "type": "FactSet", "facts": facts },
+            ],
+        });
+
+        size += county.to_string().len();
+        // Leave room for the notices, rankings, and small print
+        if size > MAX_CARD_BYTES / 2 {
+            body.push(text_block(&format!(
+                "…and {} more counties",
+                counties.len() - i
+            )));
+            break;
+        }
+        body.push(county);
+    }
+
+    let notices = &report.notices;
+    let notices = notices
+        .threshold_alerts
+        .iter()
+        .map(|alert| format!("🚨 {alert}"))
+        .chain(
+            notices
+                .coverage_changes
+                .iter()
+                .map(|change| format!("📍 {change}")),
+        )
+        .chain(
+            notices
+                .season_onsets
+                .iter()
+                .map(|onset| format!("🦠 {onset}")),
+        )
+        .chain(
+            notices
+                .revisions
+                .iter()
+                .map(|revisions| format!("✏️ {revisions}")),
+        )
+        .chain(
+            notices
+                .vintage
+                .iter()
+                .map(|vintage| format!("🔄 {vintage}")),
+        );
+    for notice in notices {
+        if size > MAX_CARD_BYTES {
+            break;
+        }
+        let block = text_block(&notice);
+        size += block.to_string().len();
+        body.push(block);
+    }
+
+    let rankings: Vec<Value> = report
+        .rankings
+        .iter()
+        .filter(|ranking| !ranking.counties.is_empty())
+        .map(|ranking| {
+            let counties: Vec<String> = ranking
+                .counties
+                .iter()
+                .enumerate()
+                .map(|(i, county)| {
+                    let entry = format!(
+                        "{}. {} ({})",
+                        i + 1,
+                        county.county,
+                        precision.format(county.level)
+                    );
+                    if county.highlighted {
+                        format!("**{entry}**")
+                    } else {
+                        entry
+                    }
+                })
+                .collect();
+            json!({ "title": ranking.pathogen, "value": counties.join(" · ") })
+        })
+        .collect();
+    if !rankings.is_empty() {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": "Statewide ranking (mean of each site's latest sample)",
+            "weight": "Bolder",
+            "separator": true,
+            "wrap": true,
+        }));
+        body.push(json!({ "type": "FactSet", "facts": rankings }));
+    }
+
+    let mut small_print = Vec::new();
+    if let Some(period) = report.period() {
+        if report.range.is_unbounded() {
+            small_print.push(format!("Latest samples collected {period}"));
+        } else {
+            small_print.push(format!(
+                "Samples {}, latest collected {period}",
+                report.range
+            ));
+        }
+    }
+    if let Some(provenance) = &report.provenance {
+        small_print.push(provenance.footer().replace(
+            &format!("<{}>", provenance.source_url),
+            &format!("[{0}]({0})", provenance.source_url),
+        ));
+    }
+    for text in small_print {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": text,
+            "size": "Small",
+            "isSubtle": true,
+            "wrap": true,
+        }));
+    }
+
+    card(body)
+}
+
+/// An Adaptive Card of `body`, at the newest version Teams renders everywhere.
+fn card(body: Vec<Value>) -> Value {
+    json!({
+        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+        "type": "AdaptiveCard",
+        "version": "1.4",
+        "msteams": { "width": "Full" },
+        "body": body,
+    })
+}
+
+fn text_block(text: &str) -> Value {
+    json!({ "type": "TextBlock", "text": text, "wrap": true })
+}
@@ -0,0 +1,150 @@
+//! Posts reports to a Telegram chat through the Bot API's `sendMessage`, formatted as MarkdownV2.
+//! Reports over Telegram's message limit are sent as several messages, split between lines.
+
+use color_eyre::eyre;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, instrument};
+
+use crate::context::RunContext;
+use crate::discord::split_message;
+use crate::http::{Body, HttpClient};
+use crate::pending::PendingReport;
+use crate::pipeline::Notifier;
+use crate::tenants::Tenant;
+use crate::useful::Secret;
+
+/// Where the Bot API is served.
+pub const DEFAULT_TELEGRAM_API_URL: &str = "https://api.telegram.org";
+/// Most characters Telegram accepts in a message.
+const MESSAGE_LIMIT: usize = 4096;
+/// Characters MarkdownV2 needs escaped outside of formatting.
+const SPECIAL_CHARACTERS: &str = "_*[]()~`>#+-=|{}.!\\";
+
+/// A chat reports are sent to, and the bot sending them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramChat {
+    /// Token BotFather gave the bot, e.g. `123456:ABC-DEF...`.
+    pub bot_token: Secret,
+    /// The chat's ID, or `@username` for a public channel.
+    pub chat_id: String,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_api_url() -> String {
+    DEFAULT_TELEGRAM_API_URL.to_owned()
+}
+
+impl TelegramChat {
+    /// Sends `markdown` to the chat, as several messages if it's too long for one.
+    #[instrument(skip_all, fields(chat_id = self.chat_id))]
+    pub fn send(&self, conn: &Connection, http: &HttpClient, markdown: &str) -> eyre::Result<()> {
+        let url = format!(
+            "{}/bot{}/sendMessage",
+            self.api_url.trim_end_matches('/'),
+            self.bot_token.expose()
+        );
+
+        let chunks = split_message(&markdown_v2(markdown), MESSAGE_LIMIT);
+        for chunk in &chunks {
+            let payload = json!({
+                "chat_id": self.chat_id,
+                "text": chunk,
+                "parse_mode": "MarkdownV2",
+                "link_preview_options": { "is_disabled": true },
+            });
+            http.send(conn, http.post(&url), Body::Json(&payload))?;
+        }
+        info!("Sent report to Telegram chat in {} messages", chunks.len());
+
+        Ok(())
+    }
+}
+
+/// Sends to the tenant's Telegram chat, if it has one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TelegramNotifier;
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    fn send(&self, ctx: &RunContext, tenant: &Tenant, report: &PendingReport) -> eyre::Result<()> {
+        let Some(chat) = &tenant.telegram_chat else {
+            return Ok(());
+        };
+
+        chat.send(&ctx.db, &ctx.http, &report.markdown)
+    }
+}
+
+/// Renders the markdown reports are written in as MarkdownV2: `**bold**` as bold, `[text](<url>)`
+/// as links, and `-# ` subtext lines as italics, with everything else escaped.
+pub fn markdown_v2(markdown: &str) -> String {
+    markdown
+        .lines()
+        .map(|line| match line.strip_prefix("-# ") {
+            Some(subtext) => format!("_{}_", inline(subtext)),
+            None => inline(line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn inline(line: &str) -> String {
+    let mut text = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let link = rest[start..].split_once("](<").and_then(|(label, rest)| {
+            let (url, after) = rest.split_once(">)")?;
+            Some((&label[1..], url, after))
+        });
+        let Some((label, url, after)) = link else {
+            break;
+        };
+
+        text.push_str(&bold(&rest[..start]));
+        text.push_str(&format!(
+            "[{}]({})",
+            bold(label),
+            url.replace('\\', "\\\\").replace(')', "\\)")
+        ));
+        rest = after;
+    }
+    text.push_str(&bold(rest));
+    text
+}
+
+/// Escapes `text`, turning `**` pairs into bold.
+fn bold(text: &str) -> String {
+    let parts: Vec<&str> = text.split("**").collect();
+    // An odd number of ** leaves the last one unpaired, so it is kept as text
+    let paired = parts.len() - (parts.len() + 1) % 2;
+    let mut bold = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i >= paired {
+            bold.push_str("\\*\\*");
+        }
+        if i % 2 == 1 && i < paired {
+            bold.push_str(&format!("*{}*", escape(part)));
+        } else {
+            bold.push_str(&escape(part));
+        }
+    }
+    bold
+}
+
+/// Escapes the characters MarkdownV2 uses for formatting.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if SPECIAL_CHARACTERS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
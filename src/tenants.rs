@@ -0,0 +1,350 @@
+//! Tenants are independent communities served from one database, each with its own counties,
+//! webhooks, greeting, and quiet hours.
+//!
+//! Tenants are listed in a JSON file, e.g.
+//!
+//! ```json
+//! [
+//!     {
+//!         "name": "tacoma",
+//!         "counties": ["Pierce"],
+//!         "discord_webhook_url": "https://discord.com/api/webhooks/...",
+//!         "greeting": "Good morning Tacoma! Here is the wastewater data {range}:",
+//!         "quiet_hours": "22:00-07:00 America/Los_Angeles"
+//!     },
+//!     {
+//!         "name": "staging",
+//!         "counties": "all",
+//!         "discord_webhook_url": "https://discord.com/api/webhooks/...",
+//!         "canary": true
+//!     }
+//! ]
+//! ```
+//!
+//! Every tenant gets its own report, stored and delivered separately, so one tenant's delivery
+//! doesn't depend on another's. Without a tenants file, the top-level configuration acts as a
+//! single tenant named [DEFAULT_TENANT].
+//!
+//! The file is checked against [tenants_schema] before it is read, so a misspelled key like
+//! `countys` is an error rather than silently ignored. `hygieia config schema` prints the schema
+//! for editors to autocomplete from.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use color_eyre::eyre::{self, eyre, Context};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::context::{Config, Selection};
+use crate::json_schema;
+use crate::mastodon::MastodonAccount;
+use crate::matrix::MatrixRoom;
+use crate::ntfy::NtfyTopic;
+use crate::pipeline::DEFAULT_PATHOGENS;
+use crate::pushover::PushoverUser;
+use crate::rules::{AlertRules, NotifyMode};
+use crate::telegram::TelegramChat;
+use crate::useful::Secret;
+
+/// Name of the tenant made from the top-level configuration.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// One community's selection of counties and where its reports go.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tenant {
+    /// Identifies the tenant's reports in the database and logs.
+    pub name: String,
+    /// A list of counties, or "all" for every county with stored samples.
+    pub counties: Selection,
+    /// Pathogen targets reported, a list or "all", defaulting to [DEFAULT_PATHOGENS].
+    #[serde(default = "default_pathogens")]
+    pub pathogens: Selection,
+    /// Replaces the report's opening line. `{range}` in it is replaced with the report's date range.
+    pub greeting: Option<String>,
+    pub discord_webhook_url: Option<String>,
+    pub json_webhook_url: Option<String>,
+    pub json_webhook_secret: Option<Secret>,
+    pub slack_webhook_url: Option<String>,
+    pub teams_webhook_url: Option<String>,
+    pub matrix_room: Option<MatrixRoom>,
+    pub telegram_chat: Option<TelegramChat>,
+    pub mastodon_account: Option<MastodonAccount>,
+    pub ntfy_topic: Option<NtfyTopic>,
+    pub pushover_user: Option<PushoverUser>,
+    /// Addresses reports are emailed to, through the configured SMTP server.
+    #[serde(default)]
+    pub email_to: Vec<String>,
+    /// When reports are held back, to be delivered by the first notify after.
+    pub quiet_hours: Option<QuietHours>,
+    /// Conditions reported as alerts when they hold. See [crate::rules].
+    #[serde(default)]
+    pub alert_rules: AlertRules,
+    /// Whether the tenant is sent every report or only ones where an alert rule fired.
+    #[serde(default)]
+    pub notify_mode: NotifyMode,
+    /// Receives reports built with a new version or configuration before other tenants do. See
+    /// [crate::canary].
+    #[serde(default)]
+    pub canary: bool,
+}
+
+fn default_pathogens() -> Selection {
+    Selection::only(&DEFAULT_PATHOGENS)
+}
+
+impl Tenant {
+    /// The tenant made from the top-level configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            name: DEFAULT_TENANT.to_owned(),
+            counties: config.counties.clone(),
+            pathogens: config.pathogens.clone(),
+            greeting: None,
+            discord_webhook_url: config.discord_webhook_url.clone(),
+            json_webhook_url: config.json_webhook_url.clone(),
+            json_webhook_secret: config.json_webhook_secret.clone(),
+            slack_webhook_url: config.slack_webhook_url.clone(),
+            teams_webhook_url: config.teams_webhook_url.clone(),
+            matrix_room: config.matrix_room.clone(),
+            telegram_chat: config.telegram_chat.clone(),
+            mastodon_account: config.mastodon_account.clone(),
+            ntfy_topic: config.ntfy_topic.clone(),
+            pushover_user: config.pushover_user.clone(),
+            email_to: config.email_to.clone(),
+            quiet_hours: None,
+            alert_rules: config.alert_rules.clone(),
+            notify_mode: config.notify_mode,
+            canary: false,
+        }
+    }
+}
+
+/// A daily window in a time zone, e.g. `22:00-07:00 America/Los_Angeles`. Windows ending before they
+/// start run past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+}
+
+impl QuietHours {
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let time = at.with_timezone(&self.timezone).time();
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (window, timezone) = s
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| format!("Expected HH:MM-HH:MM and a time zone, got {s}"))?;
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| format!("Expected HH:MM-HH:MM, got {window}"))?;
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|e| format!("Invalid time {time}: {e}"))
+        };
+
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+            timezone: timezone
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid time zone {timezone}: {e}"))?,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// JSON Schema of the tenants file.
+pub fn tenants_schema() -> Value {
+    let selection = |description: &str| {
+        json!({
+            "description": description,
+            "anyOf": [
+                { "type": "string", "enum": ["all"] },
+                { "type": "array", "items": { "type": "string" } },
+            ],
+        })
+    };
+    let optional_string =
+        |description: &str| json!({ "type": ["string", "null"], "description": description });
+    let string = |description: &str| json!({ "type": "string", "description": description });
+
+    let tenant = json!({
+        "type": "object",
+        "required": ["name", "counties"],
+        "additionalProperties": false,
+        "properties": {
+            "name": {
+                "type": "string",
+                "minLength": 1,
+                "description": "Identifies the tenant's reports in the database and logs.",
+            },
+            "counties": selection("A list of counties, or \"all\" for every county with stored samples."),
+            "pathogens": selection("Pathogen targets reported, a list or \"all\"."),
+            "greeting": optional_string("Replaces the report's opening line. {range} in it is replaced with the report's date range."),
+            "discord_webhook_url": optional_string("Discord webhook reports are posted to."),
+            "json_webhook_url": optional_string("Endpoint reports are posted to as JSON."),
+            "json_webhook_secret": optional_string("Secret the JSON webhook payload is signed with."),
+            "slack_webhook_url": optional_string("Slack incoming webhook reports are posted to."),
+            "teams_webhook_url": optional_string("Microsoft Teams incoming webhook reports are posted to."),
+            "matrix_room": {
+                "type": ["object", "null"],
+                "description": "Matrix room reports are posted to.",
+                "required": ["homeserver_url", "access_token", "room_id"],
+                "additionalProperties": false,
+                "properties": {
+                    "homeserver_url": string("Base URL of the homeserver, e.g. https://matrix.example.org."),
+                    "access_token": string("Access token of the account posting reports."),
+                    "room_id": string("The room's ID, e.g. !abcdef:example.org."),
+                },
+            },
+            "telegram_chat": {
+                "type": ["object", "null"],
+                "description": "Telegram chat reports are sent to.",
+                "required": ["bot_token", "chat_id"],
+                "additionalProperties": false,
+                "properties": {
+                    "bot_token": string("Token BotFather gave the bot."),
+                    "chat_id": string("The chat's ID, or @username for a public channel."),
+                    "api_url": string("Where the Bot API is served."),
+                },
+            },
+            "mastodon_account": {
+                "type": ["object", "null"],
+                "description": "Mastodon account reports are posted from.",
+                "required": ["instance_url", "access_token"],
+                "additionalProperties": false,
+                "properties": {
+                    "instance_url": string("Base URL of the account's instance, e.g. https://mastodon.social."),
+                    "access_token": string("Token of an application with the write:statuses scope."),
+                    "visibility": {
+                        "type": "string",
+                        "enum": ["public", "unlisted", "private", "direct"],
+                        "description": "Who statuses are shown to, defaulting to public.",
+                    },
+                },
+            },
+            "ntfy_topic": {
+                "type": ["object", "null"],
+                "description": "ntfy topic reports are published to.",
+                "required": ["url"],
+                "additionalProperties": false,
+                "properties": {
+                    "url": string("The topic's URL, e.g. https://ntfy.sh/my-wastewater."),
+                    "token": optional_string("Access token for topics that need one."),
+                },
+            },
+            "pushover_user": {
+                "type": ["object", "null"],
+                "description": "Pushover user reports are sent to.",
+                "required": ["app_token", "user_key"],
+                "additionalProperties": false,
+                "properties": {
+                    "app_token": string("API token of the application sending reports."),
+                    "user_key": string("The user's or group's key."),
+                    "alert_thresholds": {
+                        "type": "object",
+                        "additionalProperties": { "type": "number" },
+                        "description": "Concentrations by pathogen target that send the report at high priority when a county crosses them.",
+                    },
+                    "api_url": string("Where the Pushover API is served."),
+                },
+            },
+            "email_to": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Addresses reports are emailed to, through the configured SMTP server.",
+            },
+            "quiet_hours": optional_string("When reports are held back, e.g. 22:00-07:00 America/Los_Angeles."),
+            "alert_rules": {
+                "type": "array",
+                "items": { "type": "string", "minLength": 1 },
+                "description": "Conditions reported as alerts when they hold, e.g. \"King sars-cov-2 weekly_change > 25%\" or \"* RSV latest >= 500000\".",
+            },
+            "notify_mode": {
+                "type": "string",
+                "enum": ["always", "alert"],
+                "description": "Whether the tenant is sent every report, the default, or only reports where an alert rule fired.",
+            },
+            "canary": {
+                "type": "boolean",
+                "description": "Whether the tenant receives reports built with a new version or configuration before the others.",
+            },
+        },
+    });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "hygieia tenants",
+        "description": "Communities served from one database, each with its own counties and destinations.",
+        "type": "array",
+        "items": tenant,
+    })
+}
+
+/// Reads the tenants from a JSON file, checking it against [tenants_schema] and that names are
+/// unique, every tenant has counties, and tenants in alert mode have alert rules.
+pub fn load_tenants(path: &Path) -> eyre::Result<Vec<Tenant>> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("Error reading tenants file {}", path.display()))?;
+    let value: Value = serde_json::from_str(&json)
+        .with_context(|| format!("Error parsing tenants file {}", path.display()))?;
+    let errors = json_schema::validate(&tenants_schema(), &value);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        return Err(eyre!(
+            "Invalid tenants file {}:\n{}",
+            path.display(),
+            errors.join("\n")
+        ));
+    }
+    let tenants: Vec<Tenant> = serde_json::from_value(value)
+        .with_context(|| format!("Error parsing tenants file {}", path.display()))?;
+
+    let mut names = HashSet::new();
+    for tenant in &tenants {
+        if !names.insert(tenant.name.as_str()) {
+            return Err(eyre!("Tenant {} is listed more than once", tenant.name));
+        }
+        if tenant.counties.is_empty() {
+            return Err(eyre!("Tenant {} has no counties", tenant.name));
+        }
+        if tenant.pathogens.is_empty() {
+            return Err(eyre!("Tenant {} has no pathogens", tenant.name));
+        }
+        if tenant.notify_mode == NotifyMode::Alert && tenant.alert_rules.is_empty() {
+            return Err(eyre!(
+                "Tenant {} is notified in alert mode without alert rules",
+                tenant.name
+            ));
+        }
+    }
+
+    Ok(tenants)
+}
@@ -1,25 +1,115 @@
+use std::collections::hash_map::RandomState;
 use std::env::{self, VarError};
 use std::ffi::OsStr;
+use std::hash::{BuildHasher, Hasher};
 use std::str::FromStr;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex, PoisonError};
 
+use chrono::{DateTime, Utc};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-/// Attempts to get the current Unix timestamp in seconds.
-///
-/// # Returns
-///
-/// - `Ok(u64)`: The current Unix timestamp in seconds.
-/// - `Err(std::time::SystemTimeError)`: If the system time is before UNIX_EPOCH.
-pub fn try_unix_timestamp() -> Result<u64, std::time::SystemTimeError> {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|duration| duration.as_secs())
+/// Source of the current time.
+/// Code takes a clock instead of reading the system time, so tests are deterministic and past runs
+/// can be replayed as of when they happened.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The current Unix timestamp in seconds, or 0 before the epoch.
+    fn unix_timestamp(&self) -> u64 {
+        self.now().timestamp().max(0) as u64
+    }
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock stopped at a fixed time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Source of a run's randomness, such as retry jitter and synthetic data.
+/// Code draws from the run's generator instead of the system's, so a run given a seed draws the
+/// same numbers every time and simulations and tests can be reproduced. Clones share one sequence.
+#[derive(Clone)]
+pub struct SeededRng {
+    seed: u64,
+    rng: Arc<Mutex<SmallRng>>,
+}
+
+impl SeededRng {
+    /// A generator seeded with `seed`, or with a random seed without one.
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Self {
+            seed,
+            rng: Arc::new(Mutex::new(SmallRng::seed_from_u64(seed))),
+        }
+    }
+
+    /// The seed, which reproduces the run's draws when given again.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A number from 0 up to but not including 1.
+    pub fn next_f64(&self) -> f64 {
+        self.rng
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .gen()
+    }
+}
+
+impl std::fmt::Debug for SeededRng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SeededRng")
+            .field("seed", &self.seed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A value kept out of logs and debug output, such as a key or token.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(REDACTED)")
+    }
 }
 
 /// Initializes tracing with a pretty print format for the console.
+/// Logs go to stderr so reports and other output printed to stdout can be piped.
 pub fn init_tracing() {
     let subscriber = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
         .pretty()
         .with_file(true)
         .with_line_number(true)
@@ -90,3 +180,20 @@ where
 {
     env_or_else(key, || default)
 }
+
+/// Like [env_or], but returns None when the variable isn't set.
+pub fn env_opt<K, V>(key: K) -> Result<Option<V>, EnvVarError>
+where
+    K: AsRef<OsStr>,
+    V: FromStr,
+    V::Err: std::fmt::Debug,
+{
+    match env::var(&key) {
+        Ok(val) => val.parse().map(Some).map_err(|_| EnvVarError::Parse {
+            value: key.as_ref().to_string_lossy().into_owned(),
+            expected_type: std::any::type_name::<V>().to_string(),
+        }),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(e) => Err(EnvVarError::VarError(e)),
+    }
+}